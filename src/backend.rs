@@ -1,7 +1,9 @@
 use std::env;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
+use std::sync::Arc;
 
+use anyhow::{anyhow, Result};
 use tokio::process;
 
 fn native_jdktools_path() -> String {
@@ -16,9 +18,66 @@ fn kotlinc_path() -> String {
     )
 }
 
-#[derive(Debug, Copy, Clone)]
+pub(crate) fn exe_name(name: &str) -> String {
+    if cfg!(windows) {
+        format!("{}.exe", name)
+    } else {
+        name.to_string()
+    }
+}
+
+/// Find an executable in the directories listed in PATH
+pub fn find_program(name: &str) -> Option<PathBuf> {
+    let file_name = exe_name(name);
+    env::var_os("PATH").and_then(|paths| {
+        env::split_paths(&paths)
+            .map(|dir| dir.join(&file_name))
+            .find(|it| it.is_file())
+    })
+}
+
+fn native_jdktools_program() -> Option<PathBuf> {
+    env::var_os("NATIVE_JDK").map(PathBuf::from)
+}
+
+/// JDK used by the jdk backends, its tools are looked up in PATH unless a home is given
+#[derive(Debug, Default, Clone)]
+pub struct Jdk {
+    home: Option<Arc<Path>>,
+}
+
+impl Jdk {
+    /// Use the JDK installed in `home`, which must contain `bin/javac`
+    pub fn at(home: &Path) -> Result<Self> {
+        if !home.join("bin").join(exe_name("javac")).is_file() {
+            return Err(anyhow!(
+                "{} is not a JDK home, there is no bin/javac",
+                home.display()
+            ));
+        }
+        Ok(Self {
+            home: Some(Arc::from(home)),
+        })
+    }
+
+    fn command(&self, tool: &str) -> process::Command {
+        match &self.home {
+            Some(home) => process::Command::new(home.join("bin").join(exe_name(tool))),
+            None => process::Command::new(tool),
+        }
+    }
+
+    fn program(&self, tool: &str) -> Option<PathBuf> {
+        match &self.home {
+            Some(home) => Some(home.join("bin").join(exe_name(tool))).filter(|it| it.is_file()),
+            None => find_program(tool),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
 pub enum JavaCompilationBackend {
-    JdkJavac,
+    JdkJavac(Jdk),
     NativeJavac,
 }
 
@@ -27,7 +86,7 @@ impl FromStr for JavaCompilationBackend {
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         match s {
-            "javac" => Ok(JavaCompilationBackend::JdkJavac),
+            "javac" => Ok(JavaCompilationBackend::JdkJavac(Jdk::default())),
             "native" => Ok(JavaCompilationBackend::NativeJavac),
             other => Err(format!("Can't convert {} to a valid Backend", other)),
         }
@@ -37,7 +96,7 @@ impl FromStr for JavaCompilationBackend {
 impl JavaCompilationBackend {
     pub fn command(&self) -> process::Command {
         match self {
-            JavaCompilationBackend::JdkJavac => process::Command::new("javac"),
+            JavaCompilationBackend::JdkJavac(jdk) => jdk.command("javac"),
             JavaCompilationBackend::NativeJavac => {
                 let mut cmd = process::Command::new(native_jdktools_path());
                 cmd.arg("javac");
@@ -45,6 +104,28 @@ impl JavaCompilationBackend {
             }
         }
     }
+
+    /// Path to the executable this backend will launch, if it can be found
+    pub fn program(&self) -> Option<PathBuf> {
+        match self {
+            JavaCompilationBackend::JdkJavac(jdk) => jdk.program("javac"),
+            JavaCompilationBackend::NativeJavac => native_jdktools_program(),
+        }
+    }
+
+    /// Ask the compiler for its version (e.g. `17.0.2`)
+    pub async fn version(&self) -> Option<String> {
+        let output = self.command().arg("-version").output().await.ok()?;
+        // Old javac versions print to stderr
+        let text = if output.stdout.is_empty() {
+            String::from_utf8_lossy(&output.stderr).into_owned()
+        } else {
+            String::from_utf8_lossy(&output.stdout).into_owned()
+        };
+        text.trim()
+            .strip_prefix("javac ")
+            .map(|it| it.trim().to_string())
+    }
 }
 
 #[derive(Debug, Copy, Clone)]
@@ -55,40 +136,68 @@ pub enum KotlinCompilationBackend {
 impl KotlinCompilationBackend {
     pub fn command(&self) -> process::Command {
         match self {
-            KotlinCompilationBackend::Kotlinc => {
-                let mut cmd = process::Command::new(kotlinc_path());
-                cmd
-            }
+            KotlinCompilationBackend::Kotlinc => process::Command::new(kotlinc_path()),
+        }
+    }
+
+    /// Path to the executable this backend will launch, if it can be found
+    pub fn program(&self) -> Option<PathBuf> {
+        match self {
+            KotlinCompilationBackend::Kotlinc => env::var_os("KOTLINC_HOME")
+                .map(|home| PathBuf::from(home).join("bin/kotlinc"))
+                .filter(|it| it.exists()),
         }
     }
 }
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Clone)]
 pub enum Runtime {
-    Java,
+    Java(Jdk),
 }
 
 impl Runtime {
     pub fn command(&self) -> process::Command {
         match self {
-            Runtime::Java => process::Command::new("java"),
+            Runtime::Java(jdk) => jdk.command("java"),
         }
     }
+
+    /// Path to the executable this backend will launch, if it can be found
+    pub fn program(&self) -> Option<PathBuf> {
+        match self {
+            Runtime::Java(jdk) => jdk.program("java"),
+        }
+    }
+
+    /// Names of the modules of the runtime image, None if they can't be listed
+    pub async fn modules(&self) -> Option<Vec<String>> {
+        let output = self.command().arg("--list-modules").output().await.ok()?;
+        if !output.status.success() {
+            return None;
+        }
+        Some(
+            String::from_utf8_lossy(&output.stdout)
+                .lines()
+                // jdk.incubator.vector@17.0.2
+                .filter_map(|it| it.split('@').next())
+                .map(str::trim)
+                .filter(|it| !it.is_empty())
+                .map(str::to_string)
+                .collect(),
+        )
+    }
 }
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Clone)]
 pub enum DocumentationBackend {
-    JdkJavadoc,
+    JdkJavadoc(Jdk),
     NativeJavadoc,
 }
 
 impl DocumentationBackend {
     pub fn command(&self) -> process::Command {
         match self {
-            DocumentationBackend::JdkJavadoc => {
-                let cmd = process::Command::new("javadoc");
-                cmd
-            }
+            DocumentationBackend::JdkJavadoc(jdk) => jdk.command("javadoc"),
             DocumentationBackend::NativeJavadoc => {
                 let mut cmd = process::Command::new(native_jdktools_path());
                 cmd.arg("javadoc");
@@ -96,21 +205,26 @@ impl DocumentationBackend {
             }
         }
     }
+
+    /// Path to the executable this backend will launch, if it can be found
+    pub fn program(&self) -> Option<PathBuf> {
+        match self {
+            DocumentationBackend::JdkJavadoc(jdk) => jdk.program("javadoc"),
+            DocumentationBackend::NativeJavadoc => native_jdktools_program(),
+        }
+    }
 }
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Clone)]
 pub enum PackageBackend {
-    JdkJar,
+    JdkJar(Jdk),
     NativeJar,
 }
 
 impl PackageBackend {
     pub fn command(&self) -> process::Command {
         match self {
-            PackageBackend::JdkJar => {
-                let cmd = process::Command::new("jar");
-                cmd
-            }
+            PackageBackend::JdkJar(jdk) => jdk.command("jar"),
             PackageBackend::NativeJar => {
                 let mut cmd = process::Command::new(native_jdktools_path());
                 cmd.arg("jar");
@@ -118,4 +232,60 @@ impl PackageBackend {
             }
         }
     }
+
+    /// jarsigner of the same JDK, native-jdktools doesn't have one so it comes from PATH
+    pub fn signer_command(&self) -> process::Command {
+        match self {
+            PackageBackend::JdkJar(jdk) => jdk.command("jarsigner"),
+            PackageBackend::NativeJar => Jdk::default().command("jarsigner"),
+        }
+    }
+
+    /// Path to the executable this backend will launch, if it can be found
+    pub fn program(&self) -> Option<PathBuf> {
+        match self {
+            PackageBackend::JdkJar(jdk) => jdk.program("jar"),
+            PackageBackend::NativeJar => native_jdktools_program(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::backend::{exe_name, Jdk};
+    use crate::test_utils::temp_dir;
+    use crate::Env;
+
+    #[test]
+    fn test_java_home() {
+        let home = temp_dir("java-home");
+        assert!(Jdk::at(&home).is_err());
+
+        std::fs::create_dir_all(home.join("bin")).unwrap();
+        for tool in ["javac", "java", "javadoc", "jar"] {
+            std::fs::write(home.join("bin").join(exe_name(tool)), "").unwrap();
+        }
+        let mut env = Env::new(false);
+        env.set_java_home(&home).unwrap();
+
+        let bin = home.join("bin");
+        let program = |cmd: tokio::process::Command| cmd.as_std().get_program().to_owned();
+        assert_eq!(
+            program(env.comp_backend.command()),
+            bin.join(exe_name("javac"))
+        );
+        assert_eq!(program(env.runtime.command()), bin.join(exe_name("java")));
+        assert_eq!(
+            program(env.doc_backend.command()),
+            bin.join(exe_name("javadoc"))
+        );
+        assert_eq!(
+            program(env.package_backend.command()),
+            bin.join(exe_name("jar"))
+        );
+        assert_eq!(
+            env.package_backend.program(),
+            Some(bin.join(exe_name("jar")))
+        );
+    }
 }
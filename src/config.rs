@@ -0,0 +1,182 @@
+use std::path::PathBuf;
+
+use anyhow::Result;
+use serde::Serialize;
+
+use crate::backend::KotlinCompilationBackend;
+use crate::dependencies::RepoLayout;
+use crate::module::Module;
+use crate::tasks::javac_flags;
+use crate::workspace::Workspace;
+use crate::Env;
+
+/// Configuration a task would run with, the manifests merged with the command line, for
+/// `--print cfg`
+#[derive(Debug, Serialize)]
+pub struct EffectiveConfig {
+    pub workspace_root: String,
+    pub offline: bool,
+    pub locked: bool,
+    pub strict: bool,
+    pub verify_signatures: bool,
+    pub path_separator: String,
+    pub download_attempts: u32,
+    pub download_timeout_secs: Option<u64>,
+    /// `--config` overrides, already applied to the modules
+    pub overrides: Vec<String>,
+    pub toolchain: ToolchainConfig,
+    pub repositories: Vec<RepositoryConfig>,
+    pub modules: Vec<ModuleConfig>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ToolchainConfig {
+    pub javac_version: Option<String>,
+    pub javac: Option<String>,
+    pub java: Option<String>,
+    pub javadoc: Option<String>,
+    pub jar: Option<String>,
+    pub kotlinc: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RepositoryConfig {
+    pub name: String,
+    pub url: String,
+    pub layout: String,
+    /// Of the ivy layout
+    pub pattern: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ModuleConfig {
+    /// `group:artifact:version`
+    pub module: String,
+    pub root: String,
+    /// Where the dependencies are downloaded
+    pub cache: String,
+    pub source: String,
+    pub target: String,
+    pub enable_preview: bool,
+    /// Without the classpath and the sources
+    pub javac_args: Vec<String>,
+    pub kotlin_max_heap: Option<String>,
+    pub launcher_args: Vec<String>,
+}
+
+fn display(path: Option<PathBuf>) -> Option<String> {
+    path.map(|it| it.display().to_string())
+}
+
+impl EffectiveConfig {
+    pub async fn new(workspace: &Workspace, env: &Env) -> Self {
+        Self {
+            workspace_root: workspace.root.display().to_string(),
+            offline: env.offline,
+            locked: env.locked,
+            strict: env.strict,
+            verify_signatures: env.verify_signatures,
+            path_separator: env.path_separator.to_string(),
+            download_attempts: env.downloads.attempts,
+            download_timeout_secs: env.downloads.timeout.map(|it| it.as_secs()),
+            overrides: env
+                .config
+                .iter()
+                .map(|it| format!("{}={}", it.key, it.value))
+                .collect(),
+            toolchain: ToolchainConfig {
+                javac_version: env.comp_backend.version().await,
+                javac: display(env.comp_backend.program()),
+                java: display(env.runtime.program()),
+                javadoc: display(env.doc_backend.program()),
+                jar: display(env.package_backend.program()),
+                kotlinc: display(KotlinCompilationBackend::Kotlinc.program()),
+            },
+            repositories: env
+                .repos
+                .iter()
+                .map(|it| {
+                    let (layout, pattern) = match &it.layout {
+                        RepoLayout::Maven => ("maven", None),
+                        RepoLayout::Ivy(pattern) => ("ivy", Some(pattern.clone())),
+                    };
+                    RepositoryConfig {
+                        name: it.name.clone(),
+                        url: it.url.to_string(),
+                        layout: layout.to_string(),
+                        pattern,
+                    }
+                })
+                .collect(),
+            modules: workspace
+                .members
+                .iter()
+                .map(|it| ModuleConfig::new(it, env))
+                .collect(),
+        }
+    }
+
+    pub fn to_toml(&self) -> Result<String> {
+        Ok(toml::to_string(self)?)
+    }
+}
+
+impl ModuleConfig {
+    fn new(module: &Module, env: &Env) -> Self {
+        Self {
+            module: format!("{}:{}:{}", module.group, module.artifact, module.version),
+            root: module.dir.display().to_string(),
+            cache: module.dir.join("libs").display().to_string(),
+            source: module.java.source.clone(),
+            target: module.java.target.clone(),
+            enable_preview: module.java.enable_preview,
+            javac_args: javac_flags(module, &env.comp_backend),
+            kotlin_max_heap: module.kotlin.max_heap.clone(),
+            launcher_args: module.jvm.launcher_args(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::EffectiveConfig;
+    use crate::test_utils::temp_dir;
+    use crate::{Env, Workspace};
+
+    #[tokio::test]
+    async fn test_cli_override_wins() {
+        let mut env = Env::new(false);
+        env.config = vec!["java.target=17".parse().unwrap()];
+        let dir = temp_dir("effective-config");
+        std::fs::write(
+            dir.join("jcargo.toml"),
+            r#"
+            group = "marais"
+            artifact = "configured"
+            version = "0.1.0"
+
+            [java]
+            source = "11"
+            target = "11"
+            "#,
+        )
+        .unwrap();
+        let workspace = Workspace::load(&dir, &env).await.unwrap();
+
+        let config = EffectiveConfig::new(&workspace, &env).await;
+        let module = &config.modules[0];
+        assert_eq!(module.source, "11");
+        assert_eq!(module.target, "17");
+        let javac = module.javac_args.join(" ");
+        assert!(javac.contains("-target 17"), "{}", javac);
+        assert!(!javac.contains("-cp"));
+
+        let text = config.to_toml().unwrap();
+        assert!(
+            text.contains("overrides = [\"java.target=17\"]"),
+            "{}",
+            text
+        );
+        assert!(text.contains("target = \"17\""));
+    }
+}
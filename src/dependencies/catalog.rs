@@ -0,0 +1,200 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use anyhow::{anyhow, Context, Result};
+use semver::VersionReq;
+use serde::Deserialize;
+
+use crate::manifest::CompleteDependencyDef;
+
+/// Where Gradle puts the version catalog, relative to the module
+pub const DEFAULT_CATALOG: &str = "gradle/libs.versions.toml";
+
+/// A Gradle version catalog (`libs.versions.toml`), only the libraries are used
+#[derive(Debug, Default)]
+pub struct VersionCatalog {
+    /// By normalized alias
+    libraries: BTreeMap<String, CompleteDependencyDef>,
+}
+
+#[derive(Debug, Deserialize)]
+struct CatalogDef {
+    #[serde(default)]
+    versions: BTreeMap<String, VersionDef>,
+    #[serde(default)]
+    libraries: BTreeMap<String, LibraryDef>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum LibraryDef {
+    /// `group:artifact:version`
+    Notation(String),
+    Table {
+        /// `group:artifact`
+        module: Option<String>,
+        group: Option<String>,
+        name: Option<String>,
+        version: Option<VersionDef>,
+    },
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum VersionDef {
+    Version(String),
+    /// `version.ref = "..."`, or a rich version
+    Table {
+        #[serde(rename = "ref")]
+        reference: Option<String>,
+        strictly: Option<String>,
+        require: Option<String>,
+        prefer: Option<String>,
+    },
+}
+
+/// `junit-jupiter`, `junit_jupiter` and `junit.jupiter` are the same alias, like in Gradle
+fn normalize_alias(alias: &str) -> String {
+    alias.replace(['-', '_'], ".")
+}
+
+impl VersionCatalog {
+    pub fn parse(document: &str) -> Result<Self> {
+        let def: CatalogDef = toml::from_str(document)?;
+        let version = |alias: &str, version: &VersionDef| -> Result<String> {
+            match version {
+                VersionDef::Version(version) => Ok(version.clone()),
+                VersionDef::Table {
+                    reference: Some(reference),
+                    ..
+                } => match def.versions.get(reference) {
+                    Some(VersionDef::Version(version)) => Ok(version.clone()),
+                    _ => Err(anyhow!(
+                        "Library '{}' references the unknown version '{}'",
+                        alias,
+                        reference
+                    )),
+                },
+                VersionDef::Table {
+                    strictly,
+                    require,
+                    prefer,
+                    ..
+                } => strictly
+                    .as_ref()
+                    .or(require.as_ref())
+                    .or(prefer.as_ref())
+                    .cloned()
+                    .ok_or_else(|| anyhow!("Library '{}' has an empty version", alias)),
+            }
+        };
+
+        let mut libraries = BTreeMap::new();
+        for (alias, library) in &def.libraries {
+            let (group, artifact, version) = match library {
+                LibraryDef::Notation(notation) => {
+                    let mut pieces = notation.splitn(3, ':');
+                    match (pieces.next(), pieces.next(), pieces.next()) {
+                        (Some(group), Some(artifact), version) => (
+                            group.to_string(),
+                            artifact.to_string(),
+                            version.map(str::to_string),
+                        ),
+                        _ => return Err(anyhow!("Invalid library '{}' : {}", alias, notation)),
+                    }
+                }
+                LibraryDef::Table {
+                    module,
+                    group,
+                    name,
+                    version: library_version,
+                } => {
+                    let (group, artifact) = match (module, group, name) {
+                        (Some(module), _, _) => module
+                            .split_once(':')
+                            .map(|(g, a)| (g.to_string(), a.to_string()))
+                            .ok_or_else(|| {
+                                anyhow!("Invalid module of library '{}' : {}", alias, module)
+                            })?,
+                        (None, Some(group), Some(name)) => (group.clone(), name.clone()),
+                        _ => {
+                            return Err(anyhow!(
+                                "Library '{}' needs a module or a group and a name",
+                                alias
+                            ))
+                        }
+                    };
+                    let library_version = library_version
+                        .as_ref()
+                        .map(|it| version(alias, it))
+                        .transpose()?;
+                    (group, artifact, library_version)
+                }
+            };
+            let version = version
+                .map(|it| VersionReq::parse(&it))
+                .transpose()
+                .with_context(|| format!("Invalid version of library '{}'", alias))?;
+            libraries.insert(
+                normalize_alias(alias),
+                CompleteDependencyDef {
+                    group,
+                    artifact,
+                    version,
+                    classifier: None,
+                    jdk_classifiers: Vec::new(),
+                },
+            );
+        }
+        Ok(Self { libraries })
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        let document = std::fs::read_to_string(path)
+            .with_context(|| format!("Can't read the version catalog {}", path.display()))?;
+        Self::parse(&document)
+            .with_context(|| format!("Invalid version catalog {}", path.display()))
+    }
+
+    /// The library declared under `alias`
+    pub fn library(&self, alias: &str) -> Result<CompleteDependencyDef> {
+        self.libraries
+            .get(&normalize_alias(alias))
+            .cloned()
+            .ok_or_else(|| anyhow!("Unknown catalog alias '{}'", alias))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::dependencies::catalog::VersionCatalog;
+
+    #[test]
+    fn test_catalog_libraries() {
+        let catalog = VersionCatalog::parse(
+            r#"
+            [versions]
+            junit = "5.8.2"
+
+            [libraries]
+            junit-jupiter = { module = "org.junit.jupiter:junit-jupiter", version.ref = "junit" }
+            guava = "com.google.guava:guava:31.0.1-jre"
+            log4j_api = { group = "org.apache.logging.log4j", name = "log4j-api", version = { strictly = "2.17.1" } }
+            "#,
+        )
+        .unwrap();
+
+        let junit = catalog.library("junit.jupiter").unwrap();
+        assert_eq!(
+            (junit.group.as_str(), junit.artifact.as_str()),
+            ("org.junit.jupiter", "junit-jupiter")
+        );
+        assert_eq!(junit.version.unwrap().to_string(), "^5.8.2");
+        assert_eq!(catalog.library("guava").unwrap().artifact, "guava");
+        assert_eq!(catalog.library("log4j-api").unwrap().artifact, "log4j-api");
+        assert_eq!(
+            catalog.library("junit.api").unwrap_err().to_string(),
+            "Unknown catalog alias 'junit.api'"
+        );
+    }
+}
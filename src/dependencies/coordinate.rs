@@ -0,0 +1,105 @@
+use std::fmt::{Display, Formatter};
+
+use anyhow::{anyhow, Result};
+
+/// Maven coordinates of an artifact.
+/// The canonical rendering is `group:artifact:version[:classifier]`, used everywhere an artifact
+/// is printed.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Coordinate {
+    pub group: String,
+    pub artifact: String,
+    pub version: String,
+    /// Secondary artifact of the same version (e.g. sources, linux-x86_64)
+    pub classifier: Option<String>,
+}
+
+impl Coordinate {
+    pub fn new(
+        group: impl Into<String>,
+        artifact: impl Into<String>,
+        version: impl Into<String>,
+    ) -> Self {
+        Self {
+            group: group.into(),
+            artifact: artifact.into(),
+            version: version.into(),
+            classifier: None,
+        }
+    }
+
+    pub fn with_classifier(mut self, classifier: impl Into<String>) -> Self {
+        self.classifier = Some(classifier.into());
+        self
+    }
+
+    /// Parse the canonical rendering
+    pub fn parse(notation: &str) -> Result<Self> {
+        let pieces: Vec<_> = notation.split(':').collect();
+        match pieces.as_slice() {
+            [group, artifact, version] => Ok(Self::new(*group, *artifact, *version)),
+            [group, artifact, version, classifier] => {
+                Ok(Self::new(*group, *artifact, *version).with_classifier(*classifier))
+            }
+            _ => Err(anyhow!(
+                "Invalid coordinates '{}', expected group:artifact:version[:classifier]",
+                notation
+            )),
+        }
+    }
+
+    /// Path of the version directory in a maven repository
+    pub fn repo_path(&self) -> String {
+        format!(
+            "{}/{}/{}/",
+            self.group.replace('.', "/"),
+            self.artifact,
+            self.version
+        )
+    }
+
+    /// Base file name, the pom is shared by all classifiers
+    pub fn base_name(&self) -> String {
+        format!("{}-{}", self.artifact, self.version)
+    }
+
+    pub fn jar_name(&self) -> String {
+        match &self.classifier {
+            Some(classifier) => format!("{}-{}.jar", self.base_name(), classifier),
+            None => format!("{}.jar", self.base_name()),
+        }
+    }
+}
+
+impl Display for Coordinate {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}:{}", self.group, self.artifact, self.version)?;
+        if let Some(classifier) = &self.classifier {
+            write!(f, ":{}", classifier)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::dependencies::coordinate::Coordinate;
+
+    #[test]
+    fn test_display() {
+        let coordinate = Coordinate::new("org.lwjgl", "lwjgl", "3.3.1");
+        assert_eq!(coordinate.to_string(), "org.lwjgl:lwjgl:3.3.1");
+        assert_eq!(coordinate.jar_name(), "lwjgl-3.3.1.jar");
+
+        let coordinate = coordinate.with_classifier("natives-linux");
+        assert_eq!(
+            coordinate.to_string(),
+            "org.lwjgl:lwjgl:3.3.1:natives-linux"
+        );
+        assert_eq!(coordinate.jar_name(), "lwjgl-3.3.1-natives-linux.jar");
+        assert_eq!(
+            Coordinate::parse(&coordinate.to_string()).unwrap(),
+            coordinate
+        );
+    }
+}
@@ -8,16 +8,17 @@ use tokio::sync::Mutex;
 
 use crate::dependencies::mavenpom::MavenPom;
 
-#[derive(Clone)]
+#[derive(Clone, Default)]
 pub struct DependencyGraph {
     graph: Arc<Mutex<HashMap<String, Arc<OnceCell<MavenPom>>>>>,
+    /// Artifact downloads by destination file, explorers reaching the same node concurrently
+    /// would otherwise write the same file
+    downloads: Arc<Mutex<HashMap<String, Arc<OnceCell<()>>>>>,
 }
 
 impl DependencyGraph {
     pub fn new() -> Self {
-        Self {
-            graph: Arc::new(Mutex::new(HashMap::new())),
-        }
+        Self::default()
     }
 
     pub async fn get(&self, key: &str) -> Option<MavenPom> {
@@ -37,6 +38,23 @@ impl DependencyGraph {
             graph_.get(key).unwrap().clone()
         };
         // The map lock is released so we can still operate on the graph while waiting on a specific cell
-        cell.get_or_try_init(init).await.map(|p| p.clone())
+        Ok(cell.get_or_try_init(init).await?.clone())
+    }
+
+    /// Run `download` once per `key`, concurrent callers await the same download.
+    /// A failed download isn't cached, the next caller tries again.
+    pub async fn download_once<F>(&self, key: &str, download: F) -> Result<()>
+    where
+        F: Future<Output = Result<()>>,
+    {
+        let cell = self
+            .downloads
+            .lock()
+            .await
+            .entry(key.to_string())
+            .or_insert_with(|| Arc::new(OnceCell::new()))
+            .clone();
+        cell.get_or_try_init(download).await?;
+        Ok(())
     }
 }
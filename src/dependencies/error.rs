@@ -0,0 +1,58 @@
+use reqwest::StatusCode;
+use thiserror::Error;
+use url::Url;
+
+/// Why a dependency couldn't be downloaded or resolved, for the callers to decide whether to try
+/// elsewhere. Rendered through anyhow at the top level.
+#[derive(Debug, Error)]
+pub enum ResolutionError {
+    /// The repository doesn't have it, another one may
+    #[error("{url} doesn't exist")]
+    NotFound { url: Url },
+    #[error("Can't download {url} : {reason}")]
+    Network { url: Url, reason: String },
+    #[error("Not allowed to download {url} ({status}), check the credentials")]
+    Auth { url: Url, status: StatusCode },
+    #[error("Checksum mismatch of {name} : expected {expected}, got {actual}")]
+    ChecksumMismatch {
+        name: String,
+        expected: String,
+        actual: String,
+    },
+    #[error("Invalid {what} : {reason}")]
+    Parse { what: String, reason: String },
+    /// Only the cache is available
+    #[error("{what} is not cached, it can't be resolved offline")]
+    Offline { what: String },
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+}
+
+impl ResolutionError {
+    /// A request to `url` was answered with the unsuccessful `status`
+    pub fn from_status(url: &Url, status: StatusCode) -> Self {
+        match status {
+            StatusCode::NOT_FOUND | StatusCode::GONE => Self::NotFound { url: url.clone() },
+            StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => Self::Auth {
+                url: url.clone(),
+                status,
+            },
+            status => Self::Network {
+                url: url.clone(),
+                reason: format!("the server answered {}", status),
+            },
+        }
+    }
+
+    pub fn network(url: &Url, reason: impl Into<anyhow::Error>) -> Self {
+        Self::Network {
+            url: url.clone(),
+            reason: format!("{:#}", reason.into()),
+        }
+    }
+
+    /// Whether `e` is, or was caused by, a [ResolutionError::NotFound]
+    pub fn is_not_found(e: &anyhow::Error) -> bool {
+        matches!(e.downcast_ref(), Some(ResolutionError::NotFound { .. }))
+    }
+}
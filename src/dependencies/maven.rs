@@ -1,103 +1,407 @@
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
+use std::time::Instant;
 
-use anyhow::Result;
+use anyhow::{anyhow, Context, Result};
 use reqwest::Client;
 use tokio::fs;
-use tokio::sync::mpsc::UnboundedSender;
-use tokio::task::JoinHandle;
+use tokio::sync::mpsc;
+use tracing::{debug, info, warn};
+use url::Url;
 
+use crate::dependencies::coordinate::Coordinate;
 use crate::dependencies::dependency_graph::DependencyGraph;
-use crate::dependencies::mavenpom::MavenPom;
-use crate::dependencies::MavenRepoDependency;
-use crate::io::{download_file, download_memory, save_to_file};
+use crate::dependencies::error::ResolutionError;
+use crate::dependencies::mavenpom::{MavenDependencyScope, MavenPom, PropertiesExt};
+use crate::dependencies::resolution::ResolutionTrace;
+use crate::dependencies::signature::{verify_jar, Keyring};
+use crate::dependencies::{Dependency, MavenRepo, MavenRepoDependency};
+use crate::io::{download_file, download_memory, http_client_with, save_to_file};
+use crate::timings::Timings;
+use crate::Env;
 
 /*
 We have a dependency graph
-- We explore the nodes concurrently
-- When an explorer arrive on a node, it checks whether the node task has not been done
-- If the task is open, it launches a task stored in the graph node itself
-- It then awaits the task output
-- If the task is not open, it awaits the task result
-- The task result must be cached since it can be awaited multiple times
+- The driver keeps a queue of the nodes to explore, each coordinate is queued once
+- Up to `jobs` nodes are explored concurrently, each exploration returns the children of its node
+- The children not seen yet are queued, the resolution is done when the queue is empty and no
+  exploration is running
+- Poms and jars reached through different paths (e.g. relocations) are still fetched once by the
+  cells of the graph
  */
 
-#[async_recursion::async_recursion]
+/// State shared by all the tasks exploring the dependency graph
+#[derive(Clone)]
+pub struct ExploreContext {
+    pub client: Client,
+    pub graph: DependencyGraph,
+    /// Where artifacts are downloaded
+    pub base_dir: PathBuf,
+    /// When set, every jar must be signed by one of the keys
+    pub keyring: Option<Arc<Keyring>>,
+    /// Only use what is already in `base_dir`
+    pub offline: bool,
+    /// Records the requested versions when explaining the resolution
+    pub trace: Option<ResolutionTrace>,
+    /// Dependencies leading to the node being explored
+    pub path: Vec<Coordinate>,
+    /// The last one of `path` gave the version of the node in its dependency management
+    pub managed_by: Option<Coordinate>,
+    /// Tries before giving up on a jar download
+    pub download_attempts: u32,
+    /// Versions overriding the ones requested by transitive dependencies
+    pub platform: Arc<Vec<Coordinate>>,
+    /// Also download the `-sources.jar` of every jar
+    pub sources: bool,
+    /// Also download the `-javadoc.jar` of every jar
+    pub docs: bool,
+    /// Records the resolution and download of every dependency with `--timings`
+    pub timings: Option<Timings>,
+    /// Tried in order when the pom or the jar of a dependency isn't in its own repository
+    pub repos: Arc<Vec<Arc<MavenRepo>>>,
+}
+
+impl ExploreContext {
+    /// `dep` in its own repository, then in each of the other repositories
+    fn candidates(&self, dep: &MavenRepoDependency) -> Vec<MavenRepoDependency> {
+        let mut candidates = vec![dep.clone()];
+        candidates.extend(
+            self.repos
+                .iter()
+                .filter(|it| it.url != dep.repo.url)
+                .map(|repo| MavenRepoDependency {
+                    coordinate: dep.coordinate.clone(),
+                    repo: Arc::clone(repo),
+                }),
+        );
+        candidates
+    }
+}
+
+/// A dependency to explore, with the context of the path leading to it
+pub type ExploreNode = (ExploreContext, MavenRepoDependency);
+
+/// Dependencies explored at once by default
+pub const RESOLUTION_JOBS: usize = 16;
+
+/// Outcome of [explore_all]
+#[derive(Debug, Default)]
+pub struct Exploration {
+    /// Every coordinate explored, breadth first in the order of the poms
+    pub visited: Vec<Coordinate>,
+    /// Dependencies of each visited coordinate, as requested by its pom
+    pub dependencies: HashMap<Coordinate, Vec<Coordinate>>,
+    /// Failed explorations, their children weren't explored
+    pub failures: Vec<(Coordinate, anyhow::Error)>,
+}
+
+/// Explore the `roots` and everything they depend on, `jobs` dependencies at a time. Every
+/// exploration runs to completion, so all the failures are reported at once. A coordinate reached
+/// again is explored only the first time, in the context of the first path to reach it.
+///
+/// The results are applied in the order the explorations started rather than the order they end
+/// in, so the graph is discovered breadth first with the dependencies of a pom in their declared
+/// order. The first path, the trace and so the nearest requested versions are the same on every run.
+/// The price is that the children of a pom are only queued once every exploration started before
+/// it has ended : a slow pom holds back what was discovered after it, and the concurrency drops
+/// until it ends. The results waiting for it are only those of the nodes already queued when it
+/// started, since nothing new is queued in the meantime.
+pub async fn explore_all(roots: Vec<ExploreNode>, jobs: usize) -> Exploration {
+    let jobs = jobs.max(1);
+    let mut exploration = Exploration::default();
+    let mut seen = HashSet::new();
+    let mut queue = VecDeque::new();
+    for node in roots {
+        if seen.insert(node.1.coordinate.clone()) {
+            queue.push_back(node);
+        }
+    }
+
+    // Every running exploration sends its result exactly once, even when it panics
+    let (tx, mut rx) = mpsc::channel(jobs);
+    let mut running = 0;
+    // Ended explorations waiting for the ones started before them, by start order
+    let mut ended = BTreeMap::new();
+    let (mut started, mut applied) = (0usize, 0usize);
+    loop {
+        while running < jobs {
+            let (ctx, dep) = match queue.pop_front() {
+                Some(node) => node,
+                None => break,
+            };
+            let tx = tx.clone();
+            let id = started;
+            tokio::spawn(async move {
+                let coordinate = dep.coordinate.clone();
+                let res = match tokio::spawn(explore_dependency(ctx, dep)).await {
+                    Ok(res) => res,
+                    Err(e) => Err(anyhow!("The worker panicked : {}", e)),
+                };
+                // The driver only stops receiving once nothing is running
+                let _ = tx.send((id, coordinate, res)).await;
+            });
+            started += 1;
+            running += 1;
+        }
+        if running == 0 {
+            return exploration;
+        }
+        let (id, coordinate, res) = rx.recv().await.expect("The driver holds a sender");
+        running -= 1;
+        ended.insert(id, (coordinate, res));
+        while let Some((coordinate, res)) = ended.remove(&applied) {
+            applied += 1;
+            match res {
+                Ok(children) => {
+                    exploration.dependencies.insert(
+                        coordinate.clone(),
+                        children.iter().map(|it| it.1.coordinate.clone()).collect(),
+                    );
+                    for node in children {
+                        if let Some(trace) = &node.0.trace {
+                            trace.record(
+                                &node.1.coordinate,
+                                &node.0.path,
+                                node.0.managed_by.clone(),
+                            );
+                        }
+                        if seen.insert(node.1.coordinate.clone()) {
+                            queue.push_back(node);
+                        }
+                    }
+                    exploration.visited.push(coordinate);
+                }
+                Err(e) => exploration.failures.push((coordinate, e)),
+            }
+        }
+    }
+}
+
+/// Cache of the parent poms, under `base_dir`
+const PARENTS_DIR: &str = "parents";
+
+/// Fetch the pom and the jar of `root`, returns its dependencies to explore
 pub async fn explore_dependency(
-    client: Client,
-    graph: DependencyGraph,
-    base_dir: PathBuf,
+    ctx: ExploreContext,
     root: MavenRepoDependency,
-    sub_tasks: UnboundedSender<JoinHandle<Result<()>>>,
-) -> Result<()> {
-    println!("Exploring main node '{}'", root);
+) -> Result<Vec<ExploreNode>> {
+    debug!(dependency = %root, "Exploring main node");
 
+    let instant = Instant::now();
+    let (mut root, mut pom) = fetch_pom_from_any(&ctx, root).await?;
     let repo = Arc::clone(&root.repo);
-    let pom = fetch_pom(graph.clone(), client.clone(), &base_dir, root.clone()).await?;
     //println!("Downloaded pom : {:#?}", pom);
+    // The stub pom of a moved artifact has no jar, resolution continues at the new coordinates
+    let mut relocated = vec![root.coordinate.clone()];
+    while let Some(target) = pom.relocation() {
+        if relocated.contains(&target) {
+            break;
+        }
+        // The classpath is made of the declared coordinates, the old jar would be missing
+        if ctx.path.is_empty() {
+            return Err(anyhow!(
+                "'{}' has been relocated to '{}', declare the new coordinates instead",
+                root.coordinate,
+                target
+            ));
+        }
+        info!("Dependency '{}' has been relocated to '{}'", root, target);
+        relocated.push(target.clone());
+        let (target, target_pom) = fetch_pom_from_any(
+            &ctx,
+            MavenRepoDependency {
+                coordinate: target,
+                repo: Arc::clone(&repo),
+            },
+        )
+        .await?;
+        root = target;
+        pom = target_pom;
+    }
+    if let Some(timings) = &ctx.timings {
+        timings.record("resolve", root.to_string(), instant);
+    }
 
-    let jar_file = base_dir.join(root.jar_name());
-    if !jar_file.exists() {
-        println!(
-            "Downloading artifacts for '{}' (jar) from {}",
-            root.dependency_notation(),
-            &repo.name
-        );
-        download_file(&client, root.jar_url(), &jar_file).await?;
+    let jar_file = ctx.base_dir.join(root.jar_name());
+    if pom.is_pom_packaging() {
+        // Nothing to download, only its dependencies matter
+        debug!("Dependency '{}' is a pom", root);
     } else {
-        println!("Dependency '{}' OK", root.dependency_notation());
+        let key = jar_file.display().to_string();
+        ctx.graph
+            .download_once(&key, async {
+                if jar_file.exists() {
+                    debug!("Dependency '{}' OK", root);
+                    return Ok(());
+                }
+                if ctx.offline {
+                    return Err(anyhow!(
+                        "The jar of '{}' is not cached, it can't be downloaded offline",
+                        root
+                    ));
+                }
+                let instant = Instant::now();
+                let mut res = Ok(());
+                // Only a missing jar is worth looking for elsewhere
+                for candidate in ctx.candidates(&root) {
+                    info!(
+                        "Downloading artifacts for '{}' (jar) from {}",
+                        root.dependency_notation(),
+                        &candidate.repo.name
+                    );
+                    res = download_file(
+                        &ctx.client,
+                        candidate.jar_url(),
+                        &jar_file,
+                        ctx.download_attempts,
+                    )
+                    .await;
+                    if !matches!(res, Err(ResolutionError::NotFound { .. })) {
+                        break;
+                    }
+                }
+                if let Some(timings) = &ctx.timings {
+                    timings.record("download", root.to_string(), instant);
+                }
+                Ok(res?)
+            })
+            .await?;
+    }
+    if let (Some(keyring), false) = (&ctx.keyring, pom.is_pom_packaging()) {
+        verify_jar(&ctx.client, keyring, &root, &jar_file).await?;
+    }
+    if ctx.sources && !pom.is_pom_packaging() {
+        download_attachment(&ctx, &root, root.sources_url(), "sources").await;
+    }
+    if ctx.docs && !pom.is_pom_packaging() {
+        download_attachment(&ctx, &root, root.docs_url(), "javadoc").await;
     }
 
+    let mut children = Vec::new();
     if let Some(deps) = pom.dependencies {
         for dep in deps.dependencies {
             //println!("Should download dependency : {}", dep.dependency_notation());
-            let repo = Arc::clone(&repo);
-            let task = tokio::spawn(explore_dependency(
-                client.clone(),
-                graph.clone(),
-                base_dir.clone(),
-                MavenRepoDependency {
-                    group: dep.group_id.value,
-                    artifact: dep.artifact_id.value,
-                    version: dep.version.unwrap().value,
-                    repo,
-                },
-                sub_tasks.clone(),
-            ));
-            sub_tasks.send(task)?;
+            // Needed to compile against a declared dependency, but not any further
+            if (dep.is_provided() || dep.is_system()) && !ctx.path.is_empty() {
+                continue;
+            }
+            match dep.to_dependency(Arc::clone(&repo)) {
+                Dependency::MavenRepo(mut repodep) => {
+                    if let Some(constraint) = ctx.platform.iter().find(|it| {
+                        it.group == repodep.coordinate.group
+                            && it.artifact == repodep.coordinate.artifact
+                    }) {
+                        repodep.coordinate.version = constraint.version.clone();
+                    }
+                    let mut child = ctx.clone();
+                    child.path.push(root.coordinate.clone());
+                    let managed = pom.dependency_management.as_ref().map_or(false, |it| {
+                        it.dependencies.dependencies.iter().any(|rule| {
+                            rule.group_id == dep.group_id
+                                && rule.artifact_id == dep.artifact_id
+                                && rule.version.is_some()
+                        })
+                    });
+                    // Recorded in the trace by explore_all, in a stable order
+                    child.managed_by = managed.then(|| root.coordinate.clone());
+                    children.push((child, repodep));
+                }
+                // Put on the compile classpath next to the declared dependency, see provided_api
+                Dependency::PrebuiltLocal(local) => {
+                    if !Path::new(&local.path).is_file() {
+                        return Err(anyhow!(
+                            "The system dependency '{}' of '{}' isn't at '{}'",
+                            dep.dependency_notation(),
+                            root,
+                            local.path
+                        ));
+                    }
+                    info!(
+                        "Dependency '{}' is provided by the system at '{}'",
+                        dep.dependency_notation(),
+                        local.path
+                    );
+                }
+                _ => {}
+            }
         }
     }
-    Ok(())
+    Ok(children)
 }
 
-/// The returned pom will have all its parents merged.
-async fn fetch_pom(
-    graph: DependencyGraph,
-    client: Client,
-    dir: &Path,
+/// Download the `-<classifier>.jar` of a dependency. Many artifacts don't publish one,
+/// so a failure is only reported.
+async fn download_attachment(
+    ctx: &ExploreContext,
+    dep: &MavenRepoDependency,
+    url: Url,
+    classifier: &str,
+) {
+    let file = ctx
+        .base_dir
+        .join(format!("{}-{}.jar", dep.base_name(), classifier));
+    let key = file.display().to_string();
+    let res = ctx
+        .graph
+        .download_once(&key, async {
+            if file.exists() || ctx.offline {
+                return Ok(());
+            }
+            info!("Downloading artifacts for '{}' ({})", dep, classifier);
+            Ok(download_file(&ctx.client, url, &file, ctx.download_attempts).await?)
+        })
+        .await;
+    if let Err(e) = res {
+        warn!("No {} jar for '{}' : {:#}", classifier, dep, e);
+    }
+}
+
+/// `fetch_pom` from the repository of `dep`, then from the other repositories while it isn't
+/// found. Returns `dep` in the repository it was found in, or the error of its own repository.
+async fn fetch_pom_from_any(
+    ctx: &ExploreContext,
     dep: MavenRepoDependency,
-) -> Result<MavenPom> {
+) -> Result<(MavenRepoDependency, MavenPom)> {
+    let mut first_error = None;
+    for candidate in ctx.candidates(&dep) {
+        match fetch_pom(ctx.clone(), candidate.clone()).await {
+            Ok(pom) => return Ok((candidate, pom)),
+            Err(e) if ResolutionError::is_not_found(&e) => {
+                debug!("'{}' isn't in {} : {:#}", dep, candidate.repo.name, e);
+                first_error.get_or_insert(e);
+            }
+            // Another repository won't fix a network or credentials problem
+            Err(e) => return Err(e),
+        }
+    }
+    Err(first_error.unwrap())
+}
+
+/// The returned pom will have all its parents merged.
+async fn fetch_pom(ctx: ExploreContext, dep: MavenRepoDependency) -> Result<MavenPom> {
     let key = dep.dependency_notation();
-    let graph_ = graph.clone();
+    let graph = ctx.graph.clone();
     graph
         .get_or_init(&key, async {
-            let file = dir.join(dep.pom_name());
+            let file = ctx.base_dir.join(dep.pom_name());
 
             Ok(if file.exists() {
-                println!("Running in main node '{}': fetching pom (cache hit)", &key);
+                debug!("Running in main node '{}': fetching pom (cache hit)", &key);
                 MavenPom::parse(&fs::read_to_string(&file).await?).unwrap()
             } else {
-                println!("Running in main node '{}': fetching pom", &key);
-                let mut pom = MavenPom::parse(&download_memory(&client, dep.pom_url()).await?)?;
+                debug!("Running in main node '{}': fetching pom", &key);
+                let mut pom = download_pom(&ctx, &dep).await?;
                 if let Some(parent) = pom.parent.clone() {
                     // Recurse to download and merge parent pom hierarchy
                     let parent = fetch_parent_pom(
-                        graph_,
-                        client,
+                        ctx.clone(),
                         MavenRepoDependency {
-                            group: parent.group_id.value,
-                            artifact: parent.artifact_id.value,
-                            version: parent.version.value,
+                            coordinate: Coordinate::new(
+                                parent.group_id.value,
+                                parent.artifact_id.value,
+                                parent.version.value,
+                            ),
                             repo: Arc::clone(&dep.repo),
                         },
                     )
@@ -113,26 +417,34 @@ async fn fetch_pom(
         .await
 }
 
+/// Parent poms are cached merged with their own parents, but not cleaned since their
+/// dependency management and properties apply to the children
 #[async_recursion::async_recursion]
-async fn fetch_parent_pom(
-    graph: DependencyGraph,
-    client: Client,
-    dep: MavenRepoDependency,
-) -> Result<MavenPom> {
+async fn fetch_parent_pom(ctx: ExploreContext, dep: MavenRepoDependency) -> Result<MavenPom> {
     let key = dep.dependency_notation();
-    let graph_ = graph.clone();
+    let graph = ctx.graph.clone();
     graph
         .get_or_init(&key, async {
-            println!("Running in parent node '{}': fetching pom", &key);
-            let mut pom = MavenPom::parse(&download_memory(&client, dep.pom_url()).await?)?;
+            let file = ctx.base_dir.join(PARENTS_DIR).join(dep.pom_name());
+            if file.exists() {
+                debug!(
+                    "Running in parent node '{}': fetching pom (cache hit)",
+                    &key
+                );
+                return MavenPom::parse(&fs::read_to_string(&file).await?);
+            }
+
+            debug!("Running in parent node '{}': fetching pom", &key);
+            let mut pom = download_pom(&ctx, &dep).await?;
             if let Some(parent) = pom.parent.clone() {
                 let parent = fetch_parent_pom(
-                    graph_,
-                    client,
+                    ctx.clone(),
                     MavenRepoDependency {
-                        group: parent.group_id.value,
-                        artifact: parent.artifact_id.value,
-                        version: parent.version.value,
+                        coordinate: Coordinate::new(
+                            parent.group_id.value,
+                            parent.artifact_id.value,
+                            parent.version.value,
+                        ),
                         repo: Arc::clone(&dep.repo),
                     },
                 )
@@ -140,7 +452,655 @@ async fn fetch_parent_pom(
                 // Merge current pom with parent
                 pom = parent.merge(&pom);
             }
+            fs::create_dir_all(ctx.base_dir.join(PARENTS_DIR)).await?;
+            save_to_file(&pom.save()?, &file).await?;
             Ok(pom)
         })
         .await
 }
+
+/// Versions managed by the BOM `bom`, then by the BOMs it imports, like a maven `import` scope.
+/// The BOMs are cached with the parent poms under `base_dir`.
+pub async fn import_bom(
+    env: &Env,
+    base_dir: &Path,
+    bom: MavenRepoDependency,
+) -> Result<Vec<Coordinate>> {
+    let ctx = ExploreContext {
+        client: http_client_with(&env.downloads),
+        graph: DependencyGraph::new(),
+        base_dir: base_dir.to_path_buf(),
+        keyring: None,
+        offline: env.offline,
+        trace: None,
+        path: Vec::new(),
+        managed_by: None,
+        download_attempts: env.downloads.attempts,
+        platform: Arc::new(Vec::new()),
+        sources: false,
+        docs: false,
+        timings: None,
+        repos: Arc::new(env.repos.clone()),
+    };
+    let name = bom.to_string();
+    fetch_bom(ctx, bom, &mut HashSet::new())
+        .await
+        .with_context(|| format!("Can't import the BOM '{}'", name))
+}
+
+/// A BOM already in `imported` is skipped, so import cycles end
+#[async_recursion::async_recursion]
+async fn fetch_bom(
+    ctx: ExploreContext,
+    bom: MavenRepoDependency,
+    imported: &mut HashSet<Coordinate>,
+) -> Result<Vec<Coordinate>> {
+    if !imported.insert(bom.coordinate.clone()) {
+        return Ok(Vec::new());
+    }
+    let (bom, pom) = fetch_bom_from_any(&ctx, bom).await?;
+    let mut versions = Vec::new();
+    let mut imports = Vec::new();
+    for (coordinate, import) in managed_versions(&pom) {
+        if import {
+            imports.push(coordinate);
+        } else {
+            versions.push(coordinate);
+        }
+    }
+    // Looked for in the repository of the BOM first, then in the others
+    for coordinate in imports {
+        let import = MavenRepoDependency {
+            coordinate,
+            repo: Arc::clone(&bom.repo),
+        };
+        versions.extend(fetch_bom(ctx.clone(), import, imported).await?);
+    }
+    Ok(versions)
+}
+
+/// Like [fetch_pom_from_any], but the pom is only merged with its parents : the dependency
+/// management is all that matters
+async fn fetch_bom_from_any(
+    ctx: &ExploreContext,
+    bom: MavenRepoDependency,
+) -> Result<(MavenRepoDependency, MavenPom)> {
+    let mut first_error = None;
+    for candidate in ctx.candidates(&bom) {
+        match fetch_parent_pom(ctx.clone(), candidate.clone()).await {
+            Ok(pom) => return Ok((candidate, pom)),
+            Err(e) if ResolutionError::is_not_found(&e) => {
+                debug!("'{}' isn't in {} : {:#}", bom, candidate.repo.name, e);
+                first_error.get_or_insert(e);
+            }
+            Err(e) => return Err(e),
+        }
+    }
+    Err(first_error.unwrap())
+}
+
+/// The versioned entries of the dependency management of `pom`, with its properties resolved.
+/// The second field is true for the imported BOMs.
+fn managed_versions(pom: &MavenPom) -> Vec<(Coordinate, bool)> {
+    let props = pom.properties.clone().unwrap_or_default();
+    let project_version = pom
+        .version
+        .as_ref()
+        .map(|it| it.value.as_str())
+        .or_else(|| pom.parent.as_ref().map(|it| it.version.value.as_str()))
+        .unwrap_or_default();
+    let resolve = |text: &str| props.recurse_resolve(text, project_version).into_owned();
+    pom.dependency_management
+        .iter()
+        .flat_map(|it| &it.dependencies.dependencies)
+        .filter_map(|dep| {
+            let mut coordinate = Coordinate::new(
+                resolve(&dep.group_id.value),
+                resolve(&dep.artifact_id.value),
+                resolve(&dep.version.as_ref()?.value),
+            );
+            coordinate.classifier = dep.classifier.as_ref().map(|it| resolve(&it.value));
+            let import = matches!(
+                dep.scope.as_ref().map(|it| it.value),
+                Some(MavenDependencyScope::Import)
+            );
+            Some((coordinate, import))
+        })
+        .collect()
+}
+
+/// Download a pom from the repository, offline only the cache is available
+async fn download_pom(
+    ctx: &ExploreContext,
+    dep: &MavenRepoDependency,
+) -> Result<MavenPom, ResolutionError> {
+    if ctx.offline {
+        return Err(ResolutionError::Offline {
+            what: format!("The pom of '{}'", dep),
+        });
+    }
+    MavenPom::parse(&download_memory(&ctx.client, dep.pom_url()).await?).map_err(|e| {
+        ResolutionError::Parse {
+            what: format!("pom of '{}'", dep),
+            reason: format!("{:#}", e),
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+    use std::sync::Arc;
+
+    use anyhow::Result;
+    use url::Url;
+
+    use crate::dependencies::coordinate::Coordinate;
+    use crate::dependencies::dependency_graph::DependencyGraph;
+    use crate::dependencies::maven::{self, ExploreContext, ExploreNode, RESOLUTION_JOBS};
+    use crate::dependencies::resolution::ResolutionTrace;
+    use crate::dependencies::{MavenRepo, MavenRepoDependency, RepoLayout};
+    use crate::io::{http_client, DOWNLOAD_ATTEMPTS};
+    use crate::test_utils::{capture_logs, response, temp_dir, MockServer};
+
+    fn pom(artifact: &str, packaging: &str, deps: &str) -> String {
+        format!(
+            r#"<project xmlns:xsi="http://www.w3.org/2001/XMLSchema-instance" xsi:schemaLocation="http://maven.apache.org/POM/4.0.0 http://maven.apache.org/maven-v4_0_0.xsd">
+                <modelVersion>4.0.0</modelVersion>
+                <groupId>marais</groupId>
+                <artifactId>{}</artifactId>
+                <version>1.0</version>
+                <packaging>{}</packaging>
+                <dependencies><dependency>{}</dependency></dependencies>
+            </project>"#,
+            artifact, packaging, deps
+        )
+    }
+
+    /// A jar pom depending on the marais `deps`, as (artifact, version)
+    fn pom_with_deps(artifact: &str, deps: &[(&str, &str)]) -> String {
+        let deps: String = deps
+            .iter()
+            .map(|(artifact, version)| {
+                format!(
+                    "<dependency><groupId>marais</groupId><artifactId>{}</artifactId>\
+                     <version>{}</version></dependency>",
+                    artifact, version
+                )
+            })
+            .collect();
+        pom(artifact, "jar", "").replace(
+            "<dependencies><dependency></dependency></dependencies>",
+            &format!("<dependencies>{}</dependencies>", deps),
+        )
+    }
+
+    /// A pom-packaged bom depending on a jar library
+    async fn bom_server() -> MockServer {
+        MockServer::start(|req| match req.path.as_str() {
+            "/marais/bom/1.0/bom-1.0.pom" => response(
+                "200 OK",
+                pom(
+                    "bom",
+                    "pom",
+                    "<groupId>marais</groupId><artifactId>lib</artifactId><version>1.0</version>",
+                )
+                .as_bytes(),
+            ),
+            "/marais/lib/1.0/lib-1.0.pom" => response(
+                "200 OK",
+                pom(
+                    "lib",
+                    "jar",
+                    "<groupId>marais</groupId><artifactId>test</artifactId><scope>test</scope>",
+                )
+                .as_bytes(),
+            ),
+            "/marais/lib/1.0/lib-1.0.jar" => response("200 OK", b"jar"),
+            _ => response("404 Not Found", b""),
+        })
+        .await
+    }
+
+    /// Explore `root` and everything it depends on
+    async fn explore(url: &Url, dir: &Path, root: Coordinate, offline: bool) -> Result<()> {
+        explore_all(url, dir, &[root], offline).await
+    }
+
+    /// Explore the `roots` concurrently, sharing the dependency graph
+    async fn explore_all(url: &Url, dir: &Path, roots: &[Coordinate], offline: bool) -> Result<()> {
+        match maven::explore_all(nodes(url, dir, roots, offline), RESOLUTION_JOBS)
+            .await
+            .failures
+            .pop()
+        {
+            Some((_, e)) => Err(e),
+            None => Ok(()),
+        }
+    }
+
+    /// The `roots` in the mock repository, sharing a context
+    fn nodes(url: &Url, dir: &Path, roots: &[Coordinate], offline: bool) -> Vec<ExploreNode> {
+        let repo = Arc::new(MavenRepo {
+            name: "mock".to_string(),
+            url: url.clone(),
+            layout: RepoLayout::Maven,
+        });
+        let ctx = ExploreContext {
+            client: http_client(),
+            graph: DependencyGraph::new(),
+            base_dir: dir.to_path_buf(),
+            keyring: None,
+            offline,
+            trace: None,
+            path: Vec::new(),
+            managed_by: None,
+            download_attempts: DOWNLOAD_ATTEMPTS,
+            platform: Arc::new(Vec::new()),
+            sources: false,
+            docs: false,
+            timings: None,
+            repos: Arc::new(vec![Arc::clone(&repo)]),
+        };
+
+        roots
+            .iter()
+            .map(|root| {
+                let root = MavenRepoDependency {
+                    coordinate: root.clone(),
+                    repo: Arc::clone(&repo),
+                };
+                (ctx.clone(), root)
+            })
+            .collect()
+    }
+
+    /// Explore the bom and everything it depends on
+    async fn explore_bom(server: &MockServer, dir: &Path) {
+        explore(
+            &server.url,
+            dir,
+            Coordinate::new("marais", "bom", "1.0"),
+            false,
+        )
+        .await
+        .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_pom_packaging() {
+        let server = bom_server().await;
+        let dir = temp_dir("pom-packaging");
+        explore_bom(&server, &dir).await;
+
+        let paths: Vec<_> = server.requests().into_iter().map(|it| it.path).collect();
+        assert!(!paths.contains(&"/marais/bom/1.0/bom-1.0.jar".to_string()));
+        assert!(paths.contains(&"/marais/lib/1.0/lib-1.0.jar".to_string()));
+        assert!(dir.join("lib-1.0.jar").exists());
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_download_once() {
+        let server = bom_server().await;
+        let dir = temp_dir("download-once");
+        let lib = Coordinate::new("marais", "lib", "1.0");
+        explore_all(&server.url, &dir, &[lib.clone(), lib], false)
+            .await
+            .unwrap();
+
+        let downloads = server
+            .requests()
+            .into_iter()
+            .filter(|it| it.path == "/marais/lib/1.0/lib-1.0.jar")
+            .count();
+        assert_eq!(downloads, 1);
+        assert!(dir.join("lib-1.0.jar").exists());
+    }
+
+    #[tokio::test]
+    async fn test_explore_graph_once() {
+        // Diamonds over 4 levels, and e depends back on a
+        let server = MockServer::start(|req| {
+            let children: &[&str] = match req.path.as_str() {
+                "/marais/a/1.0/a-1.0.pom" => &["b", "c"],
+                "/marais/b/1.0/b-1.0.pom" => &["d"],
+                "/marais/c/1.0/c-1.0.pom" => &["d", "e"],
+                "/marais/d/1.0/d-1.0.pom" => &["e", "f"],
+                "/marais/e/1.0/e-1.0.pom" | "/marais/f/1.0/f-1.0.pom" => &["a"],
+                path if path.ends_with(".jar") => return response("200 OK", b"jar"),
+                _ => return response("404 Not Found", b""),
+            };
+            let artifact = req.path.split('/').nth(2).unwrap();
+            let deps: Vec<_> = children.iter().map(|it| (*it, "1.0")).collect();
+            response("200 OK", pom_with_deps(artifact, &deps).as_bytes())
+        })
+        .await;
+        let dir = temp_dir("explore-graph-once");
+        let roots = nodes(
+            &server.url,
+            &dir,
+            &[Coordinate::new("marais", "a", "1.0")],
+            false,
+        );
+
+        let exploration = tokio::time::timeout(
+            std::time::Duration::from_secs(30),
+            maven::explore_all(roots, 2),
+        )
+        .await
+        .expect("The resolution terminates");
+
+        assert!(
+            exploration.failures.is_empty(),
+            "{:?}",
+            exploration.failures
+        );
+        let mut visited: Vec<_> = exploration
+            .visited
+            .iter()
+            .map(|it| it.artifact.as_str())
+            .collect();
+        visited.sort_unstable();
+        assert_eq!(visited, ["a", "b", "c", "d", "e", "f"]);
+        let jars = server
+            .requests()
+            .into_iter()
+            .filter(|it| it.path.ends_with(".jar"))
+            .count();
+        assert_eq!(jars, 6);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_stable_discovery_order() {
+        // b is declared first but answers last, b and c request different versions of log
+        let server = MockServer::start(|req| {
+            let deps: &[(&str, &str)] = match req.path.as_str() {
+                "/marais/a/1.0/a-1.0.pom" => &[("b", "1.0"), ("c", "1.0")],
+                "/marais/b/1.0/b-1.0.pom" => {
+                    std::thread::sleep(std::time::Duration::from_millis(200));
+                    &[("log", "1.0")]
+                }
+                "/marais/c/1.0/c-1.0.pom" => &[("log", "2.0")],
+                "/marais/log/1.0/log-1.0.pom" | "/marais/log/2.0/log-2.0.pom" => &[],
+                path if path.ends_with(".jar") => return response("200 OK", b"jar"),
+                _ => return response("404 Not Found", b""),
+            };
+            let artifact = req.path.split('/').nth(2).unwrap();
+            response("200 OK", pom_with_deps(artifact, deps).as_bytes())
+        })
+        .await;
+
+        let mut runs = Vec::new();
+        for run in 0..2 {
+            let dir = temp_dir(&format!("stable-discovery-{}", run));
+            let trace = ResolutionTrace::default();
+            let mut roots = nodes(
+                &server.url,
+                &dir,
+                &[Coordinate::new("marais", "a", "1.0")],
+                false,
+            );
+            roots[0].0.trace = Some(trace.clone());
+            let exploration = maven::explore_all(roots, RESOLUTION_JOBS).await;
+            assert!(exploration.failures.is_empty());
+            let log = trace
+                .nearest_requested()
+                .into_iter()
+                .find(|it| it.artifact == "marais:log")
+                .unwrap();
+            runs.push((
+                exploration.visited,
+                log.version().to_string(),
+                trace.explain(),
+            ));
+        }
+
+        let visited: Vec<_> = runs[0].0.iter().map(|it| it.to_string()).collect();
+        assert_eq!(
+            visited,
+            [
+                "marais:a:1.0",
+                "marais:b:1.0",
+                "marais:c:1.0",
+                "marais:log:1.0",
+                "marais:log:2.0"
+            ]
+        );
+        assert_eq!(runs[0].1, "1.0");
+        assert_eq!(runs[0], runs[1]);
+    }
+
+    #[tokio::test]
+    async fn test_bundle_packaging() {
+        let server = MockServer::start(|req| match req.path.as_str() {
+            "/marais/osgi/1.0/osgi-1.0.pom" => response(
+                "200 OK",
+                pom(
+                    "osgi",
+                    "bundle",
+                    "<groupId>marais</groupId><artifactId>test</artifactId><scope>test</scope>",
+                )
+                .as_bytes(),
+            ),
+            "/marais/osgi/1.0/osgi-1.0.jar" => response("200 OK", b"jar"),
+            _ => response("404 Not Found", b""),
+        })
+        .await;
+        let dir = temp_dir("bundle-packaging");
+        explore(
+            &server.url,
+            &dir,
+            Coordinate::new("marais", "osgi", "1.0"),
+            false,
+        )
+        .await
+        .unwrap();
+        assert!(dir.join("osgi-1.0.jar").exists());
+    }
+
+    #[tokio::test]
+    async fn test_relocation() {
+        let server = MockServer::start(|req| match req.path.as_str() {
+            "/marais/old/1.0/old-1.0.pom" => response(
+                "200 OK",
+                br#"<project>
+                    <modelVersion>4.0.0</modelVersion>
+                    <groupId>marais</groupId>
+                    <artifactId>old</artifactId>
+                    <version>1.0</version>
+                    <distributionManagement>
+                        <relocation>
+                            <artifactId>lib</artifactId>
+                            <message>Renamed</message>
+                        </relocation>
+                    </distributionManagement>
+                </project>"#,
+            ),
+            "/marais/old/1.0/old-1.0.jar" => response("200 OK", b"stub"),
+            "/marais/lib/1.0/lib-1.0.pom" => response(
+                "200 OK",
+                pom(
+                    "lib",
+                    "jar",
+                    "<groupId>marais</groupId><artifactId>test</artifactId><scope>test</scope>",
+                )
+                .as_bytes(),
+            ),
+            "/marais/lib/1.0/lib-1.0.jar" => response("200 OK", b"jar"),
+            "/marais/app/1.0/app-1.0.pom" => response(
+                "200 OK",
+                pom(
+                    "app",
+                    "jar",
+                    "<groupId>marais</groupId><artifactId>old</artifactId><version>1.0</version>",
+                )
+                .as_bytes(),
+            ),
+            "/marais/app/1.0/app-1.0.jar" => response("200 OK", b"jar"),
+            _ => response("404 Not Found", b""),
+        })
+        .await;
+        let dir = temp_dir("relocation");
+        let app = Coordinate::new("marais", "app", "1.0");
+        explore(&server.url, &dir, app.clone(), false)
+            .await
+            .unwrap();
+
+        let paths: Vec<_> = server.requests().into_iter().map(|it| it.path).collect();
+        assert!(!paths.contains(&"/marais/old/1.0/old-1.0.jar".to_string()));
+        assert!(dir.join("lib-1.0.jar").exists());
+        // The relocation survives in the cached pom
+        explore(&server.url, &dir, app, true).await.unwrap();
+
+        // A declared one would leave its old jar on the classpath
+        let err = explore(
+            &server.url,
+            &dir,
+            Coordinate::new("marais", "old", "1.0"),
+            true,
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "'marais:old:1.0' has been relocated to 'marais:lib:1.0', declare the new coordinates instead"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_missing_system_jar() {
+        let system = |path: &str| {
+            pom(
+                "enterprise",
+                "jar",
+                &format!(
+                    "<groupId>com.sun</groupId><artifactId>tools</artifactId><version>1.8</version>\
+                     <scope>system</scope><systemPath>{}</systemPath>",
+                    path
+                ),
+            )
+        };
+        let dir = temp_dir("missing-system-jar");
+        std::fs::write(dir.join("tools.jar"), b"jar").unwrap();
+        let present = system(&dir.join("tools.jar").display().to_string());
+        let server = MockServer::start(move |req| match req.path.as_str() {
+            "/marais/enterprise/1.0/enterprise-1.0.pom" => response("200 OK", present.as_bytes()),
+            "/marais/enterprise/1.0/enterprise-1.0.jar" => response("200 OK", b"jar"),
+            "/marais/missing/1.0/missing-1.0.pom" => response(
+                "200 OK",
+                system("/nowhere/tools.jar")
+                    .replace("enterprise", "missing")
+                    .as_bytes(),
+            ),
+            "/marais/missing/1.0/missing-1.0.jar" => response("200 OK", b"jar"),
+            _ => response("404 Not Found", b""),
+        })
+        .await;
+
+        let enterprise = Coordinate::new("marais", "enterprise", "1.0");
+        explore(&server.url, &dir, enterprise, false).await.unwrap();
+        let missing = Coordinate::new("marais", "missing", "1.0");
+        let err = explore(&server.url, &dir, missing, false)
+            .await
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "The system dependency 'com.sun:tools:1.8' of 'marais:missing:1.0' isn't at '/nowhere/tools.jar'"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_resolution_events() {
+        let server = bom_server().await;
+        let (_guard, logs) = capture_logs();
+        explore_bom(&server, &temp_dir("resolution-events")).await;
+
+        let logs = logs.contents();
+        assert!(logs.contains("DEBUG"));
+        assert!(logs.contains("Exploring main node dependency=marais:bom:1.0"));
+        assert!(logs.contains("Running in main node 'marais:lib:1.0': fetching pom"));
+        assert!(logs.contains("Downloading artifacts for 'marais:lib:1.0' (jar) from mock"));
+    }
+
+    #[tokio::test]
+    async fn test_offline_from_cache() {
+        let server = MockServer::start(|req| match req.path.as_str() {
+            "/marais/parent/1.0/parent-1.0.pom" => response(
+                "200 OK",
+                br#"<project xsi:schemaLocation="http://maven.apache.org/POM/4.0.0 http://maven.apache.org/maven-v4_0_0.xsd">
+                    <modelVersion>4.0.0</modelVersion>
+                    <groupId>marais</groupId>
+                    <artifactId>parent</artifactId>
+                    <version>1.0</version>
+                    <packaging>pom</packaging>
+                </project>"#,
+            ),
+            "/marais/child/1.0/child-1.0.pom" => response(
+                "200 OK",
+                br#"<project xsi:schemaLocation="http://maven.apache.org/POM/4.0.0 http://maven.apache.org/maven-v4_0_0.xsd">
+                    <modelVersion>4.0.0</modelVersion>
+                    <parent>
+                        <groupId>marais</groupId>
+                        <artifactId>parent</artifactId>
+                        <version>1.0</version>
+                    </parent>
+                    <artifactId>child</artifactId>
+                    <version>1.0</version>
+                </project>"#,
+            ),
+            "/marais/child/1.0/child-1.0.jar" => response("200 OK", b"jar"),
+            _ => response("404 Not Found", b""),
+        })
+        .await;
+        let dir = temp_dir("offline-cache");
+        let child = Coordinate::new("marais", "child", "1.0");
+        explore(&server.url, &dir, child.clone(), false)
+            .await
+            .unwrap();
+        assert!(dir.join("parents/parent-1.0.pom").exists());
+        let online_requests = server.requests().len();
+
+        explore(&server.url, &dir, child.clone(), true)
+            .await
+            .unwrap();
+        assert_eq!(server.requests().len(), online_requests);
+
+        std::fs::remove_file(dir.join("child-1.0.pom")).unwrap();
+        let err = explore(&server.url, &dir, child, true).await.unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "The pom of 'marais:child:1.0' is not cached, it can't be resolved offline"
+        );
+        assert_eq!(server.requests().len(), online_requests);
+    }
+
+    #[tokio::test]
+    async fn test_fallback_repository_error() {
+        let missing = MockServer::start(|_| response("404 Not Found", b"")).await;
+        let private = MockServer::start(|_| response("401 Unauthorized", b"")).await;
+        let dir = temp_dir("fallback-error");
+        let mut nodes = nodes(
+            &missing.url,
+            &dir,
+            &[Coordinate::new("marais", "lib", "1.0")],
+            false,
+        );
+        let (ctx, root) = &mut nodes[0];
+        ctx.repos = Arc::new(vec![
+            Arc::clone(&root.repo),
+            Arc::new(MavenRepo {
+                name: "private".to_string(),
+                url: private.url.clone(),
+                layout: RepoLayout::Maven,
+            }),
+        ]);
+
+        let (_, err) = maven::explore_all(nodes, RESOLUTION_JOBS)
+            .await
+            .failures
+            .pop()
+            .unwrap();
+        // Not the 404 of the first repository
+        assert!(format!("{:#}", err).contains("Not allowed to download"));
+    }
+}
@@ -1,3 +1,5 @@
+use std::cmp::Ordering;
+
 use anyhow::Result;
 use serde::{Deserialize, Serialize};
 
@@ -31,12 +33,93 @@ impl MavenMetadata {
         let meta: Self = quick_xml::de::from_str(text)?;
         Ok(meta)
     }
+
+    /// Highest listed version, snapshots excluded
+    pub fn latest_release(&self) -> Option<&str> {
+        self.versioning
+            .versions
+            .versions
+            .iter()
+            .map(|it| it.value.as_str())
+            .filter(|it| !it.ends_with("-SNAPSHOT"))
+            .max_by(|a, b| compare_versions(a, b))
+    }
+}
+
+/// Split a version on `.`, `-` and the transitions between digits and letters (`rc1` is `rc`, `1`)
+fn version_pieces(version: &str) -> Vec<String> {
+    let mut pieces = Vec::new();
+    let mut piece = String::new();
+    for c in version.chars() {
+        if c == '.' || c == '-' {
+            pieces.push(std::mem::take(&mut piece));
+            continue;
+        }
+        if piece
+            .chars()
+            .last()
+            .map_or(false, |last| last.is_ascii_digit() != c.is_ascii_digit())
+        {
+            pieces.push(std::mem::take(&mut piece));
+        }
+        piece.push(c);
+    }
+    pieces.push(piece);
+    pieces
+}
+
+/// Maven's order of the well known qualifiers, the unknown ones come after them alphabetically
+fn qualifier_rank(qualifier: &str) -> (u8, String) {
+    let qualifier = qualifier.to_lowercase();
+    let rank = match qualifier.as_str() {
+        "alpha" | "a" => 0,
+        "beta" | "b" => 1,
+        "milestone" | "m" => 2,
+        "rc" | "cr" => 3,
+        "snapshot" => 4,
+        "" | "ga" | "final" | "release" => 5,
+        "sp" => 6,
+        _ => return (7, qualifier),
+    };
+    (rank, String::new())
+}
+
+/// Order maven versions piece by piece, numbers numerically and qualifiers like maven
+/// (`alpha` < `beta` < `milestone` < `rc` < `snapshot` < release < `sp`).
+/// A number is higher than a qualifier (`1.0-beta` < `1.0` < `1.0.1`).
+pub fn compare_versions(a: &str, b: &str) -> Ordering {
+    let (a, b) = (version_pieces(a), version_pieces(b));
+    for i in 0..a.len().max(b.len()) {
+        let ordering = match (a.get(i), b.get(i)) {
+            (Some(x), Some(y)) => match (x.parse::<u64>(), y.parse::<u64>()) {
+                (Ok(x), Ok(y)) => x.cmp(&y),
+                (Ok(_), Err(_)) => Ordering::Greater,
+                (Err(_), Ok(_)) => Ordering::Less,
+                (Err(_), Err(_)) => qualifier_rank(x).cmp(&qualifier_rank(y)),
+            },
+            (Some(x), None) if x.parse::<u64>().is_ok() => Ordering::Greater,
+            (Some(x), None) => qualifier_rank(x).0.cmp(&qualifier_rank("").0),
+            (None, Some(y)) if y.parse::<u64>().is_ok() => Ordering::Less,
+            (None, Some(y)) => qualifier_rank("").0.cmp(&qualifier_rank(y).0),
+            (None, None) => Ordering::Equal,
+        };
+        if ordering != Ordering::Equal {
+            return ordering;
+        }
+    }
+    Ordering::Equal
 }
 
+#[cfg(test)]
 mod tests {
-    use crate::dependencies::maven_metadata::{MavenMetadata, Versioning, Versions};
+    use std::cmp::Ordering;
+
     use anyhow::Result;
 
+    use crate::dependencies::maven_metadata::{
+        compare_versions, MavenMetadata, Versioning, Versions,
+    };
+
     #[test]
     fn test_ser() {
         println!(
@@ -56,6 +139,32 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_compare_versions() {
+        let ordered = [
+            "1.0-alpha1",
+            "1.0-alpha2",
+            "1.0-alpha10",
+            "1.0-beta",
+            "1.0-M1",
+            "1.0-RC1",
+            "1.0-cr2",
+            "1.0-SNAPSHOT",
+            "1.0",
+            "1.0-sp1",
+            "1.0.1",
+            "1.1",
+        ];
+        for (i, a) in ordered.iter().enumerate() {
+            for b in &ordered[i + 1..] {
+                assert_eq!(compare_versions(a, b), Ordering::Less, "{} < {}", a, b);
+                assert_eq!(compare_versions(b, a), Ordering::Greater, "{} > {}", b, a);
+            }
+        }
+        assert_eq!(compare_versions("1.0.Final", "1.0"), Ordering::Equal);
+        assert_eq!(compare_versions("1.0-GA", "1.0-release"), Ordering::Equal);
+    }
+
     async fn pom_source_0() -> Result<String> {
         Ok(reqwest::get("https://repo.maven.apache.org/maven2/org/apache/logging/log4j/log4j-core/maven-metadata.xml")
             .await?
@@ -0,0 +1,96 @@
+use std::path::PathBuf;
+
+use anyhow::{Context, Result};
+use serde::Deserialize;
+
+use crate::dependencies::xml_utils::Elem;
+
+/// The parts of a maven `settings.xml` we use
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename = "settings")]
+pub struct MavenSettings {
+    #[serde(default)]
+    pub servers: Servers,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct Servers {
+    #[serde(default, rename = "server")]
+    pub servers: Vec<Server>,
+}
+
+/// Credentials of a repository, referenced by id
+#[derive(Debug, Clone, Deserialize)]
+pub struct Server {
+    pub id: Elem<String>,
+    pub username: Option<Elem<String>>,
+    pub password: Option<Elem<String>>,
+}
+
+impl MavenSettings {
+    pub fn parse(text: &str) -> Result<Self> {
+        Ok(quick_xml::de::from_str(text)?)
+    }
+
+    /// Load `~/.m2/settings.xml`, if there is one
+    pub fn load_user() -> Result<Option<Self>> {
+        let path = match std::env::var_os("HOME").or_else(|| std::env::var_os("USERPROFILE")) {
+            Some(home) => PathBuf::from(home).join(".m2/settings.xml"),
+            None => return Ok(None),
+        };
+        if !path.exists() {
+            return Ok(None);
+        }
+        let text = std::fs::read_to_string(&path)?;
+        Self::parse(&text)
+            .with_context(|| format!("Malformed {}", path.display()))
+            .map(Some)
+    }
+
+    /// Username and password of the server with this id
+    pub fn credentials(&self, id: &str) -> Option<(String, String)> {
+        self.servers
+            .servers
+            .iter()
+            .find(|it| it.id.value == id)
+            .map(|it| {
+                (
+                    it.username
+                        .as_ref()
+                        .map(|it| it.value.clone())
+                        .unwrap_or_default(),
+                    it.password
+                        .as_ref()
+                        .map(|it| it.value.clone())
+                        .unwrap_or_default(),
+                )
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::dependencies::maven_settings::MavenSettings;
+
+    #[test]
+    fn test_server_credentials() {
+        let settings = MavenSettings::parse(
+            r#"<settings>
+                <localRepository>/tmp/m2</localRepository>
+                <servers>
+                    <server>
+                        <id>nexus</id>
+                        <username>marais</username>
+                        <password>hunter2</password>
+                    </server>
+                </servers>
+            </settings>"#,
+        )
+        .unwrap();
+        assert_eq!(
+            settings.credentials("nexus"),
+            Some(("marais".to_string(), "hunter2".to_string()))
+        );
+        assert_eq!(settings.credentials("central"), None);
+    }
+}
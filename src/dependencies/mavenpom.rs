@@ -1,13 +1,19 @@
 use std::borrow::Cow;
 use std::collections::HashMap;
 use std::fmt::Debug;
+use std::sync::Arc;
 
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use lazy_regex::{regex, Lazy};
 use regex::{Captures, Regex};
-use serde::{Deserialize, Serialize};
+use serde::ser::Error as _;
+use serde::{Deserialize, Serialize, Serializer};
+use tracing::warn;
 
-use crate::dependencies::xml_utils::Elem;
+use crate::dependencies::coordinate::Coordinate;
+use crate::dependencies::xml_utils::{normalize_xml, Elem};
+use crate::dependencies::{Dependency, MavenRepo, MavenRepoDependency, PrebuiltLocalDependency};
+use crate::module::Module;
 
 const SCHEMA_XSD: &str =
     "http://maven.apache.org/POM/4.0.0 http://maven.apache.org/maven-v4_0_0.xsd";
@@ -15,7 +21,8 @@ const SCHEMA_XSD: &str =
 #[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
 #[serde(rename = "project")]
 pub struct MavenPom {
-    #[serde(rename = "xsi:schemaLocation")]
+    /// Optional in poms, but always written
+    #[serde(rename = "xsi:schemaLocation", default = "default_schema_location")]
     schema_location: String,
     #[serde(rename = "modelVersion")]
     pub model_version: Elem<String>,
@@ -26,12 +33,21 @@ pub struct MavenPom {
     pub artifact_id: Elem<String>,
     /// If none, then derived from parent
     pub version: Option<Elem<String>>,
+    /// Defaults to jar, "pom" for aggregators and boms that have no jar
+    pub packaging: Option<Elem<String>>,
     /// None if this is a top level pom
     pub parent: Option<ParentPom>,
     pub properties: Option<HashMap<String, String>>,
     pub dependencies: Option<PomDependencies>,
     #[serde(rename = "dependencyManagement")]
     pub dependency_management: Option<DependencyManagement>,
+    /// Only the relocation is kept
+    #[serde(rename = "distributionManagement")]
+    pub distribution_management: Option<DistributionManagement>,
+}
+
+fn default_schema_location() -> String {
+    SCHEMA_XSD.to_string()
 }
 
 #[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
@@ -47,7 +63,8 @@ pub struct ParentPom {
 
 #[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
 pub struct PomDependencies {
-    #[serde(rename = "dependency")]
+    // <dependencies/> and lists with only comments are found in the wild
+    #[serde(default, rename = "dependency")]
     pub dependencies: Vec<PomDependency>,
 }
 
@@ -58,12 +75,16 @@ pub struct PomDependency {
     #[serde(rename = "artifactId")]
     pub artifact_id: Elem<String>,
     pub version: Option<Elem<String>>,
+    pub classifier: Option<Elem<String>>,
     pub scope: Option<Elem<MavenDependencyScope>>,
     pub r#type: Option<Elem<String>>,
     pub optional: Option<Elem<bool>>,
+    /// Only used with the system scope, absolute path to the jar
+    #[serde(rename = "systemPath")]
+    pub system_path: Option<Elem<String>>,
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
 pub enum MavenDependencyScope {
     #[serde(rename = "compile")]
     Compile,
@@ -73,6 +94,38 @@ pub enum MavenDependencyScope {
     Test,
     #[serde(rename = "provided")]
     Provided,
+    #[serde(rename = "system")]
+    System,
+    /// A BOM whose dependency management is merged in
+    #[serde(rename = "import")]
+    Import,
+    /// Any other scope we don't care about
+    #[serde(other)]
+    Other,
+}
+
+impl MavenDependencyScope {
+    pub fn as_str(&self) -> Option<&'static str> {
+        match self {
+            MavenDependencyScope::Compile => Some("compile"),
+            MavenDependencyScope::Runtime => Some("runtime"),
+            MavenDependencyScope::Test => Some("test"),
+            MavenDependencyScope::Provided => Some("provided"),
+            MavenDependencyScope::System => Some("system"),
+            MavenDependencyScope::Import => Some("import"),
+            MavenDependencyScope::Other => None,
+        }
+    }
+}
+
+/// Written as text, quick-xml would write unit variants as empty elements
+impl Serialize for MavenDependencyScope {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self.as_str() {
+            Some(scope) => serializer.serialize_str(scope),
+            None => Err(S::Error::custom("unknown dependency scope")),
+        }
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
@@ -80,29 +133,110 @@ pub struct DependencyManagement {
     pub dependencies: PomDependencies,
 }
 
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct DistributionManagement {
+    pub relocation: Option<Relocation>,
+}
+
+/// The artifact moved, this pom is a stub. Missing coordinates are the same as the stub's.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct Relocation {
+    #[serde(rename = "groupId")]
+    pub group_id: Option<Elem<String>>,
+    #[serde(rename = "artifactId")]
+    pub artifact_id: Option<Elem<String>>,
+    pub version: Option<Elem<String>>,
+    pub message: Option<Elem<String>>,
+}
+
 impl MavenPom {
     pub fn parse(text: &str) -> Result<Self> {
-        let mut pom: Self = quick_xml::de::from_str(text)?;
-        if pom.group_id.is_none() {
-            pom.group_id = Some(pom.parent.as_ref().unwrap().group_id.clone());
-        }
-        if pom.version.is_none() {
-            pom.version = Some(pom.parent.as_ref().unwrap().version.clone());
+        let mut pom: Self = quick_xml::de::from_str(&normalize_xml(text))?;
+        if pom.group_id.is_none() || pom.version.is_none() {
+            let parent = pom.parent.as_ref().ok_or_else(|| {
+                anyhow!(
+                    "The pom of '{}' has no groupId or version and no parent to inherit them from",
+                    pom.artifact_id
+                )
+            })?;
+            let (group_id, version) = (parent.group_id.clone(), parent.version.clone());
+            pom.group_id.get_or_insert(group_id);
+            pom.version.get_or_insert(version);
         }
         Ok(pom)
     }
 
+    /// Pom published along a module, only maven dependencies can be expressed
+    pub fn for_module(module: &Module) -> Self {
+        let dependencies: Vec<_> = module
+            .dependencies
+            .iter_published()
+            .filter_map(|(scope, dep)| match dep {
+                Dependency::MavenRepo(dep) => Some(PomDependency {
+                    group_id: dep.coordinate.group.clone().into(),
+                    artifact_id: dep.coordinate.artifact.clone().into(),
+                    version: Some(dep.coordinate.version.clone().into()),
+                    classifier: dep.coordinate.classifier.clone().map(Elem::new),
+                    scope: Some(Elem::new(scope)),
+                    r#type: None,
+                    optional: None,
+                    system_path: None,
+                }),
+                _ => None,
+            })
+            .collect();
+        MavenPom {
+            schema_location: SCHEMA_XSD.to_string(),
+            model_version: "4.0.0".into(),
+            group_id: Some(module.group.clone().into()),
+            artifact_id: module.artifact.clone().into(),
+            version: Some(module.version.clone().into()),
+            packaging: None,
+            parent: None,
+            properties: None,
+            dependencies: if dependencies.is_empty() {
+                None
+            } else {
+                Some(PomDependencies { dependencies })
+            },
+            dependency_management: None,
+            distribution_management: None,
+        }
+    }
+
     pub fn save(&self) -> Result<String> {
         Ok(quick_xml::se::to_string(self)?)
     }
 
+    /// Whether this artifact is only a pom, without a jar.
+    /// Any other packaging is downloaded as a jar : `bundle` (OSGi), `maven-plugin`,
+    /// `eclipse-plugin` and the like are jars with extra metadata.
+    pub fn is_pom_packaging(&self) -> bool {
+        self.packaging
+            .as_ref()
+            .map_or(false, |it| it.value == "pom")
+    }
+
     pub fn dependency_notation(&self) -> String {
-        return format!(
+        format!(
             "{}:{}:{}",
             self.group_id.as_ref().unwrap().value,
             self.artifact_id.value,
             self.version.as_ref().unwrap().value
-        );
+        )
+    }
+
+    /// Where this artifact moved to, if it declares a relocation
+    pub fn relocation(&self) -> Option<Coordinate> {
+        let relocation = self.distribution_management.as_ref()?.relocation.as_ref()?;
+        let or = |it: &Option<Elem<String>>, default: &Elem<String>| {
+            it.as_ref().unwrap_or(default).value.clone()
+        };
+        Some(Coordinate::new(
+            or(&relocation.group_id, self.group_id.as_ref()?),
+            or(&relocation.artifact_id, &self.artifact_id),
+            or(&relocation.version, self.version.as_ref()?),
+        ))
     }
 
     /// Get a new pom by applying a child pom over a parent pom
@@ -114,11 +248,7 @@ impl MavenPom {
                 Some(p.clone())
             }
         } else {
-            if let Some(c) = new.properties.as_ref() {
-                Some(c.clone())
-            } else {
-                None
-            }
+            new.properties.clone()
         };
 
         let deps = if let Some(p) = self.dependencies.as_ref() {
@@ -128,11 +258,7 @@ impl MavenPom {
                 Some(p.clone())
             }
         } else {
-            if let Some(c) = new.dependencies.as_ref() {
-                Some(c.clone())
-            } else {
-                None
-            }
+            new.dependencies.clone()
         };
 
         let dep_mgmt = if let Some(p) = self.dependency_management.as_ref() {
@@ -142,11 +268,7 @@ impl MavenPom {
                 Some(p.clone())
             }
         } else {
-            if let Some(c) = new.dependency_management.as_ref() {
-                Some(c.clone())
-            } else {
-                None
-            }
+            new.dependency_management.clone()
         };
 
         MavenPom {
@@ -155,11 +277,15 @@ impl MavenPom {
             group_id: new.group_id.clone().or(self.group_id.clone()),
             artifact_id: new.artifact_id.clone(),
             version: new.version.clone().or(self.version.clone()),
+            // Packaging is never inherited
+            packaging: new.packaging.clone(),
             // The resulting merged pom has no parent
             parent: None,
             properties: props,
             dependencies: deps,
             dependency_management: dep_mgmt,
+            // A relocation only applies to the pom declaring it
+            distribution_management: new.distribution_management.clone(),
         }
     }
 
@@ -171,13 +297,16 @@ impl MavenPom {
             }
             deps.clean();
             if let Some(props) = self.properties.as_ref() {
+                let project_version = self.version.as_ref().map(|e| &e.value).unwrap();
                 for dep in deps.dependencies.iter_mut() {
                     if let Some(x) = dep.version.as_mut() {
                         x.value = props
-                            .recurse_resolve(
-                                &x.value,
-                                self.version.as_ref().map(|e| &e.value).unwrap(),
-                            )
+                            .recurse_resolve(&x.value, project_version)
+                            .into_owned();
+                    }
+                    if let Some(x) = dep.system_path.as_mut() {
+                        x.value = props
+                            .recurse_resolve(&x.value, project_version)
                             .into_owned();
                     }
                 }
@@ -197,10 +326,10 @@ impl MavenPom {
 
 impl ParentPom {
     pub fn dependency_notation(&self) -> String {
-        return format!(
+        format!(
             "{}:{}:{}",
             self.group_id.value, self.artifact_id.value, self.version.value
-        );
+        )
     }
 }
 
@@ -236,12 +365,35 @@ impl PomDependencies {
 
 impl PomDependency {
     pub fn dependency_notation(&self) -> String {
-        return format!(
+        format!(
             "{}:{}:{}",
             self.group_id.value,
             self.artifact_id.value,
             self.version.as_ref().unwrap().value
-        );
+        )
+    }
+
+    /// Convert to a dependency we can process.
+    /// System scoped dependencies are mapped to a local jar at their systemPath,
+    /// they are never downloaded.
+    pub fn to_dependency(&self, repo: Arc<MavenRepo>) -> Dependency {
+        let scope = self.scope.as_ref().map(|x| x.value);
+        match (scope, self.system_path.as_ref()) {
+            (Some(MavenDependencyScope::System), Some(path)) => {
+                Dependency::PrebuiltLocal(PrebuiltLocalDependency {
+                    path: path.value.clone(),
+                })
+            }
+            _ => {
+                let mut coordinate = Coordinate::new(
+                    self.group_id.value.clone(),
+                    self.artifact_id.value.clone(),
+                    self.version.as_ref().unwrap().value.clone(),
+                );
+                coordinate.classifier = self.classifier.as_ref().map(|it| it.value.clone());
+                Dependency::MavenRepo(MavenRepoDependency { coordinate, repo })
+            }
+        }
     }
 
     /// Merge 2 dependencies (they should be the same group:artifact)
@@ -251,9 +403,19 @@ impl PomDependency {
             group_id: new.group_id.clone(),
             artifact_id: new.artifact_id.clone(),
             version: new.version.as_ref().or(self.version.as_ref()).cloned(),
+            classifier: new
+                .classifier
+                .as_ref()
+                .or(self.classifier.as_ref())
+                .cloned(),
             scope: new.scope.as_ref().or(self.scope.as_ref()).cloned(),
             r#type: new.r#type.as_ref().or(self.r#type.as_ref()).cloned(),
             optional: new.optional.as_ref().or(self.optional.as_ref()).cloned(),
+            system_path: new
+                .system_path
+                .as_ref()
+                .or(self.system_path.as_ref())
+                .cloned(),
         }
     }
 
@@ -269,15 +431,31 @@ impl PomDependency {
                 group_id: self.group_id.clone(),
                 artifact_id: self.artifact_id.clone(),
                 version: self.version.as_ref().or(rule.version.as_ref()).cloned(),
+                classifier: self.classifier.clone(),
                 scope: self.scope.as_ref().or(rule.scope.as_ref()).cloned(),
                 r#type: self.r#type.as_ref().or(rule.r#type.as_ref()).cloned(),
                 optional: self.optional.as_ref().or(rule.optional.as_ref()).cloned(),
+                system_path: self
+                    .system_path
+                    .as_ref()
+                    .or(rule.system_path.as_ref())
+                    .cloned(),
             }
         } else {
             self.clone()
         }
     }
 
+    /// Provided dependencies aren't transitive, only those of a declared dependency are used
+    pub fn is_provided(&self) -> bool {
+        self.scope.as_ref().map(|x| x.value) == Some(MavenDependencyScope::Provided)
+    }
+
+    /// A jar at its systemPath, provided by the environment like a provided dependency
+    pub fn is_system(&self) -> bool {
+        self.scope.as_ref().map(|x| x.value) == Some(MavenDependencyScope::System)
+    }
+
     /// returns false if this dependency is useless, e.g. test dependency
     fn should_keep(&self) -> bool {
         !self.optional.clone().unwrap_or(false.into()).value && {
@@ -287,7 +465,10 @@ impl PomDependency {
                 .map(|x| x.value)
                 .unwrap_or(MavenDependencyScope::Compile);
             //println!("dep: {}, scope: {:?}", self.dependency_notation(), scope);
-            scope == MavenDependencyScope::Compile || scope == MavenDependencyScope::Runtime
+            scope == MavenDependencyScope::Compile
+                || scope == MavenDependencyScope::Runtime
+                || scope == MavenDependencyScope::System
+                || scope == MavenDependencyScope::Provided
         }
     }
 }
@@ -312,7 +493,7 @@ pub trait PropertiesExt {
 impl PropertiesExt for Properties {
     fn recurse_resolve<'t>(&self, text: &'t str, project_version: &str) -> Cow<'t, str> {
         // Regex is compiled at compile time
-        let pat: &Lazy<Regex> = regex!("\\$\\{(?P<prop_name>.+)\\}");
+        let pat: &Lazy<Regex> = regex!("\\$\\{(?P<prop_name>[^}]+)\\}");
         pat.replace_all(text, |caps: &Captures| {
             let prop = caps.name("prop_name").unwrap().as_str();
 
@@ -321,12 +502,13 @@ impl PropertiesExt for Properties {
                 return Cow::Borrowed(project_version);
             }
 
-            let res = self.get(prop);
-            if res.is_none() {
-                println!("Can't resolve {}", text);
-                return Cow::Borrowed(text);
+            if let Some(res) = self.get(prop) {
+                self.recurse_resolve(res, project_version)
             } else {
-                return self.recurse_resolve(res.unwrap(), project_version);
+                // Left as is, like maven does
+                let token = caps.get(0).unwrap().as_str();
+                warn!("Can't resolve {}", token);
+                Cow::Owned(token.to_string())
             }
         })
     }
@@ -342,12 +524,15 @@ impl PropertiesExt for Properties {
 
 #[cfg(test)]
 mod tests {
+    use std::sync::Arc;
+
     use anyhow::Result;
-    use reqwest::Client;
+    use url::Url;
 
     use crate::dependencies::mavenpom::{
         MavenPom, ParentPom, PomDependencies, PomDependency, Properties, PropertiesExt, SCHEMA_XSD,
     };
+    use crate::dependencies::{Dependency, MavenRepo, RepoLayout};
 
     #[test]
     fn test_ser() {
@@ -359,6 +544,7 @@ mod tests {
                 group_id: None,
                 artifact_id: "jcargo-bin".into(),
                 version: None,
+                packaging: None,
                 parent: Some(ParentPom {
                     group_id: "marais".into(),
                     artifact_id: "jcargo".into(),
@@ -372,21 +558,26 @@ mod tests {
                             group_id: "marais".into(),
                             artifact_id: "pomreader".into(),
                             version: None,
+                            classifier: None,
                             scope: None,
                             r#type: None,
                             optional: None,
+                            system_path: None,
                         },
                         PomDependency {
                             group_id: "marais".into(),
                             artifact_id: "pomreader".into(),
                             version: None,
+                            classifier: None,
                             scope: None,
                             r#type: None,
                             optional: None,
+                            system_path: None,
                         },
                     ]
                 }),
                 dependency_management: None,
+                distribution_management: None,
             })
             .unwrap()
         );
@@ -462,20 +653,24 @@ mod tests {
             props.recurse_resolve("yay ${propname}", "yay").to_string(),
             "yay you thought it was me, but it was me dio".to_string()
         );
+        assert_eq!(
+            props.recurse_resolve("${other} ${unknown}", "yay"),
+            "but it was me dio ${unknown}"
+        );
         Ok(())
     }
 
     #[tokio::test]
     async fn test_merge() -> Result<()> {
-        let pomA = MavenPom::parse(&pom_source_0().await?)?;
+        let pom_a = MavenPom::parse(&pom_source_0().await?)?;
 
-        let pomB = MavenPom::parse(&pom_source_1().await?)?;
+        let pom_b = MavenPom::parse(&pom_source_1().await?)?;
 
-        let pomC = MavenPom::parse(&pom_source_2().await?)?;
+        let pom_c = MavenPom::parse(&pom_source_2().await?)?;
 
-        let pomD = MavenPom::parse(&pom_source_3().await?)?;
+        let pom_d = MavenPom::parse(&pom_source_3().await?)?;
 
-        let merged = pomD.merge(&pomC).merge(&pomB).merge(&pomA);
+        let merged = pom_d.merge(&pom_c).merge(&pom_b).merge(&pom_a);
 
         println!("merged : {:#?}", merged);
 
@@ -502,7 +697,52 @@ mod tests {
     #[test]
     fn test_deser() -> Result<()> {
         let text = r#"<project xsi:schemaLocation="http://maven.apache.org/POM/4.0.0 http://maven.apache.org/maven-v4_0_0.xsd"><modelVersion>4.0.0</modelVersion><groupId>org.apache.logging.log4j</groupId><artifactId>log4j-api</artifactId><version>2.17.1</version></project>"#;
-        let pom = MavenPom::parse(&text)?;
+        MavenPom::parse(text)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_tolerant_parsing() -> Result<()> {
+        let commented = MavenPom::parse(include_str!("fixtures/commented.pom"))?;
+        assert_eq!(commented.artifact_id.value, "commented");
+        let deps = commented.dependencies.unwrap().dependencies;
+        assert_eq!(deps.len(), 1);
+        assert_eq!(deps[0].artifact_id.value, "lib");
+
+        let bare = MavenPom::parse(include_str!("fixtures/bare.pom"))?;
+        assert_eq!(bare.version.unwrap().value, "1.0");
+        assert!(bare.dependencies.unwrap().dependencies.is_empty());
+
+        let prefixed = MavenPom::parse(include_str!("fixtures/prefixed.pom"))?;
+        assert_eq!(prefixed.artifact_id.value, "prefixed");
+
+        let orphan = MavenPom::parse(
+            "<project><modelVersion>4.0.0</modelVersion><artifactId>orphan</artifactId></project>",
+        );
+        assert_eq!(
+            orphan.unwrap_err().to_string(),
+            "The pom of 'orphan' has no groupId or version and no parent to inherit them from"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_system_scope() -> Result<()> {
+        let text = r#"<project xsi:schemaLocation="http://maven.apache.org/POM/4.0.0 http://maven.apache.org/maven-v4_0_0.xsd"><modelVersion>4.0.0</modelVersion><groupId>marais</groupId><artifactId>enterprise</artifactId><version>1.0</version><properties><libs.dir>/opt/libs</libs.dir></properties><dependencies><dependency><groupId>com.sun</groupId><artifactId>tools</artifactId><version>1.8</version><scope>system</scope><systemPath>${libs.dir}/tools.jar</systemPath></dependency><dependency><groupId>marais</groupId><artifactId>bom</artifactId><version>1.0</version><scope>import</scope></dependency></dependencies></project>"#;
+        let mut pom = MavenPom::parse(text)?;
+        pom.clean();
+        let deps = pom.dependencies.unwrap().dependencies;
+        // Import scope is dropped, system is kept
+        assert_eq!(deps.len(), 1);
+        let repo = Arc::new(MavenRepo {
+            name: "unreachable".to_string(),
+            url: Url::parse("http://localhost:1/").unwrap(),
+            layout: RepoLayout::Maven,
+        });
+        match deps[0].to_dependency(repo) {
+            Dependency::PrebuiltLocal(local) => assert_eq!(local.path, "/opt/libs/tools.jar"),
+            other => panic!("expected a local dependency, got {:?}", other),
+        }
         Ok(())
     }
 }
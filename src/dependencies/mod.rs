@@ -1,15 +1,29 @@
+use std::collections::{BTreeMap, HashSet};
 use std::fmt::{Display, Formatter};
+use std::path::Path;
 use std::sync::Arc;
 
+use anyhow::{anyhow, Context, Result};
+use tracing::warn;
 use url::Url;
 
-use crate::manifest::{CompleteDependencyDef, DependenciesDef};
+use crate::dependencies::catalog::{VersionCatalog, DEFAULT_CATALOG};
+use crate::dependencies::coordinate::Coordinate;
+use crate::dependencies::maven_metadata::compare_versions;
+use crate::dependencies::mavenpom::MavenDependencyScope;
+use crate::manifest::{CompleteDependencyDef, DependenciesDef, DependencyDef, RepositoryDef};
 use crate::Env;
 
+pub mod catalog;
+pub mod coordinate;
 pub mod dependency_graph;
+pub mod error;
 pub mod maven;
 pub mod maven_metadata;
+pub mod maven_settings;
 pub mod mavenpom;
+pub mod resolution;
+pub mod signature;
 pub mod xml_utils;
 
 #[derive(Debug, Clone)]
@@ -18,37 +32,289 @@ pub struct Dependencies {
     pub runtime: Vec<Dependency>,
     pub compile_runtime: Vec<Dependency>,
     pub transitive: Vec<Dependency>,
+    pub test: Vec<Dependency>,
+    /// Version constraints, like a BOM of the module
+    pub platform: Vec<Coordinate>,
+    /// Repositories of each scope, followed by the global ones
+    pub repositories: BTreeMap<&'static str, Vec<Arc<MavenRepo>>>,
+    /// Scopes left out of the classpaths, they are still resolved and published
+    pub excluded_scopes: Vec<String>,
+    /// BOMs managing the versions of `unversioned`, imported with [Dependencies::import_boms]
+    pub boms: Vec<MavenRepoDependency>,
+    /// Declared without a version the platform has, by scope and position in the manifest
+    unversioned: Vec<(usize, usize, CompleteDependencyDef)>,
+}
+
+/// Scope names as they appear in the manifest
+const SCOPES: [&str; 5] = ["compile", "runtime", "compileRuntime", "transitive", "test"];
+
+/// The version catalog of the module in `dir`, only read when used, most modules don't have one
+fn load_catalog(dd: &DependenciesDef, dir: &Path) -> Result<Option<VersionCatalog>> {
+    if dd.uses_catalog() {
+        let path = dd.catalog.as_deref().unwrap_or(DEFAULT_CATALOG);
+        Ok(Some(VersionCatalog::load(&dir.join(path))?))
+    } else {
+        Ok(None)
+    }
+}
+
+/// Versioned dependency without its repositories
+fn maven_dependency(
+    def: DependencyDef,
+    catalog: Option<&VersionCatalog>,
+    env: &Env,
+) -> Result<MavenRepoDependency> {
+    match Dependency::from_def(def.complete(catalog)?, &[], &env.repos)? {
+        Dependency::MavenRepo(dep) => Ok(dep),
+        _ => unreachable!(),
+    }
 }
 
 impl Dependencies {
-    pub fn from_def(dd: DependenciesDef, env: &Env) -> Self {
-        Self {
-            compile: dd
-                .compile
-                .into_iter()
-                .map(|it| Dependency::from_def(it.into(), env))
-                .collect(),
-            runtime: dd
-                .runtime
-                .into_iter()
-                .map(|it| Dependency::from_def(it.into(), env))
-                .collect(),
-            compile_runtime: dd
-                .compile_runtime
-                .into_iter()
-                .map(|it| Dependency::from_def(it.into(), env))
-                .collect(),
-            transitive: dd
-                .transitive
+    /// `java_target` selects the per JDK classifiers, the version catalog is relative to `dir`
+    pub fn from_def(dd: DependenciesDef, dir: &Path, java_target: &str, env: &Env) -> Result<Self> {
+        let catalog = load_catalog(&dd, dir)?;
+        let catalog = catalog.as_ref();
+        let platform = dd
+            .platform
+            .into_iter()
+            .map(|it| Ok(maven_dependency(it, catalog, env)?.coordinate))
+            .collect::<Result<Vec<_>>>()?;
+        let boms = dd
+            .bom
+            .into_iter()
+            .map(|it| maven_dependency(it, catalog, env))
+            .collect::<Result<Vec<_>>>()?;
+
+        let mut scoped = dd.repositories;
+        if let Some(scope) = scoped.keys().find(|it| !SCOPES.contains(&it.as_str())) {
+            return Err(anyhow!(
+                "Unknown scope '{}' in [dependencies.repositories], expected one of {}",
+                scope,
+                SCOPES.join(", ")
+            ));
+        }
+        let mut repositories = BTreeMap::new();
+        for scope in SCOPES {
+            let mut repos = scoped
+                .remove(scope)
+                .unwrap_or_default()
                 .into_iter()
-                .map(|it| Dependency::from_def(it.into(), env))
-                .collect(),
+                .map(|it| MavenRepo::from_def(it).map(Arc::new))
+                .collect::<Result<Vec<_>>>()?;
+            repos.extend(env.repos.iter().cloned());
+            repositories.insert(scope, repos);
         }
+
+        // The versions the BOMs may manage are only known once they are imported
+        let mut unversioned = Vec::new();
+        let mut convert = |defs: Vec<DependencyDef>, scope: usize| {
+            let mut deps = Vec::with_capacity(defs.len());
+            for (i, def) in defs.into_iter().enumerate() {
+                let mut dd = def.complete(catalog)?;
+                dd.classifier = dd.classifier_for(java_target);
+                if !boms.is_empty()
+                    && dd.version.is_none()
+                    && platform_version(&platform, &dd.group, &dd.artifact).is_none()
+                {
+                    unversioned.push((scope, i, dd));
+                } else {
+                    deps.push(Dependency::from_def(
+                        dd,
+                        &platform,
+                        &repositories[SCOPES[scope]],
+                    )?);
+                }
+            }
+            Ok::<_, anyhow::Error>(deps)
+        };
+        let mut deps = Self {
+            compile: convert(dd.compile, 0)?,
+            runtime: convert(dd.runtime, 1)?,
+            compile_runtime: convert(dd.compile_runtime, 2)?,
+            transitive: convert(dd.transitive, 3)?,
+            test: convert(dd.test, 4)?,
+            platform,
+            repositories,
+            excluded_scopes: env.excluded_scopes.clone(),
+            boms,
+            unversioned,
+        };
+        // Once complete
+        if deps.boms.is_empty() {
+            deps.dedup(env.strict)?;
+        }
+        Ok(deps)
+    }
+
+    /// Dependencies waiting for the BOMs to give them a version
+    pub fn unresolved(&self) -> usize {
+        self.unversioned.len()
+    }
+
+    /// Fetch the BOMs to the `libs` of the module in `dir`, and give their managed versions to
+    /// the dependencies declared without one. Only done when the dependencies are needed, the
+    /// BOMs may have to be downloaded.
+    pub async fn import_boms(&mut self, dir: &Path, env: &Env) -> Result<()> {
+        if self.boms.is_empty() {
+            return Ok(());
+        }
+        // The explicit platform wins, then the first BOM managing an artifact
+        for bom in std::mem::take(&mut self.boms) {
+            for coordinate in maven::import_bom(env, &dir.join("libs"), bom).await? {
+                if platform_version(&self.platform, &coordinate.group, &coordinate.artifact)
+                    .is_none()
+                {
+                    self.platform.push(coordinate);
+                }
+            }
+        }
+        // In declaration order, so each one goes back to its position
+        for (scope, i, dd) in std::mem::take(&mut self.unversioned) {
+            let dep = Dependency::from_def(dd, &self.platform, &self.repositories[SCOPES[scope]])?;
+            let deps = match scope {
+                0 => &mut self.compile,
+                1 => &mut self.runtime,
+                2 => &mut self.compile_runtime,
+                3 => &mut self.transitive,
+                _ => &mut self.test,
+            };
+            deps.insert(i, dep);
+        }
+        self.dedup(env.strict)
+    }
+
+    /// Keep a single declaration of each artifact with the highest declared version, in the first
+    /// scope putting it on every classpath its declarations did. An artifact in both compile and
+    /// runtime moves to compileRuntime. Duplicates are an error when `strict`.
+    fn dedup(&mut self, strict: bool) -> Result<()> {
+        // The classpaths of each scope, in the order of `scopes`
+        const COMPILE: u8 = 1;
+        const RUNTIME: u8 = 2;
+        const TEST: u8 = 4;
+        const CLASSPATHS: [u8; 5] = [
+            COMPILE | TEST,
+            RUNTIME | TEST,
+            COMPILE | RUNTIME | TEST,
+            COMPILE | RUNTIME | TEST,
+            TEST,
+        ];
+
+        let mut scopes = [
+            (SCOPES[0], &mut self.compile),
+            (SCOPES[1], &mut self.runtime),
+            (SCOPES[2], &mut self.compile_runtime),
+            (SCOPES[3], &mut self.transitive),
+            (SCOPES[4], &mut self.test),
+        ];
+
+        // group:artifact[:classifier] to the declarations, (scope, index, version)
+        let mut declarations: BTreeMap<String, Vec<(usize, usize, String)>> = BTreeMap::new();
+        for (scope, (_, deps)) in scopes.iter().enumerate() {
+            for (i, dep) in deps.iter().enumerate() {
+                if let Dependency::MavenRepo(dep) = dep {
+                    let c = &dep.coordinate;
+                    let mut key = format!("{}:{}", c.group, c.artifact);
+                    if let Some(classifier) = &c.classifier {
+                        key = format!("{}:{}", key, classifier);
+                    }
+                    declarations
+                        .entry(key)
+                        .or_default()
+                        .push((scope, i, c.version.clone()));
+                }
+            }
+        }
+
+        let mut problems = Vec::new();
+        let mut removed = HashSet::new();
+        let mut merged = Vec::new();
+        for (key, found) in declarations.iter().filter(|(_, it)| it.len() > 1) {
+            let version = found
+                .iter()
+                .map(|(_, _, version)| version)
+                .max_by(|a, b| compare_versions(a, b))
+                .unwrap()
+                .clone();
+            let classpaths = found
+                .iter()
+                .fold(0, |acc, (scope, _, _)| acc | CLASSPATHS[*scope]);
+            let kept = found
+                .iter()
+                .position(|(scope, _, _)| CLASSPATHS[*scope] == classpaths);
+            let kept_scope = match kept {
+                Some(kept) => found[kept].0,
+                // Only compile and runtime, together they are compileRuntime
+                None => 2,
+            };
+            problems.push(format!(
+                "'{}' is declared more than once ({}), {} is kept in {}",
+                key,
+                found
+                    .iter()
+                    .map(|(scope, _, version)| format!("{} {}", scopes[*scope].0, version))
+                    .collect::<Vec<_>>()
+                    .join(", "),
+                version,
+                scopes[kept_scope].0
+            ));
+            let (scope, i, _) = found[kept.unwrap_or(0)];
+            let mut dep = scopes[scope].1[i].clone();
+            if let Dependency::MavenRepo(dep) = &mut dep {
+                dep.coordinate.version = version;
+            }
+            match kept {
+                Some(_) => scopes[scope].1[i] = dep,
+                None => merged.push(dep),
+            }
+            removed.extend(
+                found
+                    .iter()
+                    .enumerate()
+                    .filter(|(j, _)| Some(*j) != kept)
+                    .map(|(_, (scope, i, _))| (*scope, *i)),
+            );
+        }
+        if strict && !problems.is_empty() {
+            return Err(anyhow!("{}", problems.join("\n")));
+        }
+        for problem in &problems {
+            warn!("{}", problem);
+        }
+
+        for (scope, (_, deps)) in scopes.iter_mut().enumerate() {
+            let mut i = 0;
+            deps.retain(|_| {
+                i += 1;
+                !removed.contains(&(scope, i - 1))
+            });
+        }
+        scopes[2].1.extend(merged);
+        Ok(())
+    }
+
+    /// The dependencies of each scope, with its name
+    pub fn scopes(&self) -> [(&'static str, &[Dependency]); 5] {
+        [
+            (SCOPES[0], &self.compile),
+            (SCOPES[1], &self.runtime),
+            (SCOPES[2], &self.compile_runtime),
+            (SCOPES[3], &self.transitive),
+            (SCOPES[4], &self.test),
+        ]
     }
 
     /// Total number of dependencies, all scopes
     pub fn len(&self) -> usize {
-        self.compile.len() + self.runtime.len() + self.compile_runtime.len() + self.transitive.len()
+        self.compile.len()
+            + self.runtime.len()
+            + self.compile_runtime.len()
+            + self.transitive.len()
+            + self.test.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
     }
 
     /// Returns an iterator over all dependencies
@@ -58,22 +324,68 @@ impl Dependencies {
             .chain(self.runtime.iter())
             .chain(self.compile_runtime.iter())
             .chain(self.transitive.iter())
+            .chain(self.test.iter())
+    }
+
+    /// Returns a mutable iterator over all dependencies
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut Dependency> {
+        self.compile
+            .iter_mut()
+            .chain(self.runtime.iter_mut())
+            .chain(self.compile_runtime.iter_mut())
+            .chain(self.transitive.iter_mut())
+            .chain(self.test.iter_mut())
     }
 
     /// Returns an Iterator over all dependencies that should be available at compile time
     pub fn iter_compile(&self) -> impl Iterator<Item = &Dependency> {
+        self.scope("compile", &self.compile)
+            .chain(self.scope("compileRuntime", &self.compile_runtime))
+            .chain(self.scope("transitive", &self.transitive))
+    }
+
+    /// Dependencies as they appear in a published pom, test dependencies are left out.
+    /// Compile only dependencies are provided by the environment in maven terms.
+    pub fn iter_published(&self) -> impl Iterator<Item = (MavenDependencyScope, &Dependency)> {
         self.compile
             .iter()
-            .chain(self.compile_runtime.iter())
-            .chain(self.transitive.iter())
+            .map(|it| (MavenDependencyScope::Provided, it))
+            .chain(
+                self.compile_runtime
+                    .iter()
+                    .chain(self.transitive.iter())
+                    .map(|it| (MavenDependencyScope::Compile, it)),
+            )
+            .chain(
+                self.runtime
+                    .iter()
+                    .map(|it| (MavenDependencyScope::Runtime, it)),
+            )
     }
 
     /// Returns an Iterator over all dependencies that should be available at runtime
     pub fn iter_runtime(&self) -> impl Iterator<Item = &Dependency> {
-        self.runtime
-            .iter()
-            .chain(self.compile_runtime.iter())
-            .chain(self.transitive.iter())
+        self.scope("runtime", &self.runtime)
+            .chain(self.scope("compileRuntime", &self.compile_runtime))
+            .chain(self.scope("transitive", &self.transitive))
+    }
+
+    /// Returns an Iterator over all dependencies that should be available to the tests
+    pub fn iter_test(&self) -> impl Iterator<Item = &Dependency> {
+        self.scope("compile", &self.compile)
+            .chain(self.scope("runtime", &self.runtime))
+            .chain(self.scope("compileRuntime", &self.compile_runtime))
+            .chain(self.scope("transitive", &self.transitive))
+            .chain(self.scope("test", &self.test))
+    }
+
+    /// `deps` of the scope `name`, none if it is excluded from the classpaths
+    fn scope<'a>(&self, name: &str, deps: &'a [Dependency]) -> std::slice::Iter<'a, Dependency> {
+        if self.excluded_scopes.iter().any(|it| it == name) {
+            [].iter()
+        } else {
+            deps.iter()
+        }
     }
 }
 
@@ -89,20 +401,68 @@ pub enum Dependency {
     PrebuiltLocal(PrebuiltLocalDependency),
 }
 
+fn platform_version<'a>(
+    platform: &'a [Coordinate],
+    group: &str,
+    artifact: &str,
+) -> Option<&'a str> {
+    platform
+        .iter()
+        .find(|it| it.group == group && it.artifact == artifact)
+        .map(|it| it.version.as_str())
+}
+
 impl Dependency {
-    pub fn from_def(dd: CompleteDependencyDef, env: &Env) -> Self {
-        let first = dd.version.comparators.first().unwrap();
-        Self::MavenRepo(MavenRepoDependency {
-            group: dd.group,
-            artifact: dd.artifact,
-            version: first.to_string()[1..].to_string(),
-            repo: Arc::clone(&env.repos[0]),
-        })
+    /// A dependency declared without a version gets the one of the `platform`, it is resolved
+    /// from the first of `repos`
+    pub fn from_def(
+        dd: CompleteDependencyDef,
+        platform: &[Coordinate],
+        repos: &[Arc<MavenRepo>],
+    ) -> Result<Self> {
+        let version = match &dd.version {
+            Some(req) => req
+                .comparators
+                .first()
+                .ok_or_else(|| {
+                    anyhow!(
+                        "Invalid version '{}' of '{}:{}', expected an exact version",
+                        req,
+                        dd.group,
+                        dd.artifact
+                    )
+                })?
+                .to_string()[1..]
+                .to_string(),
+            None => platform_version(platform, &dd.group, &dd.artifact)
+                .ok_or_else(|| {
+                    anyhow!(
+                        "No version for '{}:{}', declare one or add it to the platform",
+                        dd.group,
+                        dd.artifact
+                    )
+                })?
+                .to_string(),
+        };
+        let mut coordinate = Coordinate::new(dd.group, dd.artifact, version);
+        coordinate.classifier = dd.classifier;
+        let repo = repos.first().ok_or_else(|| {
+            anyhow!(
+                "'{}' can't be resolved, no repository is configured (Maven Central is disabled by --no-default-repos)",
+                coordinate
+            )
+        })?;
+        Ok(Self::MavenRepo(MavenRepoDependency {
+            coordinate,
+            repo: Arc::clone(repo),
+        }))
     }
 
     pub fn classpath(&self) -> String {
         match self {
             Dependency::MavenRepo(repodep) => format!("libs/{}", repodep.jar_name()),
+            Dependency::PrebuiltLocal(local) => local.path.clone(),
+            Dependency::JcargoLocal(local) => format!("{}/target/classes", local.path),
             _ => todo!(),
         }
     }
@@ -112,105 +472,365 @@ impl Dependency {
 pub struct MavenRepo {
     pub name: String,
     pub url: Url,
+    pub layout: RepoLayout,
+}
+
+impl MavenRepo {
+    pub fn from_def(def: RepositoryDef) -> Result<Self> {
+        let url = repo_url(&def.url)
+            .with_context(|| format!("Invalid url of the repository '{}'", def.name))?;
+        let layout = RepoLayout::parse(&def.layout, def.pattern)
+            .with_context(|| format!("Invalid repository '{}'", def.name))?;
+        Ok(Self {
+            name: def.name,
+            url,
+            layout,
+        })
+    }
+}
+
+/// Without the trailing slash, joining paths would replace the last segment
+pub(crate) fn repo_url(url: &str) -> Result<Url> {
+    let mut url = Url::parse(url)?;
+    if !url.path().ends_with('/') {
+        url.set_path(&format!("{}/", url.path()));
+    }
+    Ok(url)
+}
+
+/// Ivy pattern of the repositories with `layout = "ivy"` and no `pattern`
+pub const DEFAULT_IVY_PATTERN: &str =
+    "[organisation]/[module]/[revision]/[artifact]-[revision](-[classifier]).[ext]";
+
+/// How the artifact files are laid out in a repository
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RepoLayout {
+    /// `group/as/dirs/artifact/version/artifact-version-classifier.ext`
+    Maven,
+    /// An Ivy pattern, parenthesized parts are dropped when a token in them has no value
+    Ivy(String),
+}
+
+impl RepoLayout {
+    pub fn parse(layout: &str, pattern: Option<String>) -> Result<Self> {
+        match (layout, pattern) {
+            ("maven", None) => Ok(Self::Maven),
+            ("maven", Some(_)) => Err(anyhow!("A pattern is only used by the ivy layout")),
+            ("ivy", pattern) => Ok(Self::Ivy(
+                pattern.unwrap_or_else(|| DEFAULT_IVY_PATTERN.to_string()),
+            )),
+            (other, _) => Err(anyhow!(
+                "Unknown repository layout '{}', expected maven or ivy",
+                other
+            )),
+        }
+    }
+}
+
+/// Replace the `[token]`s of an Ivy pattern, an optional `(...)` part is kept only if all its
+/// tokens have a value
+fn expand_ivy_pattern(pattern: &str, token: impl Fn(&str) -> Option<String>) -> String {
+    fn expand(part: &str, token: &impl Fn(&str) -> Option<String>) -> Option<String> {
+        let mut out = String::new();
+        let mut rest = part;
+        while let Some(start) = rest.find('[') {
+            out.push_str(&rest[..start]);
+            let end = rest[start..].find(']').map_or(rest.len(), |it| start + it);
+            out.push_str(&token(&rest[start + 1..end])?);
+            rest = rest.get(end + 1..).unwrap_or("");
+        }
+        out.push_str(rest);
+        Some(out)
+    }
+
+    let mut out = String::new();
+    let mut rest = pattern;
+    while let Some(start) = rest.find('(') {
+        out.push_str(&expand(&rest[..start], &token).unwrap_or_default());
+        let end = rest[start..].find(')').map_or(rest.len(), |it| start + it);
+        out.push_str(&expand(&rest[start + 1..end], &token).unwrap_or_default());
+        rest = rest.get(end + 1..).unwrap_or("");
+    }
+    out.push_str(&expand(rest, &token).unwrap_or_default());
+    out
 }
 
 #[derive(Debug, Clone)]
 pub struct MavenRepoDependency {
-    pub group: String,
-    pub artifact: String,
-    pub version: String,
+    pub coordinate: Coordinate,
     pub repo: Arc<MavenRepo>,
 }
 
 impl MavenRepoDependency {
     pub fn get_path(&self) -> String {
-        format!(
-            "{}/{}/{}/",
-            self.group.replace(".", "/"),
-            self.artifact,
-            self.version
-        )
+        self.coordinate.repo_path()
     }
 
     pub fn base_name(&self) -> String {
-        format!("{}-{}", self.artifact, self.version)
+        self.coordinate.base_name()
     }
 
     pub fn jar_name(&self) -> String {
-        format!("{}.jar", self.base_name())
+        self.coordinate.jar_name()
     }
 
     pub fn pom_name(&self) -> String {
         format!("{}.pom", self.base_name())
     }
 
+    /// Url of a file of this version of the artifact, following the layout of the repository
+    pub fn artifact_url(&self, classifier: Option<&str>, ext: &str) -> Url {
+        let path = match &self.repo.layout {
+            RepoLayout::Maven => {
+                let name = match classifier {
+                    Some(classifier) => format!("{}-{}.{}", self.base_name(), classifier, ext),
+                    None => format!("{}.{}", self.base_name(), ext),
+                };
+                format!("{}{}", self.get_path(), name)
+            }
+            RepoLayout::Ivy(pattern) => expand_ivy_pattern(pattern, |token| match token {
+                "organisation" | "organization" => Some(self.coordinate.group.clone()),
+                "orgPath" => Some(self.coordinate.group.replace('.', "/")),
+                "module" | "artifact" => Some(self.coordinate.artifact.clone()),
+                "revision" => Some(self.coordinate.version.clone()),
+                "classifier" => classifier.map(str::to_string),
+                "ext" => Some(ext.to_string()),
+                "type" => Some(ext.trim_end_matches(".asc").to_string()),
+                _ => None,
+            }),
+        };
+        self.repo.url.join(&path).unwrap()
+    }
+
     pub fn jar_url(&self) -> Url {
-        self.repo
-            .url
-            .join(&self.get_path())
-            .unwrap()
-            .join(&self.jar_name())
-            .unwrap()
+        self.artifact_url(self.coordinate.classifier.as_deref(), "jar")
+    }
+
+    /// Detached signature of the jar
+    pub fn jar_signature_url(&self) -> Url {
+        self.artifact_url(self.coordinate.classifier.as_deref(), "jar.asc")
     }
 
     pub fn sources_url(&self) -> Url {
-        self.repo
-            .url
-            .join(&self.get_path())
-            .unwrap()
-            .join(&format!("{}-sources.jar", self.base_name()))
-            .unwrap()
+        self.artifact_url(Some("sources"), "jar")
     }
 
     pub fn docs_url(&self) -> Url {
+        self.artifact_url(Some("javadoc"), "jar")
+    }
+
+    /// The `maven-metadata.xml` listing the versions of the artifact, next to the version
+    /// directories in an Ivy repository
+    pub fn metadata_url(&self) -> Url {
+        let group = match self.repo.layout {
+            RepoLayout::Maven => self.coordinate.group.replace('.', "/"),
+            RepoLayout::Ivy(_) => self.coordinate.group.clone(),
+        };
         self.repo
             .url
-            .join(&self.get_path())
-            .unwrap()
-            .join(&format!("{}-javadoc.jar", self.base_name()))
+            .join(&format!(
+                "{}/{}/maven-metadata.xml",
+                group, self.coordinate.artifact
+            ))
             .unwrap()
     }
 
+    /// Ivy repositories are expected to have poms too, `ivy.xml` descriptors aren't read
     pub fn pom_url(&self) -> Url {
-        self.repo
-            .url
-            .join(&self.get_path())
-            .unwrap()
-            .join(&self.pom_name())
-            .unwrap()
+        self.artifact_url(None, "pom")
     }
 
     pub fn dependency_notation(&self) -> String {
-        format!("{}:{}:{}", self.group, self.artifact, self.version)
+        self.coordinate.to_string()
     }
 }
 
 impl Display for MavenRepoDependency {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}:{}:{}", self.group, self.artifact, self.version)
+        Display::fmt(&self.coordinate, f)
     }
 }
 
 #[derive(Debug, Clone)]
 pub struct JcargoGitDependency {
     /// Repository url
-    url: String,
+    pub url: String,
     /// Repo branch or tag
     /// Defaults to master or main
-    branch: String,
+    pub branch: String,
     /// Commit to fetch
     /// Defaults to latest
-    commit: String,
+    pub commit: String,
     /// Subdirectory to include as a dependency
-    dir: String,
+    pub dir: String,
 }
 
 #[derive(Debug, Clone)]
 pub struct JcargoLocalDependency {
-    path: String,
+    /// Path to the module directory
+    pub path: String,
 }
 
 #[derive(Debug, Clone)]
 pub struct PrebuiltLocalDependency {
-    path: String,
+    pub path: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use url::Url;
+
+    use crate::dependencies::coordinate::Coordinate;
+    use crate::dependencies::{Dependency, MavenRepo, MavenRepoDependency, RepoLayout};
+    use crate::manifest::ModuleManifest;
+    use crate::test_utils::{capture_logs, temp_dir};
+    use crate::{Env, Module};
+
+    const DUPLICATED: &str = r#"
+        group = "marais"
+        artifact = "twice"
+        version = "0.1.0"
+
+        [dependencies]
+        compileRuntime = ["marais:lib:1.0", "marais:other:1.0", "marais:lib:1.2"]
+        test = ["marais:lib:1.1", "marais:lib:1.0:tests"]
+        "#;
+
+    #[test]
+    fn test_duplicate_dependencies() {
+        let mut env = Env::new(false);
+        let dir = temp_dir("duplicate-deps");
+        let module = {
+            let (_guard, logs) = capture_logs();
+            let manifest = ModuleManifest::parse(DUPLICATED, None).unwrap();
+            let module = Module::from_manifest(&dir, manifest, &env).unwrap();
+            assert!(logs.contents().contains(
+                "'marais:lib' is declared more than once (compileRuntime 1.0, compileRuntime 1.2, test 1.1), 1.2 is kept in compileRuntime"
+            ));
+            module
+        };
+
+        let deps: Vec<_> = module
+            .dependencies
+            .iter()
+            .map(|it| it.classpath())
+            .collect();
+        assert_eq!(
+            deps,
+            [
+                "libs/lib-1.2.jar",
+                "libs/other-1.0.jar",
+                "libs/lib-1.0-tests.jar"
+            ]
+        );
+
+        env.strict = true;
+        let manifest = ModuleManifest::parse(DUPLICATED, None).unwrap();
+        let err = Module::from_manifest(&dir, manifest, &env).unwrap_err();
+        assert!(format!("{:#}", err).contains("'marais:lib' is declared more than once"));
+    }
+
+    #[test]
+    fn test_compile_and_runtime_duplicates() {
+        let env = Env::new(false);
+        let manifest = ModuleManifest::parse(
+            r#"
+            group = "marais"
+            artifact = "scoped"
+            version = "0.1.0"
+
+            [dependencies]
+            compile = ["marais:both:1.0", "marais:api:1.0", "marais:covered:1.0"]
+            runtime = ["marais:both:1.1", "marais:impl:1.0"]
+            compileRuntime = ["marais:covered:1.0"]
+            test = ["marais:api:1.0"]
+            "#,
+            None,
+        )
+        .unwrap();
+        let module = {
+            let (_guard, logs) = capture_logs();
+            let module = Module::from_manifest(&temp_dir("scoped-deps"), manifest, &env).unwrap();
+            assert!(logs.contents().contains(
+                "'marais:both' is declared more than once (compile 1.0, runtime 1.1), 1.1 is kept in compileRuntime"
+            ));
+            module
+        };
+
+        let classpath = |deps: &mut dyn Iterator<Item = &Dependency>| {
+            deps.map(|it| it.classpath()).collect::<Vec<_>>()
+        };
+        assert_eq!(
+            classpath(&mut module.dependencies.iter_compile()),
+            [
+                "libs/api-1.0.jar",
+                "libs/covered-1.0.jar",
+                "libs/both-1.1.jar"
+            ]
+        );
+        assert_eq!(
+            classpath(&mut module.dependencies.iter_runtime()),
+            [
+                "libs/impl-1.0.jar",
+                "libs/covered-1.0.jar",
+                "libs/both-1.1.jar"
+            ]
+        );
+        // The test declaration was a true duplicate of the compile one
+        assert!(module.dependencies.test.is_empty());
+    }
+
+    #[test]
+    fn test_ivy_layout_urls() {
+        let layout = RepoLayout::parse("ivy", None).unwrap();
+        let mut coordinate = Coordinate::new("org.example", "lib", "1.2");
+        let dep = MavenRepoDependency {
+            coordinate: coordinate.clone(),
+            repo: Arc::new(MavenRepo {
+                name: "ivy".to_string(),
+                url: Url::parse("https://ivy.example.com/repo/").unwrap(),
+                layout,
+            }),
+        };
+        assert_eq!(
+            dep.jar_url().as_str(),
+            "https://ivy.example.com/repo/org.example/lib/1.2/lib-1.2.jar"
+        );
+        assert_eq!(
+            dep.sources_url().as_str(),
+            "https://ivy.example.com/repo/org.example/lib/1.2/lib-1.2-sources.jar"
+        );
+        assert_eq!(
+            dep.pom_url().as_str(),
+            "https://ivy.example.com/repo/org.example/lib/1.2/lib-1.2.pom"
+        );
+
+        coordinate.classifier = Some("linux".to_string());
+        let custom = MavenRepoDependency {
+            coordinate,
+            repo: Arc::new(MavenRepo {
+                name: "custom".to_string(),
+                url: Url::parse("https://ivy.example.com/").unwrap(),
+                layout: RepoLayout::parse(
+                    "ivy",
+                    Some(
+                        "[orgPath]/[module]/[revision]/[type]s/[artifact](-[classifier]).[ext]"
+                            .to_string(),
+                    ),
+                )
+                .unwrap(),
+            }),
+        };
+        assert_eq!(
+            custom.jar_url().as_str(),
+            "https://ivy.example.com/org/example/lib/1.2/jars/lib-linux.jar"
+        );
+        assert_eq!(
+            custom.pom_url().as_str(),
+            "https://ivy.example.com/org/example/lib/1.2/poms/lib.pom"
+        );
+        assert!(RepoLayout::parse("p2", None).is_err());
+    }
 }
@@ -0,0 +1,198 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::{Arc, Mutex};
+
+use crate::dependencies::coordinate::Coordinate;
+
+/// A version of an artifact requested somewhere in the dependency graph
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Candidate {
+    pub version: String,
+    /// Dependencies leading to the one requesting this version, empty if declared in the manifest
+    pub path: Vec<Coordinate>,
+    /// The version comes from the dependency management of this pom
+    pub managed_by: Option<Coordinate>,
+}
+
+/// Why a candidate is the nearest requested one
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Reason {
+    /// Only one version was requested
+    Only,
+    /// Declared in the manifest, transitive versions can't override it
+    Declared,
+    /// Shortest path from the module
+    Nearest,
+    /// Several versions at the same depth, the first one seen wins
+    FirstSeen,
+}
+
+impl Reason {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Reason::Only => "only candidate",
+            Reason::Declared => "declared in the manifest",
+            Reason::Nearest => "shortest path",
+            Reason::FirstSeen => "first seen at the shortest depth",
+        }
+    }
+}
+
+/// The nearest version requested for an artifact among its candidates, the one maven would pick.
+/// jcargo downloads every requested version and only puts the declared ones on the classpath.
+#[derive(Debug)]
+pub struct NearestRequested {
+    /// `group:artifact`
+    pub artifact: String,
+    pub candidates: Vec<Candidate>,
+    /// Index in `candidates`
+    pub nearest: usize,
+    pub reason: Reason,
+}
+
+impl NearestRequested {
+    pub fn version(&self) -> &str {
+        &self.candidates[self.nearest].version
+    }
+}
+
+/// Every version requested during a resolution, to explain which one maven rules would pick
+#[derive(Debug, Clone, Default)]
+pub struct ResolutionTrace {
+    candidates: Arc<Mutex<BTreeMap<String, Vec<Candidate>>>>,
+}
+
+impl ResolutionTrace {
+    pub fn record(
+        &self,
+        coordinate: &Coordinate,
+        path: &[Coordinate],
+        managed_by: Option<Coordinate>,
+    ) {
+        self.candidates
+            .lock()
+            .unwrap()
+            .entry(format!("{}:{}", coordinate.group, coordinate.artifact))
+            .or_default()
+            .push(Candidate {
+                version: coordinate.version.clone(),
+                path: path.to_vec(),
+                managed_by,
+            });
+    }
+
+    /// The nearest requested version per artifact : declared in the manifest, else the shortest
+    /// path, else first seen
+    pub fn nearest_requested(&self) -> Vec<NearestRequested> {
+        self.candidates
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(artifact, candidates)| {
+                let depth = candidates.iter().map(|it| it.path.len()).min().unwrap_or(0);
+                let nearest = candidates
+                    .iter()
+                    .position(|it| it.path.len() == depth)
+                    .unwrap_or(0);
+                let version = &candidates[nearest].version;
+                let reason = if candidates.iter().all(|it| &it.version == version) {
+                    Reason::Only
+                } else if depth == 0 {
+                    Reason::Declared
+                } else if candidates
+                    .iter()
+                    .any(|it| it.path.len() == depth && &it.version != version)
+                {
+                    Reason::FirstSeen
+                } else {
+                    Reason::Nearest
+                };
+                NearestRequested {
+                    artifact: artifact.clone(),
+                    candidates: candidates.clone(),
+                    nearest,
+                    reason,
+                }
+            })
+            .collect()
+    }
+
+    /// Human readable trace of the candidates and the nearest requested version of each artifact
+    pub fn explain(&self) -> String {
+        let mut out = String::new();
+        for nearest in self.nearest_requested() {
+            writeln!(out, "{}", nearest.artifact).unwrap();
+            for candidate in &nearest.candidates {
+                write!(out, "  {} ", candidate.version).unwrap();
+                if candidate.path.is_empty() {
+                    write!(out, "declared in the manifest").unwrap();
+                } else {
+                    let path: Vec<_> = candidate.path.iter().map(|it| it.to_string()).collect();
+                    write!(out, "via {}", path.join(" > ")).unwrap();
+                }
+                if let Some(manager) = &candidate.managed_by {
+                    write!(out, " (managed by {})", manager).unwrap();
+                }
+                writeln!(out).unwrap();
+            }
+            writeln!(
+                out,
+                "  nearest requested {} ({})",
+                nearest.version(),
+                nearest.reason.as_str()
+            )
+            .unwrap();
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::dependencies::coordinate::Coordinate;
+    use crate::dependencies::resolution::{Reason, ResolutionTrace};
+
+    #[test]
+    fn test_explain() {
+        let app = Coordinate::new("marais", "app", "1.0");
+        let http = Coordinate::new("marais", "http", "1.0");
+        let json = Coordinate::new("marais", "json", "1.0");
+        let trace = ResolutionTrace::default();
+        // app > http > json > log:1.2, app > log:1.1 (managed), app > json > log:1.0
+        trace.record(&app, &[], None);
+        trace.record(
+            &Coordinate::new("marais", "log", "1.2"),
+            &[app.clone(), http.clone(), json.clone()],
+            None,
+        );
+        trace.record(
+            &Coordinate::new("marais", "log", "1.1"),
+            std::slice::from_ref(&app),
+            Some(app.clone()),
+        );
+        trace.record(
+            &Coordinate::new("marais", "log", "1.0"),
+            &[app.clone(), json],
+            None,
+        );
+
+        let nearest = trace.nearest_requested();
+        let log = nearest
+            .iter()
+            .find(|it| it.artifact == "marais:log")
+            .unwrap();
+        assert_eq!(log.version(), "1.1");
+        assert_eq!(log.reason, Reason::Nearest);
+
+        let explained = trace.explain();
+        assert!(explained.contains(
+            "marais:app\n  1.0 declared in the manifest\n  nearest requested 1.0 (only candidate)\n"
+        ));
+        assert!(
+            explained.contains("  1.2 via marais:app:1.0 > marais:http:1.0 > marais:json:1.0\n")
+        );
+        assert!(explained.contains("  1.1 via marais:app:1.0 (managed by marais:app:1.0)\n"));
+        assert!(explained.contains("  1.0 via marais:app:1.0 > marais:json:1.0\n"));
+        assert!(explained.contains("  nearest requested 1.1 (shortest path)\n"));
+    }
+}
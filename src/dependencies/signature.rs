@@ -0,0 +1,196 @@
+use std::fmt::{Display, Formatter};
+use std::path::Path;
+
+use anyhow::Result;
+use pgp::{Deserializable, SignedPublicKey, StandaloneSignature};
+use reqwest::{Client, StatusCode};
+use tokio::fs;
+
+use crate::dependencies::MavenRepoDependency;
+use crate::io::save_to_file;
+
+/// Trusted public keys used to verify artifact signatures
+#[derive(Debug)]
+pub struct Keyring {
+    keys: Vec<SignedPublicKey>,
+}
+
+#[derive(Debug)]
+pub enum SignatureError {
+    /// The artifact has no signature but one is required
+    Missing(String),
+    /// The signature doesn't match the artifact or isn't from a trusted key
+    Invalid(String),
+}
+
+impl Display for SignatureError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SignatureError::Missing(artifact) => {
+                write!(
+                    f,
+                    "'{}' is not signed but signatures are required",
+                    artifact
+                )
+            }
+            SignatureError::Invalid(artifact) => write!(
+                f,
+                "'{}' has an invalid signature or isn't signed by a trusted key",
+                artifact
+            ),
+        }
+    }
+}
+
+impl std::error::Error for SignatureError {}
+
+impl Keyring {
+    /// Load all the keys from an armored keyring file
+    pub async fn load(path: &Path) -> Result<Self> {
+        Self::parse(&fs::read_to_string(path).await?)
+    }
+
+    pub fn parse(armored: &str) -> Result<Self> {
+        let (keys, _) = SignedPublicKey::from_string_many(armored)?;
+        Ok(Self {
+            keys: keys.collect::<std::result::Result<_, _>>()?,
+        })
+    }
+
+    /// Check that `data` is signed by one of the keys (or subkeys) of this keyring
+    pub fn verify(&self, data: &[u8], armored_signature: &str) -> bool {
+        let signature = match StandaloneSignature::from_string(armored_signature) {
+            Ok((signature, _)) => signature,
+            Err(_) => return false,
+        };
+        self.keys.iter().any(|key| {
+            signature.verify(key, data).is_ok()
+                || key
+                    .public_subkeys
+                    .iter()
+                    .any(|sub| signature.verify(sub, data).is_ok())
+        })
+    }
+}
+
+/// Verify the jar of a dependency against its detached signature (`.jar.asc`),
+/// downloading the signature next to the jar if it isn't already there.
+pub async fn verify_jar(
+    client: &Client,
+    keyring: &Keyring,
+    dep: &MavenRepoDependency,
+    jar_file: &Path,
+) -> Result<()> {
+    let notation = dep.dependency_notation();
+    let sig_file = jar_file.with_file_name(format!("{}.asc", dep.jar_name()));
+    let signature = if sig_file.exists() {
+        fs::read_to_string(&sig_file).await?
+    } else {
+        let res = client.get(dep.jar_signature_url()).send().await?;
+        if res.status() == StatusCode::NOT_FOUND {
+            return Err(SignatureError::Missing(notation).into());
+        }
+        let text = res.error_for_status()?.text().await?;
+        save_to_file(&text, &sig_file).await?;
+        text
+    };
+    if keyring.verify(&fs::read(jar_file).await?, &signature) {
+        Ok(())
+    } else {
+        Err(SignatureError::Invalid(notation).into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use url::Url;
+
+    use crate::dependencies::coordinate::Coordinate;
+    use crate::dependencies::signature::{verify_jar, Keyring, SignatureError};
+    use crate::dependencies::{MavenRepo, MavenRepoDependency, RepoLayout};
+    use crate::io::http_client;
+    use crate::test_utils::{response, temp_dir, MockServer};
+
+    const KEYRING: &str = include_str!("fixtures/keyring.asc");
+    const JAR: &[u8] = include_bytes!("fixtures/signed.jar");
+    const SIGNATURE: &str = include_str!("fixtures/signed.jar.asc");
+
+    #[test]
+    fn test_verify_valid() {
+        let keyring = Keyring::parse(KEYRING).unwrap();
+        assert!(keyring.verify(JAR, SIGNATURE));
+    }
+
+    #[test]
+    fn test_verify_tampered() {
+        let keyring = Keyring::parse(KEYRING).unwrap();
+        let mut tampered = JAR.to_vec();
+        tampered[10] ^= 0xff;
+        assert!(!keyring.verify(&tampered, SIGNATURE));
+    }
+
+    fn dependency(url: Url) -> MavenRepoDependency {
+        MavenRepoDependency {
+            coordinate: Coordinate::new(
+                "marais".to_string(),
+                "signed".to_string(),
+                "1.0".to_string(),
+            ),
+            repo: Arc::new(MavenRepo {
+                name: "mock".to_string(),
+                url,
+                layout: RepoLayout::Maven,
+            }),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_verify_jar_downloads_signature() {
+        let server = MockServer::start(|req| {
+            if req.path == "/marais/signed/1.0/signed-1.0.jar.asc" {
+                response("200 OK", SIGNATURE.as_bytes())
+            } else {
+                response("404 Not Found", b"")
+            }
+        })
+        .await;
+        let dir = temp_dir("verify-jar");
+        let jar = dir.join("signed-1.0.jar");
+        std::fs::write(&jar, JAR).unwrap();
+        let keyring = Keyring::parse(KEYRING).unwrap();
+
+        verify_jar(
+            &http_client(),
+            &keyring,
+            &dependency(server.url.clone()),
+            &jar,
+        )
+        .await
+        .unwrap();
+        assert!(dir.join("signed-1.0.jar.asc").exists());
+    }
+
+    #[tokio::test]
+    async fn test_verify_jar_missing_signature() {
+        let server = MockServer::start(|_| response("404 Not Found", b"")).await;
+        let dir = temp_dir("verify-jar-missing");
+        let jar = dir.join("signed-1.0.jar");
+        std::fs::write(&jar, JAR).unwrap();
+        let keyring = Keyring::parse(KEYRING).unwrap();
+
+        let err = verify_jar(
+            &http_client(),
+            &keyring,
+            &dependency(server.url.clone()),
+            &jar,
+        )
+        .await
+        .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<SignatureError>(),
+            Some(SignatureError::Missing(_))
+        ));
+    }
+}
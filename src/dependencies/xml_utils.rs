@@ -1,5 +1,7 @@
+use std::borrow::Cow;
 use std::fmt::{Debug, Display, Formatter};
 
+use lazy_regex::regex;
 use serde::{Deserialize, Serialize};
 
 #[derive(Clone, PartialEq, Default, Deserialize, Serialize)]
@@ -45,3 +47,19 @@ impl<E: Debug> Debug for Elem<E> {
         Debug::fmt(&self.value, f)
     }
 }
+
+/// Drop comments and processing instructions, and turn CDATA sections into escaped text,
+/// the deserializer only understands elements and text
+pub fn normalize_xml(text: &str) -> Cow<'_, str> {
+    regex!(r"(?s)<!\[CDATA\[(?P<cdata>.*?)\]\]>|<!--.*?-->|<\?.*?\?>").replace_all(
+        text,
+        |caps: &regex::Captures| match caps.name("cdata") {
+            Some(cdata) => cdata
+                .as_str()
+                .replace('&', "&amp;")
+                .replace('<', "&lt;")
+                .replace('>', "&gt;"),
+            None => String::new(),
+        },
+    )
+}
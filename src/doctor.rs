@@ -0,0 +1,243 @@
+use std::fmt::{Display, Formatter};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::backend::{Jdk, KotlinCompilationBackend};
+use crate::io::http_client_with;
+use crate::tasks::jvm_sources;
+use crate::toolchains::major_version;
+use crate::{logging, Env, Module};
+
+/// How long a repository has to answer before it is reported unreachable
+const REPO_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// One line of the `doctor` checklist
+#[derive(Debug)]
+pub struct Check {
+    pub name: String,
+    pub ok: bool,
+    pub detail: String,
+    /// How to fix it, only for failed checks
+    pub hint: Option<String>,
+}
+
+impl Check {
+    fn ok(name: impl Into<String>, detail: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            ok: true,
+            detail: detail.into(),
+            hint: None,
+        }
+    }
+
+    fn failed(name: impl Into<String>, detail: impl Into<String>, hint: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            ok: false,
+            detail: detail.into(),
+            hint: Some(hint.into()),
+        }
+    }
+}
+
+/// Result of `jcargo doctor`, in the order the checks ran
+#[derive(Debug, Default)]
+pub struct Diagnosis {
+    pub checks: Vec<Check>,
+}
+
+impl Diagnosis {
+    pub fn failures(&self) -> usize {
+        self.checks.iter().filter(|it| !it.ok).count()
+    }
+}
+
+impl Display for Diagnosis {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        for check in &self.checks {
+            let (mark, color) = if check.ok { ("ok", 32) } else { ("FAIL", 31) };
+            let mark = if logging::is_colored() {
+                format!("\x1b[1;{}m{:<4}\x1b[0m", color, mark)
+            } else {
+                format!("{:<4}", mark)
+            };
+            writeln!(f, "   {} {}: {}", mark, check.name, check.detail)?;
+            if let Some(hint) = &check.hint {
+                writeln!(f, "        {}", hint)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Check the toolchain, the repositories and the dependency caches of the `workspace` modules,
+/// without modifying anything. A manifest that can't be loaded is a failed check.
+/// `java_home` is the value of `JAVA_HOME`.
+pub async fn diagnose(
+    env: &Env,
+    java_home: Option<&Path>,
+    workspace: Result<&[Module], &anyhow::Error>,
+) -> Diagnosis {
+    let mut diagnosis = Diagnosis::default();
+    let checks = &mut diagnosis.checks;
+    let modules = workspace.unwrap_or_default();
+
+    if let Some(home) = java_home {
+        checks.push(match Jdk::at(home) {
+            Ok(_) => Check::ok("JAVA_HOME", home.display().to_string()),
+            Err(e) => Check::failed(
+                "JAVA_HOME",
+                e.to_string(),
+                "Point JAVA_HOME at a JDK installation or unset it",
+            ),
+        });
+    }
+
+    let missing = "Install a JDK and put its bin directory in PATH, or use --java-home";
+    let javac = env.comp_backend.program();
+    checks.push(match (&javac, env.comp_backend.version().await) {
+        (Some(path), Some(version)) => {
+            match modules
+                .iter()
+                .find(|it| major_version(&it.java.source) > major_version(&version))
+            {
+                Some(module) => Check::failed(
+                    "javac",
+                    format!(
+                        "{} can't compile '{}' for java {}",
+                        version, module.artifact, module.java.source
+                    ),
+                    format!("Install a JDK {} or newer", module.java.source),
+                ),
+                None => Check::ok("javac", format!("{} ({})", version, path.display())),
+            }
+        }
+        (Some(path), None) => Check::failed(
+            "javac",
+            format!("{} doesn't report its version", path.display()),
+            missing,
+        ),
+        (None, _) => Check::failed("javac", "not found", missing),
+    });
+    for (name, program) in [
+        ("java", env.runtime.program()),
+        ("javadoc", env.doc_backend.program()),
+        ("jar", env.package_backend.program()),
+    ] {
+        checks.push(match program {
+            Some(path) => Check::ok(name, path.display().to_string()),
+            None => Check::failed(name, "not found", missing),
+        });
+    }
+
+    // Only needed to build kotlin sources
+    if modules
+        .iter()
+        .any(|it| !jvm_sources(it, &[".kt"]).is_empty())
+    {
+        checks.push(match KotlinCompilationBackend::Kotlinc.program() {
+            Some(path) => Check::ok("kotlinc", path.display().to_string()),
+            None => Check::failed(
+                "kotlinc",
+                "not found, but there are kotlin sources",
+                "Set KOTLINC_HOME to where the kotlin compiler is installed",
+            ),
+        });
+    }
+
+    if env.offline {
+        checks.push(Check::ok("repositories", "not checked offline"));
+    } else {
+        let client = http_client_with(&env.downloads);
+        for repo in &env.repos {
+            let name = format!("repository {}", repo.name);
+            let res = client
+                .head(repo.url.clone())
+                .timeout(REPO_TIMEOUT)
+                .send()
+                .await;
+            checks.push(match res {
+                // Any answer will do, repository roots often aren't browsable
+                Ok(_) => Check::ok(name, repo.url.to_string()),
+                Err(e) => Check::failed(
+                    name,
+                    format!("{} is unreachable ({})", repo.url, e),
+                    "Check the network and proxy, or build --offline from the cache",
+                ),
+            });
+        }
+    }
+
+    if let Err(e) = workspace {
+        checks.push(Check::failed(
+            "manifest",
+            format!("{:#}", e),
+            "Fix the manifest, the project checks need it",
+        ));
+    }
+    for module in modules {
+        let libs = module.dir.join("libs");
+        let name = format!("cache of '{}'", module.artifact);
+        checks.push(match writable_dir(&libs) {
+            Some(dir) => Check::ok(name, dir.display().to_string()),
+            None => Check::failed(
+                name,
+                format!("{} isn't writable", libs.display()),
+                "Fix the permissions, dependencies are downloaded there",
+            ),
+        });
+    }
+
+    diagnosis
+}
+
+/// `dir`, or its first existing parent as it would be created, if it isn't read-only
+fn writable_dir(dir: &Path) -> Option<PathBuf> {
+    let existing = dir.ancestors().find(|it| it.exists())?;
+    let metadata = existing.metadata().ok()?;
+    (metadata.is_dir() && !metadata.permissions().readonly()).then(|| existing.to_path_buf())
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::anyhow;
+
+    use crate::doctor::diagnose;
+    use crate::test_utils::temp_dir;
+    use crate::Env;
+
+    #[tokio::test]
+    async fn test_bad_java_home() {
+        let mut env = Env::new(false);
+        env.repos.clear();
+        let home = temp_dir("doctor").join("missing-jdk");
+
+        let diagnosis = diagnose(&env, Some(&home), Ok(&[])).await;
+
+        let check = &diagnosis.checks[0];
+        assert_eq!(check.name, "JAVA_HOME");
+        assert!(!check.ok);
+        assert!(check.detail.contains("is not a JDK home"));
+        assert!(diagnosis.failures() >= 1);
+        assert!(diagnosis.to_string().starts_with("   FAIL JAVA_HOME: "));
+    }
+
+    #[tokio::test]
+    async fn test_invalid_manifest() {
+        let mut env = Env::new(false);
+        env.repos.clear();
+        let error = anyhow!("unknown key `gruop`");
+
+        let diagnosis = diagnose(&env, None, Err(&error)).await;
+
+        let check = diagnosis
+            .checks
+            .iter()
+            .find(|it| it.name == "manifest")
+            .unwrap();
+        assert!(!check.ok);
+        assert_eq!(check.detail, "unknown key `gruop`");
+        assert!(diagnosis.failures() >= 1);
+    }
+}
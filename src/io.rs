@@ -1,39 +1,221 @@
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
 use anyhow::{anyhow, Result};
-use reqwest::Client;
+use reqwest::header::{CONTENT_RANGE, RANGE};
+use reqwest::redirect::Policy;
+use reqwest::{Client, StatusCode};
 use tokio::fs;
 use tokio::io::{AsyncWriteExt, BufWriter};
+use tracing::warn;
 use url::Url;
 
-pub async fn download_file(client: &Client, url: Url, path: impl AsRef<Path>) -> Result<()> {
-    let mut res = client.get(url).send().await?;
+use crate::dependencies::error::ResolutionError;
 
-    if res.status().is_success() {
-        let file = fs::OpenOptions::new()
-            .write(true)
-            .create(true)
-            .truncate(true)
-            .open(&path)
-            .await?;
-        let mut buf_file = BufWriter::new(file);
-        while let Some(chunk) = res.chunk().await? {
-            buf_file.write(&chunk).await?;
+/// Maximum number of redirects followed, repositories often redirect to a CDN
+const MAX_REDIRECTS: usize = 10;
+
+/// Create the http client used for all repository requests
+pub fn http_client() -> Client {
+    http_client_with(&DownloadOptions::default())
+}
+
+/// Same as [http_client] with a timeout for each request
+pub fn http_client_with(options: &DownloadOptions) -> Client {
+    let mut builder = Client::builder()
+        .redirect(Policy::limited(MAX_REDIRECTS))
+        .user_agent(concat!("jcargo/", env!("CARGO_PKG_VERSION")));
+    if let Some(timeout) = options.timeout {
+        builder = builder.timeout(timeout);
+    }
+    builder.build().unwrap()
+}
+
+/// Default number of tries before giving up on a download
+pub const DOWNLOAD_ATTEMPTS: u32 = 3;
+
+/// How hard requests try, set from the command line
+#[derive(Debug, Copy, Clone)]
+pub struct DownloadOptions {
+    /// Tries before giving up on a download, at least 1
+    pub attempts: u32,
+    /// Timeout of each request, none by default
+    pub timeout: Option<Duration>,
+}
+
+impl Default for DownloadOptions {
+    fn default() -> Self {
+        Self {
+            attempts: DOWNLOAD_ATTEMPTS,
+            timeout: None,
+        }
+    }
+}
+/// Wait between tries, multiplied by the attempt number
+const RETRY_DELAY: Duration = Duration::from_millis(200);
+
+/// Download a file to disk.
+/// Data is written to a `.part` file which is only renamed to its final name once the whole
+/// content has been received, so an interrupted download never looks complete.
+/// A failed download is tried up to `attempts` times, resuming from the bytes already in the
+/// `.part` file when the server supports range requests.
+pub async fn download_file(
+    client: &Client,
+    url: Url,
+    path: impl AsRef<Path>,
+    attempts: u32,
+) -> Result<(), ResolutionError> {
+    let path = path.as_ref();
+    let part = part_path(path);
+    let mut attempt = 1;
+    loop {
+        match download_attempt(client, &url, &part).await {
+            Ok(()) => break,
+            Err(Attempt::Failed(e)) => return Err(e),
+            Err(Attempt::Interrupted(e)) if attempt >= attempts => {
+                return Err(ResolutionError::network(&url, e))
+            }
+            Err(Attempt::Interrupted(e)) => {
+                warn!("Retrying {} ({:#})", url, e);
+                tokio::time::sleep(RETRY_DELAY * attempt).await;
+                attempt += 1;
+            }
         }
-        buf_file.flush().await?;
-        Ok(())
+    }
+    fs::rename(&part, path).await?;
+    Ok(())
+}
+
+/// Why a download attempt didn't complete
+enum Attempt {
+    /// Retrying won't help
+    Failed(ResolutionError),
+    /// Partial data may have been received, worth retrying
+    Interrupted(anyhow::Error),
+}
+
+impl<E: Into<anyhow::Error>> From<E> for Attempt {
+    fn from(e: E) -> Self {
+        Attempt::Interrupted(e.into())
+    }
+}
+
+async fn download_attempt(client: &Client, url: &Url, part: &Path) -> Result<(), Attempt> {
+    let existing = fs::metadata(part).await.map_or(0, |it| it.len());
+    let mut req = client.get(url.clone());
+    if existing > 0 {
+        req = req.header(RANGE, format!("bytes={}-", existing));
+    }
+    let mut res = req.send().await?;
+
+    // The server may ignore the range and send the whole file
+    let resumed = res.status() == StatusCode::PARTIAL_CONTENT
+        && res
+            .headers()
+            .get(CONTENT_RANGE)
+            .and_then(|it| it.to_str().ok())
+            .map_or(false, |it| it.starts_with(&format!("bytes {}-", existing)));
+    if !resumed && !res.status().is_success() {
+        if res.status() == StatusCode::RANGE_NOT_SATISFIABLE {
+            // Whatever we have is unusable
+            fs::remove_file(part).await?;
+            return Err(Attempt::Interrupted(anyhow!("Can't resume {}", url)));
+        }
+        return Err(Attempt::Failed(ResolutionError::from_status(
+            url,
+            res.status(),
+        )));
+    }
+    let (file, already) = if resumed {
+        (
+            fs::OpenOptions::new().append(true).open(part).await?,
+            existing,
+        )
     } else {
-        Err(anyhow!("Url is probably incorrect"))
+        (
+            fs::OpenOptions::new()
+                .write(true)
+                .create(true)
+                .truncate(true)
+                .open(part)
+                .await?,
+            0,
+        )
+    };
+    let expected = res.content_length().map(|it| it + already);
+    let mut buf_file = BufWriter::new(file);
+    let mut written = already;
+    // Keep what has been received so the next attempt can resume from there
+    let received = loop {
+        match res.chunk().await {
+            Ok(Some(chunk)) => {
+                buf_file.write_all(&chunk).await?;
+                written += chunk.len() as u64;
+            }
+            Ok(None) => break Ok(()),
+            Err(e) => break Err(e),
+        }
+    };
+    buf_file.flush().await?;
+    received?;
+    if let Some(expected) = expected {
+        if written != expected {
+            return Err(Attempt::Interrupted(anyhow!(
+                "Incomplete download of {} : received {} bytes out of {}",
+                url,
+                written,
+                expected
+            )));
+        }
     }
+    Ok(())
 }
 
-pub async fn download_memory(client: &Client, url: Url) -> Result<String> {
-    let res = client.get(url).send().await?;
+/// Upload data with a PUT request, as maven repositories expect
+pub async fn upload(
+    client: &Client,
+    url: Url,
+    data: Vec<u8>,
+    credentials: Option<&(String, String)>,
+) -> Result<()> {
+    let mut req = client.put(url.clone()).body(data);
+    if let Some((username, password)) = credentials {
+        req = req.basic_auth(username, Some(password));
+    }
+    let res = req.send().await?;
+    match res.status() {
+        status if status.is_success() => Ok(()),
+        StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => Err(anyhow!(
+            "Not allowed to upload {} ({}), check the publish credentials",
+            url,
+            res.status()
+        )),
+        // Release repositories usually refuse redeploys
+        StatusCode::CONFLICT => Err(anyhow!("{} already exists in the repository", url)),
+        status => Err(anyhow!("Upload of {} failed : {}", url, status)),
+    }
+}
+
+/// Temporary file used while downloading to `path`
+fn part_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".part");
+    path.with_file_name(name)
+}
+
+pub async fn download_memory(client: &Client, url: Url) -> Result<String, ResolutionError> {
+    let res = client
+        .get(url.clone())
+        .send()
+        .await
+        .map_err(|e| ResolutionError::network(&url, e))?;
 
     if res.status().is_success() {
-        Ok(res.text().await?)
+        res.text()
+            .await
+            .map_err(|e| ResolutionError::network(&url, e))
     } else {
-        Err(anyhow!("Url is probably incorrect"))
+        Err(ResolutionError::from_status(&url, res.status()))
     }
 }
 
@@ -41,7 +223,7 @@ pub async fn download_memory_and_file(
     client: &Client,
     url: Url,
     path: impl AsRef<Path>,
-) -> Result<String> {
+) -> Result<String, ResolutionError> {
     let mut file = fs::OpenOptions::new()
         .write(true)
         .create(true)
@@ -65,3 +247,153 @@ pub async fn save_to_file(content: &str, path: impl AsRef<Path>) -> Result<()> {
     file.flush().await?;
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::dependencies::error::ResolutionError;
+    use crate::io::{download_file, download_memory, http_client, DOWNLOAD_ATTEMPTS};
+    use crate::test_utils::{response, temp_dir, MockServer};
+
+    #[tokio::test]
+    async fn test_download_truncated() {
+        // Advertise more bytes than we actually send
+        let server = MockServer::start(|_| {
+            b"HTTP/1.1 200 OK\r\nContent-Length: 100\r\nConnection: close\r\n\r\nshort".to_vec()
+        })
+        .await;
+        let dir = temp_dir("download-truncated");
+        let file = dir.join("lib.jar");
+
+        let res = download_file(
+            &http_client(),
+            server.url.join("lib.jar").unwrap(),
+            &file,
+            DOWNLOAD_ATTEMPTS,
+        )
+        .await;
+
+        assert!(res.is_err());
+        assert!(!file.exists());
+        assert_eq!(server.requests().len(), DOWNLOAD_ATTEMPTS as usize);
+    }
+
+    #[tokio::test]
+    async fn test_download_status_errors() {
+        let server = MockServer::start(|req| match req.path.as_str() {
+            "/private/lib.jar" => response("401 Unauthorized", b""),
+            _ => response("404 Not Found", b""),
+        })
+        .await;
+        let file = temp_dir("download-status").join("lib.jar");
+        let client = http_client();
+
+        let missing = server.url.join("lib.jar").unwrap();
+        let err = download_file(&client, missing.clone(), &file, DOWNLOAD_ATTEMPTS)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, ResolutionError::NotFound { ref url } if *url == missing));
+        // Not retried
+        assert_eq!(server.requests().len(), 1);
+        assert!(ResolutionError::is_not_found(
+            &anyhow::Error::from(err).context("Resolving")
+        ));
+
+        let private = server.url.join("private/lib.jar").unwrap();
+        let err = download_memory(&client, private).await.unwrap_err();
+        assert!(matches!(err, ResolutionError::Auth { status, .. } if status == 401));
+        assert!(!file.exists());
+    }
+
+    #[tokio::test]
+    async fn test_download_attempts() {
+        let server = MockServer::start(|_| {
+            b"HTTP/1.1 200 OK\r\nContent-Length: 100\r\nConnection: close\r\n\r\nshort".to_vec()
+        })
+        .await;
+        let dir = temp_dir("download-attempts");
+        let file = dir.join("lib.jar");
+
+        let res = download_file(
+            &http_client(),
+            server.url.join("lib.jar").unwrap(),
+            &file,
+            1,
+        )
+        .await;
+
+        assert!(res.is_err());
+        assert_eq!(server.requests().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_download_redirect() {
+        let server = MockServer::start(|req| {
+            if req.path == "/moved.jar" {
+                b"HTTP/1.1 302 Found\r\nLocation: /cdn/lib.jar\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_vec()
+            } else {
+                response("200 OK", b"jar content")
+            }
+        })
+        .await;
+        let dir = temp_dir("download-redirect");
+        let file = dir.join("lib.jar");
+
+        download_file(
+            &http_client(),
+            server.url.join("moved.jar").unwrap(),
+            &file,
+            DOWNLOAD_ATTEMPTS,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(std::fs::read(&file).unwrap(), b"jar content");
+        assert_eq!(server.requests()[1].path, "/cdn/lib.jar");
+    }
+
+    #[tokio::test]
+    async fn test_download_resume() {
+        let server = MockServer::start(|req| match req.headers.get("range") {
+            Some(range) if range == "bytes=4-" => b"HTTP/1.1 206 Partial Content\r\nContent-Range: bytes 4-9/10\r\nContent-Length: 6\r\nConnection: close\r\n\r\n456789".to_vec(),
+            // Connection drops after the first 4 bytes
+            _ => b"HTTP/1.1 200 OK\r\nContent-Length: 10\r\nConnection: close\r\n\r\n0123".to_vec(),
+        })
+        .await;
+        let dir = temp_dir("download-resume");
+        let file = dir.join("lib.jar");
+
+        download_file(
+            &http_client(),
+            server.url.join("lib.jar").unwrap(),
+            &file,
+            DOWNLOAD_ATTEMPTS,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(std::fs::read(&file).unwrap(), b"0123456789");
+        let requests = server.requests();
+        assert_eq!(requests.len(), 2);
+        assert_eq!(requests[1].headers["range"], "bytes=4-");
+    }
+
+    #[tokio::test]
+    async fn test_download_resume_unsupported() {
+        // The server ignores ranges and always sends everything
+        let server = MockServer::start(|_| response("200 OK", b"0123456789")).await;
+        let dir = temp_dir("download-resume-unsupported");
+        let file = dir.join("lib.jar");
+        std::fs::write(dir.join("lib.jar.part"), b"0123").unwrap();
+
+        download_file(
+            &http_client(),
+            server.url.join("lib.jar").unwrap(),
+            &file,
+            DOWNLOAD_ATTEMPTS,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(std::fs::read(&file).unwrap(), b"0123456789");
+    }
+}
@@ -0,0 +1,376 @@
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::iter;
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Context, Result};
+use walkdir::WalkDir;
+use zip::write::FileOptions;
+use zip::{ZipArchive, ZipWriter};
+
+/// Location of the manifest inside a jar
+pub const MANIFEST_PATH: &str = "META-INF/MANIFEST.MF";
+
+/// Main attributes of a jar manifest, in declaration order
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JarManifest {
+    pub attributes: Vec<(String, String)>,
+}
+
+impl JarManifest {
+    /// Parse the main section of a manifest, continuation lines start with a single space
+    pub fn parse(text: &str) -> Result<Self> {
+        let mut attributes: Vec<(String, String)> = Vec::new();
+        for (i, line) in text.lines().enumerate() {
+            // Per-entry sections start after the first blank line
+            if line.is_empty() {
+                break;
+            }
+            if let Some(rest) = line.strip_prefix(' ') {
+                let (_, value) = attributes
+                    .last_mut()
+                    .ok_or_else(|| anyhow!("Line {} : continuation without attribute", i + 1))?;
+                value.push_str(rest);
+                continue;
+            }
+            let (key, value) = line
+                .split_once(": ")
+                .ok_or_else(|| anyhow!("Line {} : expected 'Name: value'", i + 1))?;
+            if key.is_empty()
+                || !key
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+            {
+                return Err(anyhow!("Line {} : invalid attribute name '{}'", i + 1, key));
+            }
+            attributes.push((key.to_string(), value.to_string()));
+        }
+        let manifest = Self { attributes };
+        if manifest.get("Manifest-Version").is_none() {
+            return Err(anyhow!("Missing Manifest-Version"));
+        }
+        Ok(manifest)
+    }
+
+    /// Attribute names are case insensitive
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.attributes
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+
+    pub fn main_class(&self) -> Option<&str> {
+        self.get("Main-Class")
+    }
+
+    /// Entries of the space separated Class-Path attribute
+    pub fn class_path(&self) -> Vec<&str> {
+        self.get("Class-Path")
+            .map(|it| it.split_whitespace().collect())
+            .unwrap_or_default()
+    }
+
+    /// The manifest text, lines are wrapped at 72 bytes as the JDK requires
+    pub fn render(&self) -> String {
+        let mut text = String::new();
+        for (key, value) in &self.attributes {
+            let line = format!("{}: {}", key, value);
+            let mut rest = line.as_str();
+            let mut width = 72;
+            while rest.len() > width {
+                let mut split = width;
+                while !rest.is_char_boundary(split) {
+                    split -= 1;
+                }
+                text.push_str(&rest[..split]);
+                text.push_str("\r\n ");
+                rest = &rest[split..];
+                width = 71;
+            }
+            text.push_str(rest);
+            text.push_str("\r\n");
+        }
+        text
+    }
+}
+
+/// Contents of a jar file
+#[derive(Debug)]
+pub struct JarContents {
+    pub entries: Vec<String>,
+    /// None if the jar has no manifest
+    pub manifest: Option<JarManifest>,
+}
+
+impl JarContents {
+    pub fn read(path: &Path) -> Result<Self> {
+        let mut archive = ZipArchive::new(File::open(path)?)
+            .with_context(|| format!("{} is not a valid jar", path.display()))?;
+        // In archive order, file_names() isn't
+        let entries = (0..archive.len())
+            .map(|i| Ok(archive.by_index_raw(i)?.name().to_string()))
+            .collect::<Result<_>>()?;
+        let manifest = match archive.by_name(MANIFEST_PATH) {
+            Ok(mut file) => {
+                let mut text = String::new();
+                file.read_to_string(&mut text)?;
+                Some(JarManifest::parse(&text).context("Malformed manifest")?)
+            }
+            Err(zip::result::ZipError::FileNotFound) => None,
+            Err(e) => return Err(e.into()),
+        };
+        Ok(Self { entries, manifest })
+    }
+}
+
+/// A class found in several jars, the first one on the classpath is loaded
+#[derive(Debug, PartialEq, Eq)]
+pub struct DuplicateClass {
+    /// Entry name, e.g. `marais/Main.class`
+    pub class: String,
+    /// In classpath order
+    pub jars: Vec<PathBuf>,
+}
+
+/// Classes present in more than one of `jars`, sorted by name. `module-info.class` and the
+/// `META-INF` entries (e.g. multi-release versions) are expected in many jars and ignored.
+pub fn duplicate_classes(jars: &[PathBuf]) -> Result<Vec<DuplicateClass>> {
+    let mut providers: BTreeMap<String, Vec<PathBuf>> = BTreeMap::new();
+    for jar in jars {
+        let contents =
+            JarContents::read(jar).with_context(|| format!("Can't read jar {}", jar.display()))?;
+        for entry in contents.entries {
+            if entry.ends_with(".class")
+                && !entry.starts_with("META-INF/")
+                && !entry.ends_with("module-info.class")
+            {
+                providers.entry(entry).or_default().push(jar.clone());
+            }
+        }
+    }
+    Ok(providers
+        .into_iter()
+        .filter(|(_, jars)| jars.len() > 1)
+        .map(|(class, jars)| DuplicateClass { class, jars })
+        .collect())
+}
+
+/// A package with classes in several jars, which the module system refuses
+#[derive(Debug, PartialEq, Eq)]
+pub struct SplitPackage {
+    /// e.g. `marais.shared`
+    pub package: String,
+    /// In classpath order
+    pub jars: Vec<PathBuf>,
+}
+
+/// Packages with classes in more than one of `jars`, sorted by name. Like [duplicate_classes],
+/// `module-info.class` and the `META-INF` entries are ignored, and so is the unnamed package.
+pub fn split_packages(jars: &[PathBuf]) -> Result<Vec<SplitPackage>> {
+    let mut providers: BTreeMap<String, Vec<PathBuf>> = BTreeMap::new();
+    for jar in jars {
+        let contents =
+            JarContents::read(jar).with_context(|| format!("Can't read jar {}", jar.display()))?;
+        let packages: BTreeSet<_> = contents
+            .entries
+            .iter()
+            .filter(|it| {
+                it.ends_with(".class")
+                    && !it.starts_with("META-INF/")
+                    && !it.ends_with("module-info.class")
+            })
+            .filter_map(|it| it.rsplit_once('/'))
+            .map(|(package, _)| package.replace('/', "."))
+            .collect();
+        for package in packages {
+            providers.entry(package).or_default().push(jar.clone());
+        }
+    }
+    Ok(providers
+        .into_iter()
+        .filter(|(_, jars)| jars.len() > 1)
+        .map(|(package, jars)| SplitPackage { package, jars })
+        .collect())
+}
+
+/// Write a jar with the classes in `classes` and the contents of every `bundled` entry
+/// (a jar or a classes directory). The manifests and signatures of bundled jars are dropped and the
+/// first entry with a given name wins.
+pub fn write_fat_jar(
+    out: &Path,
+    classes: &Path,
+    bundled: &[PathBuf],
+    main_class: Option<&str>,
+) -> Result<()> {
+    let mut zip = ZipWriter::new(File::create(out)?);
+    let mut seen = HashSet::new();
+
+    let mut manifest = "Manifest-Version: 1.0\r\nCreated-By: jcargo\r\n".to_string();
+    if let Some(main_class) = main_class {
+        manifest.push_str(&format!("Main-Class: {}\r\n", main_class));
+    }
+    zip.start_file(MANIFEST_PATH, FileOptions::default())?;
+    zip.write_all(manifest.as_bytes())?;
+    seen.insert(MANIFEST_PATH.to_string());
+
+    for entry in iter::once(classes).chain(bundled.iter().map(PathBuf::as_path)) {
+        if entry.is_dir() {
+            add_dir(&mut zip, &mut seen, entry)?;
+        } else {
+            let mut archive = ZipArchive::new(File::open(entry)?)
+                .with_context(|| format!("{} is not a valid jar", entry.display()))?;
+            for i in 0..archive.len() {
+                let file = archive.by_index_raw(i)?;
+                let name = file.name().to_string();
+                if is_jar_metadata(&name) || !seen.insert(name) {
+                    continue;
+                }
+                zip.raw_copy_file(file)?;
+            }
+        }
+    }
+    zip.finish()?;
+    Ok(())
+}
+
+fn add_dir(zip: &mut ZipWriter<File>, seen: &mut HashSet<String>, dir: &Path) -> Result<()> {
+    for entry in WalkDir::new(dir).sort_by_file_name() {
+        let entry = entry?;
+        if !entry.file_type().is_file() {
+            continue;
+        }
+        let name = entry
+            .path()
+            .strip_prefix(dir)?
+            .components()
+            .map(|it| it.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/");
+        if !seen.insert(name.clone()) {
+            continue;
+        }
+        zip.start_file(name, FileOptions::default())?;
+        io::copy(&mut File::open(entry.path())?, zip)?;
+    }
+    Ok(())
+}
+
+/// Manifest and signature files, only valid for the jar they come from
+fn is_jar_metadata(name: &str) -> bool {
+    name == MANIFEST_PATH
+        || name.strip_prefix("META-INF/").map_or(false, |it| {
+            !it.contains('/')
+                && [".SF", ".RSA", ".DSA", ".EC"]
+                    .iter()
+                    .any(|ext| it.ends_with(ext))
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+    use std::path::{Path, PathBuf};
+
+    use zip::write::FileOptions;
+    use zip::ZipWriter;
+
+    use crate::jar::{
+        duplicate_classes, split_packages, DuplicateClass, JarManifest, SplitPackage,
+    };
+    use crate::test_utils::temp_dir;
+
+    fn write_jar(path: &Path, entries: &[&str]) -> PathBuf {
+        let mut zip = ZipWriter::new(std::fs::File::create(path).unwrap());
+        for entry in entries {
+            zip.start_file(*entry, FileOptions::default()).unwrap();
+            zip.write_all(b"class").unwrap();
+        }
+        zip.finish().unwrap();
+        path.to_path_buf()
+    }
+
+    #[test]
+    fn test_duplicate_classes() {
+        let dir = temp_dir("duplicate-classes");
+        let jars = [
+            write_jar(
+                &dir.join("lib-1.0.jar"),
+                &[
+                    "module-info.class",
+                    "marais/Shared.class",
+                    "marais/Lib.class",
+                ],
+            ),
+            write_jar(
+                &dir.join("shaded-1.0.jar"),
+                &[
+                    "module-info.class",
+                    "META-INF/versions/11/marais/Shared.class",
+                    "marais/Shared.class",
+                ],
+            ),
+            write_jar(&dir.join("other-1.0.jar"), &["marais/Other.class"]),
+        ];
+        assert_eq!(
+            duplicate_classes(&jars).unwrap(),
+            vec![DuplicateClass {
+                class: "marais/Shared.class".to_string(),
+                jars: jars[..2].to_vec(),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_split_packages() {
+        let dir = temp_dir("split-packages");
+        let jars = [
+            write_jar(
+                &dir.join("api-1.0.jar"),
+                &["module-info.class", "marais/shared/Api.class", "Root.class"],
+            ),
+            write_jar(&dir.join("other-1.0.jar"), &["marais/other/Other.class"]),
+            write_jar(
+                &dir.join("impl-1.0.jar"),
+                &[
+                    "module-info.class",
+                    "marais/shared/Impl.class",
+                    "marais/shared/Util.class",
+                    "Root.class",
+                ],
+            ),
+        ];
+        let split = split_packages(&jars).unwrap();
+        assert_eq!(
+            split,
+            vec![SplitPackage {
+                package: "marais.shared".to_string(),
+                jars: vec![jars[0].clone(), jars[2].clone()],
+            }]
+        );
+        let names: Vec<_> = split[0]
+            .jars
+            .iter()
+            .map(|it| it.file_name().unwrap())
+            .collect();
+        assert_eq!(names, ["api-1.0.jar", "impl-1.0.jar"]);
+    }
+
+    #[test]
+    fn test_parse_manifest() {
+        let manifest = JarManifest::parse(
+            "Manifest-Version: 1.0\r\nMain-Class: marais.Main\r\nClass-Path: libs/a.jar lib\r\n s/b.jar\r\n\r\nName: marais/\r\nSealed: true\r\n",
+        )
+        .unwrap();
+        assert_eq!(manifest.main_class(), Some("marais.Main"));
+        assert_eq!(manifest.class_path(), vec!["libs/a.jar", "libs/b.jar"]);
+        assert_eq!(manifest.get("sealed"), None);
+    }
+
+    #[test]
+    fn test_malformed_manifest() {
+        assert!(JarManifest::parse("Main-Class: Main\n").is_err());
+        assert!(JarManifest::parse("Manifest-Version: 1.0\nMain-Class\n").is_err());
+    }
+}
@@ -1,23 +1,262 @@
-use regex::RegexBuilder;
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter};
 
-pub fn parse_javac_output() {
-    let regex = RegexBuilder::new(r"^(?P<file>.+):(?P<line>\d+): (warning|error): (?P<error>.+)$")
-        .multi_line(true)
-        .case_insensitive(true)
-        .build()
+use lazy_regex::regex;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+/// A diagnostic reported by javac
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub file: String,
+    pub line: u32,
+    pub severity: Severity,
+    /// Lint category, e.g. `deprecation` for `warning: [deprecation] ...`
+    pub category: Option<String>,
+    pub message: String,
+    /// The diagnostic with its source excerpt, as javac printed it
+    pub text: String,
+}
+
+/// Split javac output into diagnostics, the trailing counts and notes are dropped
+pub fn parse_javac_output(output: &str) -> Vec<Diagnostic> {
+    let mut parser = JavacOutputParser::default();
+    let mut diagnostics: Vec<_> = output.lines().filter_map(|it| parser.push(it)).collect();
+    diagnostics.extend(parser.finish());
+    diagnostics
+}
+
+/// Parses javac output line by line as it is printed
+#[derive(Debug, Default)]
+pub struct JavacOutputParser {
+    /// Still receiving its source excerpt
+    current: Option<Diagnostic>,
+}
+
+impl JavacOutputParser {
+    /// Feed the next line, returns the previous diagnostic once this line ends it
+    pub fn push(&mut self, line: &str) -> Option<Diagnostic> {
+        let header =
+            regex!(r"^(?P<file>.+):(?P<line>\d+): (?P<severity>warning|error): (?P<message>.+)$");
+        let category = regex!(r"^\[(?P<category>[\w-]+)\] ");
+        let trailer = regex!(r"^(\d+ (warnings?|errors?)|Note: .*)$");
+
+        if let Some(caps) = header.captures(line) {
+            let message = &caps["message"];
+            return self.current.replace(Diagnostic {
+                file: caps["file"].to_string(),
+                line: caps["line"].parse().unwrap_or(0),
+                severity: if &caps["severity"] == "error" {
+                    Severity::Error
+                } else {
+                    Severity::Warning
+                },
+                category: category
+                    .captures(message)
+                    .map(|it| it["category"].to_string()),
+                message: category.replace(message, "").into_owned(),
+                text: line.to_string(),
+            });
+        }
+        if trailer.is_match(line) {
+            return self.current.take();
+        }
+        if let Some(current) = &mut self.current {
+            current.text.push('\n');
+            current.text.push_str(line);
+        }
+        None
+    }
+
+    /// The last diagnostic, once the output ended
+    pub fn finish(self) -> Option<Diagnostic> {
+        self.current
+    }
+}
+
+/// A java release javac refused, its JDK doesn't have the symbols of that release in `ct.sym`
+/// or doesn't know it yet
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnsupportedRelease {
+    pub release: u32,
+    /// Oldest release the JDK accepts, when javac says it
+    pub oldest: Option<u32>,
+}
+
+/// Last JDK accepting each dropped release
+const LAST_JDK_WITH: [(u32, u32); 3] = [(5, 8), (6, 11), (7, 19)];
+
+impl UnsupportedRelease {
+    /// Releases before 8 were dropped by newer JDKs, later ones are too new for this JDK
+    fn too_old(&self) -> bool {
+        self.oldest.map_or(self.release < 8, |it| self.release < it)
+    }
+}
+
+impl Display for UnsupportedRelease {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        if !self.too_old() {
+            return write!(
+                f,
+                "The installed JDK doesn't know java {0}, use a JDK {0} or newer (--java-home)",
+                self.release
+            );
+        }
+        write!(
+            f,
+            "The installed JDK can't compile for java {}, it has no symbols for that release",
+            self.release
+        )?;
+        match LAST_JDK_WITH
+            .iter()
+            .find(|(release, _)| self.release <= *release)
+        {
+            Some((_, jdk)) => write!(f, ", use a JDK {} or older (--java-home)", jdk)?,
+            None => write!(f, ", use an older JDK (--java-home)")?,
+        }
+        if let Some(oldest) = self.oldest {
+            write!(f, " or raise the java source and target to {}", oldest)?;
+        }
+        Ok(())
+    }
+}
+
+/// The release rejected by `--release N`, `-source N` or `-target N`, if that's why javac failed
+pub fn unsupported_release(output: &str) -> Option<UnsupportedRelease> {
+    let release = regex!(r"^error: release version (?P<release>\d+) not supported");
+    let option = regex!(
+        r"^error: (Source|Target) option (?P<release>\d+) is no longer supported\. Use (?P<oldest>\d+) or later\."
+    );
+    output.lines().find_map(|line| {
+        let caps = release.captures(line).or_else(|| option.captures(line))?;
+        Some(UnsupportedRelease {
+            release: caps["release"].parse().ok()?,
+            oldest: caps.name("oldest").and_then(|it| it.as_str().parse().ok()),
+        })
+    })
+}
+
+/// Count of warnings per category, most frequent first, e.g. `12 deprecation, 3 unchecked`.
+/// Warnings without a category are counted as `other`.
+pub fn warning_summary(diagnostics: &[Diagnostic]) -> String {
+    let mut counts = BTreeMap::new();
+    for it in diagnostics
+        .iter()
+        .filter(|it| it.severity == Severity::Warning)
+    {
+        *counts
+            .entry(it.category.as_deref().unwrap_or("other"))
+            .or_insert(0) += 1;
+    }
+    let mut counts: Vec<_> = counts.into_iter().collect();
+    // Stable, so equal counts stay in alphabetical order
+    counts.sort_by_key(|it| std::cmp::Reverse(it.1));
+    counts
+        .iter()
+        .map(|(category, count)| format!("{} {}", count, category))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::javac_parser::{
+        parse_javac_output, unsupported_release, warning_summary, JavacOutputParser, Severity,
+        UnsupportedRelease,
+    };
+
+    const OUTPUT: &str =
+        "src/marais/Main.java:4: warning: [deprecation] getYear() in Date has been deprecated
+        int year = date.getYear();
+                       ^
+src/marais/Main.java:5: warning: [unchecked] unchecked conversion
+        List<String> list = new ArrayList();
+                            ^
+src/marais/Main.java:6: error: ';' expected
+        System.out.println(\"hello\")
+                                    ^
+src/marais/Other.java:9: warning: [deprecation] getMonth() in Date has been deprecated
+        int month = date.getMonth();
+                        ^
+1 error
+3 warnings
+";
+
+    #[test]
+    fn test_parse_streamed() {
+        let mut parser = JavacOutputParser::default();
+        let mut lines = OUTPUT.lines();
+        // A diagnostic ends with the header of the next one
+        let first: Vec<_> = lines
+            .by_ref()
+            .take(4)
+            .filter_map(|it| parser.push(it))
+            .collect();
+        assert_eq!(first.len(), 1);
+        assert_eq!(first[0].message, "getYear() in Date has been deprecated");
+        let ended: Vec<_> = lines.filter_map(|it| parser.push(it)).collect();
+        assert_eq!(ended.len(), 3);
+        assert_eq!(ended[2].severity, Severity::Warning);
+        assert!(ended[2].text.ends_with('^'));
+        assert!(parser.finish().is_none());
+    }
+
+    #[test]
+    fn test_parse_output() {
+        let diagnostics = parse_javac_output(OUTPUT);
+        assert_eq!(diagnostics.len(), 4);
+
+        let error = &diagnostics[2];
+        assert_eq!(error.severity, Severity::Error);
+        assert_eq!(error.file, "src/marais/Main.java");
+        assert_eq!(error.line, 6);
+        assert_eq!(error.category, None);
+        assert_eq!(error.message, "';' expected");
+        assert_eq!(error.text.lines().count(), 3);
+
+        assert_eq!(diagnostics[3].category.as_deref(), Some("deprecation"));
+        assert_eq!(
+            diagnostics[3].message,
+            "getMonth() in Date has been deprecated"
+        );
+        assert!(!diagnostics[3].text.contains("3 warnings"));
+
+        assert_eq!(warning_summary(&diagnostics), "2 deprecation, 1 unchecked");
+    }
+
+    #[test]
+    fn test_unsupported_release() {
+        let dropped = unsupported_release(
+            "error: release version 7 not supported\nUsage: javac <options> <source files>\n",
+        )
         .unwrap();
-    let string = "srcjava\\Main.java:4: error: ',', ')', or '[' expected
-    public static void main(String[] args|) {
-                                         ^
-srcjava\\Main.java:5: error: ';' expected
-        System.out.println(Bruh.getHello() + \" from java\")
-                                                          ^
-2 errors";
-
-    // result will be an iterator over tuples containing the start and end indices for each match in the string
-    let result = regex.captures_iter(string);
-
-    for mat in result {
-        println!(" match {:?}", mat);
+        assert_eq!(
+            dropped,
+            UnsupportedRelease {
+                release: 7,
+                oldest: None
+            }
+        );
+        assert_eq!(
+            dropped.to_string(),
+            "The installed JDK can't compile for java 7, it has no symbols for that release, use a JDK 19 or older (--java-home)"
+        );
+
+        let source = unsupported_release(
+            "warning: [options] bootstrap class path not set in conjunction with -source 5\n\
+             error: Source option 5 is no longer supported. Use 7 or later.\n",
+        )
+        .unwrap();
+        assert!(source.to_string().ends_with(
+            "use a JDK 8 or older (--java-home) or raise the java source and target to 7"
+        ));
+
+        let newer = unsupported_release("error: release version 99 not supported").unwrap();
+        assert!(newer.to_string().contains("use a JDK 99 or newer"));
+        assert_eq!(unsupported_release(OUTPUT), None);
     }
 }
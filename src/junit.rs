@@ -0,0 +1,141 @@
+use std::fmt::{Display, Formatter};
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use serde::Deserialize;
+
+/// Main class of the JUnit Platform console launcher
+pub const CONSOLE_LAUNCHER: &str = "org.junit.platform.console.ConsoleLauncher";
+
+/// A `TEST-*.xml` report, as written by `--reports-dir`
+#[derive(Debug, Deserialize)]
+#[serde(rename = "testsuite")]
+struct TestSuite {
+    #[serde(default)]
+    tests: u32,
+    #[serde(default)]
+    skipped: u32,
+    #[serde(default, rename = "testcase")]
+    cases: Vec<TestCase>,
+}
+
+#[derive(Debug, Deserialize)]
+struct TestCase {
+    name: String,
+    #[serde(default)]
+    classname: String,
+    failure: Option<Failure>,
+    error: Option<Failure>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Failure {
+    #[serde(default)]
+    message: String,
+}
+
+/// Results of a test run
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct TestSummary {
+    pub tests: u32,
+    pub skipped: u32,
+    /// `class.method: message` of each failed or errored test
+    pub failed: Vec<String>,
+}
+
+impl TestSummary {
+    pub fn parse(report: &str) -> Result<Self> {
+        let suite: TestSuite = quick_xml::de::from_str(report)?;
+        let failed = suite
+            .cases
+            .iter()
+            .filter_map(|case| {
+                let failure = case.failure.as_ref().or(case.error.as_ref())?;
+                Some(format!(
+                    "{}.{}: {}",
+                    case.classname, case.name, failure.message
+                ))
+            })
+            .collect();
+        Ok(Self {
+            tests: suite.tests,
+            skipped: suite.skipped,
+            failed,
+        })
+    }
+
+    /// Merge the `TEST-*.xml` reports in `dir`
+    pub fn read_reports(dir: &Path) -> Result<Self> {
+        let mut summary = Self::default();
+        let mut reports: Vec<_> = std::fs::read_dir(dir)?
+            .map(|it| Ok(it?.path()))
+            .collect::<Result<_>>()?;
+        reports.sort();
+        for report in reports.iter().filter(|it| {
+            it.file_name()
+                .and_then(|it| it.to_str())
+                .map_or(false, |it| it.starts_with("TEST-") && it.ends_with(".xml"))
+        }) {
+            let text = std::fs::read_to_string(report)?;
+            let suite = Self::parse(&text)
+                .with_context(|| format!("Malformed test report {}", report.display()))?;
+            summary.tests += suite.tests;
+            summary.skipped += suite.skipped;
+            summary.failed.extend(suite.failed);
+        }
+        Ok(summary)
+    }
+
+    pub fn passed(&self) -> u32 {
+        self.tests - self.skipped - self.failed.len() as u32
+    }
+}
+
+impl Display for TestSummary {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} passed, {} failed, {} skipped",
+            self.passed(),
+            self.failed.len(),
+            self.skipped
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::junit::TestSummary;
+
+    #[test]
+    fn test_parse_report() {
+        let summary = TestSummary::parse(
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<testsuite name="JUnit Jupiter" tests="4" skipped="1" failures="1" errors="1" time="0.1">
+<properties>
+<property name="java.version" value="17"/>
+</properties>
+<testcase name="adds()" classname="marais.CalcTest" time="0.01"/>
+<testcase name="divides()" classname="marais.CalcTest" time="0.01">
+<failure message="expected: &lt;2&gt; but was: &lt;3&gt;" type="org.opentest4j.AssertionFailedError">stack trace</failure>
+<system-out>output</system-out>
+</testcase>
+<testcase name="throws()" classname="marais.CalcTest" time="0.01">
+<error message="boom" type="java.lang.IllegalStateException">stack trace</error>
+</testcase>
+<testcase name="later()" classname="marais.CalcTest" time="0">
+<skipped/>
+</testcase>
+</testsuite>"#,
+        )
+        .unwrap();
+        assert_eq!(
+            summary.failed,
+            vec![
+                "marais.CalcTest.divides(): expected: <2> but was: <3>",
+                "marais.CalcTest.throws(): boom"
+            ]
+        );
+        assert_eq!(summary.to_string(), "1 passed, 2 failed, 1 skipped");
+    }
+}
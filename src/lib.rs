@@ -0,0 +1,457 @@
+//! jcargo as a library, the `jcargo` binary only parses the command line and reports errors.
+//!
+//! Load a [Workspace] (or a single [Module]) with an [Env] and pass them to
+//! [tasks::execute_task], or call the task functions in [tasks] directly.
+
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+use structopt::StructOpt;
+use url::Url;
+
+pub use crate::backend::{
+    DocumentationBackend, JavaCompilationBackend, Jdk, PackageBackend, Runtime,
+};
+use crate::dependencies::{repo_url, MavenRepo, RepoLayout};
+use crate::io::DownloadOptions;
+use crate::manifest::{Classifier, ConfigOverride, WorkspaceManifest};
+pub use crate::module::Module;
+use crate::tasks::CLASSPATH_SEPARATOR;
+use crate::timings::Timings;
+use crate::toolchains::SigningDef;
+pub use crate::workspace::Workspace;
+
+pub mod backend;
+pub mod config;
+pub mod dependencies;
+pub mod doctor;
+pub mod io;
+pub mod jar;
+pub mod javac_parser;
+pub mod junit;
+pub mod lockfile;
+pub mod logging;
+pub mod manifest;
+pub mod metadata;
+pub mod module;
+pub mod report;
+pub mod tasks;
+#[cfg(test)]
+mod test_utils;
+pub mod timings;
+pub mod toolchains;
+pub mod workspace;
+
+#[derive(StructOpt, Debug, Clone)]
+pub enum Task {
+    /// Init a new project in the current directory
+    Init { group: String, artifact: String },
+    /// Check project consistency (manifest, dependencies)
+    Check {
+        /// Sort and normalize dependencies in the manifest
+        #[structopt(long)]
+        fix: bool,
+        /// Report the declared dependencies with a newer release in their repository
+        #[structopt(long, alias = "outdated")]
+        deps: bool,
+        /// Report the classes provided by more than one runtime dependency jar
+        #[structopt(long)]
+        duplicate_classes: bool,
+        /// Report the packages split across runtime dependency jars, the module system rejects them
+        #[structopt(long)]
+        split_packages: bool,
+    },
+    /// Resolve and download all the dependencies without building, for later offline builds
+    Fetch {
+        /// Also download the sources jars
+        #[structopt(long)]
+        sources: bool,
+        /// Also download the javadoc jars
+        #[structopt(long)]
+        docs: bool,
+    },
+    /// Build project classes
+    Build {
+        /// What to produce, e.g. `--emit=classes,jar` : classes (default), jar, docs
+        #[structopt(long, use_delimiter = true)]
+        emit: Vec<Emit>,
+    },
+    /// Run a main class
+    Run {
+        entrypoint: Option<String>,
+        /// Run the Main-Class of this packaged jar instead, without building
+        #[structopt(long)]
+        jar: Option<PathBuf>,
+        /// Run every entrypoint in turn, stopping at the first failure unless --keep-going
+        #[structopt(long, conflicts_with_all = &["entrypoint", "jar"])]
+        all: bool,
+        #[structopt(flatten)]
+        profiling: Profiling,
+        /// Run with the java assertions, or only those of these packages (`com.example...`) and
+        /// classes, instead of the [run] assertions
+        #[structopt(
+            long,
+            alias = "ea",
+            min_values = 0,
+            require_equals = true,
+            use_delimiter = true
+        )]
+        enable_assertions: Option<Vec<String>>,
+        /// Also write the output of the program to this file, truncated first
+        #[structopt(long, parse(from_os_str), conflicts_with = "all")]
+        log: Option<PathBuf>,
+    },
+    /// Compile and run the tests in `test` with the JUnit Platform console launcher
+    Test {
+        /// Only run the test classes whose fully qualified name matches this regex
+        #[structopt(long = "test-filter", alias = "test")]
+        filter: Option<String>,
+    },
+    /// Create javadoc
+    Doc,
+    /// Create a jar of the built classes
+    Package {
+        /// Create a sources jar
+        #[structopt(long = "sources")]
+        sources: bool,
+        /// Create a doc jar
+        #[structopt(long = "docs")]
+        docs: bool,
+        /// Also create a jar bundling the runtime dependencies
+        #[structopt(long)]
+        fat: bool,
+        /// Copy the runtime dependencies to `target/artifacts/lib`, referenced by the jar Class-Path
+        #[structopt(long = "with-libs")]
+        with_libs: bool,
+        /// Also create a classified jar, `tests` jars the test classes as
+        /// `<artifact>-<version>-tests.jar`
+        #[structopt(long)]
+        classifier: Option<Classifier>,
+        /// Sign the jars, the fat and the classified ones too, with the `[signing]` key of the
+        /// global config
+        #[structopt(long)]
+        sign: bool,
+        entrypoint: Option<String>,
+    },
+    /// Package and upload the artifacts to the `[publish]` repository
+    Deploy,
+    /// Delete all generated directories
+    Clean {
+        /// Only print what would be removed
+        #[structopt(long)]
+        dry_run: bool,
+    },
+    /// Manage the downloaded dependencies in `libs`
+    Cache(CacheTask),
+    /// Print resolved project information
+    Info,
+    /// Print a classpath of the module, for other tools
+    Classpath {
+        #[structopt(long, default_value = "runtime", possible_values = &["compile", "runtime", "test"])]
+        scope: String,
+    },
+    /// Check the toolchain, the repositories and the caches, changes nothing
+    Doctor,
+    /// List the entries and manifest of a jar
+    Inspect { jar: PathBuf },
+    /// Dump the resolved project model for other tools
+    Metadata {
+        /// Output format
+        #[structopt(long, default_value = "json", possible_values = &["json"])]
+        format: String,
+    },
+}
+
+impl Task {
+    /// Whether the task looks at the dependencies, the BOMs are only imported for those
+    pub fn needs_dependencies(&self) -> bool {
+        !matches!(
+            self,
+            Task::Init { .. }
+                | Task::Clean { .. }
+                | Task::Cache(_)
+                | Task::Info
+                | Task::Doctor
+                | Task::Inspect { .. }
+        )
+    }
+}
+
+/// Outputs of `build --emit`, whatever their order on the command line the classes come first,
+/// then the docs, then the jar
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Emit {
+    Classes,
+    Docs,
+    Jar,
+}
+
+impl FromStr for Emit {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "classes" => Ok(Emit::Classes),
+            "docs" => Ok(Emit::Docs),
+            "jar" => Ok(Emit::Jar),
+            other => Err(format!(
+                "Can't convert {} to an output (classes, jar or docs)",
+                other
+            )),
+        }
+    }
+}
+
+/// Profilers of `run`, their output goes in the target dir
+#[derive(StructOpt, Debug, Clone, Default)]
+pub struct Profiling {
+    /// Record the run with Java Flight Recorder to target/recording.jfr, with the settings of a
+    /// preset (`default`, `profile`) or a .jfc file
+    #[structopt(long, min_values = 0, require_equals = true, conflicts_with = "all")]
+    pub jfr: Option<Option<String>>,
+    /// Load a profiling agent, `async:<path to libasyncProfiler>` writes a flame graph to
+    /// target/profile.html
+    #[structopt(long, conflicts_with = "all")]
+    pub profiler: Option<Profiler>,
+}
+
+impl Profiling {
+    pub fn is_enabled(&self) -> bool {
+        self.jfr.is_some() || self.profiler.is_some()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Profiler {
+    /// async-profiler, loaded from this library
+    Async(PathBuf),
+}
+
+impl FromStr for Profiler {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(':') {
+            Some(("async", path)) if !path.is_empty() => Ok(Profiler::Async(PathBuf::from(path))),
+            _ => Err(format!(
+                "Can't convert {} to a profiler, expected async:<path to libasyncProfiler>",
+                s
+            )),
+        }
+    }
+}
+
+#[derive(StructOpt, Debug, Clone)]
+pub enum CacheTask {
+    /// Remove the artifacts no longer in the lockfile
+    Prune {
+        /// Also remove the artifacts not modified for this many days
+        #[structopt(long)]
+        max_age: Option<u64>,
+        /// Only print what would be removed
+        #[structopt(long)]
+        dry_run: bool,
+    },
+    /// Remove every downloaded artifact
+    Clean {
+        /// Only print what would be removed
+        #[structopt(long)]
+        dry_run: bool,
+    },
+}
+
+#[derive(Debug)]
+pub struct Env {
+    pub repos: Vec<Arc<MavenRepo>>,
+    pub comp_backend: JavaCompilationBackend,
+    pub runtime: Runtime,
+    pub doc_backend: DocumentationBackend,
+    pub package_backend: PackageBackend,
+    /// Don't stop at the first failed workspace member
+    pub keep_going: bool,
+    /// Workspace members selected on the command line
+    pub members: Vec<String>,
+    /// Verify dependency signatures against the module keyring
+    pub verify_signatures: bool,
+    /// Lockfile drift is an error
+    pub locked: bool,
+    /// Resolve dependencies from the cache only
+    pub offline: bool,
+    /// Print the resolution trace after resolving dependencies
+    pub explain_resolution: bool,
+    /// Collapse javac warnings into a count per category
+    pub warning_summary: bool,
+    /// Retries and timeout of repository requests
+    pub downloads: DownloadOptions,
+    /// Git ref to compare with to find the changed members
+    pub since: Option<String>,
+    /// Applied over every module manifest
+    pub config: Vec<ConfigOverride>,
+    /// Write `target/build-report.json` after a build
+    pub build_report: bool,
+    /// Set with `--timings`, written to `target/timings.json` of the workspace root
+    pub timings: Option<Timings>,
+    /// Separator of the printed classpaths, the host one unless targeting another platform
+    pub path_separator: &'static str,
+    /// Fail on the manifest mistakes that are otherwise warned about, e.g. duplicate dependencies
+    pub strict: bool,
+    /// Directory of the compiled classes shared between builds, keyed by their inputs
+    pub build_cache: Option<PathBuf>,
+    /// Dependency scopes left out of the classpaths, with `--exclude-scope`
+    pub excluded_scopes: Vec<String>,
+    /// Key of `package --sign`, from the global config
+    pub signing: SigningDef,
+}
+
+/// Base url of Maven Central
+pub const MAVEN_CENTRAL: &str = "https://repo.maven.apache.org/maven2/";
+
+impl Env {
+    pub fn new(native: bool) -> Self {
+        Self {
+            repos: vec![Arc::new(MavenRepo {
+                name: "maven-central".to_string(),
+                url: Url::parse(MAVEN_CENTRAL).unwrap(),
+                layout: RepoLayout::Maven,
+            })],
+            comp_backend: if native {
+                JavaCompilationBackend::NativeJavac
+            } else {
+                JavaCompilationBackend::JdkJavac(Jdk::default())
+            },
+            runtime: Runtime::Java(Jdk::default()),
+            doc_backend: if native {
+                DocumentationBackend::NativeJavadoc
+            } else {
+                DocumentationBackend::JdkJavadoc(Jdk::default())
+            },
+            package_backend: if native {
+                PackageBackend::NativeJar
+            } else {
+                PackageBackend::JdkJar(Jdk::default())
+            },
+            keep_going: false,
+            members: Vec::new(),
+            verify_signatures: false,
+            locked: false,
+            offline: false,
+            explain_resolution: false,
+            warning_summary: false,
+            downloads: DownloadOptions::default(),
+            since: None,
+            config: Vec::new(),
+            build_report: true,
+            timings: None,
+            path_separator: CLASSPATH_SEPARATOR,
+            strict: false,
+            build_cache: None,
+            excluded_scopes: Vec::new(),
+            signing: SigningDef::default(),
+        }
+    }
+
+    /// Use the JDK in `home` instead of the one in PATH, native backends are left alone
+    pub fn set_java_home(&mut self, home: &Path) -> Result<()> {
+        let jdk = Jdk::at(home)?;
+        if let JavaCompilationBackend::JdkJavac(_) = self.comp_backend {
+            self.comp_backend = JavaCompilationBackend::JdkJavac(jdk.clone());
+        }
+        self.runtime = Runtime::Java(jdk.clone());
+        if let DocumentationBackend::JdkJavadoc(_) = self.doc_backend {
+            self.doc_backend = DocumentationBackend::JdkJavadoc(jdk.clone());
+        }
+        if let PackageBackend::JdkJar(_) = self.package_backend {
+            self.package_backend = PackageBackend::JdkJar(jdk);
+        }
+        Ok(())
+    }
+
+    /// Send all Maven Central traffic to `mirror`, the repository keeps its name
+    pub fn set_central_mirror(&mut self, mirror: &str) -> Result<()> {
+        let mirror =
+            repo_url(mirror).with_context(|| format!("Invalid central_mirror '{}'", mirror))?;
+        for repo in self.repos.iter_mut() {
+            if repo.url.as_str() == MAVEN_CENTRAL {
+                *repo = Arc::new(MavenRepo {
+                    name: repo.name.clone(),
+                    url: mirror.clone(),
+                    layout: repo.layout.clone(),
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Apply the `[http]` settings and the `[[repositories]]` of the root `manifest`, if it exists
+pub fn load_http_config(manifest: &Path, env: &mut Env) -> Result<()> {
+    let document = match std::fs::read_to_string(manifest) {
+        Ok(document) => document,
+        Err(_) => return Ok(()),
+    };
+    let manifest = WorkspaceManifest::parse(&document)?;
+    if let Some(mirror) = manifest.http.central_mirror {
+        env.set_central_mirror(&mirror)?;
+    }
+    // Dependencies are resolved from the first repository, the configured ones go before Maven
+    // Central which is still tried when they don't have an artifact
+    let repos = manifest
+        .repositories
+        .into_iter()
+        .map(|it| MavenRepo::from_def(it).map(Arc::new))
+        .collect::<Result<Vec<_>>>()?;
+    env.repos.splice(0..0, repos);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::dependencies::Dependency;
+    use crate::manifest::DependencyDef;
+    use crate::Env;
+
+    #[test]
+    fn test_central_mirror() {
+        let mut env = Env::new(false);
+        env.set_central_mirror("https://mirror.example.com/central")
+            .unwrap();
+        let dep = Dependency::from_def(
+            DependencyDef::ShortNotation("org.apache.logging.log4j:log4j-api:2.17.1".to_string())
+                .complete(None)
+                .unwrap(),
+            &[],
+            &env.repos,
+        )
+        .unwrap();
+        match dep {
+            Dependency::MavenRepo(dep) => {
+                assert_eq!(dep.repo.name, "maven-central");
+                assert_eq!(
+                    dep.jar_url().as_str(),
+                    "https://mirror.example.com/central/org/apache/logging/log4j/log4j-api/2.17.1/log4j-api-2.17.1.jar"
+                );
+            }
+            _ => panic!("Expected a maven dependency"),
+        }
+    }
+
+    #[test]
+    fn test_no_default_repos() {
+        let mut env = Env::new(false);
+        env.repos.clear();
+        let err = Dependency::from_def(
+            DependencyDef::ShortNotation("org.apache.logging.log4j:log4j-api:2.17.1".to_string())
+                .complete(None)
+                .unwrap(),
+            &[],
+            &env.repos,
+        )
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "'org.apache.logging.log4j:log4j-api:2.17.1' can't be resolved, no repository is configured (Maven Central is disabled by --no-default-repos)"
+        );
+    }
+}
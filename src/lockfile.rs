@@ -0,0 +1,325 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt::{Display, Formatter};
+use std::path::Path;
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use sha1::{Digest, Sha1};
+use sha2::Sha256;
+use tokio::fs;
+
+use crate::dependencies::coordinate::Coordinate;
+use crate::dependencies::error::ResolutionError;
+use crate::dependencies::Dependency;
+use crate::module::Module;
+
+/// File name of the lockfile, next to the manifest
+pub const LOCKFILE: &str = "jcargo.lock";
+
+/// Exact versions of the resolved dependency tree, written after each resolution
+#[derive(Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Lockfile {
+    #[serde(default, rename = "dependency")]
+    pub dependencies: Vec<LockedDependency>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct LockedDependency {
+    pub group: String,
+    pub artifact: String,
+    pub version: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub classifier: Option<String>,
+    /// Dependencies pulled by this one, as `group:artifact:version[:classifier]`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub dependencies: Vec<String>,
+    /// Hex digests of the jar
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sha1: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sha256: Option<String>,
+}
+
+impl LockedDependency {
+    pub fn coordinate(&self) -> Coordinate {
+        let coordinate = Coordinate::new(&self.group, &self.artifact, &self.version);
+        match &self.classifier {
+            Some(classifier) => coordinate.with_classifier(classifier),
+            None => coordinate,
+        }
+    }
+
+    pub fn notation(&self) -> String {
+        self.coordinate().to_string()
+    }
+
+    /// Check the jar against the locked digests, there is nothing to check without any
+    pub fn verify(&self, jar: &[u8]) -> Result<()> {
+        let digests = [
+            ("sha1", &self.sha1, format!("{:x}", Sha1::digest(jar))),
+            ("sha256", &self.sha256, format!("{:x}", Sha256::digest(jar))),
+        ];
+        for (name, locked, actual) in digests {
+            if let Some(locked) = locked {
+                if !locked.eq_ignore_ascii_case(&actual) {
+                    return Err(ResolutionError::ChecksumMismatch {
+                        name: format!("the jar of '{}' ({})", self.notation(), name),
+                        expected: locked.clone(),
+                        actual,
+                    }
+                    .into());
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A difference between the lockfile and the manifest
+#[derive(Debug, PartialEq, Eq)]
+pub enum Drift {
+    /// Declared in the manifest but not in the lockfile
+    Missing(String),
+    /// Declared with a different version than the locked one
+    Changed { declared: String, locked: String },
+    /// Locked but not reachable from any declared dependency
+    Orphaned(String),
+}
+
+impl Display for Drift {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Drift::Missing(dep) => write!(f, "'{}' is not in the lockfile", dep),
+            Drift::Changed { declared, locked } => {
+                write!(f, "'{}' is locked at '{}'", declared, locked)
+            }
+            Drift::Orphaned(dep) => write!(f, "'{}' is locked but no longer required", dep),
+        }
+    }
+}
+
+/// The drift as a diff of the lockfile : `+` to add, `-` to remove and `~` to update
+pub fn drift_diff(drift: &[Drift]) -> String {
+    let mut added = Vec::new();
+    let mut removed = Vec::new();
+    let mut changed = Vec::new();
+    for it in drift {
+        match it {
+            Drift::Missing(dep) => added.push(format!("  + {} (declared, not locked)", dep)),
+            Drift::Orphaned(dep) => {
+                removed.push(format!("  - {} (locked, no longer required)", dep))
+            }
+            Drift::Changed { declared, locked } => {
+                let (dep, version) = declared.rsplit_once(':').unwrap_or((declared, ""));
+                changed.push(format!("  ~ {} {} -> {}", dep, locked, version))
+            }
+        }
+    }
+    added
+        .into_iter()
+        .chain(removed)
+        .chain(changed)
+        .map(|it| it + "\n")
+        .collect()
+}
+
+impl Lockfile {
+    pub fn parse(document: &str) -> Result<Self> {
+        Ok(toml::from_str(document)?)
+    }
+
+    /// The lockfile of a resolution, from the dependencies of each resolved coordinate. The
+    /// entries already in `previous` keep their digests, the new ones are computed from the jars
+    /// in `libs`.
+    pub async fn resolved(
+        dependencies: &HashMap<Coordinate, Vec<Coordinate>>,
+        libs: &Path,
+        previous: Option<&Lockfile>,
+    ) -> Result<Self> {
+        let mut locked = Vec::with_capacity(dependencies.len());
+        for (coordinate, children) in dependencies {
+            let mut children: Vec<_> = children.iter().map(Coordinate::to_string).collect();
+            children.sort();
+            children.dedup();
+            let pinned = previous.and_then(|it| {
+                it.dependencies
+                    .iter()
+                    .find(|it| it.coordinate() == *coordinate)
+            });
+            let (sha1, sha256) = match pinned {
+                Some(pinned) => (pinned.sha1.clone(), pinned.sha256.clone()),
+                // Pom only artifacts have no jar
+                None => match fs::read(libs.join(coordinate.jar_name())).await {
+                    Ok(jar) => (
+                        Some(format!("{:x}", Sha1::digest(&jar))),
+                        Some(format!("{:x}", Sha256::digest(&jar))),
+                    ),
+                    Err(_) => (None, None),
+                },
+            };
+            locked.push(LockedDependency {
+                group: coordinate.group.clone(),
+                artifact: coordinate.artifact.clone(),
+                version: coordinate.version.clone(),
+                classifier: coordinate.classifier.clone(),
+                dependencies: children,
+                sha1,
+                sha256,
+            });
+        }
+        locked.sort_by_key(LockedDependency::notation);
+        Ok(Self {
+            dependencies: locked,
+        })
+    }
+
+    /// Write the lockfile of the module in `dir`, unless it didn't change
+    pub async fn save(&self, dir: &Path, previous: Option<&Lockfile>) -> Result<()> {
+        if previous == Some(self) {
+            return Ok(());
+        }
+        let document = format!(
+            "# Written by jcargo after resolving the dependencies\n\n{}",
+            toml::to_string(self)?
+        );
+        fs::write(dir.join(LOCKFILE), document).await?;
+        Ok(())
+    }
+
+    /// Load the lockfile of the module in `dir`, if there is one
+    pub async fn load(dir: &Path) -> Result<Option<Self>> {
+        let path = dir.join(LOCKFILE);
+        if !path.exists() {
+            return Ok(None);
+        }
+        Ok(Some(Self::parse(&fs::read_to_string(path).await?)?))
+    }
+
+    /// The locked version of the artifact of `coordinate`, with the same classifier
+    fn find(&self, coordinate: &Coordinate) -> Option<&LockedDependency> {
+        self.dependencies.iter().find(|it| {
+            it.group == coordinate.group
+                && it.artifact == coordinate.artifact
+                && it.classifier == coordinate.classifier
+        })
+    }
+
+    /// Compare the lockfile with the dependencies declared by `module`, without resolving anything
+    pub fn drift(&self, module: &Module) -> Vec<Drift> {
+        let mut drift = Vec::new();
+        // Locked entries reachable from the manifest
+        let mut reachable = HashSet::new();
+        let mut queue = Vec::new();
+        for dep in module.dependencies.iter() {
+            if let Dependency::MavenRepo(dep) = dep {
+                let coordinate = &dep.coordinate;
+                match self.find(coordinate) {
+                    Some(locked) if locked.version == coordinate.version => queue.push(locked),
+                    Some(locked) => {
+                        drift.push(Drift::Changed {
+                            declared: dep.dependency_notation(),
+                            locked: locked.version.clone(),
+                        });
+                        queue.push(locked);
+                    }
+                    None => drift.push(Drift::Missing(dep.dependency_notation())),
+                }
+            }
+        }
+        while let Some(locked) = queue.pop() {
+            if reachable.insert(locked.notation()) {
+                queue.extend(
+                    self.dependencies
+                        .iter()
+                        .filter(|it| locked.dependencies.contains(&it.notation())),
+                );
+            }
+        }
+        drift.extend(
+            self.dependencies
+                .iter()
+                .map(LockedDependency::notation)
+                .filter(|it| !reachable.contains(it))
+                .map(Drift::Orphaned),
+        );
+        drift
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use crate::lockfile::{drift_diff, Drift, Lockfile};
+    use crate::manifest::ModuleManifest;
+    use crate::{Env, Module};
+
+    const LOCK: &str = r#"
+        [[dependency]]
+        group = "org.apache.logging.log4j"
+        artifact = "log4j-core"
+        version = "2.17.1"
+        dependencies = ["org.apache.logging.log4j:log4j-api:2.17.1"]
+
+        [[dependency]]
+        group = "org.apache.logging.log4j"
+        artifact = "log4j-api"
+        version = "2.17.1"
+        "#;
+
+    fn module(deps: &str) -> Module {
+        let manifest = ModuleManifest::parse(
+            &format!(
+                r#"
+                group = "marais"
+                artifact = "locked"
+                version = "0.1.0"
+
+                [dependencies]
+                compileRuntime = [{}]
+                "#,
+                deps
+            ),
+            None,
+        )
+        .unwrap();
+        Module::from_manifest(Path::new("locked"), manifest, &Env::new(false)).unwrap()
+    }
+
+    #[test]
+    fn test_consistent() {
+        let lock = Lockfile::parse(LOCK).unwrap();
+        let module = module(r#""org.apache.logging.log4j:log4j-core:2.17.1""#);
+        assert!(lock.drift(&module).is_empty());
+    }
+
+    #[test]
+    fn test_drift() {
+        let lock = Lockfile::parse(LOCK).unwrap();
+        let module = module(
+            r#""org.apache.logging.log4j:log4j-api:2.17.1", "com.google.code.gson:gson:2.8.9""#,
+        );
+        assert_eq!(
+            lock.drift(&module),
+            vec![
+                Drift::Missing("com.google.code.gson:gson:2.8.9".to_string()),
+                Drift::Orphaned("org.apache.logging.log4j:log4j-core:2.17.1".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_drift_diff() {
+        let lock = Lockfile::parse(LOCK).unwrap();
+        let module = module(
+            r#""com.google.code.gson:gson:2.8.9", "org.apache.logging.log4j:log4j-api:2.17.2""#,
+        );
+        assert_eq!(
+            drift_diff(&lock.drift(&module)),
+            "  + com.google.code.gson:gson:2.8.9 (declared, not locked)
+  - org.apache.logging.log4j:log4j-core:2.17.1 (locked, no longer required)
+  ~ org.apache.logging.log4j:log4j-api 2.17.1 -> 2.17.2
+"
+        );
+    }
+}
@@ -0,0 +1,156 @@
+//! Diagnostics are `tracing` events filtered by level (or `RUST_LOG`),
+//! user facing status lines go through [status!] and are only silenced by `--quiet`.
+
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use tracing::Level;
+use tracing_subscriber::EnvFilter;
+
+static QUIET: AtomicBool = AtomicBool::new(false);
+static PRINT_COMMANDS: AtomicBool = AtomicBool::new(false);
+static COLOR: AtomicBool = AtomicBool::new(false);
+
+/// Print a status line, unless running with `--quiet`
+#[macro_export]
+macro_rules! status {
+    ($($arg:tt)*) => {
+        if !$crate::logging::is_quiet() {
+            println!("{}", $crate::logging::paint_status(format!($($arg)*)));
+        }
+    };
+}
+
+/// `--color` flag
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ColorChoice {
+    /// Only when writing to a terminal, `NO_COLOR` and `CLICOLOR_FORCE` are honored
+    Auto,
+    Always,
+    Never,
+}
+
+impl FromStr for ColorChoice {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "auto" => Ok(ColorChoice::Auto),
+            "always" => Ok(ColorChoice::Always),
+            "never" => Ok(ColorChoice::Never),
+            other => Err(format!(
+                "Can't convert {} to a color choice (auto, always or never)",
+                other
+            )),
+        }
+    }
+}
+
+impl ColorChoice {
+    /// Whether to emit ANSI codes, `var` reads an environment variable
+    pub fn enabled(&self, is_terminal: bool, var: impl Fn(&str) -> Option<String>) -> bool {
+        let set = |name: &str| var(name).map_or(false, |it| !it.is_empty() && it != "0");
+        match self {
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+            ColorChoice::Auto if var("NO_COLOR").map_or(false, |it| !it.is_empty()) => false,
+            ColorChoice::Auto => set("CLICOLOR_FORCE") || is_terminal,
+        }
+    }
+}
+
+pub fn is_colored() -> bool {
+    COLOR.load(Ordering::Relaxed)
+}
+
+/// Bold green verb of a status line (`   Compiling ...`), when colors are enabled
+pub fn paint_status(line: String) -> String {
+    if !is_colored() {
+        return line;
+    }
+    let start = line.len() - line.trim_start().len();
+    let end = line[start..]
+        .find(char::is_whitespace)
+        .map_or(line.len(), |it| start + it);
+    if start == end {
+        return line;
+    }
+    format!(
+        "{}\x1b[1;32m{}\x1b[0m{}",
+        &line[..start],
+        &line[start..end],
+        &line[end..]
+    )
+}
+
+pub fn is_quiet() -> bool {
+    QUIET.load(Ordering::Relaxed)
+}
+
+/// Print the command line of every spawned tool, for `--print-command`
+pub fn set_print_commands(enabled: bool) {
+    PRINT_COMMANDS.store(enabled, Ordering::Relaxed);
+}
+
+pub fn prints_commands() -> bool {
+    PRINT_COMMANDS.load(Ordering::Relaxed)
+}
+
+/// Level of the diagnostics shown for the command line flags
+pub fn level(quiet: bool, verbose: bool, debug: bool) -> Level {
+    if debug {
+        Level::DEBUG
+    } else if verbose {
+        Level::INFO
+    } else if quiet {
+        Level::ERROR
+    } else {
+        Level::WARN
+    }
+}
+
+/// Install the global subscriber, diagnostics are written to stderr
+pub fn init(quiet: bool, verbose: bool, debug: bool, color: ColorChoice) {
+    QUIET.store(quiet, Ordering::Relaxed);
+    let var = |name: &str| std::env::var(name).ok();
+    COLOR.store(
+        color.enabled(atty::is(atty::Stream::Stdout), var),
+        Ordering::Relaxed,
+    );
+    let filter = EnvFilter::try_from_default_env()
+        .unwrap_or_else(|_| EnvFilter::new(format!("jcargo={}", level(quiet, verbose, debug))));
+    tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(std::io::stderr)
+        .with_ansi(color.enabled(atty::is(atty::Stream::Stderr), var))
+        .without_time()
+        .init();
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::logging::{paint_status, ColorChoice};
+
+    #[test]
+    fn test_color_choice() {
+        let none = |_: &str| None;
+        assert!(!ColorChoice::Auto.enabled(false, none));
+        assert!(ColorChoice::Auto.enabled(true, none));
+        assert!(
+            !ColorChoice::Auto.enabled(true, |name: &str| (name == "NO_COLOR")
+                .then(|| "1".to_string()))
+        );
+        assert!(
+            ColorChoice::Auto.enabled(false, |name: &str| (name == "CLICOLOR_FORCE")
+                .then(|| "1".to_string()))
+        );
+        assert!(
+            !ColorChoice::Auto.enabled(false, |name: &str| (name == "CLICOLOR_FORCE")
+                .then(|| "0".to_string()))
+        );
+        assert!(ColorChoice::Always.enabled(false, none));
+        assert!(!ColorChoice::Never.enabled(true, none));
+        // Not initialized, like when piped
+        assert_eq!(paint_status("   Compiling".to_string()), "   Compiling");
+    }
+}
@@ -1,105 +1,291 @@
-use std::path::PathBuf;
-use std::sync::Arc;
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::time::Duration;
 
+use anyhow::{anyhow, Context, Result};
 use structopt::StructOpt;
-use url::Url;
+use tracing::{debug, warn};
 
-use crate::backend::{DocumentationBackend, JavaCompilationBackend, PackageBackend, Runtime};
-use crate::dependencies::MavenRepo;
-use crate::module::Module;
-use crate::tasks::execute_task;
-
-mod backend;
-mod dependencies;
-mod io;
-mod javac_parser;
-mod manifest;
-mod module;
-mod tasks;
+use jcargo::config::EffectiveConfig;
+use jcargo::io::http_client_with;
+use jcargo::logging::ColorChoice;
+use jcargo::manifest::{ConfigOverride, DEPENDENCY_SCOPES};
+use jcargo::module::MANIFEST_FILE;
+use jcargo::tasks::execute_task;
+use jcargo::timings::Timings;
+use jcargo::toolchains::{
+    jcargo_home, major_version, manifest_source, select_jdk, Provisioner, Toolchains, JDKS_DIR,
+};
+use jcargo::workspace::find_workspace_root;
+use jcargo::{load_http_config, logging, Env, Task, Workspace};
 
 #[derive(StructOpt, Debug)]
 #[structopt(name = "jcargo", about = "Cargo but for java")]
 struct Opts {
+    /// Show debug diagnostics
     #[structopt(short, long)]
     debug: bool,
+    /// Show more diagnostics (downloads, compilation steps)
+    #[structopt(short, long)]
+    verbose: bool,
+    /// Only print errors
+    #[structopt(short, long)]
+    quiet: bool,
+    /// Colored output : auto (when writing to a terminal), always or never
+    #[structopt(long, default_value = "auto")]
+    color: ColorChoice,
     /// Set working dir
     #[structopt(short, long = "--working-dir", default_value = ".")]
     working_dir: PathBuf,
+    /// Manifest to load instead of the jcargo.toml found from the working dir
+    #[structopt(long, global = true, parse(from_os_str))]
+    config_file: Option<PathBuf>,
+    /// Root dir of the module or workspace of --config-file, the file's directory by default
+    #[structopt(long, global = true, parse(from_os_str), requires = "config-file")]
+    root: Option<PathBuf>,
     /// Force using native-jdktools
     #[structopt(long)]
     native: bool,
+    /// Use the JDK installed there instead of the one in PATH or the one of the .java-version
+    #[structopt(long)]
+    java_home: Option<PathBuf>,
+    /// Download the JDK the project needs from Adoptium when no local one fits
+    #[structopt(long)]
+    auto_provision: bool,
+    /// Continue with independent workspace members when one fails
+    #[structopt(long)]
+    keep_going: bool,
+    /// Only process these workspace members (artifact or path), and what they depend on
+    #[structopt(long = "member")]
+    members: Vec<String>,
+    /// Require dependency jars to be signed by a key of the `[security]` keyring
+    #[structopt(long)]
+    verify_signatures: bool,
+    /// Fail if the lockfile is not consistent with the manifest
+    #[structopt(long)]
+    locked: bool,
+    /// Never touch the network, only use the cached poms and jars
+    #[structopt(long)]
+    offline: bool,
+    /// Print the versions requested for each dependency and the nearest one, which maven would pick
+    #[structopt(long)]
+    explain_resolution: bool,
+    /// Only print a count per category of the compiler warnings, errors are still shown
+    #[structopt(long)]
+    warning_summary: bool,
+    /// Retries of an interrupted download before giving up
+    #[structopt(long)]
+    max_download_retries: Option<u32>,
+    /// Timeout of each http request, in seconds
+    #[structopt(long)]
+    download_timeout: Option<u64>,
+    /// Only process the members changed since this git ref, and the members depending on them
+    #[structopt(long)]
+    since: Option<String>,
+    /// Print the command line of every tool jcargo spawns
+    #[structopt(long)]
+    print_command: bool,
+    /// Override a manifest value, e.g. `--config version=1.2.3`
+    #[structopt(long = "config", number_of_values = 1)]
+    config: Vec<ConfigOverride>,
+    /// Leave the dependencies of a scope out of the classpaths, e.g. `--exclude-scope transitive`
+    #[structopt(long, number_of_values = 1, possible_values = &DEPENDENCY_SCOPES)]
+    exclude_scope: Vec<String>,
+    /// Don't write the build report to `target/build-report.json`
+    #[structopt(long)]
+    no_report: bool,
+    /// Don't use Maven Central, only the explicitly configured repositories
+    #[structopt(long)]
+    no_default_repos: bool,
+    /// Write `target/timings.json` and `target/timings.html`, what the run spent its time on
+    #[structopt(long)]
+    timings: bool,
+    /// Separator of the printed classpaths, for another platform. Defaults to
+    /// JCARGO_PATH_SEPARATOR, else the one of the host
+    #[structopt(long, possible_values = &[";", ":"])]
+    path_separator: Option<String>,
+    /// Fail on the manifest mistakes that are otherwise warned about, e.g. duplicate dependencies
+    #[structopt(long)]
+    strict: bool,
+    /// Print something instead of running a task : `cfg` for the effective configuration.
+    /// Can't be combined with a task
+    #[structopt(long, possible_values = &["cfg"])]
+    print: Option<String>,
+    /// Restore the compiled classes from this directory when the same inputs were built before,
+    /// e.g. on another CI runner. Defaults to JCARGO_BUILD_CACHE
+    #[structopt(long, parse(from_os_str))]
+    build_cache: Option<PathBuf>,
     #[structopt(subcommand)]
-    task: Task,
+    task: Option<Task>,
 }
 
-#[derive(StructOpt, Debug)]
-pub enum Task {
-    /// Init a new project in the current directory
-    Init { group: String, artifact: String },
-    /// Check project consistency (manifest, dependencies)
-    Check,
-    /// Build project classes
-    Build,
-    /// Run a main class
-    Run { entrypoint: Option<String> },
-    /// Create javadoc
-    Doc,
-    /// Create a jar of the built classes
-    Package {
-        /// Create a sources jar
-        #[structopt(long = "sources")]
-        sources: bool,
-        /// Create a doc jar
-        #[structopt(long = "docs")]
-        docs: bool,
-        entrypoint: Option<String>,
-    },
-    /// Delete all generated directories
-    Clean,
+/// The `--config-file` and the root of its module, `root` or the directory of the file
+fn manifest_file_root(file: &Path, root: Option<&Path>) -> Result<(PathBuf, PathBuf)> {
+    let file = file
+        .canonicalize()
+        .with_context(|| format!("Can't access {}", file.display()))?;
+    let root = match root {
+        Some(root) => root
+            .canonicalize()
+            .with_context(|| format!("Can't access {}", root.display()))?,
+        None => file.parent().unwrap().to_path_buf(),
+    };
+    Ok((file, root))
+}
+
+/// The JDK requested by the `.java-version` of `root`, or the one provisioned for its manifest
+/// `file` when `auto_provision` (or the global config) allows it
+async fn project_toolchain(
+    file: &Path,
+    root: &Path,
+    env: &Env,
+    toolchains: &Toolchains,
+    auto_provision: bool,
+) -> Result<Option<PathBuf>> {
+    let provisioner = match (auto_provision || toolchains.auto_provision, jcargo_home()) {
+        (true, Some(home)) if !env.offline => Some(Provisioner::new(
+            http_client_with(&env.downloads),
+            home.join(JDKS_DIR),
+        )),
+        _ => None,
+    };
+    // Only needed to know whether the JDK in PATH is recent enough
+    let (source, javac) = match &provisioner {
+        Some(_) => {
+            let source = match env.config.iter().rev().find(|it| it.key == "java.source") {
+                Some(it) => it.value.trim_matches('"').to_string(),
+                None => manifest_source(&std::fs::read_to_string(file).unwrap_or_default()),
+            };
+            let javac = env.comp_backend.version().await;
+            (source, javac.map(|it| major_version(&it)))
+        }
+        None => (String::new(), None),
+    };
+    select_jdk(root, &source, javac, toolchains, provisioner.as_ref()).await
 }
 
-#[derive(Debug)]
-pub struct Env {
-    pub repos: Vec<Arc<MavenRepo>>,
-    pub comp_backend: JavaCompilationBackend,
-    pub runtime: Runtime,
-    pub doc_backend: DocumentationBackend,
-    pub package_backend: PackageBackend,
+/// `--print cfg`
+async fn print_config(
+    env: &Env,
+    workspace_resolver: impl Future<Output = Result<Workspace>>,
+) -> Result<()> {
+    let workspace = workspace_resolver.await?;
+    print!("{}", EffectiveConfig::new(&workspace, env).await.to_toml()?);
+    Ok(())
 }
 
 #[tokio::main(flavor = "multi_thread", worker_threads = 4)]
 async fn main() {
     let opts = Opts::from_args();
-    //dbg!(&opts);
+    logging::init(opts.quiet, opts.verbose, opts.debug, opts.color);
+    logging::set_print_commands(opts.print_command);
+    debug!(?opts);
 
-    let env = Env {
-        repos: vec![Arc::new(MavenRepo {
-            name: "maven-central".to_string(),
-            url: Url::parse("https://repo.maven.apache.org/maven2/").unwrap(),
-        })],
-        comp_backend: if opts.native {
-            JavaCompilationBackend::NativeJavac
-        } else {
-            JavaCompilationBackend::JdkJavac
-        },
-        runtime: Runtime::Java,
-        doc_backend: if opts.native {
-            DocumentationBackend::NativeJavadoc
-        } else {
-            DocumentationBackend::JdkJavadoc
-        },
-        package_backend: if opts.native {
-            PackageBackend::NativeJar
-        } else {
-            PackageBackend::JdkJar
-        },
+    let mut env = Env::new(opts.native);
+    env.keep_going = opts.keep_going;
+    env.members = opts.members;
+    env.verify_signatures = opts.verify_signatures;
+    env.locked = opts.locked;
+    env.offline = opts.offline;
+    env.explain_resolution = opts.explain_resolution;
+    env.warning_summary = opts.warning_summary;
+    if let Some(retries) = opts.max_download_retries {
+        env.downloads.attempts = retries.saturating_add(1);
+    }
+    env.downloads.timeout = opts.download_timeout.map(Duration::from_secs);
+    env.since = opts.since;
+    env.config = opts.config;
+    env.excluded_scopes = opts.exclude_scope;
+    env.build_report = !opts.no_report;
+    env.timings = opts.timings.then(Timings::new);
+    env.strict = opts.strict;
+    env.build_cache = opts
+        .build_cache
+        .or_else(|| std::env::var_os("JCARGO_BUILD_CACHE").map(PathBuf::from));
+    match opts
+        .path_separator
+        .or_else(|| std::env::var("JCARGO_PATH_SEPARATOR").ok())
+        .as_deref()
+    {
+        Some(";") => env.path_separator = ";",
+        Some(":") => env.path_separator = ":",
+        Some(other) => {
+            eprintln!(
+                "Error: Invalid path separator '{}', expected ';' or ':'",
+                other
+            );
+            process::exit(1);
+        }
+        None => {}
+    }
+    let toolchains = Toolchains::load().unwrap_or_else(|e| {
+        warn!("{:#}, ignoring the global config", e);
+        Toolchains::default()
+    });
+    env.signing = toolchains.signing.clone();
+    if opts.no_default_repos {
+        env.repos.clear();
+    }
+    if let Some(home) = &opts.java_home {
+        if let Err(e) = env.set_java_home(home) {
+            eprintln!("Error: {:#}", e);
+            process::exit(1);
+        }
+    }
+    // Resolved lazily, init doesn't need a manifest
+    let manifest = match &opts.config_file {
+        Some(file) => manifest_file_root(&opts.working_dir.join(file), opts.root.as_deref())
+            .map(|(file, root)| (file, root, None)),
+        // From a member directory, the whole workspace is loaded with this member selected
+        None => find_workspace_root(&opts.working_dir)
+            .map(|(root, member)| (root.join(MANIFEST_FILE), root, member)),
     };
+    if let Ok((file, root, _)) = &manifest {
+        if let Err(e) = load_http_config(file, &mut env) {
+            eprintln!("Error: {:#}", e);
+            process::exit(1);
+        }
+        if opts.java_home.is_none() {
+            match project_toolchain(file, root, &env, &toolchains, opts.auto_provision).await {
+                Ok(Some(home)) => {
+                    debug!(home = %home.display(), "Toolchain selected");
+                    if let Err(e) = env.set_java_home(&home) {
+                        eprintln!("Error: {:#}", e);
+                        process::exit(1);
+                    }
+                }
+                Ok(None) => {}
+                Err(e) => warn!("{:#}, using the JDK in PATH", e),
+            }
+        }
+    }
 
-    let module_resolver = async {
-        let module = Module::load(&opts.working_dir, &env).await;
-        //dbg!(&module);
-        module
+    let workspace_resolver = async {
+        let (file, root, member) = manifest?;
+        let mut workspace = Workspace::load_from(&file, &root, &env).await;
+        if let (Ok(workspace), Some(member)) = (&mut workspace, member) {
+            workspace.select_current(member);
+        }
+        debug!(?workspace);
+        workspace
     };
 
-    execute_task(opts.task, &env, module_resolver).await;
+    let res = match (opts.print, opts.task) {
+        (Some(print), Some(_)) => Err(anyhow!(
+            "--print {} replaces the task, run them separately",
+            print
+        )),
+        (Some(_), None) => print_config(&env, workspace_resolver).await,
+        (None, Some(task)) => execute_task(task, &env, workspace_resolver).await,
+        (None, None) => {
+            Opts::clap().print_help().unwrap();
+            println!();
+            process::exit(1);
+        }
+    };
+    if let Err(e) = res {
+        eprintln!("Error: {:#}", e);
+        process::exit(1);
+    }
 }
@@ -1,6 +1,20 @@
-use anyhow::Result;
+use std::collections::BTreeMap;
+use std::fmt::Formatter;
+use std::path::Path;
+use std::str::FromStr;
+
+use anyhow::{anyhow, Result};
+use lazy_regex::regex;
 use semver::VersionReq;
-use serde::Deserialize;
+use serde::de::value::MapAccessDeserializer;
+use serde::de::{self, MapAccess, Visitor};
+use serde::{Deserialize, Deserializer};
+use toml_edit::{Array, Document, Item, Value};
+use url::Url;
+
+use crate::dependencies::catalog::VersionCatalog;
+use crate::dependencies::maven_settings::MavenSettings;
+use crate::dependencies::mavenpom::{Properties, PropertiesExt};
 
 /// Root of the TOML document
 #[derive(Debug, Deserialize)]
@@ -8,7 +22,10 @@ pub struct ModuleManifest {
     // Group can be inferred from the root manifest
     pub group: Option<String>,
     pub artifact: String,
-    pub version: String,
+    /// Either a version or "git" to derive it from `git describe`
+    pub version: Option<String>,
+    /// Where to derive the version from : "git-describe" or "file:<path>"
+    pub version_from: Option<String>,
     #[serde(flatten)]
     pub extra_info: ExtraInfo,
     // May be a library without entrypoints
@@ -17,22 +34,402 @@ pub struct ModuleManifest {
     // No dependencies is ok
     #[serde(default)]
     pub dependencies: DependenciesDef,
+    #[serde(default)]
+    pub java: JavaDef,
+    #[serde(default)]
+    pub javac: JavacDef,
+    #[serde(default)]
+    pub kotlin: KotlinDef,
+    #[serde(default)]
+    pub jvm: JvmDef,
+    #[serde(default)]
+    pub security: SecurityDef,
+    #[serde(default)]
+    pub package: PackageDef,
+    #[serde(default)]
+    pub publish: PublishDef,
+    #[serde(default)]
+    pub resources: ResourcesDef,
+    #[serde(default)]
+    pub run: RunDef,
+    /// `${name}` values usable in the dependency versions
+    #[serde(default)]
+    pub properties: Properties,
+}
+
+/// Dependency scopes as they appear in the manifest
+pub const DEPENDENCY_SCOPES: [&str; 5] =
+    ["compile", "runtime", "compileRuntime", "transitive", "test"];
+
+/// Normalize a manifest document while keeping comments and formatting intact.
+/// In each dependency scope : complete notations are rewritten to the short notation,
+/// duplicates are merged (the last declaration wins) and entries are sorted.
+/// Applying it multiple times gives the same result.
+pub fn normalize_manifest(document: &str) -> Result<String> {
+    let mut doc: Document = document.parse()?;
+    if let Some(deps) = doc
+        .as_table_mut()
+        .get_mut("dependencies")
+        .and_then(Item::as_table_like_mut)
+    {
+        for scope in DEPENDENCY_SCOPES {
+            if let Some(array) = deps.get_mut(scope).and_then(Item::as_array_mut) {
+                normalize_dependency_array(array);
+            }
+        }
+    }
+    Ok(doc.to_string())
+}
+
+fn normalize_dependency_array(array: &mut Array) {
+    let mut entries: Vec<(String, Value)> = Vec::with_capacity(array.len());
+    for value in array.iter() {
+        let (key, normalized) = normalize_dependency_value(value);
+        if let Some(existing) = entries.iter_mut().find(|(k, _)| *k == key) {
+            // Keep the comments of the first declaration
+            let decor = existing.1.decor().clone();
+            existing.1 = normalized;
+            *existing.1.decor_mut() = decor;
+        } else {
+            entries.push((key, normalized));
+        }
+    }
+    entries.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+    let multiline = entries
+        .iter()
+        .any(|(_, v)| v.decor().prefix().map_or(false, |p| p.contains('\n')));
+    array.clear();
+    for (_, mut value) in entries {
+        if multiline && !value.decor().prefix().map_or(false, |p| p.contains('\n')) {
+            value.decor_mut().set_prefix("\n    ");
+        }
+        array.push_formatted(value);
+    }
+    if !multiline {
+        array.fmt();
+    }
+}
+
+/// Returns the group:artifact[:classifier] key of a dependency and its short notation
+fn normalize_dependency_value(value: &Value) -> (String, Value) {
+    let notation = match value {
+        Value::String(s) => Some(s.value().trim().to_string()),
+        Value::InlineTable(table) if table.len() == 3 => {
+            match (
+                table.get("group").and_then(Value::as_str),
+                table.get("artifact").and_then(Value::as_str),
+                table.get("version").and_then(Value::as_str),
+            ) {
+                (Some(g), Some(a), Some(v)) => Some(format!("{}:{}:{}", g, a, v)),
+                _ => None,
+            }
+        }
+        _ => None,
+    };
+    match notation {
+        Some(notation) => {
+            // group:artifact, versionless dependencies included, and the classifier
+            let parts: Vec<_> = notation.split(':').collect();
+            let mut key = parts.iter().take(2).copied().collect::<Vec<_>>().join(":");
+            if let Some(classifier) = parts.get(3) {
+                key = format!("{}:{}", key, classifier);
+            }
+            let mut normalized = Value::from(notation);
+            *normalized.decor_mut() = value.decor().clone();
+            (key, normalized)
+        }
+        // Leave anything we don't understand as is
+        None => (value.to_string().trim().to_string(), value.clone()),
+    }
+}
+
+/// Keys allowed at the root of a manifest, `workspace` and `http` are only read from the root one
+const ROOT_KEYS: [&str; 21] = [
+    "group",
+    "artifact",
+    "version",
+    "version_from",
+    "authors",
+    "license",
+    "entrypoints",
+    "dependencies",
+    "java",
+    "javac",
+    "kotlin",
+    "jvm",
+    "security",
+    "package",
+    "publish",
+    "resources",
+    "run",
+    "workspace",
+    "http",
+    "properties",
+    "repositories",
+];
+
+/// Reject the misspelled root keys, which serde can't do because of the flattened `ExtraInfo`
+fn check_root_keys(document: &str) -> Result<()> {
+    let value: toml::Value = toml::from_str(document).map_err(|e| manifest_error(document, e))?;
+    let table = match value.as_table() {
+        Some(table) => table,
+        None => return Ok(()),
+    };
+    match table.keys().find(|key| !ROOT_KEYS.contains(&key.as_str())) {
+        Some(key) => {
+            let line = document.lines().position(|line| {
+                line.trim_start()
+                    .strip_prefix(key.as_str())
+                    .map_or(false, |rest| rest.trim_start().starts_with(['=', '.']))
+            });
+            Err(with_excerpt(
+                document,
+                format!("unknown key `{}`", key),
+                line,
+            ))
+        }
+        None => Ok(()),
+    }
+}
+
+/// Reword a deserialization error and show the offending line
+fn manifest_error(document: &str, error: toml::de::Error) -> anyhow::Error {
+    with_excerpt(
+        document,
+        reword(&error.to_string()),
+        error.line_col().map(|(line, _)| line),
+    )
+}
+
+/// Serde's invalid type errors, with what is expected first
+fn reword(error: &str) -> String {
+    let invalid_type = regex!(
+        r"^invalid type: (?P<found>.+?), expected (?P<expected>.+?)(?P<rest> (for key|at line) .*)?$"
+    );
+    invalid_type
+        .replace(error, "expected $expected, found $found$rest")
+        .into_owned()
+}
+
+fn with_excerpt(document: &str, message: String, line: Option<usize>) -> anyhow::Error {
+    match line.and_then(|line| Some((line, document.lines().nth(line)?))) {
+        Some((line, text)) => anyhow!("{}\n{:>4} | {}", message, line + 1, text.trim_end()),
+        None => anyhow!(message),
+    }
 }
 
 impl ModuleManifest {
     /// If parent is None, the manifest is the root manifest
     pub fn parse(document: &str, parent: Option<&ModuleManifest>) -> Result<Self> {
-        let mut document: ModuleManifest = toml::from_str(document)?;
+        let mut manifest = Self::parse_with_properties(document, parent.map(|it| &it.properties))?;
         if let Some(parent) = parent {
-            if document.group.is_none() {
-                document.group = parent.group.clone();
+            if manifest.group.is_none() {
+                manifest.group = parent.group.clone();
             }
         }
-        Ok(document)
+        Ok(manifest)
     }
 
-    pub fn validate(&self) -> bool {
-        todo!()
+    fn parse_with_properties(document: &str, parent: Option<&Properties>) -> Result<Self> {
+        check_root_keys(document)?;
+        let mut value: toml::Value =
+            toml::from_str(document).map_err(|e| manifest_error(document, e))?;
+        if resolve_properties(&mut value, parent)? {
+            Ok(value.try_into()?)
+        } else {
+            // Straight from the document to keep the line numbers in the errors
+            toml::from_str(document).map_err(|e| manifest_error(document, e))
+        }
+    }
+
+    /// Parse a manifest with some values replaced (`--config`), the `parent` properties are those
+    /// of the workspace root
+    pub fn parse_with_overrides(
+        document: &str,
+        overrides: &[ConfigOverride],
+        parent: Option<&Properties>,
+    ) -> Result<Self> {
+        if overrides.is_empty() {
+            return Self::parse_with_properties(document, parent);
+        }
+        check_root_keys(document)?;
+        let mut value: toml::Value =
+            toml::from_str(document).map_err(|e| manifest_error(document, e))?;
+        for it in overrides {
+            it.apply(&mut value, parent)?;
+        }
+        resolve_properties(&mut value, parent)?;
+        Ok(value.try_into()?)
+    }
+}
+
+/// Replace the `${name}` in the dependency versions with the `[properties]`, those of the
+/// `parent` apply unless overridden. Whether anything was replaced.
+fn resolve_properties(value: &mut toml::Value, parent: Option<&Properties>) -> Result<bool> {
+    let mut properties = parent.cloned().unwrap_or_default();
+    if let Some(own) = value.get("properties").and_then(|it| it.as_table()) {
+        for (name, it) in own {
+            if let Some(it) = it.as_str() {
+                properties.insert(name.clone(), it.to_string());
+            }
+        }
+    }
+    let project_version = value
+        .get("version")
+        .and_then(|it| it.as_str())
+        .unwrap_or_default()
+        .to_string();
+    let scopes = match value
+        .get_mut("dependencies")
+        .and_then(|it| it.as_table_mut())
+    {
+        Some(scopes) => scopes,
+        None => return Ok(false),
+    };
+
+    let token = regex!(r"\$\{([^}]+)\}");
+    let mut replaced = false;
+    for (scope, deps) in scopes.iter_mut() {
+        for dep in deps.as_array_mut().into_iter().flatten() {
+            let text = match dep {
+                toml::Value::String(text) => text,
+                toml::Value::Table(table) => match table.get_mut("version") {
+                    Some(toml::Value::String(text)) => text,
+                    _ => continue,
+                },
+                _ => continue,
+            };
+            if !text.contains("${") {
+                continue;
+            }
+            if let Some(name) = token
+                .captures_iter(text)
+                .map(|it| it.get(1).unwrap().as_str())
+                .find(|it| *it != "project.version" && !properties.contains_key(*it))
+            {
+                return Err(anyhow!(
+                    "Undefined property '{}' in the {} dependency '{}'",
+                    name,
+                    scope,
+                    text
+                ));
+            }
+            *text = properties
+                .recurse_resolve(text, &project_version)
+                .into_owned();
+            replaced = true;
+        }
+    }
+    Ok(replaced)
+}
+
+/// A `key=value` manifest override, the key is dotted for nested tables
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfigOverride {
+    pub key: String,
+    pub value: String,
+}
+
+impl FromStr for ConfigOverride {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (key, value) = s
+            .split_once('=')
+            .ok_or_else(|| anyhow!("Expected KEY=VALUE, got '{}'", s))?;
+        Ok(Self {
+            key: key.trim().to_string(),
+            value: value.to_string(),
+        })
+    }
+}
+
+impl ConfigOverride {
+    /// The value read as TOML, e.g. `true` or `["a:a:1"]`, text that isn't valid TOML is a string
+    fn toml_value(&self) -> toml::Value {
+        toml::from_str::<toml::value::Table>(&format!("v = {}", self.value))
+            .ok()
+            .and_then(|mut it| it.remove("v"))
+            .unwrap_or_else(|| toml::Value::String(self.value.clone()))
+    }
+
+    /// Set the key in `document`, checked against the manifest schema. A value of the wrong type
+    /// is retried as a string, so `java.target=11` is the string the field expects.
+    fn apply(&self, document: &mut toml::Value, parent: Option<&Properties>) -> Result<()> {
+        let root = self.key.split('.').next().unwrap_or_default();
+        if !ROOT_KEYS.contains(&root) {
+            return Err(anyhow!("Unknown manifest key '{}'", self.key));
+        }
+        let value = self.toml_value();
+        let mut overridden = document.clone();
+        self.set(&mut overridden, value.clone())?;
+        if let Err(e) = self.check(&overridden, parent) {
+            let scalar = !matches!(
+                value,
+                toml::Value::String(_) | toml::Value::Array(_) | toml::Value::Table(_)
+            );
+            overridden = document.clone();
+            self.set(&mut overridden, toml::Value::String(self.value.clone()))?;
+            if !scalar || self.check(&overridden, parent).is_err() {
+                return Err(e);
+            }
+        }
+        *document = overridden;
+        Ok(())
+    }
+
+    /// Whether the manifest still deserializes, only the errors caused by this key count
+    fn check(&self, document: &toml::Value, parent: Option<&Properties>) -> Result<()> {
+        let mut document = document.clone();
+        resolve_properties(&mut document, parent)?;
+        let error = match document.try_into::<ModuleManifest>() {
+            Ok(_) => return Ok(()),
+            Err(e) => e.to_string(),
+        };
+        let (table, last) = match self.key.rsplit_once('.') {
+            Some((table, last)) => (Some(table), last),
+            None => (None, self.key.as_str()),
+        };
+        let unknown = format!("unknown field `{}`", last);
+        if error.starts_with(&unknown)
+            && table.map_or(true, |it| {
+                error.ends_with(&format!("for key `{}`", it))
+                    || error.contains(&format!("for key `{}` ", it))
+            })
+        {
+            Err(anyhow!("Unknown manifest key '{}'", self.key))
+        } else if error.contains(&format!("for key `{}`", self.key)) {
+            Err(anyhow!(
+                "Invalid value for '{}' : {}",
+                self.key,
+                reword(&error)
+            ))
+        } else {
+            // Not ours, reported when the whole manifest is deserialized
+            Ok(())
+        }
+    }
+
+    fn set(&self, document: &mut toml::Value, value: toml::Value) -> Result<()> {
+        let mut path: Vec<&str> = self.key.split('.').collect();
+        let last = path.pop().unwrap();
+        let mut table = document
+            .as_table_mut()
+            .ok_or_else(|| anyhow!("The manifest is not a table"))?;
+        for part in path {
+            table = table
+                .entry(part)
+                .or_insert_with(|| toml::Value::Table(Default::default()))
+                .as_table_mut()
+                .ok_or_else(|| anyhow!("'{}' is not a table", part))?;
+        }
+        // An explicit version takes precedence over where it would be derived from
+        if self.key == "version" {
+            table.remove("version_from");
+        }
+        table.insert(last.to_string(), value);
+        Ok(())
     }
 }
 
@@ -44,7 +441,424 @@ pub struct ExtraInfo {
     pub license: String,
 }
 
+/// Only the workspace related parts of a root manifest
+#[derive(Debug, Deserialize)]
+pub struct WorkspaceManifest {
+    /// Inherited by members without a group
+    pub group: Option<String>,
+    pub workspace: Option<WorkspaceDef>,
+    #[serde(default)]
+    pub http: HttpDef,
+    #[serde(default)]
+    pub repositories: Vec<RepositoryDef>,
+    /// Inherited by the members, which can override them
+    #[serde(default)]
+    pub properties: Properties,
+}
+
+impl WorkspaceManifest {
+    pub fn parse(document: &str) -> Result<Self> {
+        toml::from_str(document).map_err(|e| manifest_error(document, e))
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct WorkspaceDef {
+    /// Paths to the member modules, relative to the workspace root
+    pub members: Vec<String>,
+    /// Members processed when none is selected, all of them if empty
+    #[serde(default)]
+    pub default_members: Vec<String>,
+    /// Paths never loaded, even if listed in members
+    #[serde(default)]
+    pub exclude: Vec<String>,
+}
+
+/// Network settings, only read from the root manifest
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct HttpDef {
+    /// Url all the Maven Central requests are sent to instead
+    pub central_mirror: Option<String>,
+}
+
+/// A repository to resolve dependencies from, only read from the root manifest
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RepositoryDef {
+    pub name: String,
+    pub url: String,
+    /// `maven` or `ivy`
+    #[serde(default = "default_repo_layout")]
+    pub layout: String,
+    /// Ivy pattern of the artifact paths, relative to the url
+    pub pattern: Option<String>,
+}
+
+fn default_repo_layout() -> String {
+    "maven".to_string()
+}
+
+/// Java toolchain settings
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct JavaDef {
+    /// Source level passed to the compilers
+    #[serde(default = "default_java_level")]
+    pub source: String,
+    /// Bytecode level passed to the compilers
+    #[serde(default = "default_java_level")]
+    pub target: String,
+    /// Compile and run with the preview features of the `source` release
+    #[serde(default)]
+    pub enable_preview: bool,
+}
+
+impl Default for JavaDef {
+    fn default() -> Self {
+        Self {
+            source: default_java_level(),
+            target: default_java_level(),
+            enable_preview: false,
+        }
+    }
+}
+
+fn default_java_level() -> String {
+    "17".to_string()
+}
+
+/// Compiler invocation settings
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct JavacDef {
+    #[serde(default)]
+    pub classpath_order: ClasspathOrder,
+    /// Annotation processors to run, instead of discovering them on the classpath
+    #[serde(default)]
+    pub processors: Vec<String>,
+    /// Whether to compile, run the annotation processors, or both (javac default)
+    pub proc: Option<Proc>,
+    /// What to do with the classes of the sources javac finds on its own, javac generates them by
+    /// default
+    pub implicit: Option<Implicit>,
+    /// Raw classpath entries after the dependencies, relative to the module dir
+    #[serde(default)]
+    pub extra_classpath: Vec<String>,
+    /// Replaces the platform classes (`-bootclasspath`), only for `target` 8 and below
+    #[serde(default)]
+    pub bootclasspath: Vec<String>,
+    /// Heap of the compiler JVM, e.g. `"2g"`
+    pub max_heap: Option<String>,
+}
+
+impl JavacDef {
+    /// Unless `proc = "none"`
+    pub fn runs_processors(&self) -> bool {
+        self.proc != Some(Proc::None)
+    }
+
+    /// javac flags for the annotation processing settings
+    pub fn processor_args(&self) -> Vec<String> {
+        let mut args = Vec::new();
+        if !self.processors.is_empty() {
+            args.push("-processor".to_string());
+            args.push(self.processors.join(","));
+        }
+        if let Some(proc) = self.proc {
+            args.push(format!("-proc:{}", proc.as_str()));
+        }
+        args
+    }
+}
+
+/// kotlinc settings
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct KotlinDef {
+    /// Heap of the compiler JVM, e.g. `"2g"`
+    pub max_heap: Option<String>,
+}
+
+/// `-J-Xmx` flag of a compiler, javac and kotlinc pass `-J` flags to their JVM
+pub fn max_heap_args(max_heap: &Option<String>) -> Vec<String> {
+    max_heap.iter().map(|it| format!("-J-Xmx{}", it)).collect()
+}
+
+/// javac `-proc:` modes
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Proc {
+    /// Compile without annotation processing
+    None,
+    /// Only run the annotation processors
+    Only,
+    Full,
+}
+
+impl Proc {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Proc::None => "none",
+            Proc::Only => "only",
+            Proc::Full => "full",
+        }
+    }
+}
+
+/// javac `-implicit:` modes, for the sources found on the sourcepath rather than passed to javac
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Implicit {
+    /// Only the listed sources produce classes
+    None,
+    Class,
+}
+
+impl Implicit {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Implicit::None => "none",
+            Implicit::Class => "class",
+        }
+    }
+}
+
+/// Position of the output dir in the compile classpath
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ClasspathOrder {
+    /// Dependencies shadow the compiled classes
+    DepsFirst,
+    /// Freshly compiled classes shadow stale jar contents
+    ClassesFirst,
+}
+
+impl Default for ClasspathOrder {
+    fn default() -> Self {
+        ClasspathOrder::DepsFirst
+    }
+}
+
+/// Access to JDK internals, e.g. `add_exports = ["java.base/sun.nio.ch=ALL-UNNAMED"]`
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct JvmDef {
+    /// Root modules to resolve, at compile and run time
+    #[serde(default)]
+    pub add_modules: Vec<String>,
+    /// `module/package=target-module`, at compile and run time
+    #[serde(default)]
+    pub add_exports: Vec<String>,
+    /// `module/package=target-module`, deep reflection is only checked at run time
+    #[serde(default)]
+    pub add_opens: Vec<String>,
+}
+
+impl JvmDef {
+    /// Flags understood by javac
+    pub fn compiler_args(&self) -> Vec<String> {
+        let mut args = Vec::new();
+        push_flags(&mut args, "--add-modules", &self.add_modules);
+        push_flags(&mut args, "--add-exports", &self.add_exports);
+        args
+    }
+
+    /// Flags understood by the java launcher
+    pub fn launcher_args(&self) -> Vec<String> {
+        let mut args = self.compiler_args();
+        push_flags(&mut args, "--add-opens", &self.add_opens);
+        args
+    }
+}
+
+fn push_flags(args: &mut Vec<String>, flag: &str, values: &[String]) {
+    for value in values {
+        args.push(flag.to_string());
+        args.push(value.clone());
+    }
+}
+
+/// `jcargo run` settings
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RunDef {
+    /// Name or class of the entrypoint run when none is given
+    pub default: Option<String>,
+    /// Java assertions, all of them or only in some packages and classes
+    #[serde(default)]
+    pub assertions: AssertionsDef,
+}
+
+/// `true`, or the scopes of `-ea:`, e.g. `["com.example...", "com.example.Main"]`
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(untagged)]
+pub enum AssertionsDef {
+    Enabled(bool),
+    Scopes(Vec<String>),
+}
+
+impl Default for AssertionsDef {
+    fn default() -> Self {
+        AssertionsDef::Enabled(false)
+    }
+}
+
+impl AssertionsDef {
+    /// From `--enable-assertions`, enabled everywhere without scopes
+    pub fn from_scopes(scopes: &[String]) -> Self {
+        if scopes.is_empty() {
+            AssertionsDef::Enabled(true)
+        } else {
+            AssertionsDef::Scopes(scopes.to_vec())
+        }
+    }
+
+    pub fn args(&self) -> Vec<String> {
+        match self {
+            AssertionsDef::Enabled(true) => vec!["-ea".to_string()],
+            AssertionsDef::Enabled(false) => Vec::new(),
+            AssertionsDef::Scopes(scopes) => {
+                scopes.iter().map(|it| format!("-ea:{}", it)).collect()
+            }
+        }
+    }
+}
+
+/// How `resources` is copied to the classes
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ResourcesDef {
+    /// Replace `${project.version}` and the like in the text resources
+    #[serde(default)]
+    pub filter: bool,
+    /// Files or directories relative to `resources`, only these are filtered when set
+    #[serde(default)]
+    pub filtered: Vec<String>,
+    /// Files or directories relative to `resources` that are never filtered
+    #[serde(default)]
+    pub unfiltered: Vec<String>,
+}
+
+impl ResourcesDef {
+    /// Whether the resource at `path`, relative to `resources`, must be filtered
+    pub fn is_filtered(&self, path: &Path) -> bool {
+        let listed = |paths: &[String]| paths.iter().any(|it| path.starts_with(it));
+        self.filter
+            && (self.filtered.is_empty() || listed(&self.filtered))
+            && !listed(&self.unfiltered)
+    }
+}
+
+/// `jcargo package` settings
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PackageDef {
+    /// Classified jars always packaged, and so deployed, next to the main one
+    #[serde(default)]
+    pub classifiers: Vec<Classifier>,
+}
+
+/// Extra jars attached to the main artifact, named `<artifact>-<version>-<classifier>.jar`
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Classifier {
+    /// The compiled test classes
+    Tests,
+}
+
+impl Classifier {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Classifier::Tests => "tests",
+        }
+    }
+}
+
+impl FromStr for Classifier {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "tests" => Ok(Classifier::Tests),
+            other => Err(format!(
+                "Can't package the classifier {}, only tests is supported",
+                other
+            )),
+        }
+    }
+}
+
+/// Where `deploy` uploads the artifacts
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PublishDef {
+    /// Repository receiving releases
+    pub repository: Option<String>,
+    /// Repository receiving `-SNAPSHOT` versions
+    pub snapshot_repository: Option<String>,
+    /// Prefix of the environment variables holding the credentials,
+    /// `credentials = "NEXUS"` reads `NEXUS_USERNAME` and `NEXUS_PASSWORD`,
+    /// or the id of a server in the maven settings.xml
+    pub credentials: Option<String>,
+}
+
+impl PublishDef {
+    /// The repository to deploy `version` to, snapshots never go to the release repository
+    pub fn repository_for(&self, version: &str) -> Result<Url> {
+        let (repo, key) = if version.ends_with("-SNAPSHOT") {
+            (&self.snapshot_repository, "snapshot_repository")
+        } else {
+            (&self.repository, "repository")
+        };
+        let repo = repo
+            .as_ref()
+            .ok_or_else(|| anyhow!("No publish.{} to deploy version {}", key, version))?;
+        // Without the trailing slash, joining paths would replace the last segment
+        let mut url = Url::parse(repo).map_err(|e| anyhow!("Invalid publish.{} : {}", key, e))?;
+        if !url.path().ends_with('/') {
+            url.set_path(&format!("{}/", url.path()));
+        }
+        Ok(url)
+    }
+
+    /// Username and password from the environment, or else from the settings.xml servers,
+    /// if credentials are configured
+    pub fn credentials(
+        &self,
+        settings: Option<&MavenSettings>,
+    ) -> Result<Option<(String, String)>> {
+        let reference = match &self.credentials {
+            Some(reference) => reference,
+            None => return Ok(None),
+        };
+        let var = |suffix: &str| std::env::var(format!("{}_{}", reference, suffix)).ok();
+        if let (Some(username), Some(password)) = (var("USERNAME"), var("PASSWORD")) {
+            return Ok(Some((username, password)));
+        }
+        settings
+            .and_then(|it| it.credentials(reference))
+            .map(Some)
+            .ok_or_else(|| {
+                anyhow!(
+                    "Missing credentials '{0}', set {0}_USERNAME and {0}_PASSWORD or add a server to settings.xml",
+                    reference
+                )
+            })
+    }
+}
+
+/// Artifact verification settings
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SecurityDef {
+    /// Armored file with the public keys trusted to sign dependencies, relative to the module
+    pub keyring: Option<String>,
+}
+
 #[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct EntrypointDef {
     /// Name used when invoking the run task
     #[serde(default)]
@@ -54,15 +868,25 @@ pub struct EntrypointDef {
 }
 
 impl EntrypointDef {
+    /// Name if it has one, main class otherwise
+    pub fn label(&self) -> &str {
+        if self.name.is_empty() {
+            &self.class
+        } else {
+            &self.name
+        }
+    }
+
     pub fn validate(&self) -> bool {
         if self.name.contains(" ") {
             return false;
         }
-        return true;
+        true
     }
 }
 
 #[derive(Debug, Deserialize, Default)]
+#[serde(deny_unknown_fields)]
 pub struct DependenciesDef {
     #[serde(default)]
     pub compile: Vec<DependencyDef>,
@@ -72,34 +896,411 @@ pub struct DependenciesDef {
     pub compile_runtime: Vec<DependencyDef>,
     #[serde(default)]
     pub transitive: Vec<DependencyDef>,
+    /// Only used to compile and run tests, never published
+    #[serde(default)]
+    pub test: Vec<DependencyDef>,
+    /// Versions applied to the dependencies declared without one and to transitive dependencies
+    #[serde(default)]
+    pub platform: Vec<DependencyDef>,
+    /// BOMs whose managed versions apply like the platform ones, after them
+    #[serde(default)]
+    pub bom: Vec<DependencyDef>,
+    /// Gradle version catalog the `alias` dependencies come from, relative to the module,
+    /// `gradle/libs.versions.toml` by default
+    pub catalog: Option<String>,
+    /// Repositories by scope, tried before the global ones for the dependencies of that scope
+    /// and their transitive dependencies
+    #[serde(default)]
+    pub repositories: BTreeMap<String, Vec<RepositoryDef>>,
 }
 
-#[derive(Debug, Deserialize)]
-#[serde(untagged)]
+#[derive(Debug)]
 pub enum DependencyDef {
     ShortNotation(String),
     CompleteNotation(CompleteDependencyDef),
+    /// `{ alias = "junit.jupiter" }`, a library of the version catalog
+    CatalogAlias(String),
+}
+
+/// Either notation of a table, told apart once deserialized
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct DependencyTable {
+    alias: Option<String>,
+    group: Option<String>,
+    artifact: Option<String>,
+    #[serde(default)]
+    version: Option<VersionReq>,
+    #[serde(default)]
+    classifier: Option<String>,
+    #[serde(default)]
+    jdk_classifiers: Vec<String>,
 }
 
-#[derive(Debug, Deserialize, Clone)]
+// Not untagged, so the errors of the complete notation aren't swallowed
+impl<'de> Deserialize<'de> for DependencyDef {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct DependencyVisitor;
+
+        impl<'de> Visitor<'de> for DependencyVisitor {
+            type Value = DependencyDef;
+
+            fn expecting(&self, f: &mut Formatter) -> std::fmt::Result {
+                f.write_str("a `group:artifact:version` string or a table")
+            }
+
+            fn visit_str<E: de::Error>(self, v: &str) -> Result<Self::Value, E> {
+                Ok(DependencyDef::ShortNotation(v.to_string()))
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, map: A) -> Result<Self::Value, A::Error> {
+                let table = DependencyTable::deserialize(MapAccessDeserializer::new(map))?;
+                if let Some(alias) = table.alias {
+                    if table.group.is_some() || table.artifact.is_some() || table.version.is_some()
+                    {
+                        return Err(de::Error::custom(
+                            "an alias dependency takes its coordinate from the catalog",
+                        ));
+                    }
+                    return Ok(DependencyDef::CatalogAlias(alias));
+                }
+                Ok(DependencyDef::CompleteNotation(CompleteDependencyDef {
+                    group: table
+                        .group
+                        .ok_or_else(|| de::Error::missing_field("group"))?,
+                    artifact: table
+                        .artifact
+                        .ok_or_else(|| de::Error::missing_field("artifact"))?,
+                    version: table.version,
+                    classifier: table.classifier,
+                    jdk_classifiers: table.jdk_classifiers,
+                }))
+            }
+        }
+
+        deserializer.deserialize_any(DependencyVisitor)
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct CompleteDependencyDef {
     pub group: String,
     pub artifact: String,
-    pub version: VersionReq,
+    /// None to use the version of the `platform`
+    pub version: Option<VersionReq>,
+    pub classifier: Option<String>,
+    /// Per JDK classifiers (e.g. `["jdk8", "jdk11"]`), the highest one up to `java.target` is used
+    pub jdk_classifiers: Vec<String>,
+}
+
+impl CompleteDependencyDef {
+    /// The explicit classifier, else the best of `jdk_classifiers` for the `target` level
+    pub fn classifier_for(&self, target: &str) -> Option<String> {
+        if self.classifier.is_some() {
+            return self.classifier.clone();
+        }
+        let target: u32 = target.trim_start_matches("1.").parse().ok()?;
+        self.jdk_classifiers
+            .iter()
+            .filter_map(|it| {
+                let level = it.strip_prefix("jdk")?;
+                let digits = level
+                    .find(|c: char| !c.is_ascii_digit())
+                    .unwrap_or(level.len());
+                Some((level[..digits].parse::<u32>().ok()?, it))
+            })
+            .filter(|(level, _)| *level <= target)
+            .max_by_key(|(level, _)| *level)
+            .map(|(_, it)| it.clone())
+    }
 }
 
-impl From<DependencyDef> for CompleteDependencyDef {
-    fn from(dd: DependencyDef) -> Self {
-        match dd {
+impl DependencyDef {
+    /// The complete notation, aliases are looked up in the `catalog`
+    pub fn complete(self, catalog: Option<&VersionCatalog>) -> Result<CompleteDependencyDef> {
+        match self {
             DependencyDef::ShortNotation(full) => {
-                let mut pieces = full.split(":");
-                Self {
-                    group: pieces.next().unwrap().to_string(),
-                    artifact: pieces.next().unwrap().to_string(),
-                    version: VersionReq::parse(pieces.next().unwrap()).unwrap(),
-                }
+                let mut pieces = full.split(':');
+                let (group, artifact) = match (pieces.next(), pieces.next()) {
+                    (Some(group), Some(artifact)) if !group.is_empty() && !artifact.is_empty() => {
+                        (group.to_string(), artifact.to_string())
+                    }
+                    _ => {
+                        return Err(anyhow!(
+                        "Invalid dependency '{}', expected group:artifact[:version[:classifier]]",
+                        full
+                    ))
+                    }
+                };
+                let version = pieces
+                    .next()
+                    .map(|it| {
+                        VersionReq::parse(it).map_err(|e| {
+                            anyhow!("Invalid version '{}' of dependency '{}' : {}", it, full, e)
+                        })
+                    })
+                    .transpose()?;
+                Ok(CompleteDependencyDef {
+                    group,
+                    artifact,
+                    version,
+                    classifier: pieces.next().map(str::to_string),
+                    jdk_classifiers: Vec::new(),
+                })
             }
-            DependencyDef::CompleteNotation(complete) => complete,
+            DependencyDef::CompleteNotation(complete) => Ok(complete),
+            DependencyDef::CatalogAlias(alias) => catalog
+                .ok_or_else(|| anyhow!("No version catalog to look up '{}' in", alias))?
+                .library(&alias),
         }
     }
+
+    fn is_alias(&self) -> bool {
+        matches!(self, DependencyDef::CatalogAlias(_))
+    }
+}
+
+impl DependenciesDef {
+    /// Whether a dependency references the version catalog
+    pub fn uses_catalog(&self) -> bool {
+        [
+            &self.compile,
+            &self.runtime,
+            &self.compile_runtime,
+            &self.transitive,
+            &self.test,
+            &self.platform,
+            &self.bom,
+        ]
+        .iter()
+        .any(|scope| scope.iter().any(DependencyDef::is_alias))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::manifest::{
+        normalize_manifest, AssertionsDef, DependencyDef, Implicit, ModuleManifest, PublishDef,
+    };
+
+    const UNSORTED: &str = r#"group = "marais"
+artifact = "fix"
+version = "0.1.0"
+
+# Dependencies of the project
+[dependencies]
+compileRuntime = [
+    # Logging implementation
+    "org.slf4j:slf4j-simple:1.7.32",
+    { group = "org.apache.logging.log4j", artifact = "log4j-api", version = "2.17.1" },
+    "com.google.guava:guava:31.0.1-jre",
+    "org.apache.logging.log4j:log4j-api:2.17.0",
+]
+runtime = ["b:b:1", "a:a:1"]
+"#;
+
+    #[test]
+    fn test_malformed_manifests() {
+        let error = |document: &str| {
+            ModuleManifest::parse(document, None)
+                .unwrap_err()
+                .to_string()
+        };
+        assert_eq!(
+            error("artifact = \"a\"\ngruop = \"marais\"\n"),
+            "unknown key `gruop`\n   2 | gruop = \"marais\""
+        );
+        assert_eq!(
+            error("artifact = \"a\"\n[java]\ntarget = 17\n"),
+            "expected a string, found integer `17` for key `java.target` at line 3 column 10\n   3 | target = 17"
+        );
+        assert!(error("artifact = \"a\"\n[java]\ntraget = \"17\"\n")
+            .starts_with(
+            "unknown field `traget`, expected one of `source`, `target`, `enable_preview` for key `java`"
+        ));
+        assert!(error(
+            "artifact = \"a\"\n[dependencies]\ncompile = [{ group = \"g\", artifact = \"a\", verison = \"1\" }]\n"
+        )
+        .starts_with("unknown field `verison`"));
+        assert!(
+            error("artifact = \"a\"\n[dependencies]\ncompile = [1]\n").starts_with(
+                "expected a `group:artifact:version` string or a table, found integer `1`"
+            )
+        );
+        assert!(error("group = \"marais\"\n").starts_with("missing field `artifact`"));
+    }
+
+    #[test]
+    fn test_invalid_short_notations() {
+        let error = |notation: &str| {
+            DependencyDef::ShortNotation(notation.to_string())
+                .complete(None)
+                .unwrap_err()
+                .to_string()
+        };
+        assert_eq!(
+            error("guava"),
+            "Invalid dependency 'guava', expected group:artifact[:version[:classifier]]"
+        );
+        assert!(error("org.hibernate:hibernate-core:5.6.5.Final").starts_with(
+            "Invalid version '5.6.5.Final' of dependency 'org.hibernate:hibernate-core:5.6.5.Final' : "
+        ));
+        let versionless = DependencyDef::ShortNotation("com.google.guava:guava".to_string())
+            .complete(None)
+            .unwrap();
+        assert_eq!(versionless.artifact, "guava");
+        assert!(versionless.version.is_none());
+    }
+
+    #[test]
+    fn test_normalize_sorts() {
+        let fixed = normalize_manifest(UNSORTED).unwrap();
+        assert_eq!(
+            fixed,
+            r#"group = "marais"
+artifact = "fix"
+version = "0.1.0"
+
+# Dependencies of the project
+[dependencies]
+compileRuntime = [
+    "com.google.guava:guava:31.0.1-jre",
+    "org.apache.logging.log4j:log4j-api:2.17.0",
+    # Logging implementation
+    "org.slf4j:slf4j-simple:1.7.32",
+]
+runtime = ["a:a:1", "b:b:1"]
+"#
+        );
+    }
+
+    #[test]
+    fn test_normalize_keeps_classifiers() {
+        let fixed = normalize_manifest(
+            r#"[dependencies]
+compile = ["org.lwjgl:lwjgl:3.3.1:natives-windows", "org.lwjgl:lwjgl:3.3.1", "org.lwjgl:lwjgl:3.3.1:natives-linux", "org.lwjgl:lwjgl:3.3.1"]
+"#,
+        )
+        .unwrap();
+        assert_eq!(
+            fixed,
+            r#"[dependencies]
+compile = ["org.lwjgl:lwjgl:3.3.1", "org.lwjgl:lwjgl:3.3.1:natives-linux", "org.lwjgl:lwjgl:3.3.1:natives-windows"]
+"#
+        );
+    }
+
+    #[test]
+    fn test_normalize_idempotent() {
+        let once = normalize_manifest(UNSORTED).unwrap();
+        let twice = normalize_manifest(&once).unwrap();
+        assert_eq!(once, twice);
+    }
+
+    #[test]
+    fn test_config_overrides() {
+        let manifest = ModuleManifest::parse_with_overrides(
+            UNSORTED,
+            &[
+                "version=1.2.3".parse().unwrap(),
+                "java.target=11".parse().unwrap(),
+                r#"dependencies.runtime=["c:c:1"]"#.parse().unwrap(),
+            ],
+            None,
+        )
+        .unwrap();
+        assert_eq!(manifest.version.as_deref(), Some("1.2.3"));
+        assert_eq!(manifest.java.target, "11");
+        assert_eq!(manifest.java.source, "17");
+        assert_eq!(manifest.dependencies.runtime.len(), 1);
+
+        let manifest = ModuleManifest::parse_with_overrides(
+            UNSORTED,
+            &[
+                "java.enable_preview=true".parse().unwrap(),
+                "javac.implicit=none".parse().unwrap(),
+                r#"run.assertions=["marais..."]"#.parse().unwrap(),
+            ],
+            None,
+        )
+        .unwrap();
+        assert!(manifest.java.enable_preview);
+        assert_eq!(manifest.javac.implicit, Some(Implicit::None));
+        assert_eq!(
+            manifest.run.assertions,
+            AssertionsDef::Scopes(vec!["marais...".to_string()])
+        );
+
+        let error = |it: &str| {
+            ModuleManifest::parse_with_overrides(UNSORTED, &[it.parse().unwrap()], None)
+                .unwrap_err()
+                .to_string()
+        };
+        assert_eq!(
+            error("java.release=11"),
+            "Unknown manifest key 'java.release'"
+        );
+        assert_eq!(error("verison=1.0"), "Unknown manifest key 'verison'");
+        assert_eq!(
+            error("java.enable_preview=yes"),
+            "Invalid value for 'java.enable_preview' : expected a boolean, found string \"yes\" for key `java.enable_preview`"
+        );
+    }
+
+    #[test]
+    fn test_version_properties() {
+        let manifest = ModuleManifest::parse(
+            r#"artifact = "a"
+version = "0.3.0"
+[properties]
+"junit.version" = "5.8.2"
+[dependencies]
+test = ["org.junit.jupiter:junit-jupiter:${junit.version}"]
+compile = [{ group = "marais", artifact = "core", version = "${project.version}" }]
+"#,
+            None,
+        )
+        .unwrap();
+        let versions: Vec<_> = manifest
+            .dependencies
+            .test
+            .into_iter()
+            .chain(manifest.dependencies.compile)
+            .map(|it| it.complete(None).unwrap().version.unwrap().to_string())
+            .collect();
+        assert_eq!(versions, ["^5.8.2", "^0.3.0"]);
+
+        let err = ModuleManifest::parse(
+            "artifact = \"a\"\n[dependencies]\ntest = [\"g:a:${junit.version}\"]\n",
+            None,
+        )
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Undefined property 'junit.version' in the test dependency 'g:a:${junit.version}'"
+        );
+    }
+
+    #[test]
+    fn test_publish_repository() {
+        let publish = PublishDef {
+            repository: Some("https://repo.example.com/releases".to_string()),
+            snapshot_repository: Some("https://repo.example.com/snapshots/".to_string()),
+            credentials: None,
+        };
+        assert_eq!(
+            publish.repository_for("0.2.0-SNAPSHOT").unwrap().as_str(),
+            "https://repo.example.com/snapshots/"
+        );
+        assert_eq!(
+            publish.repository_for("0.2.0").unwrap().as_str(),
+            "https://repo.example.com/releases/"
+        );
+
+        let releases_only = PublishDef {
+            snapshot_repository: None,
+            ..publish
+        };
+        assert!(releases_only.repository_for("0.2.0-SNAPSHOT").is_err());
+    }
 }
@@ -0,0 +1,201 @@
+use serde::{Deserialize, Serialize};
+
+use crate::dependencies::Dependency;
+use crate::module::Module;
+use crate::workspace::Workspace;
+use crate::Env;
+
+/// Bumped on every breaking change to the metadata format
+pub const METADATA_VERSION: u32 = 1;
+
+/// Resolved project model, meant to be consumed by other tools
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Metadata {
+    pub version: u32,
+    pub workspace_root: String,
+    pub repositories: Vec<RepositoryMetadata>,
+    pub modules: Vec<ModuleMetadata>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RepositoryMetadata {
+    pub name: String,
+    pub url: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ModuleMetadata {
+    pub group: String,
+    pub artifact: String,
+    pub version: String,
+    pub root: String,
+    pub entrypoints: Vec<EntrypointMetadata>,
+    pub dependencies: Vec<DependencyMetadata>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct EntrypointMetadata {
+    pub name: String,
+    pub class: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DependencyMetadata {
+    /// Scope as written in the manifest (e.g. compileRuntime)
+    pub scope: String,
+    /// One of maven, git, local or prebuilt
+    pub kind: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub group: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub artifact: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub repository: Option<String>,
+    /// Where the dependency classes are on disk
+    pub path: String,
+}
+
+impl Metadata {
+    pub fn new(workspace: &Workspace, env: &Env) -> Self {
+        Self {
+            version: METADATA_VERSION,
+            workspace_root: workspace.root.display().to_string(),
+            repositories: env
+                .repos
+                .iter()
+                .map(|it| RepositoryMetadata {
+                    name: it.name.clone(),
+                    url: it.url.to_string(),
+                })
+                .collect(),
+            modules: workspace.members.iter().map(ModuleMetadata::new).collect(),
+        }
+    }
+}
+
+impl ModuleMetadata {
+    pub fn new(module: &Module) -> Self {
+        let deps = &module.dependencies;
+        let scoped = [
+            ("compile", &deps.compile),
+            ("runtime", &deps.runtime),
+            ("compileRuntime", &deps.compile_runtime),
+            ("transitive", &deps.transitive),
+            ("test", &deps.test),
+        ];
+        Self {
+            group: module.group.clone(),
+            artifact: module.artifact.clone(),
+            version: module.version.clone(),
+            root: module.dir.display().to_string(),
+            entrypoints: module
+                .entrypoints
+                .iter()
+                .map(|it| EntrypointMetadata {
+                    name: it.name.clone(),
+                    class: it.class.clone(),
+                })
+                .collect(),
+            dependencies: scoped
+                .iter()
+                .flat_map(|(scope, deps)| {
+                    deps.iter()
+                        .map(move |it| DependencyMetadata::new(module, scope, it))
+                })
+                .collect(),
+        }
+    }
+}
+
+impl DependencyMetadata {
+    fn new(module: &Module, scope: &str, dep: &Dependency) -> Self {
+        let path = module.dir.join(dep.classpath()).display().to_string();
+        let (kind, coordinates) = match dep {
+            Dependency::MavenRepo(repodep) => (
+                "maven",
+                Some((
+                    repodep.coordinate.group.clone(),
+                    repodep.coordinate.artifact.clone(),
+                    repodep.coordinate.version.clone(),
+                    repodep.repo.name.clone(),
+                )),
+            ),
+            Dependency::JcargoGit(_) => ("git", None),
+            Dependency::JcargoLocal(_) => ("local", None),
+            Dependency::PrebuiltLocal(_) => ("prebuilt", None),
+        };
+        let (group, artifact, version, repository) = match coordinates {
+            Some((g, a, v, r)) => (Some(g), Some(a), Some(v), Some(r)),
+            None => (None, None, None, None),
+        };
+        Self {
+            scope: scope.to_string(),
+            kind: kind.to_string(),
+            group,
+            artifact,
+            version,
+            repository,
+            path,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use crate::manifest::ModuleManifest;
+    use crate::metadata::{Metadata, METADATA_VERSION};
+    use crate::workspace::Workspace;
+    use crate::{Env, Module};
+
+    #[test]
+    fn test_metadata_json() {
+        let env = Env::new(false);
+        let manifest = ModuleManifest::parse(
+            r#"
+            group = "marais"
+            artifact = "meta"
+            version = "0.3.0"
+
+            [dependencies]
+            compileRuntime = ["org.apache.logging.log4j:log4j-api:2.17.1"]
+            runtime = [{ group = "org.apache.logging.log4j", artifact = "log4j-core", version = "2.17.1" }]
+
+            [[entrypoints]]
+            name = "app"
+            class = "Main"
+            "#,
+            None,
+        )
+        .unwrap();
+        let workspace = Workspace {
+            root: Path::new("meta").to_path_buf(),
+            members: vec![Module::from_manifest(Path::new("meta"), manifest, &env).unwrap()],
+            default_members: Vec::new(),
+        };
+
+        let json = serde_json::to_string(&Metadata::new(&workspace, &env)).unwrap();
+        let metadata: Metadata = serde_json::from_str(&json).unwrap();
+
+        assert_eq!(metadata.version, METADATA_VERSION);
+        assert_eq!(metadata.repositories[0].name, "maven-central");
+        let module = &metadata.modules[0];
+        assert_eq!(module.entrypoints[0].class, "Main");
+        assert_eq!(module.dependencies.len(), 2);
+        let core = module
+            .dependencies
+            .iter()
+            .find(|it| it.scope == "runtime")
+            .unwrap();
+        assert_eq!(core.group.as_deref(), Some("org.apache.logging.log4j"));
+        assert_eq!(core.artifact.as_deref(), Some("log4j-core"));
+        assert_eq!(core.version.as_deref(), Some("2.17.1"));
+        assert_eq!(
+            Path::new(&core.path),
+            Path::new("meta/libs/log4j-core-2.17.1.jar")
+        );
+    }
+}
@@ -1,10 +1,15 @@
+use std::io::{self, Write};
 use std::path::{Path, PathBuf};
+use std::process;
 
-use anyhow::Result;
+use anyhow::{anyhow, Context, Result};
 use tokio::fs;
 
 use crate::dependencies::{Dependencies, Dependency};
-use crate::manifest::{EntrypointDef, ModuleManifest};
+use crate::manifest::{
+    EntrypointDef, JavaDef, JavacDef, JvmDef, KotlinDef, ModuleManifest, PackageDef, PublishDef,
+    ResourcesDef, RunDef, SecurityDef,
+};
 use crate::Env;
 
 pub struct CompilationUnit {
@@ -25,19 +30,116 @@ pub struct Module {
     pub version: String,
     pub entrypoints: Vec<EntrypointDef>,
     pub dependencies: Dependencies,
+    pub java: JavaDef,
+    pub javac: JavacDef,
+    pub kotlin: KotlinDef,
+    pub jvm: JvmDef,
+    pub security: SecurityDef,
+    pub package: PackageDef,
+    pub publish: PublishDef,
+    pub resources: ResourcesDef,
+    pub run: RunDef,
+}
+
+/// Name of the manifest file of a module or workspace
+pub const MANIFEST_FILE: &str = "jcargo.toml";
+
+/// The closest directory from `start` up to the filesystem root with a manifest, like cargo
+pub fn find_manifest_dir(start: &Path) -> Result<PathBuf> {
+    let start = start
+        .canonicalize()
+        .with_context(|| format!("Can't access {}", start.display()))?;
+    start
+        .ancestors()
+        .find(|dir| dir.join(MANIFEST_FILE).is_file())
+        .map(Path::to_path_buf)
+        .ok_or_else(|| missing_manifest(&start))
+}
+
+/// Read the manifest of the module or workspace in `dir`
+pub async fn read_manifest(dir: &Path) -> Result<String> {
+    match fs::read_to_string(dir.join(MANIFEST_FILE)).await {
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Err(missing_manifest(dir)),
+        res => Ok(res?),
+    }
+}
+
+/// There is no manifest in the directory, or in its parents when looking for one
+#[derive(Debug, thiserror::Error)]
+#[error("no {} found in {}; run `jcargo init` to create one", MANIFEST_FILE, .0.display())]
+pub struct MissingManifest(pub PathBuf);
+
+fn missing_manifest(dir: &Path) -> anyhow::Error {
+    MissingManifest(dir.to_path_buf()).into()
 }
 
 impl Module {
     pub async fn load(path: &Path, env: &Env) -> Result<Self> {
-        let document = fs::read_to_string(path.join("jcargo.toml")).await?;
-        let manifest = ModuleManifest::parse(&document, None)?;
+        let document = read_manifest(path).await?;
+        Self::from_document(&document, &path.join(MANIFEST_FILE), path, env)
+    }
+
+    /// Load the manifest `file` instead of the `jcargo.toml` of the module rooted at `root`
+    pub async fn load_from(file: &Path, root: &Path, env: &Env) -> Result<Self> {
+        let document = fs::read_to_string(file)
+            .await
+            .with_context(|| format!("Can't read {}", file.display()))?;
+        Self::from_document(&document, file, root, env)
+    }
+
+    /// `document` is the content of the manifest `file`
+    pub(crate) fn from_document(
+        document: &str,
+        file: &Path,
+        root: &Path,
+        env: &Env,
+    ) -> Result<Self> {
+        let manifest = ModuleManifest::parse_with_overrides(document, &env.config, None)
+            .with_context(|| format!("Invalid {}", file.display()))?;
+        Self::from_manifest(root, manifest, env)
+    }
+
+    /// Create a module rooted at `path` from an already parsed manifest
+    pub fn from_manifest(path: &Path, manifest: ModuleManifest, env: &Env) -> Result<Self> {
+        let group = manifest.group.clone().ok_or_else(|| {
+            anyhow!(
+                "Missing `group` (required in the root manifest or inherited from the workspace)"
+            )
+        })?;
+        let version = resolve_version(path, &manifest)?;
+        if let Some(default) = &manifest.run.default {
+            if !manifest
+                .entrypoints
+                .iter()
+                .any(|it| &it.name == default || &it.class == default)
+            {
+                return Err(anyhow!(
+                    "The default entrypoint '{}' of [run] doesn't exist",
+                    default
+                ));
+            }
+        }
         Ok(Self {
             dir: path.to_path_buf(),
-            group: manifest.group.unwrap(),
+            group,
             artifact: manifest.artifact,
-            version: manifest.version,
+            version,
             entrypoints: manifest.entrypoints,
-            dependencies: Dependencies::from_def(manifest.dependencies, env),
+            dependencies: Dependencies::from_def(
+                manifest.dependencies,
+                path,
+                &manifest.java.target,
+                env,
+            )?,
+            java: manifest.java,
+            javac: manifest.javac,
+            kotlin: manifest.kotlin,
+            jvm: manifest.jvm,
+            security: manifest.security,
+            package: manifest.package,
+            publish: manifest.publish,
+            resources: manifest.resources,
+            run: manifest.run,
         })
     }
 
@@ -50,15 +152,58 @@ impl Module {
             .or_else(|| self.entrypoints.iter().find(|it| it.class == name))
     }
 
-    /// Pick the first in the list.
-    pub fn pick_entrypoint(&self) -> Option<&EntrypointDef> {
-        self.entrypoints.first()
+    /// Entrypoint to use when none is named, `[run] default` if set.
+    /// When there are several, the user chooses one if `interactive`, it's an error otherwise.
+    pub fn pick_entrypoint(&self, interactive: bool) -> Result<&EntrypointDef> {
+        if let Some(default) = &self.run.default {
+            return self.find_entrypoint(default).ok_or_else(|| {
+                anyhow!(
+                    "The default entrypoint '{}' of [run] doesn't exist",
+                    default
+                )
+            });
+        }
+        match self.entrypoints.as_slice() {
+            [] => Err(anyhow!("No entrypoint defined")),
+            [entrypoint] => Ok(entrypoint),
+            entrypoints if interactive => Ok(&entrypoints[prompt_entrypoint(entrypoints)?]),
+            entrypoints => Err(anyhow!(
+                "Multiple entrypoints, specify one of : {}",
+                entrypoints
+                    .iter()
+                    .map(EntrypointDef::label)
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            )),
+        }
     }
 
     pub fn source_dir(&self) -> PathBuf {
         self.dir.join("src")
     }
 
+    /// Common sources of the kotlin multiplatform layout
+    pub fn common_source_dir(&self) -> PathBuf {
+        self.source_dir().join("commonMain")
+    }
+
+    /// Source directories compiled for the JVM : `src`, or `src/commonMain` and `src/jvmMain`
+    /// with the kotlin multiplatform layout, where the other source sets target other platforms
+    pub fn jvm_source_dirs(&self) -> Vec<PathBuf> {
+        let common = self.common_source_dir();
+        let jvm = self.source_dir().join("jvmMain");
+        if common.is_dir() || jvm.is_dir() {
+            vec![common, jvm]
+        } else {
+            vec![self.source_dir()]
+        }
+    }
+
+    /// Test sources, compiled separately from the module sources
+    pub fn test_source_dir(&self) -> PathBuf {
+        self.dir.join("test")
+    }
+
     pub fn resources_dir(&self) -> PathBuf {
         self.dir.join("resources")
     }
@@ -71,6 +216,24 @@ impl Module {
         self.target_dir().join("classes")
     }
 
+    pub fn test_classes_dir(&self) -> PathBuf {
+        self.target_dir().join("test-classes")
+    }
+
+    /// Where the annotation processors write their sources (javac `-s`)
+    pub fn generated_sources_dir(&self) -> PathBuf {
+        self.target_dir().join("generated-sources")
+    }
+
+    pub fn generated_test_sources_dir(&self) -> PathBuf {
+        self.target_dir().join("generated-test-sources")
+    }
+
+    /// JUnit XML reports of the last test run
+    pub fn test_results_dir(&self) -> PathBuf {
+        self.target_dir().join("test-results")
+    }
+
     pub fn docs_dir(&self) -> PathBuf {
         self.target_dir().join("docs")
     }
@@ -79,3 +242,520 @@ impl Module {
         self.target_dir().join("artifacts")
     }
 }
+
+/// Ask on the terminal which entrypoint to use, returns its index
+fn prompt_entrypoint(entrypoints: &[EntrypointDef]) -> Result<usize> {
+    println!("Multiple entrypoints available :");
+    for (i, entrypoint) in entrypoints.iter().enumerate() {
+        println!("  {}) {}", i + 1, entrypoint.label());
+    }
+    let stdin = io::stdin();
+    loop {
+        print!("Entrypoint to run [1-{}] : ", entrypoints.len());
+        io::stdout().flush()?;
+        let mut line = String::new();
+        if stdin.read_line(&mut line)? == 0 {
+            return Err(anyhow!("No entrypoint selected"));
+        }
+        match line.trim().parse::<usize>() {
+            Ok(i) if i >= 1 && i <= entrypoints.len() => return Ok(i - 1),
+            _ => println!("Invalid choice '{}'", line.trim()),
+        }
+    }
+}
+
+/// Version used when it can't be derived from git
+const UNKNOWN_VERSION: &str = "0.0.0-unknown";
+
+/// Get the module version, either from the manifest or from where the manifest tells us to look
+fn resolve_version(dir: &Path, manifest: &ModuleManifest) -> Result<String> {
+    match (
+        manifest.version.as_deref(),
+        manifest.version_from.as_deref(),
+    ) {
+        (Some(_), Some(_)) => Err(anyhow!(
+            "The manifest specifies both 'version' and 'version_from', keep only one"
+        )),
+        (None, Some("git-describe")) | (Some("git"), None) => {
+            Ok(git_describe(dir).unwrap_or_else(|| UNKNOWN_VERSION.to_string()))
+        }
+        (None, Some(from)) => {
+            if let Some(file) = from.strip_prefix("file:") {
+                let path = dir.join(file);
+                let version = std::fs::read_to_string(&path).with_context(|| {
+                    format!("Can't read {} (version_from = '{}')", path.display(), from)
+                })?;
+                Ok(version.trim().to_string())
+            } else {
+                Err(anyhow!(
+                    "Invalid version_from '{}', expected 'git-describe' or 'file:<path>'",
+                    from
+                ))
+            }
+        }
+        (Some(version), None) => Ok(version.to_string()),
+        (None, None) => Err(anyhow!(
+            "The manifest must specify either 'version' or 'version_from'"
+        )),
+    }
+}
+
+/// Version from the latest tag reachable in the git repo, without the leading 'v'
+fn git_describe(dir: &Path) -> Option<String> {
+    let output = process::Command::new("git")
+        .args(["describe", "--tags"])
+        .current_dir(dir)
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let tag = String::from_utf8(output.stdout).ok()?;
+    let tag = tag.trim();
+    Some(tag.strip_prefix('v').unwrap_or(tag).to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::Path;
+    use std::sync::Arc;
+
+    use anyhow::Result;
+
+    use crate::dependencies::{Dependency, MavenRepo, RepoLayout};
+    use crate::manifest::ModuleManifest;
+    use crate::module::find_manifest_dir;
+    use crate::test_utils::{response, temp_dir, MockServer};
+    use crate::{Env, Module};
+
+    #[test]
+    fn test_version_from_file() -> Result<()> {
+        let dir = std::env::temp_dir().join("jcargo-test-version-from-file");
+        fs::create_dir_all(&dir)?;
+        fs::write(dir.join("VERSION"), "2.4.1\n")?;
+        let manifest = ModuleManifest::parse(
+            r#"
+            group = "marais"
+            artifact = "versioned"
+            version_from = "file:VERSION"
+            "#,
+            None,
+        )?;
+        let module = Module::from_manifest(&dir, manifest, &Env::new(false))?;
+        assert_eq!(module.version, "2.4.1");
+        Ok(())
+    }
+
+    #[test]
+    fn test_version_from_errors() -> Result<()> {
+        let dir = std::env::temp_dir().join("jcargo-test-version-from-errors");
+        fs::create_dir_all(&dir)?;
+        let load = |versions: &str| {
+            let manifest = ModuleManifest::parse(
+                &format!(
+                    r#"
+                    group = "marais"
+                    artifact = "versioned"
+                    {}
+                    "#,
+                    versions
+                ),
+                None,
+            )?;
+            Module::from_manifest(&dir, manifest, &Env::new(false))
+        };
+
+        let e = load("version = \"1.0\"\nversion_from = \"file:VERSION\"").unwrap_err();
+        assert_eq!(
+            e.to_string(),
+            "The manifest specifies both 'version' and 'version_from', keep only one"
+        );
+        let e = load("version_from = \"file:VERSION\"").unwrap_err();
+        assert_eq!(
+            e.to_string(),
+            format!(
+                "Can't read {} (version_from = 'file:VERSION')",
+                dir.join("VERSION").display()
+            )
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_missing_group() -> Result<()> {
+        let manifest = ModuleManifest::parse(
+            r#"
+            artifact = "orphan"
+            version = "0.1.0"
+            "#,
+            None,
+        )?;
+        let e = Module::from_manifest(Path::new("orphan"), manifest, &Env::new(false)).unwrap_err();
+        assert_eq!(
+            e.to_string(),
+            "Missing `group` (required in the root manifest or inherited from the workspace)"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_version_from_git_fallback() -> Result<()> {
+        // Not a git repository
+        let dir = std::env::temp_dir().join("jcargo-test-version-from-git");
+        fs::create_dir_all(&dir)?;
+        let manifest = ModuleManifest::parse(
+            r#"
+            group = "marais"
+            artifact = "versioned"
+            version = "git"
+            "#,
+            None,
+        )?;
+        let module = Module::from_manifest(&dir, manifest, &Env::new(false))?;
+        assert_eq!(module.version, "0.0.0-unknown");
+        Ok(())
+    }
+
+    #[test]
+    fn test_ambiguous_entrypoint() -> Result<()> {
+        let manifest = ModuleManifest::parse(
+            r#"
+            group = "marais"
+            artifact = "multi"
+            version = "0.1.0"
+
+            [[entrypoints]]
+            class = "Main"
+
+            [[entrypoints]]
+            name = "Other"
+            class = "OtherMain"
+            "#,
+            None,
+        )?;
+        let module = Module::from_manifest(Path::new("multi"), manifest, &Env::new(false))?;
+        let err = module.pick_entrypoint(false).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Multiple entrypoints, specify one of : Main, Other"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_default_entrypoint() -> Result<()> {
+        let manifest = |default: &str| {
+            ModuleManifest::parse(
+                &format!(
+                    r#"
+                    group = "marais"
+                    artifact = "multi"
+                    version = "0.1.0"
+
+                    [run]
+                    default = "{}"
+
+                    [[entrypoints]]
+                    class = "Main"
+
+                    [[entrypoints]]
+                    name = "server"
+                    class = "marais.Server"
+                    "#,
+                    default
+                ),
+                None,
+            )
+        };
+        let module =
+            Module::from_manifest(Path::new("multi"), manifest("server")?, &Env::new(false))?;
+        assert_eq!(module.pick_entrypoint(false)?.class, "marais.Server");
+        let err = Module::from_manifest(Path::new("multi"), manifest("client")?, &Env::new(false))
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "The default entrypoint 'client' of [run] doesn't exist"
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_config_version() -> Result<()> {
+        let dir = temp_dir("config-version");
+        fs::write(
+            dir.join("jcargo.toml"),
+            r#"
+            group = "marais"
+            artifact = "configured"
+            version_from = "git-describe"
+            "#,
+        )?;
+        let mut env = Env::new(false);
+        env.config = vec!["version=1.2.3".parse()?];
+        let module = Module::load(&dir, &env).await?;
+        assert_eq!(module.version, "1.2.3");
+        Ok(())
+    }
+
+    #[test]
+    fn test_catalog_alias() -> Result<()> {
+        let dir = temp_dir("catalog-alias");
+        fs::create_dir_all(dir.join("gradle"))?;
+        fs::write(
+            dir.join("gradle/libs.versions.toml"),
+            r#"
+            [versions]
+            junit = "5.8.2"
+
+            [libraries]
+            junit-jupiter = { module = "org.junit.jupiter:junit-jupiter", version.ref = "junit" }
+            "#,
+        )?;
+        let manifest = |alias: &str| {
+            ModuleManifest::parse(
+                &format!(
+                    r#"
+                    group = "marais"
+                    artifact = "cataloged"
+                    version = "0.1.0"
+
+                    [dependencies]
+                    test = [{{ alias = "{}" }}]
+                    "#,
+                    alias
+                ),
+                None,
+            )
+        };
+        let module = Module::from_manifest(&dir, manifest("junit.jupiter")?, &Env::new(false))?;
+        match &module.dependencies.test[0] {
+            Dependency::MavenRepo(dep) => assert_eq!(
+                dep.coordinate.to_string(),
+                "org.junit.jupiter:junit-jupiter:5.8.2"
+            ),
+            _ => unreachable!(),
+        }
+
+        let err =
+            Module::from_manifest(&dir, manifest("junit.api")?, &Env::new(false)).unwrap_err();
+        assert_eq!(err.to_string(), "Unknown catalog alias 'junit.api'");
+        Ok(())
+    }
+
+    #[test]
+    fn test_platform_version() -> Result<()> {
+        let manifest = ModuleManifest::parse(
+            r#"
+            group = "marais"
+            artifact = "platformed"
+            version = "0.1.0"
+
+            [dependencies]
+            compileRuntime = ["com.google.guava:guava", "org.slf4j:slf4j-api:1.7.32"]
+            platform = ["com.google.guava:guava:31.0.1-jre", "org.slf4j:slf4j-api:1.7.36"]
+            "#,
+            None,
+        )?;
+        let module = Module::from_manifest(Path::new("platformed"), manifest, &Env::new(false))?;
+        let versions: Vec<_> = module
+            .dependencies
+            .iter()
+            .map(|it| match it {
+                Dependency::MavenRepo(dep) => dep.coordinate.to_string(),
+                _ => unreachable!(),
+            })
+            .collect();
+        // A declared version wins over the platform
+        assert_eq!(
+            versions,
+            vec![
+                "com.google.guava:guava:31.0.1-jre",
+                "org.slf4j:slf4j-api:1.7.32"
+            ]
+        );
+
+        let manifest = ModuleManifest::parse(
+            r#"
+            group = "marais"
+            artifact = "unversioned"
+            version = "0.1.0"
+
+            [dependencies]
+            compile = ["com.google.guava:guava"]
+            "#,
+            None,
+        )?;
+        let err = Module::from_manifest(Path::new("unversioned"), manifest, &Env::new(false))
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "No version for 'com.google.guava:guava', declare one or add it to the platform"
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_bom_import() -> Result<()> {
+        let managed = |deps: &str| {
+            format!(
+                r#"<project xmlns:xsi="http://www.w3.org/2001/XMLSchema-instance" xsi:schemaLocation="http://maven.apache.org/POM/4.0.0 http://maven.apache.org/maven-v4_0_0.xsd">
+                <modelVersion>4.0.0</modelVersion><groupId>marais</groupId><artifactId>bom</artifactId>
+                <version>1.0</version><packaging>pom</packaging>
+                <properties><lib.version>2.1</lib.version></properties>
+                <dependencyManagement><dependencies>{}</dependencies></dependencyManagement>
+                </project>"#,
+                deps
+            )
+        };
+        let server = MockServer::start(move |req| match req.path.as_str() {
+            "/marais/bom/1.0/bom-1.0.pom" => response(
+                "200 OK",
+                managed(
+                    "<dependency><groupId>marais</groupId><artifactId>lib</artifactId>\
+                     <version>${lib.version}</version></dependency>\
+                     <dependency><groupId>marais</groupId><artifactId>pinned</artifactId>\
+                     <version>1.0</version></dependency>\
+                     <dependency><groupId>marais</groupId><artifactId>nested</artifactId>\
+                     <version>1.0</version><type>pom</type><scope>import</scope></dependency>",
+                )
+                .as_bytes(),
+            ),
+            _ => response("404 Not Found", b""),
+        })
+        .await;
+        // The nested BOM is only in the second repository, and imports the first BOM back
+        let other = MockServer::start(move |req| match req.path.as_str() {
+            "/marais/nested/1.0/nested-1.0.pom" => response(
+                "200 OK",
+                managed(
+                    "<dependency><groupId>marais</groupId><artifactId>log</artifactId>\
+                     <version>3.2</version></dependency>\
+                     <dependency><groupId>marais</groupId><artifactId>bom</artifactId>\
+                     <version>1.0</version><type>pom</type><scope>import</scope></dependency>",
+                )
+                .as_bytes(),
+            ),
+            _ => response("404 Not Found", b""),
+        })
+        .await;
+        let mut env = Env::new(false);
+        env.repos = [("mock", &server), ("other", &other)]
+            .into_iter()
+            .map(|(name, server)| {
+                Arc::new(MavenRepo {
+                    name: name.to_string(),
+                    url: server.url.clone(),
+                    layout: RepoLayout::Maven,
+                })
+            })
+            .collect();
+        let dir = temp_dir("bom-import");
+        fs::write(
+            dir.join("jcargo.toml"),
+            r#"
+            group = "marais"
+            artifact = "boms"
+            version = "0.1.0"
+
+            [dependencies]
+            compile = ["marais:lib", "marais:pinned", "marais:core:1.0", "marais:log"]
+            platform = ["marais:pinned:1.5"]
+            bom = ["marais:bom:1.0"]
+            "#,
+        )?;
+        let versions = |module: &Module| -> Vec<_> {
+            module
+                .dependencies
+                .iter()
+                .map(|it| match it {
+                    Dependency::MavenRepo(dep) => dep.coordinate.to_string(),
+                    _ => unreachable!(),
+                })
+                .collect()
+        };
+
+        // Only imported when the dependencies are needed
+        let mut module = Module::load(&dir, &env).await?;
+        assert!(server.requests().is_empty());
+        assert_eq!(versions(&module), ["marais:pinned:1.5", "marais:core:1.0"]);
+
+        module.dependencies.import_boms(&dir, &env).await?;
+        // The explicit platform wins over the BOM, the declaration order is kept
+        assert_eq!(
+            versions(&module),
+            [
+                "marais:lib:2.1",
+                "marais:pinned:1.5",
+                "marais:core:1.0",
+                "marais:log:3.2"
+            ]
+        );
+        assert!(dir.join("libs/parents/bom-1.0.pom").is_file());
+        assert!(dir.join("libs/parents/nested-1.0.pom").is_file());
+        Ok(())
+    }
+
+    #[test]
+    fn test_jdk_classifier() -> Result<()> {
+        let jar_url = |target: &str| -> Result<String> {
+            let manifest = ModuleManifest::parse(
+                &format!(
+                    r#"
+                    group = "marais"
+                    artifact = "classified"
+                    version = "0.1.0"
+
+                    [java]
+                    source = "{0}"
+                    target = "{0}"
+
+                    [dependencies]
+                    compile = [{{ group = "marais", artifact = "lib", version = "1.0", jdk_classifiers = ["jdk8", "jdk11"] }}]
+                    "#,
+                    target
+                ),
+                None,
+            )?;
+            let module =
+                Module::from_manifest(Path::new("classified"), manifest, &Env::new(false))?;
+            match &module.dependencies.compile[0] {
+                Dependency::MavenRepo(dep) => Ok(dep.jar_url().to_string()),
+                _ => unreachable!(),
+            }
+        };
+        assert_eq!(
+            jar_url("8")?,
+            "https://repo.maven.apache.org/maven2/marais/lib/1.0/lib-1.0-jdk8.jar"
+        );
+        assert!(jar_url("1.8")?.ends_with("/lib-1.0-jdk8.jar"));
+        assert!(jar_url("17")?.ends_with("/lib-1.0-jdk11.jar"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_missing_manifest() -> Result<()> {
+        let dir = temp_dir("missing-manifest");
+        let err = Module::load(&dir, &Env::new(false)).await.unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "no jcargo.toml found in {}; run `jcargo init` to create one",
+                dir.display()
+            )
+        );
+        assert!(find_manifest_dir(&dir)
+            .unwrap_err()
+            .to_string()
+            .starts_with("no jcargo.toml found in"));
+
+        let nested = dir.join("src/main/java");
+        fs::create_dir_all(&nested)?;
+        fs::write(
+            dir.join("jcargo.toml"),
+            "group = \"marais\"\nartifact = \"found\"\n",
+        )?;
+        assert_eq!(find_manifest_dir(&nested)?, dir.canonicalize()?);
+        Ok(())
+    }
+}
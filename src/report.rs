@@ -0,0 +1,94 @@
+use std::collections::BTreeMap;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use tokio::fs;
+
+use crate::Module;
+
+/// Written in the target dir after each build, overwritten by the next one
+pub const REPORT_FILE: &str = "build-report.json";
+
+/// What a build did, for analysis after the fact
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BuildReport {
+    /// Seconds since the unix epoch
+    pub timestamp: u64,
+    pub task: String,
+    /// The report is written when the compilation fails too
+    pub success: bool,
+    /// `group:artifact:version`
+    pub module: String,
+    /// Dependency jars in `libs`, transitive ones included
+    pub dependencies: usize,
+    pub compiled_files: usize,
+    pub diagnostics: DiagnosticCounts,
+    /// In the order they ran
+    pub phases: Vec<Phase>,
+    /// Tool name to version
+    pub toolchain: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DiagnosticCounts {
+    pub warnings: usize,
+    pub errors: usize,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Phase {
+    pub name: String,
+    pub millis: u128,
+}
+
+impl BuildReport {
+    pub fn new(module: &Module, task: &str) -> Self {
+        Self {
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+            task: task.to_string(),
+            success: true,
+            module: format!("{}:{}:{}", module.group, module.artifact, module.version),
+            dependencies: cached_jars(module),
+            compiled_files: 0,
+            diagnostics: DiagnosticCounts::default(),
+            phases: Vec::new(),
+            toolchain: BTreeMap::new(),
+        }
+    }
+
+    pub fn phase(&mut self, name: &str, duration: Duration) {
+        self.phases.push(Phase {
+            name: name.to_string(),
+            millis: duration.as_millis(),
+        });
+    }
+
+    pub async fn write(&self, module: &Module) -> Result<()> {
+        fs::create_dir_all(module.target_dir()).await?;
+        fs::write(
+            module.target_dir().join(REPORT_FILE),
+            serde_json::to_string_pretty(self)?,
+        )
+        .await?;
+        Ok(())
+    }
+}
+
+fn cached_jars(module: &Module) -> usize {
+    std::fs::read_dir(module.dir.join("libs"))
+        .map(|dir| {
+            dir.filter_map(|it| it.ok())
+                .filter(|it| {
+                    let name = it.file_name().to_string_lossy().into_owned();
+                    name.ends_with(".jar")
+                        && !name.ends_with("-sources.jar")
+                        && !name.ends_with("-javadoc.jar")
+                })
+                .count()
+        })
+        .unwrap_or(0)
+}
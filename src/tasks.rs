@@ -1,34 +1,70 @@
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashSet};
+use std::ffi::OsStr;
+use std::fmt::Write;
 use std::future::Future;
 use std::iter;
 use std::path::{Path, PathBuf};
 use std::process::Stdio;
 use std::sync::Arc;
-use std::time::Instant;
+use std::time::{Duration, Instant, SystemTime};
 
-use anyhow::Result;
-use tokio::io::{AsyncWriteExt, BufWriter};
-use tokio::task::JoinHandle;
+use anyhow::{anyhow, Context, Result};
+use sha2::{Digest, Sha256};
+use tokio::io::{
+    AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader, BufWriter,
+};
 use tokio::{fs, process};
+use tracing::{debug, info, warn};
 use walkdir::WalkDir;
 
 use crate::backend::{DocumentationBackend, KotlinCompilationBackend};
+use crate::dependencies::coordinate::Coordinate;
 use crate::dependencies::dependency_graph::DependencyGraph;
-use crate::dependencies::maven::explore_dependency;
+use crate::dependencies::maven::{explore_all, Exploration, ExploreContext, RESOLUTION_JOBS};
+use crate::dependencies::maven_metadata::{compare_versions, MavenMetadata};
+use crate::dependencies::maven_settings::MavenSettings;
+use crate::dependencies::mavenpom::{MavenPom, Properties, PropertiesExt};
+use crate::dependencies::resolution::ResolutionTrace;
+use crate::dependencies::signature::Keyring;
 use crate::dependencies::Dependency;
-use crate::{Env, JavaCompilationBackend, Module, PackageBackend, Runtime, Task};
+use crate::doctor::diagnose;
+use crate::io::{download_memory, http_client_with, upload};
+use crate::jar::{
+    duplicate_classes, split_packages, write_fat_jar, DuplicateClass, JarContents, JarManifest,
+    SplitPackage,
+};
+use crate::javac_parser::{
+    unsupported_release, warning_summary, Diagnostic, JavacOutputParser, Severity,
+};
+use crate::junit::{TestSummary, CONSOLE_LAUNCHER};
+use crate::lockfile::{drift_diff, LockedDependency, Lockfile, LOCKFILE};
+use crate::manifest::{
+    max_heap_args, normalize_manifest, AssertionsDef, Classifier, ClasspathOrder,
+};
+use crate::metadata::Metadata;
+use crate::module::MissingManifest;
+use crate::report::{BuildReport, DiagnosticCounts};
+use crate::toolchains::SigningDef;
+use crate::workspace::Workspace;
+use crate::{logging, status};
+use crate::{
+    CacheTask, Emit, Env, JavaCompilationBackend, Module, PackageBackend, Profiler, Profiling,
+    Runtime, Task,
+};
 
 pub async fn execute_task(
     task: Task,
     env: &Env,
-    module_resolver: impl Future<Output = Result<Module>>,
-) {
+    workspace_resolver: impl Future<Output = Result<Workspace>>,
+) -> Result<()> {
     match task {
         Task::Init { group, artifact } => {
-            println!("Init '{}:{}' in the current directory", group, artifact);
+            status!("Init '{}:{}' in the current directory", group, artifact);
             let manifest_path = PathBuf::from("jcargo.toml");
             if manifest_path.exists() {
                 println!("Error: There is already a manifest in the current directory.");
-                return;
+                return Ok(());
             }
             let file = tokio::fs::OpenOptions::new()
                 .write(true)
@@ -38,7 +74,7 @@ pub async fn execute_task(
                 .unwrap();
 
             let mut buf = BufWriter::new(file);
-            buf.write(
+            buf.write_all(
                 format!(
                     r#"
         group = "{}"
@@ -53,470 +89,5093 @@ pub async fn execute_task(
             .unwrap();
             buf.flush().await.unwrap();
         }
+        Task::Inspect { jar } => {
+            print!("{}", inspect(&jar)?);
+        }
+        Task::Doctor => {
+            let workspace = workspace_resolver.await;
+            let modules = match &workspace {
+                Ok(workspace) => Ok(&workspace.members[..]),
+                // Also useful outside of a project
+                Err(e) if e.downcast_ref::<MissingManifest>().is_some() => Ok(&[][..]),
+                Err(e) => Err(e),
+            };
+            let java_home = std::env::var_os("JAVA_HOME").map(PathBuf::from);
+            let diagnosis = diagnose(env, java_home.as_deref(), modules).await;
+            print!("{}", diagnosis);
+            if diagnosis.failures() > 0 {
+                return Err(anyhow!("{} checks failed", diagnosis.failures()));
+            }
+        }
+        Task::Metadata { .. } => {
+            let mut workspace = workspace_resolver.await?;
+            workspace.import_boms(env).await?;
+            println!(
+                "{}",
+                serde_json::to_string_pretty(&Metadata::new(&workspace, env))?
+            );
+        }
         _ => {
-            let module = module_resolver.await.unwrap();
-            execute_task_mod(task, env, &module).await;
+            let mut workspace = workspace_resolver.await?;
+            workspace.select(&env.members)?;
+            if let Some(since) = &env.since {
+                let changed = workspace.select_changed(since)?;
+                if changed.is_empty() {
+                    status!("   Nothing changed since {}", since);
+                } else {
+                    status!("   Changed since {}: {}", since, changed.join(", "));
+                }
+            }
+            if task.needs_dependencies() {
+                workspace.import_boms(env).await?;
+            }
+            let res = workspace
+                .for_each_member(env.keep_going, |module| {
+                    execute_task_mod(task.clone(), env, module)
+                })
+                .await;
+            // Also when failed, the slow part may be what failed
+            if let Some(timings) = &env.timings {
+                timings.write(&workspace.root.join("target")).await?;
+            }
+            res?;
         }
     }
+    Ok(())
 }
 
 #[async_recursion::async_recursion]
-pub async fn execute_task_mod(task: Task, env: &Env, module: &Module) {
+pub async fn execute_task_mod(task: Task, env: &Env, module: &Module) -> Result<()> {
     match task {
-        Task::Check => {
-            println!("   Checking dependencies");
+        Task::Check {
+            fix,
+            deps,
+            duplicate_classes,
+            split_packages,
+        } => {
+            if fix {
+                fix_manifest(module).await?;
+            }
+            status!("   Checking dependencies");
+            let instant = Instant::now();
+
+            check(module, env).await?;
+
+            if let Some(timings) = &env.timings {
+                timings.record("check", module.artifact.as_str(), instant);
+            }
+            status!("   Done. (took {} ms)", instant.elapsed().as_millis());
+            if deps {
+                let outdated = outdated(module, &http_client_with(&env.downloads)).await;
+                if outdated.is_empty() {
+                    status!("   Dependencies are up to date");
+                } else {
+                    print!("{}", outdated_table(&outdated));
+                }
+            }
+            if duplicate_classes {
+                let duplicates = runtime_duplicate_classes(module)?;
+                if duplicates.is_empty() {
+                    status!("   No duplicate classes");
+                } else {
+                    print!("{}", duplicate_classes_report(module, &duplicates));
+                }
+            }
+            if split_packages {
+                let split = runtime_split_packages(module)?;
+                if split.is_empty() {
+                    status!("   No split packages");
+                } else {
+                    print!("{}", split_packages_report(module, &split));
+                }
+            }
+        }
+        Task::Fetch { sources, docs } => {
+            status!("   Fetching dependencies");
             let instant = Instant::now();
 
-            check(module).await;
+            let stats = fetch(module, env, sources, docs).await?;
 
-            println!("   Done. (took {} ms)", instant.elapsed().as_millis());
+            status!(
+                "   Fetched {} artifacts, {} (took {} ms)",
+                stats.artifacts,
+                format_size(stats.bytes),
+                instant.elapsed().as_millis()
+            );
         }
-        Task::Build => {
-            execute_task_mod(Task::Check, env, module).await;
-            println!(
+        Task::Build { emit } => {
+            let instant = Instant::now();
+            execute_task_mod(
+                Task::Check {
+                    fix: false,
+                    deps: false,
+                    duplicate_classes: false,
+                    split_packages: false,
+                },
+                env,
+                module,
+            )
+            .await?;
+            // After the check so the freshly resolved dependencies are counted
+            let mut report = BuildReport::new(module, "build");
+            report.phase("check", instant.elapsed());
+            status!(
                 "   Compiling {} v{} <path>",
-                module.artifact, module.version
+                module.artifact,
+                module.version
             );
 
+            check_jdk_modules(module, &env.runtime).await;
             let instant = Instant::now();
-            build(module, env.comp_backend).await;
+            let result = build_with_cache(module, env).await;
+            if let Some(timings) = &env.timings {
+                timings.record("compile", module.artifact.as_str(), instant);
+            }
+            if env.build_report {
+                report.phase("compile", instant.elapsed());
+                match &result {
+                    Ok(stats) => {
+                        report.compiled_files = stats.compiled_files;
+                        report.diagnostics = stats.diagnostics;
+                    }
+                    Err(e) => {
+                        report.success = false;
+                        if let Some(failed) = e.downcast_ref::<CompilationFailed>() {
+                            report.diagnostics = failed.diagnostics;
+                        }
+                    }
+                }
+                if let Some(version) = env.comp_backend.version().await {
+                    report.toolchain.insert("javac".to_string(), version);
+                }
+                if let Err(e) = report.write(module).await {
+                    if result.is_ok() {
+                        return Err(e);
+                    }
+                    // Don't hide why the build failed
+                    warn!("Can't write the build report : {:#}", e);
+                }
+            }
+            result?;
 
-            println!(
+            status!(
                 "   Finished build. (took {} ms)",
                 instant.elapsed().as_millis()
             );
+
+            // Classes are always built, the other outputs need them
+            if emit.contains(&Emit::Docs) {
+                execute_task_mod(Task::Doc, env, module).await?;
+            }
+            if emit.contains(&Emit::Jar) {
+                status!("   Packaging jar ...");
+                let instant = Instant::now();
+
+                let docs = emit.contains(&Emit::Docs);
+                package(
+                    module,
+                    &env.package_backend,
+                    PackageOptions {
+                        docs,
+                        ..PackageOptions::default()
+                    },
+                )
+                .await?;
+
+                status!(
+                    "   Packaging finished. (took {} ms)",
+                    instant.elapsed().as_millis()
+                );
+            }
         }
-        Task::Run { entrypoint } => {
-            execute_task_mod(Task::Build, env, module).await;
-            println!("   Running 'Main'");
+        Task::Run { all: true, .. } => {
+            execute_task_mod(Task::Build { emit: Vec::new() }, env, module).await?;
             let instant = Instant::now();
 
-            run(module, entrypoint).await;
+            let results = run_all(module, &env.runtime, env.keep_going).await;
 
-            println!(
+            let failed: Vec<_> = results
+                .iter()
+                .filter(|(_, res)| res.is_err())
+                .map(|(label, _)| label.as_str())
+                .collect();
+            status!(
+                "   Ran {} of {} entrypoints, {} failed (took {} ms)",
+                results.len(),
+                module.entrypoints.len(),
+                failed.len(),
+                instant.elapsed().as_millis()
+            );
+            if !failed.is_empty() {
+                return Err(anyhow!("Failed entrypoints : {}", failed.join(", ")));
+            }
+        }
+        Task::Run {
+            entrypoint,
+            jar: Some(jar),
+            profiling,
+            enable_assertions,
+            log,
+            ..
+        } => {
+            if entrypoint.is_some() {
+                return Err(anyhow!(
+                    "--jar runs the Main-Class of the jar, not an entrypoint"
+                ));
+            }
+            prepare_profiling(module, &env.runtime, &profiling).await?;
+            status!("   Running {}", jar.display());
+            let instant = Instant::now();
+
+            run_jar(
+                module,
+                &env.runtime,
+                &jar,
+                &profiling,
+                enable_assertions.as_deref(),
+                log.as_deref(),
+            )
+            .await?;
+
+            status!(
                 "   Execution finished. (took {} ms)",
                 instant.elapsed().as_millis()
             );
+            report_profiling(module, &profiling);
         }
-        Task::Doc => {
-            println!("   Building documentation");
+        Task::Run {
+            entrypoint,
+            jar: None,
+            profiling,
+            enable_assertions,
+            log,
+            ..
+        } => {
+            execute_task_mod(Task::Build { emit: Vec::new() }, env, module).await?;
+            prepare_profiling(module, &env.runtime, &profiling).await?;
+            status!("   Running 'Main'");
             let instant = Instant::now();
 
-            build_doc(module, env.doc_backend).await;
+            run(
+                module,
+                &env.runtime,
+                entrypoint,
+                &profiling,
+                enable_assertions.as_deref(),
+                log.as_deref(),
+            )
+            .await?;
 
-            println!(
-                "   Finished building docs. (took {} ms)",
+            status!(
+                "   Execution finished. (took {} ms)",
+                instant.elapsed().as_millis()
+            );
+            report_profiling(module, &profiling);
+        }
+        Task::Test { filter } => {
+            execute_task_mod(Task::Build { emit: Vec::new() }, env, module).await?;
+            status!("   Compiling tests");
+            if !build_tests(module, &env.comp_backend, env.warning_summary).await? {
+                status!("   No tests");
+                return Ok(());
+            }
+
+            status!("   Running tests");
+            let instant = Instant::now();
+            let summary = test(module, &env.runtime, filter.as_deref()).await?;
+
+            status!(
+                "   Tests: {} (took {} ms)",
+                summary,
                 instant.elapsed().as_millis()
             );
+            if !summary.failed.is_empty() {
+                for failed in &summary.failed {
+                    eprintln!("   Failed {}", failed);
+                }
+                return Err(anyhow!("{} test(s) failed", summary.failed.len()));
+            }
+        }
+        Task::Doc => {
+            status!("   Building documentation");
+            let instant = Instant::now();
+
+            if build_doc(module, &env.doc_backend).await? {
+                status!(
+                    "   Finished building docs. (took {} ms)",
+                    instant.elapsed().as_millis()
+                );
+            } else {
+                status!("   Docs are up to date");
+            }
         }
         Task::Package {
             sources,
             docs,
+            fat,
+            with_libs,
+            classifier,
+            sign,
             entrypoint,
         } => {
-            execute_task_mod(Task::Build, env, module).await;
+            execute_task_mod(Task::Build { emit: Vec::new() }, env, module).await?;
             if docs {
-                execute_task_mod(Task::Doc, env, module).await;
+                execute_task_mod(Task::Doc, env, module).await?;
+            }
+            let mut classifiers = module.package.classifiers.clone();
+            classifiers.extend(classifier);
+            classifiers.sort();
+            classifiers.dedup();
+            if classifiers.contains(&Classifier::Tests)
+                && !build_tests(module, &env.comp_backend, env.warning_summary).await?
+            {
+                return Err(anyhow!("There are no tests to package"));
             }
 
-            println!(
-                "   Packaging jar{}{} ...",
+            status!(
+                "   Packaging jar{}{}{}{}{} ...",
                 if sources { " +sources" } else { "" },
-                if docs { " +docs" } else { "" }
+                if docs { " +docs" } else { "" },
+                if fat { " +fat" } else { "" },
+                if with_libs { " +libs" } else { "" },
+                classifiers
+                    .iter()
+                    .map(|it| format!(" +{}", it.as_str()))
+                    .collect::<String>()
             );
             let instant = Instant::now();
 
-            package(module, env.package_backend, sources, docs, entrypoint).await;
+            package(
+                module,
+                &env.package_backend,
+                PackageOptions {
+                    sources,
+                    docs,
+                    fat,
+                    with_libs,
+                    entrypoint,
+                },
+            )
+            .await?;
+            let mut classified = Vec::new();
+            for classifier in classifiers {
+                classified
+                    .push(package_classified(module, &env.package_backend, classifier).await?);
+            }
+            if sign {
+                let base_name = format!("{}-{}", module.artifact, module.version);
+                let mut jars = vec![module.artifacts_dir().join(format!("{}.jar", base_name))];
+                if fat {
+                    jars.push(
+                        module
+                            .artifacts_dir()
+                            .join(format!("{}-all.jar", base_name)),
+                    );
+                }
+                jars.extend(classified);
+                for jar in jars {
+                    sign_jar(&env.signing, &env.package_backend, &jar).await?;
+                }
+            }
 
-            println!(
+            status!(
                 "   Packaging finished. (took {} ms)",
                 instant.elapsed().as_millis()
             );
         }
-        Task::Clean => {
-            fs::remove_dir_all(module.dir.join("target")).await.unwrap();
-            println!("Cleaned project (removed 'target' dir).")
+        Task::Deploy => {
+            execute_task_mod(
+                Task::Package {
+                    sources: true,
+                    docs: true,
+                    fat: false,
+                    with_libs: false,
+                    classifier: None,
+                    sign: false,
+                    entrypoint: None,
+                },
+                env,
+                module,
+            )
+            .await?;
+
+            let instant = Instant::now();
+            let url = deploy(module, &http_client_with(&env.downloads)).await?;
+
+            status!(
+                "   Deployed to {} (took {} ms)",
+                url,
+                instant.elapsed().as_millis()
+            );
+        }
+        Task::Clean { dry_run } => {
+            print!("{}", clean(module, dry_run).await?);
+        }
+        Task::Cache(CacheTask::Prune { max_age, dry_run }) => {
+            let max_age = max_age.map(|days| Duration::from_secs(days * 24 * 3600));
+            print!("{}", prune_cache(module, max_age, dry_run).await?);
+        }
+        Task::Cache(CacheTask::Clean { dry_run }) => {
+            print!("{}", clean_cache(module, dry_run).await?);
+        }
+        Task::Info => {
+            print!("{}", info(module, env).await);
+        }
+        Task::Classpath { scope } => {
+            println!("{}", classpath(module, &scope, env.path_separator)?);
         }
         _ => {}
     }
+    Ok(())
 }
 
-pub async fn check(module: &Module) {
-    setup_all_dependencies(module).await;
-}
-
-pub async fn build(module: &Module, backend: JavaCompilationBackend) {
-    let source_dir = module.source_dir();
-    let output_dir = module.classes_dir();
-    fs::create_dir_all(&output_dir).await.unwrap();
-
-    // We need to build kotlin first since it can handle java source files
-    // Javac can't handle kotlin source files
-    // Required for Java <-> Kotlin references
+/// Upload the main jar of the module with its pom, sources and docs jars when packaged, the jars
+/// of the `[package] classifiers`, and their checksums. Returns the directory they were uploaded to
+pub async fn deploy(module: &Module, client: &reqwest::Client) -> Result<url::Url> {
+    use md5::Md5;
+    use sha1::Sha1;
 
-    let mut sources = collect_files(&source_dir, Some(&[".kt"])).peekable();
-    // Pass if no kotlin sources
-    if sources.peek().is_some() {
-        println!("Detected kotlin sources ...");
+    let coordinate = Coordinate::new(&module.group, &module.artifact, &module.version);
+    let url = module
+        .publish
+        .repository_for(&module.version)?
+        .join(&coordinate.repo_path())?;
+    let credentials = module
+        .publish
+        .credentials(MavenSettings::load_user()?.as_ref())?;
 
-        let mut ktcmd = KotlinCompilationBackend::Kotlinc.command();
-        ktcmd.args([
-            "-jvm-target",
-            "17",
-            "-language-version",
-            "1.6",
-            "-d",
-            &output_dir.display().to_string(),
-            "-cp",
-        ]);
+    let base = coordinate.base_name();
+    let artifacts = module.artifacts_dir();
+    let main = artifacts.join(format!("{}.jar", base));
+    if !main.is_file() {
+        return Err(anyhow!(
+            "No artifacts, the module needs to be packaged ({} is missing)",
+            main.display()
+        ));
+    }
+    // Maven calls the doc jar classifier javadoc
+    let mut files = vec![(main, format!("{}.jar", base))];
+    for (local, remote) in [
+        (format!("{}.pom", base), format!("{}.pom", base)),
+        (
+            format!("{}-sources.jar", base),
+            format!("{}-sources.jar", base),
+        ),
+        (
+            format!("{}-docs.jar", base),
+            format!("{}-javadoc.jar", base),
+        ),
+    ] {
+        let local = artifacts.join(local);
+        if local.is_file() {
+            files.push((local, remote));
+        }
+    }
+    // Always packaged along the main jar
+    for classifier in &module.package.classifiers {
+        let name = format!("{}-{}.jar", base, classifier.as_str());
+        let local = artifacts.join(&name);
+        if !local.is_file() {
+            return Err(anyhow!(
+                "The {} jar isn't packaged ({} is missing)",
+                classifier.as_str(),
+                local.display()
+            ));
+        }
+        files.push((local, name));
+    }
+    for (file, name) in files {
+        info!("Uploading {}", name);
+        let data = fs::read(&file).await?;
+        let sha1 = format!("{:x}", Sha1::digest(&data));
+        let md5 = format!("{:x}", Md5::digest(&data));
+        upload(client, url.join(&name)?, data, credentials.as_ref()).await?;
+        for (ext, checksum) in [("sha1", sha1), ("md5", md5)] {
+            let checksum_url = url.join(&format!("{}.{}", name, ext))?;
+            upload(
+                client,
+                checksum_url,
+                checksum.into_bytes(),
+                credentials.as_ref(),
+            )
+            .await?;
+        }
+    }
+    Ok(url)
+}
 
-        // Collect dependencies include paths
-        let cp = module
-            .dependencies
-            .iter_compile()
-            .map(|it| format!("{}/{}", module.dir.display(), it.classpath()))
-            .chain(iter::once(output_dir.display().to_string()))
-            .reduce(|a, b| format!("{};{}", a, b))
-            .unwrap();
-        ktcmd.arg(&cp);
-        println!("compile classpath: {}", &cp);
+/// Gather what we know about the project and its environment, without building anything
+pub async fn info(module: &Module, env: &Env) -> String {
+    fn program(path: Option<PathBuf>) -> String {
+        path.map(|it| it.display().to_string())
+            .unwrap_or_else(|| "not found".to_string())
+    }
 
-        collect_files(&source_dir, Some(&[".kt", ".java"])).for_each(|it| {
-            ktcmd.arg(it);
-        });
+    let mut out = String::new();
+    writeln!(
+        out,
+        "{}:{}:{}",
+        module.group, module.artifact, module.version
+    )
+    .unwrap();
+    writeln!(out, "  root: {}", module.dir.display()).unwrap();
+    writeln!(
+        out,
+        "  java: source {}, target {}",
+        module.java.source, module.java.target
+    )
+    .unwrap();
 
-        ktcmd
-            .stdout(Stdio::inherit())
-            .stderr(Stdio::inherit())
-            .spawn()
-            .unwrap()
-            .wait_with_output()
+    writeln!(out, "toolchain:").unwrap();
+    writeln!(
+        out,
+        "  jdk: {}",
+        env.comp_backend
+            .version()
             .await
-            .unwrap();
+            .unwrap_or_else(|| "unknown".to_string())
+    )
+    .unwrap();
+    writeln!(out, "  javac: {}", program(env.comp_backend.program())).unwrap();
+    writeln!(out, "  java: {}", program(env.runtime.program())).unwrap();
+    writeln!(out, "  javadoc: {}", program(env.doc_backend.program())).unwrap();
+    writeln!(out, "  jar: {}", program(env.package_backend.program())).unwrap();
+    writeln!(
+        out,
+        "  kotlinc: {}",
+        program(KotlinCompilationBackend::Kotlinc.program())
+    )
+    .unwrap();
 
-        println!("Compiled kotlin sources.");
+    writeln!(out, "repositories:").unwrap();
+    for repo in &env.repos {
+        writeln!(out, "  {}: {}", repo.name, repo.url).unwrap();
     }
 
-    let mut sources = collect_files(&source_dir, Some(&[".java"])).peekable();
-    // Pass if no java sources
-    if sources.peek().is_some() {
-        println!("Detected java sources ...");
-
-        let mut cmd: process::Command = backend.command();
-        cmd.args([
-            "-source",
-            "17",
-            "-target",
-            "17",
-            "-encoding",
-            "UTF-8",
-            "-Xlint",
-            "-d",
-            &output_dir.display().to_string(),
-            "-cp",
-        ]);
-
-        // Collect dependencies include paths
-        let cp = module
-            .dependencies
-            .iter_compile()
-            .map(|it| format!("{}/{}", module.dir.display(), it.classpath()))
-            .chain(iter::once(output_dir.display().to_string()))
-            .reduce(|a, b| format!("{};{}", a, b))
-            .unwrap();
-        cmd.arg(&cp);
-        println!("compile classpath: {}", &cp);
-
-        sources.for_each(|it| {
-            cmd.arg(it);
-        });
+    let deps = &module.dependencies;
+    writeln!(out, "dependencies:").unwrap();
+    writeln!(out, "  compile: {}", deps.compile.len()).unwrap();
+    writeln!(out, "  runtime: {}", deps.runtime.len()).unwrap();
+    writeln!(out, "  compileRuntime: {}", deps.compile_runtime.len()).unwrap();
+    writeln!(out, "  transitive: {}", deps.transitive.len()).unwrap();
+    writeln!(out, "  test: {}", deps.test.len()).unwrap();
+    // Counting them would need the BOMs, info stays offline
+    if deps.unresolved() > 0 {
+        writeln!(
+            out,
+            "  unresolved: {} (versions from BOMs)",
+            deps.unresolved()
+        )
+        .unwrap();
+    }
+    out
+}
 
-        cmd.stdout(Stdio::inherit())
-            .stderr(Stdio::inherit())
-            .spawn()
-            .unwrap()
-            .wait_with_output()
-            .await
+/// Remove the generated directories, or only report them with `dry_run`
+pub async fn clean(module: &Module, dry_run: bool) -> Result<String> {
+    let mut out = String::new();
+    let mut total = 0;
+    for dir in [module.target_dir()] {
+        if !dir.exists() {
+            continue;
+        }
+        let size = dir_size(&dir);
+        total += size;
+        if dry_run {
+            writeln!(
+                out,
+                "   Would remove {} ({})",
+                dir.display(),
+                format_size(size)
+            )
             .unwrap();
-
-        println!("Compiled java sources.");
+        } else {
+            fs::remove_dir_all(&dir).await?;
+            writeln!(out, "   Removed {} ({})", dir.display(), format_size(size)).unwrap();
+        }
     }
+    if dry_run {
+        writeln!(out, "   Would free {}", format_size(total)).unwrap();
+    }
+    Ok(out)
 }
 
-pub async fn run(module: &Module, entrypoint_name: Option<String>) {
-    let output_dir = module.classes_dir();
+/// Files of `cache` not belonging to one of the `referenced` dependencies, or not modified
+/// since `max_age`, with their size. Parent poms aren't locked so only their age counts.
+pub fn prune_candidates(
+    cache: &Path,
+    referenced: &[&LockedDependency],
+    max_age: Option<Duration>,
+) -> Vec<(PathBuf, u64)> {
+    let bases: Vec<_> = referenced
+        .iter()
+        .map(|it| format!("{}-{}", it.artifact, it.version))
+        .collect();
+    let now = SystemTime::now();
+    let mut candidates: Vec<_> = WalkDir::new(cache)
+        .into_iter()
+        .filter_map(|it| it.ok())
+        .filter(|it| it.file_type().is_file())
+        .filter_map(|it| {
+            let metadata = it.metadata().ok()?;
+            let path = it.into_path();
+            let name = path.file_name()?.to_str()?;
+            let unreferenced = path.parent() == Some(cache)
+                && !bases.iter().any(|base| {
+                    name.strip_prefix(base.as_str()).map_or(false, |rest| {
+                        // foo-1.0 must not keep foo-1.0.1
+                        rest.starts_with('-') || [".jar", ".jar.asc", ".pom"].contains(&rest)
+                    })
+                });
+            let old = max_age.map_or(false, |max_age| {
+                metadata
+                    .modified()
+                    .ok()
+                    .and_then(|it| now.duration_since(it).ok())
+                    .map_or(false, |age| age > max_age)
+            });
+            (unreferenced || old).then(|| (path, metadata.len()))
+        })
+        .collect();
+    candidates.sort();
+    candidates
+}
 
-    let class;
-    match entrypoint_name {
-        Some(name) => class = module.find_entrypoint(&name).map(|it| &it.class),
+/// Remove the downloaded artifacts the lockfile doesn't reference anymore
+pub async fn prune_cache(
+    module: &Module,
+    max_age: Option<Duration>,
+    dry_run: bool,
+) -> Result<String> {
+    let cache = module.dir.join("libs");
+    let lockfile = match Lockfile::load(&module.dir).await? {
+        Some(lockfile) => lockfile,
         None => {
-            class = module.pick_entrypoint().map(|it| &it.class);
+            return Ok(format!(
+                "   No {}, can't tell which artifacts are unused\n",
+                LOCKFILE
+            ))
         }
     };
+    let referenced: Vec<_> = lockfile.dependencies.iter().collect();
+    let mut out = String::new();
+    let mut total = 0;
+    for (file, size) in prune_candidates(&cache, &referenced, max_age) {
+        total += size;
+        if dry_run {
+            writeln!(out, "   Would remove {}", file.display()).unwrap();
+        } else {
+            fs::remove_file(&file).await?;
+            writeln!(out, "   Removed {}", file.display()).unwrap();
+        }
+    }
+    writeln!(
+        out,
+        "   {} {}",
+        if dry_run { "Would free" } else { "Freed" },
+        format_size(total)
+    )
+    .unwrap();
+    Ok(out)
+}
 
-    if class.is_none() {
-        println!("Can't find entrypoint");
-        return;
+/// Remove every downloaded artifact, they will be downloaded again on the next build
+pub async fn clean_cache(module: &Module, dry_run: bool) -> Result<String> {
+    let cache = module.dir.join("libs");
+    if !cache.exists() {
+        return Ok(String::new());
     }
+    let size = format_size(dir_size(&cache));
+    if dry_run {
+        Ok(format!("   Would remove {} ({})\n", cache.display(), size))
+    } else {
+        fs::remove_dir_all(&cache).await?;
+        Ok(format!("   Removed {} ({})\n", cache.display(), size))
+    }
+}
 
-    let mut cmd = Runtime::Java.command();
-    cmd.args([
-        "-Xshare:on",
-        "-XX:TieredStopAtLevel=1",
-        "-XX:+UseSerialGC",
-        "-cp",
-    ]);
+/// Total size of the files under `dir`
+fn dir_size(dir: &Path) -> u64 {
+    WalkDir::new(dir)
+        .into_iter()
+        .filter_map(|it| it.ok())
+        .filter_map(|it| it.metadata().ok())
+        .filter(|it| it.is_file())
+        .map(|it| it.len())
+        .sum()
+}
 
-    // Collect dependencies include paths
-    let cp = module
-        .dependencies
-        .iter_runtime()
-        .map(|it| format!("{}/{}", module.dir.display(), it.classpath()))
-        .chain(iter::once(output_dir.display().to_string()))
-        .reduce(|a, b| format!("{};{}", a, b))
-        .unwrap();
-    cmd.arg(&cp);
+fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", size, UNITS[unit])
+}
 
-    println!("runtime classpath: {}", &cp);
+/// Describe the contents of a jar, fails if its manifest is malformed
+pub fn inspect(path: &Path) -> Result<String> {
+    let jar = JarContents::read(path)?;
+    let mut out = String::new();
+    writeln!(out, "{}", path.display()).unwrap();
+    match &jar.manifest {
+        Some(manifest) => {
+            writeln!(
+                out,
+                "  Main-Class: {}",
+                manifest.main_class().unwrap_or("none")
+            )
+            .unwrap();
+            writeln!(out, "  Class-Path:").unwrap();
+            for entry in manifest.class_path() {
+                writeln!(out, "    {}", entry).unwrap();
+            }
+            writeln!(out, "manifest:").unwrap();
+            for (key, value) in &manifest.attributes {
+                writeln!(out, "  {}: {}", key, value).unwrap();
+            }
+        }
+        None => writeln!(out, "  no manifest").unwrap(),
+    }
+    writeln!(out, "entries ({}):", jar.entries.len()).unwrap();
+    for entry in &jar.entries {
+        writeln!(out, "  {}", entry).unwrap();
+    }
+    Ok(out)
+}
 
-    cmd.arg(class.unwrap())
-        .stdout(Stdio::inherit())
-        .stderr(Stdio::inherit())
-        .spawn()
-        .unwrap()
-        .wait_with_output()
-        .await
-        .unwrap();
+/// Rewrite the manifest with its dependencies normalized
+pub async fn fix_manifest(module: &Module) -> Result<()> {
+    let path = module.dir.join("jcargo.toml");
+    let document = fs::read_to_string(&path).await?;
+    let fixed = normalize_manifest(&document)?;
+    if fixed != document {
+        fs::write(&path, fixed).await?;
+        status!("   Fixed manifest");
+    }
+    Ok(())
 }
 
-pub async fn build_doc(module: &Module, backend: DocumentationBackend) {
-    let mut cmd: process::Command = backend.command();
+pub async fn check(module: &Module, env: &Env) -> Result<()> {
+    resolve_dependencies(module, env, false, false).await
+}
 
-    let output = module.docs_dir();
+async fn resolve_dependencies(module: &Module, env: &Env, sources: bool, docs: bool) -> Result<()> {
+    let lockfile = check_lockfile(module, env).await?;
+    let exploration = setup_all_dependencies(module, env, sources, docs).await?;
+    match lockfile {
+        Some(lockfile) if env.locked => verify_locked_jars(module, &lockfile).await,
+        previous => {
+            Lockfile::resolved(
+                &exploration.dependencies,
+                &module.dir.join("libs"),
+                previous.as_ref(),
+            )
+            .await?
+            .save(&module.dir, previous.as_ref())
+            .await
+        }
+    }
+}
 
-    tokio::fs::create_dir_all(&output).await.unwrap();
+/// What `fetch` left in the dependency cache
+#[derive(Debug)]
+pub struct FetchStats {
+    /// Jars and poms in `libs`
+    pub artifacts: usize,
+    pub bytes: u64,
+}
 
-    cmd.arg("-d").arg(&output.display().to_string()).arg("-cp");
+/// Resolve and download every dependency without compiling, a following `--offline` build
+/// won't need the network
+pub async fn fetch(module: &Module, env: &Env, sources: bool, docs: bool) -> Result<FetchStats> {
+    resolve_dependencies(module, env, sources, docs).await?;
+    let libs = module.dir.join("libs");
+    let artifacts = WalkDir::new(&libs)
+        .into_iter()
+        .filter_map(|it| it.ok())
+        .filter(|it| it.file_type().is_file())
+        .filter(|it| {
+            let name = it.file_name().to_string_lossy();
+            name.ends_with(".jar") || name.ends_with(".pom")
+        })
+        .count();
+    Ok(FetchStats {
+        artifacts,
+        bytes: dir_size(&libs),
+    })
+}
 
-    // Collect dependencies include paths
-    let cp = module
+/// The resolved jars of the runtime dependencies, in classpath order
+fn runtime_jars(module: &Module) -> Vec<PathBuf> {
+    module
         .dependencies
-        .iter_compile()
-        .map(|it| format!("{}/{}", module.dir.display(), it.classpath()))
-        .reduce(|a, b| format!("{};{}", a, b))
-        .unwrap();
-    cmd.arg(&cp);
-    println!("compile classpath: {}", &cp);
-
-    collect_files(&module.source_dir(), Some(&[".java"])).for_each(|it| {
-        cmd.arg(it);
-    });
+        .iter_runtime()
+        .map(|it| module.dir.join(it.classpath()))
+        .filter(|it| it.is_file())
+        .collect()
+}
 
-    cmd.stdout(Stdio::inherit())
-        .stderr(Stdio::inherit())
-        .spawn()
-        .unwrap()
-        .wait_with_output()
-        .await
-        .unwrap();
+/// `jar` relative to the module
+fn jar_name(module: &Module, jar: &Path) -> String {
+    jar.strip_prefix(&module.dir)
+        .unwrap_or(jar)
+        .display()
+        .to_string()
 }
 
-pub async fn package(
-    module: &Module,
-    backend: PackageBackend,
-    sources: bool,
-    docs: bool,
-    entrypoint: Option<String>,
-) {
-    let base_dir = Arc::new(module.dir.clone());
-    let artifact_dir = module.artifacts_dir();
-    let artifact_base_name = Arc::new(format!(
-        "{}/{}-{}",
-        artifact_dir.display(),
-        module.artifact,
-        module.version
-    ));
+/// Duplicate classes among the jars of the runtime classpath, a read-only diagnostic
+pub fn runtime_duplicate_classes(module: &Module) -> Result<Vec<DuplicateClass>> {
+    duplicate_classes(&runtime_jars(module))
+}
 
-    let entrypoint_class = entrypoint
-        .as_ref()
-        .map(|it| module.find_entrypoint(it))
-        .flatten()
-        .map(|it| it.class.clone());
+pub fn runtime_split_packages(module: &Module) -> Result<Vec<SplitPackage>> {
+    split_packages(&runtime_jars(module))
+}
 
-    tokio::fs::create_dir_all(&artifact_dir).await.unwrap();
+pub fn split_packages_report(module: &Module, split: &[SplitPackage]) -> String {
+    let mut out = String::new();
+    for package in split {
+        writeln!(
+            out,
+            "   Split package {} : {}",
+            package.package,
+            package
+                .jars
+                .iter()
+                .map(|it| jar_name(module, it))
+                .collect::<Vec<_>>()
+                .join(", ")
+        )
+        .unwrap();
+    }
+    out
+}
 
-    let base_dir2 = base_dir.clone();
-    let artifact_base_name2 = artifact_base_name.clone();
-    let mut handles = Vec::new();
-    handles.push(tokio::spawn(async move {
-        let mut cmd: process::Command = backend.command();
+pub fn duplicate_classes_report(module: &Module, duplicates: &[DuplicateClass]) -> String {
+    let name = |jar: &PathBuf| jar_name(module, jar);
+    let mut out = String::new();
+    for duplicate in duplicates {
+        writeln!(
+            out,
+            "   Duplicate class {} : {} (loaded), {}",
+            duplicate.class,
+            name(&duplicate.jars[0]),
+            duplicate.jars[1..]
+                .iter()
+                .map(name)
+                .collect::<Vec<_>>()
+                .join(", ")
+        )
+        .unwrap();
+    }
+    out
+}
 
-        // Create mode
-        cmd.arg("-c")
-            .arg("-f")
-            .arg(&format!("{}.jar", artifact_base_name2));
+/// A declared dependency with a newer release in its repository
+#[derive(Debug, PartialEq, Eq)]
+pub struct Outdated {
+    /// `group:artifact`
+    pub dependency: String,
+    pub current: String,
+    pub latest: String,
+}
 
-        if let Some(entrypoint) = entrypoint_class {
-            cmd.arg("-e").arg(&entrypoint);
-        } else {
-            cmd.arg("-M");
+/// Compare the declared dependencies with the versions listed in their repository metadata.
+/// Artifacts without metadata are skipped with a warning.
+pub async fn outdated(module: &Module, client: &reqwest::Client) -> Vec<Outdated> {
+    let mut outdated = Vec::new();
+    let mut seen = HashSet::new();
+    for dep in module.dependencies.iter() {
+        let dep = match dep {
+            Dependency::MavenRepo(dep) => dep,
+            _ => continue,
+        };
+        let coordinate = &dep.coordinate;
+        if !seen.insert((&coordinate.group, &coordinate.artifact)) {
+            continue;
         }
-
-        cmd.arg("-C")
-            .arg(&base_dir2.join("target/classes"))
-            .arg(".");
-
-        cmd.stdout(Stdio::inherit())
-            .stderr(Stdio::inherit())
-            .spawn()
-            .unwrap()
-            .wait_with_output()
+        let metadata = match download_memory(client, dep.metadata_url())
             .await
-            .unwrap();
-    }));
+            .map_err(anyhow::Error::from)
+            .and_then(|it| MavenMetadata::parse(&it))
+        {
+            Ok(metadata) => metadata,
+            Err(e) => {
+                status!(
+                    "   Can't read the versions of '{}:{}' : {}",
+                    coordinate.group,
+                    coordinate.artifact,
+                    e
+                );
+                continue;
+            }
+        };
+        if let Some(latest) = metadata.latest_release() {
+            if compare_versions(latest, &coordinate.version) == Ordering::Greater {
+                outdated.push(Outdated {
+                    dependency: format!("{}:{}", coordinate.group, coordinate.artifact),
+                    current: coordinate.version.clone(),
+                    latest: latest.to_string(),
+                });
+            }
+        }
+    }
+    outdated
+}
 
-    if sources {
-        let base_dir2 = base_dir.clone();
-        let artifact_base_name2 = artifact_base_name.clone();
-        handles.push(tokio::spawn(async move {
-            let mut cmd: process::Command = backend.command();
-
-            // Create mode
-            cmd.arg("-c")
-                .arg("-M")
-                .arg("-f")
-                .arg(&format!("{}-sources.jar", artifact_base_name2));
+pub fn outdated_table(outdated: &[Outdated]) -> String {
+    let width = |f: fn(&Outdated) -> &str, header: &str| {
+        outdated
+            .iter()
+            .map(|it| f(it).len())
+            .chain(iter::once(header.len()))
+            .max()
+            .unwrap()
+    };
+    let name = width(|it| &it.dependency, "Dependency");
+    let current = width(|it| &it.current, "Current");
+    let mut out = String::new();
+    writeln!(
+        out,
+        "   {:name$}  {:current$}  Latest",
+        "Dependency",
+        "Current",
+        name = name,
+        current = current
+    )
+    .unwrap();
+    for it in outdated {
+        writeln!(
+            out,
+            "   {:name$}  {:current$}  {}",
+            it.dependency,
+            it.current,
+            it.latest,
+            name = name,
+            current = current
+        )
+        .unwrap();
+    }
+    out
+}
 
-            cmd.arg("-C").arg(&base_dir2.join("src")).arg(".");
+/// Report differences between the lockfile and the manifest, fails on drift when `--locked`
+async fn check_lockfile(module: &Module, env: &Env) -> Result<Option<Lockfile>> {
+    let lockfile = match Lockfile::load(&module.dir).await? {
+        Some(lockfile) => lockfile,
+        None if env.locked => return Err(anyhow!("--locked requires a {}", LOCKFILE)),
+        None => return Ok(None),
+    };
+    let drift = lockfile.drift(module);
+    if env.locked && !drift.is_empty() {
+        return Err(anyhow!(
+            "{} is out of date with the manifest ({} difference(s)) :\n{}",
+            LOCKFILE,
+            drift.len(),
+            drift_diff(&drift).trim_end()
+        ));
+    }
+    for it in &drift {
+        status!("   Lockfile drift: {}", it);
+    }
+    Ok(Some(lockfile))
+}
 
-            cmd.stdout(Stdio::inherit())
-                .stderr(Stdio::inherit())
-                .spawn()
-                .unwrap()
-                .wait_with_output()
-                .await
-                .unwrap();
-        }));
+/// Compare the cached jars with the digests of the lockfile, a mismatch means the jar was
+/// corrupted or tampered with since it was locked
+async fn verify_locked_jars(module: &Module, lockfile: &Lockfile) -> Result<()> {
+    for locked in &lockfile.dependencies {
+        let jar = module.dir.join("libs").join(locked.coordinate().jar_name());
+        // Pom only artifacts have no jar
+        if jar.exists() {
+            locked.verify(&fs::read(&jar).await?)?;
+        }
     }
+    Ok(())
+}
 
-    if docs {
-        let base_dir2 = base_dir.clone();
-        let artifact_base_name2 = artifact_base_name.clone();
-        handles.push(tokio::spawn(async move {
-            let mut cmd: process::Command = backend.command();
+/// Separator between classpath entries of the host, `--path-separator` only changes the printed
+/// classpaths
+pub const CLASSPATH_SEPARATOR: &str = if cfg!(windows) { ";" } else { ":" };
 
-            // Create mode
-            cmd.arg("-c")
-                .arg("-M")
-                .arg("-f")
-                .arg(&format!("{}-docs.jar", artifact_base_name2));
+/// Classpath made of `deps` followed by `classes`, empty if there is nothing
+fn module_classpath<'a>(
+    module: &Module,
+    deps: impl Iterator<Item = &'a Dependency>,
+    classes: Option<&Path>,
+) -> String {
+    module_classpath_entries(module, deps, classes).join(CLASSPATH_SEPARATOR)
+}
 
-            let docs_dir = base_dir2.join("target/docs");
-            cmd.arg("-C").arg(&docs_dir).arg(".");
+fn module_classpath_entries<'a>(
+    module: &Module,
+    deps: impl Iterator<Item = &'a Dependency>,
+    classes: Option<&Path>,
+) -> Vec<String> {
+    deps.map(|it| module.dir.join(it.classpath()))
+        .chain(classes.map(Path::to_path_buf))
+        .map(|it| it.display().to_string())
+        .collect()
+}
 
-            cmd.stdout(Stdio::inherit())
-                .stderr(Stdio::inherit())
-                .spawn()
-                .unwrap()
-                .wait_with_output()
-                .await
-                .unwrap();
-        }));
+/// The `compile`, `runtime` or `test` classpath of the module for `jcargo classpath`, joined with
+/// `separator` as it may be used on another platform
+pub fn classpath(module: &Module, scope: &str, separator: &str) -> Result<String> {
+    let entries = match scope {
+        "compile" => compile_classpath_entries(module),
+        "runtime" => module_classpath_entries(
+            module,
+            module.dependencies.iter_runtime(),
+            Some(&module.classes_dir()),
+        ),
+        "test" => test_classpath_entries(module),
+        other => return Err(anyhow!("Unknown classpath scope '{}'", other)),
+    };
+    Ok(entries.join(separator))
+}
+
+/// Provided dependencies and system jars of the declared `deps`, read from their cached poms.
+/// They are only needed to compile against these, never at runtime.
+fn provided_api<'a>(
+    module: &Module,
+    deps: impl Iterator<Item = &'a Dependency>,
+) -> Vec<Dependency> {
+    deps.filter_map(|it| match it {
+        Dependency::MavenRepo(dep) => Some(dep),
+        _ => None,
+    })
+    .filter_map(|dep| {
+        let text = std::fs::read_to_string(module.dir.join("libs").join(dep.pom_name())).ok()?;
+        let pom = MavenPom::parse(&text).ok()?;
+        Some(
+            pom.dependencies?
+                .dependencies
+                .into_iter()
+                .filter(|it| it.is_provided() || it.is_system())
+                .map(|it| it.to_dependency(Arc::clone(&dep.repo)))
+                .collect::<Vec<_>>(),
+        )
+    })
+    .flatten()
+    .collect()
+}
+
+/// Declared compile dependencies followed by the provided dependencies they need
+fn compile_dependencies(module: &Module) -> Vec<Dependency> {
+    module
+        .dependencies
+        .iter_compile()
+        .cloned()
+        .chain(provided_api(module, module.dependencies.iter_compile()))
+        .collect()
+}
+
+/// Classpath to compile the module, the output dir goes where `[javac] classpath_order` says
+fn compile_classpath(module: &Module) -> String {
+    compile_classpath_entries(module).join(CLASSPATH_SEPARATOR)
+}
+
+fn compile_classpath_entries(module: &Module) -> Vec<String> {
+    let mut deps = module_classpath_entries(module, compile_dependencies(module).iter(), None);
+    deps.extend(module_paths(module, &module.javac.extra_classpath));
+    let classes = vec![module.classes_dir().display().to_string()];
+    match module.javac.classpath_order {
+        ClasspathOrder::DepsFirst => [deps, classes].concat(),
+        ClasspathOrder::ClassesFirst => [classes, deps].concat(),
     }
+}
 
-    for x in handles {
-        x.await.unwrap();
+/// `paths` relative to the module dir
+fn module_paths(module: &Module, paths: &[String]) -> Vec<String> {
+    paths
+        .iter()
+        .map(|it| module.dir.join(it).display().to_string())
+        .collect()
+}
+
+/// Files with one of `extensions` in the JVM source directories
+pub(crate) fn jvm_sources(module: &Module, extensions: &'static [&'static str]) -> Vec<PathBuf> {
+    module
+        .jvm_source_dirs()
+        .iter()
+        .flat_map(|dir| collect_files(dir, Some(extensions)))
+        .collect()
+}
+
+/// kotlinc arguments, followed by the kotlin and java sources
+fn kotlinc_args(module: &Module) -> Vec<String> {
+    let mut args = max_heap_args(&module.kotlin.max_heap);
+    args.extend([
+        "-jvm-target".to_string(),
+        module.java.target.clone(),
+        "-language-version".to_string(),
+        "1.6".to_string(),
+        "-d".to_string(),
+        module.classes_dir().display().to_string(),
+    ]);
+
+    // Never empty since it contains the output dir
+    let cp = compile_classpath(module);
+    debug!("compile classpath: {}", &cp);
+    args.push("-cp".to_string());
+    args.push(cp);
+
+    // expect declarations live in the common sources, actual ones in the jvm sources
+    let common: Vec<_> = collect_files(module.common_source_dir(), Some(&[".kt"]))
+        .map(|it| it.display().to_string())
+        .collect();
+    if !common.is_empty() {
+        args.push("-Xmulti-platform".to_string());
+        args.push(format!("-Xcommon-sources={}", common.join(",")));
     }
+
+    args.extend(
+        jvm_sources(module, &[".kt", ".java"])
+            .into_iter()
+            .map(|it| it.display().to_string()),
+    );
+    args
 }
 
-fn collect_files<P: AsRef<Path>>(
-    path: P,
-    extensions: Option<&'static [&'static str]>,
-) -> impl Iterator<Item = PathBuf> {
-    WalkDir::new(path)
-        .sort_by_file_name()
+/// Warn about the `[jvm] add_modules` missing from the JDK, the names of the incubator modules
+/// change between releases. Returns the missing modules.
+pub async fn check_jdk_modules(module: &Module, runtime: &Runtime) -> Vec<String> {
+    let requested: Vec<_> = module
+        .jvm
+        .add_modules
+        .iter()
+        .flat_map(|it| it.split(','))
+        .map(str::trim)
+        // ALL-DEFAULT, ALL-SYSTEM and ALL-MODULE-PATH
+        .filter(|it| !it.starts_with("ALL-"))
+        .collect();
+    if requested.is_empty() {
+        return Vec::new();
+    }
+    let available = match runtime.modules().await {
+        Some(available) => available,
+        None => return Vec::new(),
+    };
+    let missing: Vec<_> = requested
         .into_iter()
-        .filter_map(|e| e.ok())
-        .filter(move |it| {
-            if it.file_type().is_file() {
-                if let Some(extensions) = extensions {
-                    let file_name = it.file_name().to_str().unwrap();
-                    for e in extensions {
-                        if file_name.ends_with(e) {
-                            return true;
-                        }
-                    }
-                    return false;
-                }
-                return true;
-            }
-            return false;
-        })
-        .map(|it| it.path().to_path_buf())
+        .filter(|it| !available.iter().any(|module| module == it))
+        .map(str::to_string)
+        .collect();
+    for it in &missing {
+        warn!(
+            "Module '{}' of [jvm] add_modules is not in the installed JDK, incubator modules are renamed or removed between releases",
+            it
+        );
+    }
+    missing
 }
 
-/*
-How to setup maven dependencies :
-1. Recursively download poms + parent poms
-    - Root poms (cached)
-    - Parent poms (cached)
-    - Merge poms with parent (exclude unwanted scopes)
-    - Apply dependency rules (dep management)
-    - Apply properties
-    - dependencies pom (cached)
-    - repeat until end of tree
-1.1. Cache everything in a better format
-2. Download all jars (cached)
- */
+/// What a build compiled
+#[derive(Debug, Default)]
+pub struct BuildStats {
+    pub compiled_files: usize,
+    /// Of javac, kotlinc output isn't parsed
+    pub diagnostics: DiagnosticCounts,
+    /// The classes came from the build cache, nothing was compiled
+    pub restored: bool,
+}
 
-/// Setup all dependencies from any scope
-async fn setup_all_dependencies(module: &Module) {
-    let client = reqwest::Client::new();
+/// javac failed, with the diagnostics it reported until then
+#[derive(Debug, thiserror::Error)]
+#[error("{error}")]
+pub struct CompilationFailed {
+    pub diagnostics: DiagnosticCounts,
+    error: anyhow::Error,
+}
 
-    let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel::<JoinHandle<Result<()>>>();
+pub async fn build(
+    module: &Module,
+    backend: &JavaCompilationBackend,
+    warning_summary: bool,
+) -> Result<BuildStats> {
+    let mut stats = BuildStats::default();
+    let output_dir = module.classes_dir();
+    if module.java.enable_preview {
+        warn!(
+            "Preview features are enabled, the classes will only run on a Java {} runtime with --enable-preview",
+            module.java.source
+        );
+    }
+    fs::create_dir_all(&output_dir).await?;
 
-    let dir = module.dir.join("libs");
-    fs::create_dir_all(&dir).await.unwrap();
+    // We need to build kotlin first since it can handle java source files
+    // Javac can't handle kotlin source files
+    // Required for Java <-> Kotlin references
+
+    // Pass if no kotlin sources
+    let kotlin_sources = jvm_sources(module, &[".kt"]).len();
+    if kotlin_sources > 0 {
+        debug!("Detected kotlin sources");
+        stats.compiled_files += kotlin_sources;
 
-    let graph = DependencyGraph::new();
+        let mut ktcmd = KotlinCompilationBackend::Kotlinc.command();
+        ktcmd.args(kotlinc_args(module));
+        let argfile = module.target_dir().join("kotlinc.args");
+        let result = run_command(use_argfile(ktcmd, &argfile).await?).await;
+        remove_argfile(&argfile).await?;
+        result?;
 
-    for dep in module.dependencies.iter() {
-        match dep {
-            Dependency::MavenRepo(repodep) => {
-                tx.send(tokio::spawn(explore_dependency(
-                    client.clone(),
-                    graph.clone(),
-                    dir.clone(),
-                    repodep.clone(),
-                    tx.clone(),
-                )))
-                .unwrap();
+        info!("Compiled kotlin sources");
+    }
+
+    let mut sources = jvm_sources(module, &[".java"]);
+    sources.extend(prepare_generated_sources(module, &module.generated_sources_dir()).await?);
+    let mut sources = sources.into_iter().peekable();
+    // Pass if no java sources
+    if sources.peek().is_some() {
+        debug!("Detected java sources");
+
+        let mut cmd = javac_command(module, backend);
+        sources.for_each(|it| {
+            stats.compiled_files += 1;
+            cmd.arg(it);
+        });
+
+        let diagnostics = run_javac_argfile(
+            cmd,
+            &module.target_dir().join("javac.args"),
+            warning_summary,
+        )
+        .await?;
+        stats.diagnostics = count_diagnostics(&diagnostics);
+
+        info!("Compiled java sources");
+    }
+
+    copy_resources(module, &output_dir).await?;
+    Ok(stats)
+}
+
+/// Listing of a build cache entry, written last : sha256 and path of each file
+const CACHE_CONTENTS: &str = "CONTENTS";
+
+/// `build`, restoring the classes from `env.build_cache` when the same inputs were already
+/// compiled, and storing them otherwise
+pub async fn build_with_cache(module: &Module, env: &Env) -> Result<BuildStats> {
+    let cache = match &env.build_cache {
+        Some(cache) => cache,
+        None => return build(module, &env.comp_backend, env.warning_summary).await,
+    };
+    let entry = cache.join(compile_cache_key(module, &env.comp_backend).await?);
+    let output_dir = module.classes_dir();
+    match restore_classes(&entry, &output_dir).await {
+        Ok(true) => {
+            status!(
+                "   Restored {} v{} from the build cache",
+                module.artifact,
+                module.version
+            );
+            return Ok(BuildStats {
+                restored: true,
+                ..BuildStats::default()
+            });
+        }
+        Ok(false) => debug!("{} isn't in the build cache", entry.display()),
+        Err(e) => {
+            warn!("Can't restore {} : {:#}, compiling", entry.display(), e);
+            // Replaced by this build, the cache is never a reason to fail it
+            if let Err(e) = fs::remove_dir_all(&entry).await {
+                warn!("Can't remove {} : {}", entry.display(), e);
             }
-            _ => {
-                todo!("Other than maven deps");
+        }
+    }
+
+    let stats = build(module, &env.comp_backend, env.warning_summary).await?;
+    // The build succeeded, a cache that can't be written shouldn't fail it
+    if let Err(e) = store_classes(&entry, &output_dir).await {
+        warn!("Can't store the classes in {} : {:#}", entry.display(), e);
+    }
+    Ok(stats)
+}
+
+/// Hash of everything the compiled classes depend on : the sources, the resources, the content
+/// of the classpath, the compiler flags and version. Paths are relative to the module, so the
+/// key is the same in another checkout.
+async fn compile_cache_key(module: &Module, backend: &JavaCompilationBackend) -> Result<String> {
+    let relative = |it: &str| it.replace(&module.dir.display().to_string(), "");
+    let mut hasher = Sha256::new();
+    let mut update = |it: &str| {
+        hasher.update(it.as_bytes());
+        hasher.update([0]);
+    };
+    update(&backend.version().await.unwrap_or_default());
+    update(&format!(
+        "{}:{}:{}",
+        module.group, module.artifact, module.version
+    ));
+    update(&format!("{:?}", module.resources));
+    for arg in javac_flags(module, backend) {
+        update(&relative(&arg));
+    }
+    if !jvm_sources(module, &[".kt"]).is_empty() {
+        let mut args = kotlinc_args(module).into_iter();
+        while let Some(arg) = args.next() {
+            if arg == "-cp" {
+                args.next();
+            } else {
+                update(&relative(&arg));
             }
         }
     }
-    // Drop the initial tx so we don't block indefinitely on recv
-    drop(tx);
 
-    while let Some(t) = rx.recv().await {
-        t.await
-            .expect("Error when joining dependency setup worker")
-            .expect("Error in sub task");
+    let classes = module.classes_dir().display().to_string();
+    let mut inputs: Vec<PathBuf> = compile_classpath_entries(module)
+        .into_iter()
+        .filter(|it| *it != classes)
+        .map(PathBuf::from)
+        .collect();
+    inputs.extend(module.jvm_source_dirs());
+    inputs.push(module.common_source_dir());
+    // Compiled as sources when the annotation processors don't run
+    if !module.javac.runs_processors() {
+        inputs.push(module.generated_sources_dir());
+    }
+    inputs.push(module.resources_dir());
+    for input in inputs {
+        update(&relative(&input.display().to_string()));
+        let files: Vec<_> = if input.is_dir() {
+            collect_files(&input, None).collect()
+        } else {
+            vec![input.clone()]
+        };
+        for file in files {
+            let data = match fs::read(&file).await {
+                Ok(data) => data,
+                Err(_) => continue,
+            };
+            update(&relative(&file.display().to_string()));
+            update(&format!("{:x}", Sha256::digest(&data)));
+        }
     }
+    Ok(format!("{:x}", hasher.finalize()))
 }
 
-async fn generate_jar_manifest(module: &Module, entrypoint_name: Option<String>) {
-    let manifest = module.dir.join("target/classes/META-INF/MANIFEST.MF");
+/// Replace `output_dir` with the classes of the cache `entry`, after checking all of them are
+/// there and intact. Returns false when the entry doesn't exist.
+async fn restore_classes(entry: &Path, output_dir: &Path) -> Result<bool> {
+    let contents = match fs::read_to_string(entry.join(CACHE_CONTENTS)).await {
+        Ok(contents) => contents,
+        Err(_) => return Ok(false),
+    };
+    let mut files = Vec::new();
+    for line in contents.lines() {
+        let (digest, path) = line
+            .split_once("  ")
+            .ok_or_else(|| anyhow!("Invalid line in {} : {}", CACHE_CONTENTS, line))?;
+        let data = fs::read(entry.join(path))
+            .await
+            .with_context(|| format!("{} is missing from the entry", path))?;
+        if format!("{:x}", Sha256::digest(&data)) != digest {
+            return Err(anyhow!("{} is corrupted", path));
+        }
+        files.push((path, data));
+    }
 
-    fs::write(
-        &manifest,
-        r"
-        Manifest-Version: 1.0
-        Main-Class: Main
-        ",
+    if output_dir.exists() {
+        fs::remove_dir_all(output_dir).await?;
+    }
+    for (path, data) in files {
+        let dest = output_dir.join(path);
+        if let Some(parent) = dest.parent() {
+            fs::create_dir_all(parent).await?;
+        }
+        fs::write(dest, data).await?;
+    }
+    Ok(true)
+}
+
+/// Copy `output_dir` to the cache `entry`. The entry is filled under another name then renamed,
+/// so a concurrent or interrupted build never leaves a partial one.
+async fn store_classes(entry: &Path, output_dir: &Path) -> Result<()> {
+    if entry.join(CACHE_CONTENTS).exists() {
+        return Ok(());
+    }
+    let partial = entry.with_extension(format!("partial-{}", std::process::id()));
+    if partial.exists() {
+        fs::remove_dir_all(&partial).await?;
+    }
+    let mut contents = String::new();
+    for file in collect_files(output_dir, None) {
+        let path = file.strip_prefix(output_dir)?;
+        let name = path
+            .iter()
+            .map(|it| it.to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/");
+        let data = fs::read(&file).await?;
+        let dest = partial.join(path);
+        if let Some(parent) = dest.parent() {
+            fs::create_dir_all(parent).await?;
+        }
+        fs::write(dest, &data).await?;
+        writeln!(contents, "{:x}  {}", Sha256::digest(&data), name)?;
+    }
+    fs::create_dir_all(&partial).await?;
+    fs::write(partial.join(CACHE_CONTENTS), contents).await?;
+    if let Err(e) = fs::rename(&partial, entry).await {
+        fs::remove_dir_all(&partial).await?;
+        // Stored by another build in the meantime
+        if !entry.join(CACHE_CONTENTS).exists() {
+            return Err(e.into());
+        }
+    }
+    Ok(())
+}
+
+/// Copy `resources` to `output_dir`, substituting the project properties in the filtered ones
+async fn copy_resources(module: &Module, output_dir: &Path) -> Result<()> {
+    let resources = module.resources_dir();
+    if !resources.is_dir() {
+        return Ok(());
+    }
+    let properties: Properties = [
+        ("project.groupId", &module.group),
+        ("project.group", &module.group),
+        ("project.artifactId", &module.artifact),
+        ("project.artifact", &module.artifact),
+        ("project.version", &module.version),
+    ]
+    .iter()
+    .map(|(k, v)| (k.to_string(), v.to_string()))
+    .collect();
+
+    for entry in WalkDir::new(&resources)
+        .into_iter()
+        .filter_map(|it| it.ok())
+        .filter(|it| it.file_type().is_file())
+    {
+        let relative = entry.path().strip_prefix(&resources)?;
+        let dest = output_dir.join(relative);
+        if let Some(parent) = dest.parent() {
+            fs::create_dir_all(parent).await?;
+        }
+        let content = fs::read(entry.path()).await?;
+        // Binary files are never filtered
+        let text = if module.resources.is_filtered(relative) && !content.contains(&0) {
+            String::from_utf8(content).map_err(|e| e.into_bytes())
+        } else {
+            Err(content)
+        };
+        match text {
+            Ok(text) => {
+                fs::write(
+                    &dest,
+                    properties
+                        .recurse_resolve(&text, &module.version)
+                        .as_bytes(),
+                )
+                .await?
+            }
+            Err(content) => fs::write(&dest, content).await?,
+        }
+    }
+    debug!("Copied resources");
+    Ok(())
+}
+
+/// The javac arguments without the classpath and the sources
+pub(crate) fn javac_flags(module: &Module, backend: &JavaCompilationBackend) -> Vec<String> {
+    let mut flags = Vec::new();
+    let mut args = javac_command(module, backend)
+        .as_std()
+        .get_args()
+        .map(|it| it.to_string_lossy().into_owned())
+        .collect::<Vec<_>>()
+        .into_iter();
+    while let Some(arg) = args.next() {
+        if arg == "-cp" {
+            args.next();
+        } else {
+            flags.push(arg);
+        }
+    }
+    flags
+}
+
+/// javac invocation for the module, without the sources
+pub(crate) fn javac_command(module: &Module, backend: &JavaCompilationBackend) -> process::Command {
+    let cp = compile_classpath(module);
+    debug!("compile classpath: {}", &cp);
+    javac_command_to(
+        module,
+        backend,
+        &module.classes_dir(),
+        &module.generated_sources_dir(),
+        &cp,
     )
-    .await
-    .unwrap();
+}
+
+/// Make the dir the annotation processors write to. It's cleared when they run, so the sources
+/// of deleted classes don't linger, otherwise the sources they generated last time are returned
+/// to be compiled.
+async fn prepare_generated_sources(module: &Module, dir: &Path) -> Result<Vec<PathBuf>> {
+    if module.javac.runs_processors() {
+        if dir.exists() {
+            fs::remove_dir_all(dir).await?;
+        }
+        fs::create_dir_all(dir).await?;
+        return Ok(Vec::new());
+    }
+    fs::create_dir_all(dir).await?;
+    Ok(collect_files(dir, Some(&[".java"])).collect())
+}
+
+/// `-J` flags of the JVM running javac, the native javac is compiled ahead of time and has none
+fn javac_jvm_args(module: &Module, backend: &JavaCompilationBackend) -> Vec<String> {
+    match backend {
+        JavaCompilationBackend::JdkJavac(_) => max_heap_args(&module.javac.max_heap),
+        JavaCompilationBackend::NativeJavac => Vec::new(),
+    }
+}
+
+/// javac invocation writing the classes to `output_dir` and the generated sources to
+/// `generated_dir`, without the sources
+fn javac_command_to(
+    module: &Module,
+    backend: &JavaCompilationBackend,
+    output_dir: &Path,
+    generated_dir: &Path,
+    cp: &str,
+) -> process::Command {
+    let mut cmd: process::Command = backend.command();
+    cmd.args(javac_jvm_args(module, backend));
+    // Paired with -source, javac only accepts the preview features of its own release
+    if module.java.enable_preview {
+        cmd.arg("--enable-preview");
+    }
+    cmd.args([
+        "-source",
+        &module.java.source,
+        "-target",
+        &module.java.target,
+        "-encoding",
+        "UTF-8",
+        "-Xlint",
+        "-d",
+        &output_dir.display().to_string(),
+        "-s",
+        &generated_dir.display().to_string(),
+    ]);
+    cmd.args(module.jvm.compiler_args());
+    cmd.args(module.javac.processor_args());
+    if let Some(implicit) = module.javac.implicit {
+        cmd.arg(format!("-implicit:{}", implicit.as_str()));
+    }
+    if !module.javac.bootclasspath.is_empty() {
+        cmd.arg("-bootclasspath")
+            .arg(module_paths(module, &module.javac.bootclasspath).join(CLASSPATH_SEPARATOR));
+    }
+    cmd.arg("-cp").arg(cp);
+    cmd
+}
+
+/// Classpath of the tests : the test classes, the module classes, then every dependency
+fn test_classpath(module: &Module) -> String {
+    test_classpath_entries(module).join(CLASSPATH_SEPARATOR)
+}
+
+fn test_classpath_entries(module: &Module) -> Vec<String> {
+    let mut entries = vec![
+        module.test_classes_dir().display().to_string(),
+        module.classes_dir().display().to_string(),
+    ];
+    let provided = provided_api(module, module.dependencies.iter_test());
+    entries.extend(module_classpath_entries(
+        module,
+        module.dependencies.iter_test().chain(&provided),
+        None,
+    ));
+    entries
+}
+
+/// Compile the java sources in `test` against the module classes and every dependency.
+/// Returns false if there are no tests.
+pub async fn build_tests(
+    module: &Module,
+    backend: &JavaCompilationBackend,
+    warning_summary: bool,
+) -> Result<bool> {
+    let mut sources: Vec<_> = collect_files(module.test_source_dir(), Some(&[".java"])).collect();
+    if sources.is_empty() {
+        return Ok(false);
+    }
+    let output_dir = module.test_classes_dir();
+    fs::create_dir_all(&output_dir).await?;
+    let generated_dir = module.generated_test_sources_dir();
+    sources.extend(prepare_generated_sources(module, &generated_dir).await?);
+
+    let cp = test_classpath(module);
+    debug!("test classpath: {}", &cp);
+    let mut cmd = javac_command_to(module, backend, &output_dir, &generated_dir, &cp);
+    cmd.args(sources);
+    run_javac_argfile(
+        cmd,
+        &module.target_dir().join("javac-test.args"),
+        warning_summary,
+    )
+    .await?;
+    Ok(true)
+}
+
+/// Console launcher invocation running the compiled tests, the launcher comes from the test
+/// dependencies (`org.junit.platform:junit-platform-console-standalone`)
+fn junit_command(module: &Module, runtime: &Runtime, filter: Option<&str>) -> process::Command {
+    let mut cmd = runtime.command();
+    cmd.args(module.jvm.launcher_args());
+    if module.java.enable_preview {
+        cmd.arg("--enable-preview");
+    }
+    cmd.arg("-cp").arg(test_classpath(module));
+    cmd.arg(CONSOLE_LAUNCHER);
+    cmd.arg("--disable-banner");
+    cmd.arg("--scan-class-path").arg(module.test_classes_dir());
+    cmd.arg("--reports-dir").arg(module.test_results_dir());
+    if let Some(filter) = filter {
+        cmd.arg("--include-classname").arg(filter);
+    }
+    cmd
+}
+
+/// Run the compiled tests, the results are read back from the JUnit XML reports
+pub async fn test(module: &Module, runtime: &Runtime, filter: Option<&str>) -> Result<TestSummary> {
+    let reports = module.test_results_dir();
+    if reports.exists() {
+        fs::remove_dir_all(&reports).await?;
+    }
+    let mut cmd = junit_command(module, runtime, filter);
+    print_command(&cmd);
+    let status = cmd
+        .stdout(Stdio::inherit())
+        .stderr(Stdio::inherit())
+        .spawn()?
+        .wait()
+        .await?;
+    if !reports.is_dir() {
+        exit_result(&cmd, status)?;
+        return Err(anyhow!("No test report in {}", reports.display()));
+    }
+    // The launcher also exits with an error when a test fails, only report other errors
+    let summary = TestSummary::read_reports(&reports)?;
+    if summary.failed.is_empty() {
+        exit_result(&cmd, status)?;
+    }
+    Ok(summary)
+}
+
+/// java invocation running `class` of the module
+fn java_command(module: &Module, runtime: &Runtime, class: &str) -> process::Command {
+    java_command_from(
+        module,
+        runtime,
+        &module.classes_dir(),
+        class,
+        &Profiling::default(),
+        None,
+    )
+}
+
+/// Run `class` from `classes`, a classes dir or a jar, with the runtime dependencies. The
+/// assertions are enabled in the `assertions` scopes (everywhere when empty), or as in `[run]`.
+fn java_command_from(
+    module: &Module,
+    runtime: &Runtime,
+    classes: &Path,
+    class: &str,
+    profiling: &Profiling,
+    assertions: Option<&[String]>,
+) -> process::Command {
+    let mut cmd = runtime.command();
+    if profiling.is_enabled() {
+        // The startup flags would skew the profile, the JIT and the GC run as in production
+        cmd.args(profiling_args(module, profiling));
+    } else {
+        cmd.args(["-Xshare:on", "-XX:TieredStopAtLevel=1", "-XX:+UseSerialGC"]);
+    }
+    cmd.args(module.jvm.launcher_args());
+    if module.java.enable_preview {
+        cmd.arg("--enable-preview");
+    }
+    cmd.args(match assertions {
+        Some(scopes) => AssertionsDef::from_scopes(scopes).args(),
+        None => module.run.assertions.args(),
+    });
+
+    let cp = module_classpath(module, module.dependencies.iter_runtime(), Some(classes));
+    cmd.arg("-cp").arg(&cp);
+    debug!("runtime classpath: {}", &cp);
+
+    cmd.arg(class);
+    cmd
+}
+
+/// Written in the target dir by `run --jfr` and `run --profiler async:...`
+pub const JFR_RECORDING: &str = "recording.jfr";
+pub const PROFILER_OUTPUT: &str = "profile.html";
+
+/// JVM arguments starting the requested profilers, recording to the target dir
+fn profiling_args(module: &Module, profiling: &Profiling) -> Vec<String> {
+    let mut args = Vec::new();
+    // -XX:+FlightRecorder is implied, and deprecated since JDK 13
+    if let Some(settings) = &profiling.jfr {
+        let mut recording = format!(
+            "-XX:StartFlightRecording=filename={}",
+            module.target_dir().join(JFR_RECORDING).display()
+        );
+        if let Some(settings) = settings {
+            recording.push_str(",settings=");
+            recording.push_str(settings);
+        }
+        args.push(recording);
+    }
+    if let Some(Profiler::Async(library)) = &profiling.profiler {
+        args.push(format!(
+            "-agentpath:{}=start,event=cpu,file={}",
+            module.dir.join(library).display(),
+            module.target_dir().join(PROFILER_OUTPUT).display()
+        ));
+    }
+    args
+}
+
+/// Check the JDK has JFR and the agent exists, before a profiled run
+async fn prepare_profiling(
+    module: &Module,
+    runtime: &Runtime,
+    profiling: &Profiling,
+) -> Result<()> {
+    if profiling.jfr.is_some() {
+        // Java 8 can't list its modules, it only has JFR from 8u262 and java will tell
+        if let Some(modules) = runtime.modules().await {
+            if !modules.iter().any(|it| it == "jdk.jfr") {
+                return Err(anyhow!(
+                    "--jfr needs Java Flight Recorder, the installed JDK doesn't have the jdk.jfr module"
+                ));
+            }
+        }
+    }
+    if let Some(Profiler::Async(library)) = &profiling.profiler {
+        let library = module.dir.join(library);
+        if !library.is_file() {
+            return Err(anyhow!(
+                "The async-profiler library {} doesn't exist",
+                library.display()
+            ));
+        }
+    }
+    if profiling.is_enabled() {
+        fs::create_dir_all(module.target_dir()).await?;
+    }
+    Ok(())
+}
+
+fn report_profiling(module: &Module, profiling: &Profiling) {
+    if profiling.jfr.is_some() {
+        status!(
+            "   Recording written to {}",
+            module.target_dir().join(JFR_RECORDING).display()
+        );
+    }
+    if profiling.profiler.is_some() {
+        status!(
+            "   Profile written to {}",
+            module.target_dir().join(PROFILER_OUTPUT).display()
+        );
+    }
+}
+
+pub async fn run(
+    module: &Module,
+    runtime: &Runtime,
+    entrypoint_name: Option<String>,
+    profiling: &Profiling,
+    assertions: Option<&[String]>,
+    log: Option<&Path>,
+) -> Result<()> {
+    let class = match entrypoint_name {
+        Some(name) => module
+            .find_entrypoint(&name)
+            .map(|it| &it.class)
+            .ok_or_else(|| anyhow!("Can't find entrypoint '{}'", name))?,
+        None => {
+            let interactive = atty::is(atty::Stream::Stdin) && atty::is(atty::Stream::Stdout);
+            &module.pick_entrypoint(interactive)?.class
+        }
+    };
+
+    let cmd = java_command_from(
+        module,
+        runtime,
+        &module.classes_dir(),
+        class,
+        profiling,
+        assertions,
+    );
+    match log {
+        Some(log) => run_command_tee(cmd, log).await,
+        None => run_command(cmd).await,
+    }
+}
+
+/// Run every entrypoint in order, the sequence stops at the first failure unless `keep_going`.
+/// Returns the label and outcome of each entrypoint that was launched.
+pub async fn run_all(
+    module: &Module,
+    runtime: &Runtime,
+    keep_going: bool,
+) -> Vec<(String, Result<()>)> {
+    let mut results = Vec::with_capacity(module.entrypoints.len());
+    for entrypoint in &module.entrypoints {
+        status!("   Running '{}'", entrypoint.label());
+        let res = run_command(java_command(module, runtime, &entrypoint.class)).await;
+        if let Err(e) = &res {
+            eprintln!("   Entrypoint '{}' failed : {:#}", entrypoint.label(), e);
+        }
+        let failed = res.is_err();
+        results.push((entrypoint.label().to_string(), res));
+        if failed && !keep_going {
+            break;
+        }
+    }
+    results
+}
+
+/// Run the `Main-Class` of a packaged jar. Not with `java -jar`, which would ignore the
+/// runtime dependencies that aren't in its `Class-Path`.
+fn jar_command(
+    module: &Module,
+    runtime: &Runtime,
+    jar: &Path,
+    profiling: &Profiling,
+    assertions: Option<&[String]>,
+) -> Result<process::Command> {
+    let contents =
+        JarContents::read(jar).with_context(|| format!("Can't read jar {}", jar.display()))?;
+    let class = contents
+        .manifest
+        .as_ref()
+        .and_then(|it| it.main_class())
+        .ok_or_else(|| anyhow!("{} has no Main-Class in its manifest", jar.display()))?;
+    Ok(java_command_from(
+        module, runtime, jar, class, profiling, assertions,
+    ))
+}
+
+pub async fn run_jar(
+    module: &Module,
+    runtime: &Runtime,
+    jar: &Path,
+    profiling: &Profiling,
+    assertions: Option<&[String]>,
+    log: Option<&Path>,
+) -> Result<()> {
+    let cmd = jar_command(module, runtime, jar, profiling, assertions)?;
+    match log {
+        Some(log) => run_command_tee(cmd, log).await,
+        None => run_command(cmd).await,
+    }
+}
+
+/// Fingerprint of the last successful javadoc run, in the docs dir
+const DOC_STATE: &str = ".doc-state";
+
+/// Arguments of the javadoc invocation, -cp is omitted when there are no dependencies
+fn doc_args(module: &Module) -> Vec<String> {
+    let mut args = vec!["-d".to_string(), module.docs_dir().display().to_string()];
+    if module.java.enable_preview {
+        args.extend([
+            "--enable-preview".to_string(),
+            "-source".to_string(),
+            module.java.source.clone(),
+        ]);
+    }
+    // Javadoc only needs the dependencies
+    let cp = module_classpath(module, compile_dependencies(module).iter(), None);
+    debug!("compile classpath: {}", &cp);
+    if !cp.is_empty() {
+        args.extend(["-cp".to_string(), cp]);
+    }
+    args.extend(
+        collect_files(&module.source_dir(), Some(&[".java"])).map(|it| it.display().to_string()),
+    );
+    args
+}
+
+/// Changes when an argument changes or a source file or a classpath entry is added, removed
+/// or modified
+fn doc_fingerprint(module: &Module, args: &[String]) -> String {
+    let mut hasher = Sha256::new();
+    for arg in args {
+        // Separated, so that moving text between two arguments changes the fingerprint
+        hasher.update(arg.as_bytes());
+        hasher.update([0]);
+    }
+    let source_dir = module.source_dir();
+    let deps = compile_dependencies(module);
+    let files = collect_files(&source_dir, Some(&[".java"]))
+        .chain(deps.iter().map(|it| module.dir.join(it.classpath())));
+    for file in files {
+        hasher.update(file.display().to_string().as_bytes());
+        if let Ok(meta) = std::fs::metadata(&file) {
+            hasher.update(meta.len().to_le_bytes());
+            if let Ok(modified) = meta.modified() {
+                let since_epoch = modified
+                    .duration_since(std::time::UNIX_EPOCH)
+                    .unwrap_or_default();
+                hasher.update(since_epoch.as_nanos().to_le_bytes());
+            }
+        }
+    }
+    format!("{:x}", hasher.finalize())
+}
+
+/// Generate the javadoc, unless nothing changed since the last run.
+/// Returns whether javadoc was invoked.
+pub async fn build_doc(module: &Module, backend: &DocumentationBackend) -> Result<bool> {
+    let output = module.docs_dir();
+    let state = output.join(DOC_STATE);
+
+    let args = doc_args(module);
+    let fingerprint = doc_fingerprint(module, &args);
+    if fs::read_to_string(&state).await.ok().as_deref() == Some(fingerprint.as_str()) {
+        debug!("Docs are up to date");
+        return Ok(false);
+    }
+
+    let mut cmd: process::Command = backend.command();
+
+    tokio::fs::create_dir_all(&output).await?;
+
+    cmd.args(&args);
+
+    run_command(cmd).await?;
+    fs::write(&state, fingerprint).await?;
+    Ok(true)
+}
+
+/// What goes in a fat jar along the module classes : runtime dependencies only,
+/// compile only (provided) and test dependencies are left out
+fn bundled_classpath(module: &Module) -> Vec<PathBuf> {
+    module
+        .dependencies
+        .iter_runtime()
+        .map(|it| module.dir.join(it.classpath()))
+        .collect()
+}
+
+/// The jars `package` writes next to the main one, and how the main one is made
+#[derive(Debug, Clone, Default)]
+pub struct PackageOptions {
+    /// `-sources.jar`
+    pub sources: bool,
+    /// `-docs.jar`, the docs must be built
+    pub docs: bool,
+    /// `-all.jar` with the runtime dependencies
+    pub fat: bool,
+    /// Copy the dependencies next to the jar and reference them from its manifest
+    pub with_libs: bool,
+    /// Name of the entrypoint started by `java -jar`
+    pub entrypoint: Option<String>,
+}
+
+pub async fn package(
+    module: &Module,
+    backend: &PackageBackend,
+    options: PackageOptions,
+) -> Result<()> {
+    let PackageOptions {
+        sources,
+        docs,
+        fat,
+        with_libs,
+        entrypoint,
+    } = options;
+    let base_dir = Arc::new(module.dir.clone());
+    let artifact_dir = module.artifacts_dir();
+    let artifact_base_name = Arc::new(format!(
+        "{}/{}-{}",
+        artifact_dir.display(),
+        module.artifact,
+        module.version
+    ));
+
+    let entrypoint_class = entrypoint
+        .as_ref()
+        .and_then(|it| module.find_entrypoint(it))
+        .map(|it| it.class.clone());
+
+    tokio::fs::create_dir_all(&artifact_dir).await?;
+
+    fs::write(
+        format!("{}.pom", artifact_base_name),
+        MavenPom::for_module(module).save()?,
+    )
+    .await?;
+
+    let mut handles = Vec::new();
+    if fat {
+        let out = PathBuf::from(format!("{}-all.jar", artifact_base_name));
+        let classes = module.classes_dir();
+        let bundled = bundled_classpath(module);
+        let main_class = entrypoint_class.clone();
+        handles.push(tokio::task::spawn_blocking(move || {
+            write_fat_jar(&out, &classes, &bundled, main_class.as_deref())
+        }));
+    }
+
+    let jar_manifest = if with_libs {
+        Some(copy_libs(module).await?)
+    } else {
+        None
+    };
+
+    let base_dir2 = base_dir.clone();
+    let artifact_base_name2 = artifact_base_name.clone();
+    let backend2 = backend.clone();
+    handles.push(tokio::spawn(async move {
+        let mut cmd: process::Command = backend2.command();
+
+        // Create mode
+        cmd.arg("-c")
+            .arg("-f")
+            .arg(format!("{}.jar", artifact_base_name2));
+
+        if let Some(jar_manifest) = &jar_manifest {
+            cmd.arg("-m").arg(jar_manifest);
+        }
+        if let Some(entrypoint) = entrypoint_class {
+            cmd.arg("-e").arg(&entrypoint);
+        } else if jar_manifest.is_none() {
+            cmd.arg("-M");
+        }
+
+        cmd.arg("-C").arg(base_dir2.join("target/classes")).arg(".");
+
+        run_command(cmd).await
+    }));
+
+    if sources {
+        let base_dir2 = base_dir.clone();
+        let artifact_base_name2 = artifact_base_name.clone();
+        let backend2 = backend.clone();
+        let resources = module.resources_dir();
+        let generated = module.generated_sources_dir();
+        handles.push(tokio::spawn(async move {
+            let mut cmd: process::Command = backend2.command();
+
+            // Create mode
+            cmd.arg("-c")
+                .arg("-M")
+                .arg("-f")
+                .arg(format!("{}-sources.jar", artifact_base_name2));
+
+            // Every source set is under src, the resources keep their layout next to the sources
+            cmd.arg("-C").arg(base_dir2.join("src")).arg(".");
+            if resources.is_dir() {
+                cmd.arg("-C").arg(&resources).arg(".");
+            }
+            // What the annotation processors generated, the classes are in the jar
+            if collect_files(&generated, Some(&[".java"])).next().is_some() {
+                cmd.arg("-C").arg(&generated).arg(".");
+            }
+
+            run_command(cmd).await
+        }));
+    }
+
+    if docs {
+        let base_dir2 = base_dir.clone();
+        let artifact_base_name2 = artifact_base_name.clone();
+        let backend2 = backend.clone();
+        handles.push(tokio::spawn(async move {
+            let mut cmd: process::Command = backend2.command();
+
+            // Create mode
+            cmd.arg("-c")
+                .arg("-M")
+                .arg("-f")
+                .arg(format!("{}-docs.jar", artifact_base_name2));
+
+            let docs_dir = base_dir2.join("target/docs");
+            cmd.arg("-C").arg(&docs_dir).arg(".");
+
+            run_command(cmd).await
+        }));
+    }
+
+    for x in handles {
+        x.await??;
+    }
+    Ok(())
+}
+
+/// Jar the classes of `classifier` as `<artifact>-<version>-<classifier>.jar`, next to the main
+/// jar so `deploy` uploads it with it
+pub async fn package_classified(
+    module: &Module,
+    backend: &PackageBackend,
+    classifier: Classifier,
+) -> Result<PathBuf> {
+    let classes = match classifier {
+        Classifier::Tests => module.test_classes_dir(),
+    };
+    let out = module.artifacts_dir().join(format!(
+        "{}-{}-{}.jar",
+        module.artifact,
+        module.version,
+        classifier.as_str()
+    ));
+    fs::create_dir_all(module.artifacts_dir()).await?;
+
+    let mut cmd: process::Command = backend.command();
+    cmd.arg("-c")
+        .arg("-M")
+        .arg("-f")
+        .arg(&out)
+        .arg("-C")
+        .arg(&classes)
+        .arg(".");
+    run_command(cmd).await?;
+    Ok(out)
+}
+
+/// jarsigner signing `jar` with the `[signing]` key. jarsigner reads the passwords from the
+/// environment itself, they never appear in the command. `var` reads an environment variable.
+pub fn jarsigner_command(
+    signing: &SigningDef,
+    backend: &PackageBackend,
+    jar: &Path,
+    var: impl Fn(&str) -> Option<String>,
+) -> Result<process::Command> {
+    let keystore = signing.keystore.as_ref().ok_or_else(|| {
+        anyhow!(
+            "No signing.keystore in the global config to sign {} with",
+            jar.display()
+        )
+    })?;
+    if !keystore.is_file() {
+        return Err(anyhow!(
+            "The signing keystore {} doesn't exist",
+            keystore.display()
+        ));
+    }
+    let alias = signing
+        .alias
+        .as_ref()
+        .ok_or_else(|| anyhow!("No signing.alias, the key to sign with"))?;
+
+    let mut cmd = backend.signer_command();
+    cmd.arg("-keystore").arg(keystore);
+    if let Some(prefix) = &signing.credentials {
+        let storepass = format!("{}_STOREPASS", prefix);
+        if var(&storepass).is_none() {
+            return Err(anyhow!(
+                "Missing signing credentials '{}', set {}",
+                prefix,
+                storepass
+            ));
+        }
+        cmd.arg("-storepass:env").arg(storepass);
+        let keypass = format!("{}_KEYPASS", prefix);
+        if var(&keypass).is_some() {
+            cmd.arg("-keypass:env").arg(keypass);
+        }
+    }
+    cmd.arg(jar).arg(alias);
+    Ok(cmd)
+}
+
+/// Sign `jar` in place and check the signature
+async fn sign_jar(signing: &SigningDef, backend: &PackageBackend, jar: &Path) -> Result<()> {
+    run_command(jarsigner_command(signing, backend, jar, |name| {
+        std::env::var(name).ok()
+    })?)
+    .await?;
+
+    let mut verify = backend.signer_command();
+    verify.arg("-verify").arg(jar);
+    run_command(verify).await?;
+    // -verify also succeeds on unsigned jars
+    let signed = JarContents::read(jar)?
+        .entries
+        .iter()
+        .any(|it| it.starts_with("META-INF/") && it.ends_with(".SF"));
+    if !signed {
+        return Err(anyhow!("{} isn't signed", jar.display()));
+    }
+    Ok(())
+}
+
+/// Copy the runtime jars to `target/artifacts/lib`, and write the manifest referencing them for
+/// the thin jar. Returns the path to that manifest.
+async fn copy_libs(module: &Module) -> Result<PathBuf> {
+    let lib_dir = module.artifacts_dir().join("lib");
+    if lib_dir.exists() {
+        fs::remove_dir_all(&lib_dir).await?;
+    }
+    fs::create_dir_all(&lib_dir).await?;
+
+    let mut class_path = Vec::new();
+    for jar in bundled_classpath(module) {
+        // The classes of local modules aren't a jar
+        if !jar.is_file() {
+            warn!("{} can't be copied to lib, it isn't a jar", jar.display());
+            continue;
+        }
+        let name = jar.file_name().unwrap().to_string_lossy().into_owned();
+        fs::copy(&jar, lib_dir.join(&name))
+            .await
+            .with_context(|| format!("Can't copy {} to lib", jar.display()))?;
+        class_path.push(format!("lib/{}", name));
+    }
+
+    let manifest = JarManifest {
+        attributes: vec![
+            ("Manifest-Version".to_string(), "1.0".to_string()),
+            ("Created-By".to_string(), "jcargo".to_string()),
+            ("Class-Path".to_string(), class_path.join(" ")),
+        ],
+    };
+    let path = module.target_dir().join("MANIFEST.MF");
+    fs::write(&path, manifest.render()).await?;
+    Ok(path)
+}
+
+/// Spawn a tool with inherited stdio and wait for it, failing if it exits with an error.
+/// Stdin stays connected until the tool exits, `run` programs can read `System.in`.
+async fn run_command(mut cmd: process::Command) -> Result<()> {
+    print_command(&cmd);
+    let status = cmd
+        .stdin(Stdio::inherit())
+        .stdout(Stdio::inherit())
+        .stderr(Stdio::inherit())
+        .spawn()?
+        .wait()
+        .await?;
+    exit_result(&cmd, status)
+}
+
+/// [run_command], with the output of the tool also written to `log`, which is truncated first
+async fn run_command_tee(mut cmd: process::Command, log: &Path) -> Result<()> {
+    print_command(&cmd);
+    if let Some(parent) = log.parent() {
+        fs::create_dir_all(parent).await?;
+    }
+    let file = fs::File::create(log)
+        .await
+        .with_context(|| format!("Can't create the log {}", log.display()))?;
+    let file = Arc::new(tokio::sync::Mutex::new(file));
+
+    let mut child = cmd
+        .stdin(Stdio::inherit())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    let stdout = tee(
+        child.stdout.take().expect("stdout is piped"),
+        tokio::io::stdout(),
+        Arc::clone(&file),
+    );
+    let stderr = tee(
+        child.stderr.take().expect("stderr is piped"),
+        tokio::io::stderr(),
+        file,
+    );
+    let (status, stdout, stderr) = tokio::join!(child.wait(), stdout, stderr);
+    stdout?;
+    stderr?;
+    exit_result(&cmd, status?)
+}
+
+/// Copy `from` to `console` and `log` as it comes, not line by line so prompts show up
+async fn tee(
+    mut from: impl AsyncRead + Unpin,
+    mut console: impl AsyncWrite + Unpin,
+    log: Arc<tokio::sync::Mutex<fs::File>>,
+) -> Result<()> {
+    let mut buf = [0; 8192];
+    loop {
+        let read = from.read(&mut buf).await?;
+        if read == 0 {
+            return Ok(());
+        }
+        console.write_all(&buf[..read]).await?;
+        console.flush().await?;
+        let mut log = log.lock().await;
+        log.write_all(&buf[..read]).await?;
+        log.flush().await?;
+    }
+}
+
+/// With `--print-command`, print the command about to be spawned
+fn print_command(cmd: &process::Command) {
+    if logging::prints_commands() {
+        eprintln!("   Command: {}", command_line(cmd));
+    }
+}
+
+/// The command as it would be typed in a POSIX shell, with the variables set for it
+fn command_line(cmd: &process::Command) -> String {
+    let cmd = cmd.as_std();
+    let envs = cmd.get_envs().filter_map(|(key, value)| {
+        Some(format!(
+            "{}={}",
+            key.to_string_lossy(),
+            shell_quote(&value?.to_string_lossy())
+        ))
+    });
+    let args = std::iter::once(cmd.get_program())
+        .chain(cmd.get_args())
+        .map(|it| shell_quote(&it.to_string_lossy()));
+    envs.chain(args).collect::<Vec<_>>().join(" ")
+}
+
+/// Single quote `arg` unless it only has characters the shell leaves alone
+fn shell_quote(arg: &str) -> String {
+    let plain = !arg.is_empty()
+        && arg
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_./:=,@%+".contains(c));
+    if plain {
+        arg.to_string()
+    } else {
+        format!("'{}'", arg.replace('\'', "'\\''"))
+    }
+}
+
+fn exit_result(cmd: &process::Command, status: std::process::ExitStatus) -> Result<()> {
+    if status.success() {
+        Ok(())
+    } else {
+        Err(anyhow!(
+            "'{}' exited with {}",
+            cmd.as_std().get_program().to_string_lossy(),
+            status
+        ))
+    }
+}
+
+/// `exit_result`, with an explanation when the JDK doesn't support the requested release
+fn javac_result(
+    cmd: &process::Command,
+    status: std::process::ExitStatus,
+    stderr: &str,
+) -> Result<()> {
+    match unsupported_release(stderr) {
+        Some(release) if !status.success() => Err(anyhow!("{}", release)),
+        _ => exit_result(cmd, status),
+    }
+}
+
+/// Arguments longer than this in total go in an argfile, windows limits command lines to 32k chars
+const ARGFILE_THRESHOLD: usize = 8 * 1024;
+
+/// Move the arguments of `cmd` to the argfile at `path` when they are too long for a command line.
+/// javac and kotlinc both read `@argfile` arguments, but the `-J` flags of their JVM must stay
+/// on the command line.
+async fn use_argfile(cmd: process::Command, path: &Path) -> Result<process::Command> {
+    let std = cmd.as_std();
+    let len: usize = std.get_args().map(|it| it.len() + 1).sum();
+    if len <= ARGFILE_THRESHOLD {
+        return Ok(cmd);
+    }
+    let is_jvm_flag = |it: &&OsStr| it.to_string_lossy().starts_with("-J");
+    let content = std
+        .get_args()
+        .filter(|it| !is_jvm_flag(it))
+        .map(|it| argfile_quote(&it.to_string_lossy()))
+        .collect::<Vec<_>>()
+        .join("\n");
+    fs::write(path, content).await?;
+    debug!(
+        "{} arguments written to {}",
+        std.get_args().len(),
+        path.display()
+    );
+
+    let mut argcmd = process::Command::new(std.get_program());
+    for (key, value) in std.get_envs() {
+        match value {
+            Some(value) => argcmd.env(key, value),
+            None => argcmd.env_remove(key),
+        };
+    }
+    if let Some(dir) = std.get_current_dir() {
+        argcmd.current_dir(dir);
+    }
+    argcmd.args(std.get_args().filter(is_jvm_flag));
+    argcmd.arg(format!("@{}", path.display()));
+    Ok(argcmd)
+}
+
+/// Double quoted, with backslashes and quotes escaped
+fn argfile_quote(arg: &str) -> String {
+    format!("\"{}\"", arg.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// [run_javac] through an argfile at `path` if needed, removed afterwards
+async fn run_javac_argfile(
+    cmd: process::Command,
+    path: &Path,
+    summary: bool,
+) -> Result<Vec<Diagnostic>> {
+    let cmd = use_argfile(cmd, path).await?;
+    let result = run_javac(cmd, summary).await;
+    remove_argfile(path).await?;
+    result
+}
+
+async fn remove_argfile(path: &Path) -> Result<()> {
+    if path.exists() {
+        fs::remove_file(path).await?;
+    }
+    Ok(())
+}
+
+/// Run javac and return its diagnostics, with `summary` the warnings are collapsed into a count
+/// per category while errors are still printed in full. The output is printed and parsed as javac
+/// writes it.
+async fn run_javac(mut cmd: process::Command, summary: bool) -> Result<Vec<Diagnostic>> {
+    print_command(&cmd);
+    let mut child = cmd
+        .stdout(Stdio::inherit())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    let mut reader = BufReader::new(child.stderr.take().expect("stderr is piped"));
+    let mut parser = JavacOutputParser::default();
+    let mut diagnostics = Vec::new();
+    let mut stderr = String::new();
+    let mut buf = Vec::new();
+    loop {
+        buf.clear();
+        if reader.read_until(b'\n', &mut buf).await? == 0 {
+            break;
+        }
+        let line = String::from_utf8_lossy(&buf);
+        let line = line.trim_end_matches(['\r', '\n']);
+        if !summary {
+            eprintln!("{}", line);
+        }
+        stderr.push_str(line);
+        stderr.push('\n');
+        diagnostics.extend(parser.push(line).map(|it| print_error(it, summary)));
+    }
+    diagnostics.extend(parser.finish().map(|it| print_error(it, summary)));
+    let status = child.wait().await?;
+
+    let has_errors = diagnostics.iter().any(|it| it.severity == Severity::Error);
+    if summary && !status.success() && !has_errors {
+        // Not a compilation error (e.g. an invalid flag), show everything
+        eprint!("{}", stderr);
+    }
+    if summary {
+        let warnings = warning_summary(&diagnostics);
+        if !warnings.is_empty() {
+            status!("   Warnings: {}", warnings);
+        }
+    }
+    javac_result(&cmd, status, &stderr).map_err(|error| CompilationFailed {
+        diagnostics: count_diagnostics(&diagnostics),
+        error,
+    })?;
+    Ok(diagnostics)
+}
+
+/// With the warning summary, errors are printed as soon as javac is done with them
+fn print_error(diagnostic: Diagnostic, summary: bool) -> Diagnostic {
+    if summary && diagnostic.severity == Severity::Error {
+        eprintln!("{}", diagnostic.text);
+    }
+    diagnostic
+}
+
+fn count_diagnostics(diagnostics: &[Diagnostic]) -> DiagnosticCounts {
+    let mut counts = DiagnosticCounts::default();
+    for it in diagnostics {
+        match it.severity {
+            Severity::Warning => counts.warnings += 1,
+            Severity::Error => counts.errors += 1,
+        }
+    }
+    counts
+}
+
+fn collect_files<P: AsRef<Path>>(
+    path: P,
+    extensions: Option<&'static [&'static str]>,
+) -> impl Iterator<Item = PathBuf> {
+    WalkDir::new(path)
+        .sort_by_file_name()
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .filter(move |it| {
+            if it.file_type().is_file() {
+                if let Some(extensions) = extensions {
+                    let file_name = it.file_name().to_str().unwrap();
+                    for e in extensions {
+                        if file_name.ends_with(e) {
+                            return true;
+                        }
+                    }
+                    return false;
+                }
+                return true;
+            }
+            false
+        })
+        .map(|it| it.path().to_path_buf())
+}
+
+/*
+How to setup maven dependencies :
+1. Recursively download poms + parent poms
+    - Root poms (cached)
+    - Parent poms (cached)
+    - Merge poms with parent (exclude unwanted scopes)
+    - Apply dependency rules (dep management)
+    - Apply properties
+    - dependencies pom (cached)
+    - repeat until end of tree
+1.1. Cache everything in a better format
+2. Download all jars (cached)
+ */
+
+/// Setup all dependencies from any scope
+async fn setup_all_dependencies(
+    module: &Module,
+    env: &Env,
+    sources: bool,
+    docs: bool,
+) -> Result<Exploration> {
+    let client = http_client_with(&env.downloads);
+
+    let keyring = if env.verify_signatures {
+        let path = module.security.keyring.as_ref().ok_or_else(|| {
+            anyhow!("--verify-signatures requires a keyring in the [security] table")
+        })?;
+        Some(Arc::new(
+            Keyring::load(&module.dir.join(path))
+                .await
+                .with_context(|| format!("Can't load keyring {}", path))?,
+        ))
+    } else {
+        None
+    };
+
+    let dir = module.dir.join("libs");
+    fs::create_dir_all(&dir).await.unwrap();
+
+    let ctx = ExploreContext {
+        client,
+        graph: DependencyGraph::new(),
+        base_dir: dir,
+        keyring,
+        offline: env.offline,
+        trace: env.explain_resolution.then(ResolutionTrace::default),
+        path: Vec::new(),
+        managed_by: None,
+        download_attempts: env.downloads.attempts,
+        platform: Arc::new(module.dependencies.platform.clone()),
+        sources,
+        docs,
+        timings: env.timings.clone(),
+        repos: Arc::new(env.repos.clone()),
+    };
+
+    // The same coordinate can be declared in multiple scopes, the explorer only keeps the first
+    let mut roots = Vec::new();
+    for (scope, deps) in module.dependencies.scopes() {
+        // The transitive dependencies come from the repositories of the scope too
+        let mut ctx = ctx.clone();
+        if let Some(repos) = module.dependencies.repositories.get(scope) {
+            ctx.repos = Arc::new(repos.clone());
+        }
+        for dep in deps {
+            match dep {
+                Dependency::MavenRepo(repodep) => {
+                    if let Some(trace) = &ctx.trace {
+                        trace.record(&repodep.coordinate, &[], None);
+                    }
+                    roots.push((ctx.clone(), repodep.clone()));
+                }
+                // Local dependencies have nothing to download
+                Dependency::JcargoLocal(_) | Dependency::PrebuiltLocal(_) => {}
+                _ => {
+                    todo!("Other than maven deps");
+                }
+            }
+        }
+    }
+    let mut exploration = explore_all(roots, RESOLUTION_JOBS).await;
+    let mut failures = BTreeMap::new();
+    for (coordinate, e) in std::mem::take(&mut exploration.failures) {
+        failures.insert(coordinate.to_string(), format!("{:#}", e));
+    }
+    if let Some(trace) = &ctx.trace {
+        print!("{}", trace.explain());
+    }
+    if failures.is_empty() {
+        return Ok(exploration);
+    }
+    let mut message = format!("Failed to set up {} dependencies :", failures.len());
+    for (coordinate, cause) in &failures {
+        message.push_str(&format!("\n   {} : {}", coordinate, cause));
+    }
+    Err(anyhow!(message))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+    use std::path::Path;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use structopt::StructOpt;
+    use zip::write::FileOptions;
+    use zip::ZipWriter;
+
+    use crate::backend::{exe_name, find_program, Jdk};
+    use crate::dependencies::{MavenRepo, RepoLayout};
+    use crate::io::http_client;
+    use crate::jar::{write_fat_jar, JarContents};
+    use crate::junit::CONSOLE_LAUNCHER;
+    use crate::lockfile::Lockfile;
+    use crate::manifest::{Classifier, ModuleManifest};
+    use crate::report::REPORT_FILE;
+    use crate::tasks::{
+        build, build_doc, build_tests, build_with_cache, check, check_jdk_modules, classpath,
+        clean, collect_files, command_line, compile_classpath, deploy, dir_size, doc_args,
+        doc_fingerprint, execute_task, execute_task_mod, fetch, info, inspect, jar_command,
+        jarsigner_command, java_command, java_command_from, javac_command, javac_flags,
+        javac_jvm_args, junit_command, kotlinc_args, module_classpath, outdated, outdated_table,
+        package, package_classified, prune_candidates, run, run_all, setup_all_dependencies,
+        test_classpath, use_argfile, Outdated, PackageOptions, CLASSPATH_SEPARATOR, JFR_RECORDING,
+        PROFILER_OUTPUT,
+    };
+    use crate::test_utils::{capture_logs, response, temp_dir, MockServer};
+    use crate::timings::{TimingReport, Timings, TIMINGS_FILE, TIMINGS_HTML};
+    use crate::toolchains::{SigningDef, Toolchains};
+    use crate::{
+        load_http_config, Env, JavaCompilationBackend, Module, PackageBackend, Profiling, Task,
+        Workspace,
+    };
+
+    fn fixture_module(env: &Env) -> Module {
+        let manifest = ModuleManifest::parse(
+            r#"
+            group = "marais"
+            artifact = "fixture"
+            version = "1.2.3"
+
+            [dependencies]
+            compileRuntime = ["org.apache.logging.log4j:log4j-api:2.17.1"]
+            runtime = [
+                "org.apache.logging.log4j:log4j-core:2.17.1",
+                "org.slf4j:slf4j-api:1.7.32"
+            ]
+            "#,
+            None,
+        )
+        .unwrap();
+        Module::from_manifest(Path::new("fixture"), manifest, env).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_info() {
+        let env = Env::new(false);
+        let module = fixture_module(&env);
+        let out = info(&module, &env).await;
+        assert!(out.starts_with("marais:fixture:1.2.3\n"));
+        assert!(out.contains("  java: source 17, target 17\n"));
+        assert!(out.contains("  maven-central: https://repo.maven.apache.org/maven2/\n"));
+        assert!(out.contains("  compile: 0\n"));
+        assert!(out.contains("  runtime: 2\n"));
+        assert!(out.contains("  compileRuntime: 1\n"));
+        assert!(out.contains("  transitive: 0\n"));
+    }
+
+    #[tokio::test]
+    async fn test_info_unimported_boms() {
+        let env = Env::new(false);
+        let manifest = ModuleManifest::parse(
+            r#"
+            group = "marais"
+            artifact = "boms"
+            version = "0.1.0"
+
+            [dependencies]
+            compile = ["org.junit.jupiter:junit-jupiter-api", "marais:lib:1.0"]
+            bom = ["org.junit:junit-bom:5.10.0"]
+            "#,
+            None,
+        )
+        .unwrap();
+        let module = Module::from_manifest(Path::new("boms"), manifest, &env).unwrap();
+        let out = info(&module, &env).await;
+        assert!(out.contains(
+            "  compile: 1
+"
+        ));
+        assert!(out.contains(
+            "  unresolved: 1 (versions from BOMs)
+"
+        ));
+    }
+
+    #[test]
+    fn test_inspect() {
+        let jar = temp_dir("inspect").join("app.jar");
+        let mut zip = ZipWriter::new(std::fs::File::create(&jar).unwrap());
+        zip.start_file("META-INF/MANIFEST.MF", FileOptions::default())
+            .unwrap();
+        zip.write_all(
+            b"Manifest-Version: 1.0\r\nMain-Class: marais.Main\r\nClass-Path: libs/a.jar\r\n",
+        )
+        .unwrap();
+        zip.start_file("marais/Main.class", FileOptions::default())
+            .unwrap();
+        zip.write_all(b"\xca\xfe\xba\xbe").unwrap();
+        zip.finish().unwrap();
+
+        let out = inspect(&jar).unwrap();
+        assert!(out.contains("  Main-Class: marais.Main\n"));
+        assert!(out.contains("    libs/a.jar\n"));
+        assert!(out.contains("entries (2):\n  META-INF/MANIFEST.MF\n  marais/Main.class\n"));
+    }
+
+    #[tokio::test]
+    async fn test_dependency_in_two_scopes() {
+        let server = MockServer::start(|req| match req.path.as_str() {
+            "/marais/lib/1.0/lib-1.0.pom" => response(
+                "200 OK",
+                br#"<project xsi:schemaLocation="http://maven.apache.org/POM/4.0.0 http://maven.apache.org/maven-v4_0_0.xsd">
+                    <modelVersion>4.0.0</modelVersion>
+                    <groupId>marais</groupId>
+                    <artifactId>lib</artifactId>
+                    <version>1.0</version>
+                </project>"#,
+            ),
+            "/marais/lib/1.0/lib-1.0.jar" => response("200 OK", b"jar"),
+            _ => response("404 Not Found", b""),
+        })
+        .await;
+        let mut env = Env::new(false);
+        env.repos = vec![Arc::new(MavenRepo {
+            name: "mock".to_string(),
+            url: server.url.clone(),
+            layout: RepoLayout::Maven,
+        })];
+        let manifest = ModuleManifest::parse(
+            r#"
+            group = "marais"
+            artifact = "dedup"
+            version = "0.1.0"
+
+            [dependencies]
+            compile = ["marais:lib:1.0"]
+            runtime = ["marais:lib:1.0"]
+            "#,
+            None,
+        )
+        .unwrap();
+        let module = Module::from_manifest(&temp_dir("dedup"), manifest, &env).unwrap();
+
+        setup_all_dependencies(&module, &env, false, false)
+            .await
+            .unwrap();
+
+        let downloads = server
+            .requests()
+            .into_iter()
+            .filter(|it| it.path.ends_with(".jar"))
+            .count();
+        assert_eq!(downloads, 1);
+    }
+
+    #[test]
+    fn test_empty_classpath() {
+        let env = Env::new(false);
+        let manifest = ModuleManifest::parse(
+            r#"
+            group = "marais"
+            artifact = "nodeps"
+            version = "0.1.0"
+            "#,
+            None,
+        )
+        .unwrap();
+        let module = Module::from_manifest(Path::new("nodeps"), manifest, &env).unwrap();
+        let classes = module.classes_dir();
+        assert_eq!(
+            module_classpath(&module, module.dependencies.iter_compile(), Some(&classes)),
+            classes.display().to_string()
+        );
+        assert_eq!(
+            module_classpath(&module, module.dependencies.iter_compile(), None),
+            ""
+        );
+
+        let module = fixture_module(&env);
+        let cp = module_classpath(&module, module.dependencies.iter_runtime(), None);
+        assert_eq!(cp.split(CLASSPATH_SEPARATOR).count(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_build_without_dependencies() {
+        if find_program("javac").is_none() || find_program("javadoc").is_none() {
+            return;
+        }
+        let env = Env::new(false);
+        let dir = temp_dir("build-nodeps");
+        std::fs::create_dir_all(dir.join("src/marais")).unwrap();
+        std::fs::write(
+            dir.join("src/marais/Main.java"),
+            "package marais;\n\n/** Entrypoint */\npublic class Main {\n    /** Run */\n    public static void main(String[] args) {}\n}\n",
+        )
+        .unwrap();
+        let manifest = ModuleManifest::parse(
+            r#"
+            group = "marais"
+            artifact = "nodeps"
+            version = "0.1.0"
+            "#,
+            None,
+        )
+        .unwrap();
+        let module = Module::from_manifest(&dir, manifest, &env).unwrap();
+
+        build(&module, &env.comp_backend, false).await.unwrap();
+        assert!(module.classes_dir().join("marais/Main.class").exists());
+        build_doc(&module, &env.doc_backend).await.unwrap();
+        assert!(module.docs_dir().join("index.html").exists());
+    }
+
+    #[tokio::test]
+    async fn test_run_log() {
+        if find_program("javac").is_none() {
+            return;
+        }
+        let env = Env::new(false);
+        let dir = temp_dir("run-log");
+        std::fs::create_dir_all(dir.join("src/marais")).unwrap();
+        std::fs::write(
+            dir.join("src/marais/Main.java"),
+            "package marais; public class Main { public static void main(String[] args) { \
+             System.out.println(\"to stdout\"); System.err.println(\"to stderr\"); } }",
+        )
+        .unwrap();
+        let manifest = ModuleManifest::parse(
+            r#"
+            group = "marais"
+            artifact = "logged"
+            version = "0.1.0"
+
+            [[entrypoints]]
+            class = "marais.Main"
+            "#,
+            None,
+        )
+        .unwrap();
+        let module = Module::from_manifest(&dir, manifest, &env).unwrap();
+        build(&module, &env.comp_backend, false).await.unwrap();
+        let log = module.target_dir().join("run.log");
+        std::fs::write(&log, "previous run\n").unwrap();
+
+        run(
+            &module,
+            &env.runtime,
+            Some("marais.Main".to_string()),
+            &Profiling::default(),
+            None,
+            Some(&log),
+        )
+        .await
+        .unwrap();
+
+        let text = std::fs::read_to_string(&log).unwrap();
+        assert!(text.contains("to stdout\n"), "{}", text);
+        assert!(text.contains("to stderr\n"), "{}", text);
+        assert!(!text.contains("previous run"));
+    }
+
+    #[tokio::test]
+    async fn test_run_all() {
+        if find_program("javac").is_none() {
+            return;
+        }
+        let env = Env::new(false);
+        let dir = temp_dir("run-all");
+        std::fs::create_dir_all(dir.join("src/marais")).unwrap();
+        for (class, code) in [("First", 0), ("Failing", 3), ("Last", 0)] {
+            std::fs::write(
+                dir.join(format!("src/marais/{}.java", class)),
+                format!(
+                    "package marais; public class {} {{ public static void main(String[] args) {{ System.exit({}); }} }}",
+                    class, code
+                ),
+            )
+            .unwrap();
+        }
+        let manifest = ModuleManifest::parse(
+            r#"
+            group = "marais"
+            artifact = "demos"
+            version = "0.1.0"
+
+            [[entrypoints]]
+            name = "first"
+            class = "marais.First"
+
+            [[entrypoints]]
+            class = "marais.Failing"
+
+            [[entrypoints]]
+            name = "last"
+            class = "marais.Last"
+            "#,
+            None,
+        )
+        .unwrap();
+        let module = Module::from_manifest(&dir, manifest, &env).unwrap();
+        build(&module, &env.comp_backend, false).await.unwrap();
+
+        let outcomes = |results: Vec<(String, anyhow::Result<()>)>| {
+            results
+                .into_iter()
+                .map(|(label, res)| (label, res.is_ok()))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            outcomes(run_all(&module, &env.runtime, false).await),
+            vec![
+                ("first".to_string(), true),
+                ("marais.Failing".to_string(), false)
+            ]
+        );
+        assert_eq!(
+            outcomes(run_all(&module, &env.runtime, true).await),
+            vec![
+                ("first".to_string(), true),
+                ("marais.Failing".to_string(), false),
+                ("last".to_string(), true)
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_filtered_resources() {
+        let env = Env::new(false);
+        let dir = temp_dir("filtered-resources");
+        std::fs::create_dir_all(dir.join("resources/static")).unwrap();
+        let text = "version=${project.version}\nhome=${user.home}\n";
+        std::fs::write(dir.join("resources/app.properties"), text).unwrap();
+        std::fs::write(dir.join("resources/static/raw.properties"), text).unwrap();
+        let binary = b"\x89PNG\0${project.version}\xff";
+        std::fs::write(dir.join("resources/logo.png"), binary).unwrap();
+        let manifest = ModuleManifest::parse(
+            r#"
+            group = "marais"
+            artifact = "filtered"
+            version = "1.4.0"
+
+            [resources]
+            filter = true
+            unfiltered = ["static"]
+            "#,
+            None,
+        )
+        .unwrap();
+        let module = Module::from_manifest(&dir, manifest, &env).unwrap();
+        build(&module, &env.comp_backend, false).await.unwrap();
+
+        let classes = module.classes_dir();
+        assert_eq!(
+            std::fs::read_to_string(classes.join("app.properties")).unwrap(),
+            "version=1.4.0\nhome=${user.home}\n"
+        );
+        assert_eq!(
+            std::fs::read_to_string(classes.join("static/raw.properties")).unwrap(),
+            text
+        );
+        assert_eq!(std::fs::read(classes.join("logo.png")).unwrap(), binary);
+    }
+
+    #[tokio::test]
+    async fn test_build_emit() {
+        if find_program("javac").is_none() || find_program("jar").is_none() {
+            return;
+        }
+        let mut env = Env::new(false);
+        env.build_report = false;
+        let dir = temp_dir("build-emit");
+        std::fs::create_dir_all(dir.join("src/marais")).unwrap();
+        std::fs::write(
+            dir.join("src/marais/Main.java"),
+            "package marais; public class Main { public static void main(String[] args) {} }",
+        )
+        .unwrap();
+        let manifest = ModuleManifest::parse(
+            r#"
+            group = "marais"
+            artifact = "emitted"
+            version = "0.1.0"
+            "#,
+            None,
+        )
+        .unwrap();
+        let module = Module::from_manifest(&dir, manifest, &env).unwrap();
+        let jar = module.artifacts_dir().join("emitted-0.1.0.jar");
+        let build =
+            |args: &[&str]| Task::from_iter_safe(["jcargo", "build"].iter().chain(args)).unwrap();
+
+        execute_task_mod(build(&["--emit=classes"]), &env, &module)
+            .await
+            .unwrap();
+        assert!(module.classes_dir().join("marais/Main.class").exists());
+        assert!(!jar.exists());
+
+        execute_task_mod(build(&["--emit", "jar,classes"]), &env, &module)
+            .await
+            .unwrap();
+        assert!(jar.exists());
+        assert!(Task::from_iter_safe(["jcargo", "build", "--emit=everything"]).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_build_timings() {
+        if find_program("javac").is_none() {
+            return;
+        }
+        let jar = temp_dir("timings-jar").join("lib.jar");
+        write_jar(&jar, "marais/Lib.class");
+        let jar = std::fs::read(jar).unwrap();
+        let server = MockServer::start(move |req| match req.path.as_str() {
+            "/marais/lib/1.0/lib-1.0.pom" => response(
+                "200 OK",
+                br#"<project>
+                    <modelVersion>4.0.0</modelVersion>
+                    <groupId>marais</groupId>
+                    <artifactId>lib</artifactId>
+                    <version>1.0</version>
+                </project>"#,
+            ),
+            "/marais/lib/1.0/lib-1.0.jar" => response("200 OK", &jar),
+            _ => response("404 Not Found", b""),
+        })
+        .await;
+        let mut env = Env::new(false);
+        env.build_report = false;
+        env.timings = Some(Timings::new());
+        env.repos = vec![Arc::new(MavenRepo {
+            name: "mock".to_string(),
+            url: server.url.clone(),
+            layout: RepoLayout::Maven,
+        })];
+        let dir = temp_dir("timings");
+        std::fs::create_dir_all(dir.join("src/marais")).unwrap();
+        std::fs::write(
+            dir.join("src/marais/Main.java"),
+            "package marais; public class Main {}",
+        )
+        .unwrap();
+        std::fs::write(
+            dir.join("jcargo.toml"),
+            r#"
+            group = "marais"
+            artifact = "timed"
+            version = "0.1.0"
+
+            [dependencies]
+            compile = ["marais:lib:1.0"]
+            "#,
+        )
+        .unwrap();
+
+        execute_task(
+            Task::Build { emit: Vec::new() },
+            &env,
+            Workspace::load(&dir, &env),
+        )
+        .await
+        .unwrap();
+
+        let report: TimingReport = serde_json::from_str(
+            &std::fs::read_to_string(dir.join("target").join(TIMINGS_FILE)).unwrap(),
+        )
+        .unwrap();
+        let unit = |kind: &str| report.units.iter().find(|it| it.kind == kind).unwrap();
+        assert_eq!(unit("download").name, "marais:lib:1.0");
+        assert_eq!(unit("resolve").name, "marais:lib:1.0");
+        let compile = unit("compile");
+        assert_eq!(compile.name, "timed");
+        assert!(compile.duration_ms > 0.0);
+        for unit in &report.units {
+            assert!(unit.start_ms + unit.duration_ms <= report.total_ms);
+        }
+        // Compiling waits for the dependencies
+        assert!(compile.start_ms >= unit("download").start_ms + unit("download").duration_ms);
+        assert_eq!(report.critical_path.last().unwrap(), "timed");
+        assert!(dir.join("target").join(TIMINGS_HTML).is_file());
+    }
+
+    #[tokio::test]
+    async fn test_build_report() {
+        if find_program("javac").is_none() {
+            return;
+        }
+        let env = Env::new(false);
+        let dir = temp_dir("build-report");
+        std::fs::create_dir_all(dir.join("src/marais")).unwrap();
+        std::fs::write(
+            dir.join("src/marais/Main.java"),
+            "package marais; public class Main { public static void main(String[] args) {} }",
+        )
+        .unwrap();
+        let manifest = ModuleManifest::parse(
+            r#"
+            group = "marais"
+            artifact = "reported"
+            version = "0.1.0"
+            "#,
+            None,
+        )
+        .unwrap();
+        let module = Module::from_manifest(&dir, manifest, &env).unwrap();
+        execute_task_mod(Task::Build { emit: Vec::new() }, &env, &module)
+            .await
+            .unwrap();
+
+        let report: serde_json::Value = serde_json::from_str(
+            &std::fs::read_to_string(module.target_dir().join(REPORT_FILE)).unwrap(),
+        )
+        .unwrap();
+        let keys: Vec<_> = report.as_object().unwrap().keys().cloned().collect();
+        assert_eq!(
+            keys,
+            [
+                "compiled_files",
+                "dependencies",
+                "diagnostics",
+                "module",
+                "phases",
+                "success",
+                "task",
+                "timestamp",
+                "toolchain"
+            ]
+        );
+        assert_eq!(report["success"], true);
+        assert_eq!(report["module"], "marais:reported:0.1.0");
+        assert_eq!(report["compiled_files"], 1);
+        assert_eq!(report["diagnostics"]["errors"], 0);
+        assert_eq!(report["phases"][1]["name"], "compile");
+        assert!(report["toolchain"]["javac"].is_string());
+    }
+
+    #[tokio::test]
+    async fn test_failed_build_report() {
+        if find_program("javac").is_none() {
+            return;
+        }
+        let env = Env::new(false);
+        let dir = temp_dir("failed-build-report");
+        std::fs::create_dir_all(dir.join("src/marais")).unwrap();
+        std::fs::write(
+            dir.join("src/marais/Main.java"),
+            "package marais; public class Main { int a = ; int b = ; }",
+        )
+        .unwrap();
+        let manifest = ModuleManifest::parse(
+            r#"
+            group = "marais"
+            artifact = "broken"
+            version = "0.1.0"
+            "#,
+            None,
+        )
+        .unwrap();
+        let module = Module::from_manifest(&dir, manifest, &env).unwrap();
+        let err = execute_task_mod(Task::Build { emit: Vec::new() }, &env, &module)
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("exited with"));
+
+        let report: serde_json::Value = serde_json::from_str(
+            &std::fs::read_to_string(module.target_dir().join(REPORT_FILE)).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(report["success"], false);
+        assert_eq!(report["diagnostics"]["errors"], 2);
+        assert_eq!(report["phases"][1]["name"], "compile");
+    }
+
+    #[tokio::test]
+    async fn test_doc_up_to_date() {
+        if find_program("javadoc").is_none() {
+            return;
+        }
+        let env = Env::new(false);
+        let dir = temp_dir("doc-up-to-date");
+        std::fs::create_dir_all(dir.join("src/marais")).unwrap();
+        let source = dir.join("src/marais/Lib.java");
+        std::fs::write(
+            &source,
+            "package marais;\n\n/** Lib */\npublic class Lib {}\n",
+        )
+        .unwrap();
+        let manifest = ModuleManifest::parse(
+            r#"
+            group = "marais"
+            artifact = "docs"
+            version = "0.1.0"
+            "#,
+            None,
+        )
+        .unwrap();
+        let module = Module::from_manifest(&dir, manifest, &env).unwrap();
+
+        assert!(build_doc(&module, &env.doc_backend).await.unwrap());
+        assert!(!build_doc(&module, &env.doc_backend).await.unwrap());
+
+        std::fs::write(
+            &source,
+            "package marais;\n\n/** Lib, documented */\npublic class Lib {}\n",
+        )
+        .unwrap();
+        assert!(build_doc(&module, &env.doc_backend).await.unwrap());
+    }
+
+    #[test]
+    fn test_doc_fingerprint_args() {
+        let env = Env::new(false);
+        let dir = temp_dir("doc-fingerprint");
+        std::fs::create_dir_all(dir.join("src/marais")).unwrap();
+        std::fs::write(dir.join("src/marais/Lib.java"), "package marais;\n").unwrap();
+        let manifest = ModuleManifest::parse(
+            r#"
+            group = "marais"
+            artifact = "docs"
+            version = "0.1.0"
+            "#,
+            None,
+        )
+        .unwrap();
+        let mut module = Module::from_manifest(&dir, manifest, &env).unwrap();
+        let fingerprint = doc_fingerprint(&module, &doc_args(&module));
+        assert_eq!(fingerprint, doc_fingerprint(&module, &doc_args(&module)));
+
+        // Same sources and classpath, but javadoc runs with other flags
+        module.java.enable_preview = true;
+        let preview = doc_fingerprint(&module, &doc_args(&module));
+        assert_ne!(fingerprint, preview);
+        module.java.source = "21".to_string();
+        assert_ne!(preview, doc_fingerprint(&module, &doc_args(&module)));
+    }
+
+    #[tokio::test]
+    async fn test_clean_dry_run() {
+        let env = Env::new(false);
+        let dir = temp_dir("clean-dry-run");
+        std::fs::create_dir_all(dir.join("target/classes")).unwrap();
+        std::fs::write(dir.join("target/classes/Main.class"), [0u8; 1000]).unwrap();
+        std::fs::write(dir.join("target/classes/Other.class"), [0u8; 1048]).unwrap();
+        let manifest = ModuleManifest::parse(
+            r#"
+            group = "marais"
+            artifact = "clean"
+            version = "0.1.0"
+            "#,
+            None,
+        )
+        .unwrap();
+        let module = Module::from_manifest(&dir, manifest, &env).unwrap();
+
+        let out = clean(&module, true).await.unwrap();
+        assert_eq!(
+            out,
+            format!(
+                "   Would remove {} (2.0 KiB)\n   Would free 2.0 KiB\n",
+                module.target_dir().display()
+            )
+        );
+        assert!(dir.join("target/classes/Main.class").exists());
+
+        clean(&module, false).await.unwrap();
+        assert!(!module.target_dir().exists());
+    }
+
+    #[test]
+    fn test_prune_candidates() {
+        let dir = temp_dir("prune-cache");
+        let cache = dir.join("libs");
+        std::fs::create_dir_all(cache.join("parents")).unwrap();
+        for file in [
+            "lib-1.0.jar",
+            "lib-1.0.pom",
+            "lib-1.0-sources.jar",
+            "lib-1.0.1.jar",
+            "lib-0.9.jar",
+            "parents/parent-1.0.pom",
+        ] {
+            std::fs::write(cache.join(file), [0u8; 10]).unwrap();
+        }
+        let lockfile = Lockfile::parse(
+            r#"
+            [[dependency]]
+            group = "marais"
+            artifact = "lib"
+            version = "1.0"
+            "#,
+        )
+        .unwrap();
+        let referenced: Vec<_> = lockfile.dependencies.iter().collect();
+
+        let candidates = prune_candidates(&cache, &referenced, None);
+        assert_eq!(
+            candidates,
+            vec![
+                (cache.join("lib-0.9.jar"), 10),
+                (cache.join("lib-1.0.1.jar"), 10)
+            ]
+        );
+
+        std::thread::sleep(Duration::from_millis(20));
+        let candidates = prune_candidates(&cache, &referenced, Some(Duration::from_millis(10)));
+        assert_eq!(candidates.len(), 6);
+    }
+
+    fn write_jar(path: &Path, entry: &str) {
+        let mut zip = ZipWriter::new(std::fs::File::create(path).unwrap());
+        zip.start_file(entry, FileOptions::default()).unwrap();
+        zip.write_all(b"\xca\xfe\xba\xbe").unwrap();
+        zip.finish().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_package_fat() {
+        if find_program("jar").is_none() {
+            return;
+        }
+        let env = Env::new(false);
+        let dir = temp_dir("package-fat");
+        std::fs::create_dir_all(dir.join("libs")).unwrap();
+        std::fs::create_dir_all(dir.join("target/classes")).unwrap();
+        std::fs::write(dir.join("target/classes/Main.class"), b"\xca\xfe\xba\xbe").unwrap();
+        write_jar(&dir.join("libs/provided-1.0.jar"), "marais/Provided.class");
+        write_jar(&dir.join("libs/bundled-1.0.jar"), "marais/Bundled.class");
+        write_jar(&dir.join("libs/junit-1.0.jar"), "marais/Junit.class");
+        let manifest = ModuleManifest::parse(
+            r#"
+            group = "marais"
+            artifact = "fat"
+            version = "0.1.0"
+
+            [dependencies]
+            compile = ["marais:provided:1.0"]
+            runtime = ["marais:bundled:1.0"]
+            test = ["marais:junit:1.0"]
+            "#,
+            None,
+        )
+        .unwrap();
+        let module = Module::from_manifest(&dir, manifest, &env).unwrap();
+
+        package(
+            &module,
+            &env.package_backend,
+            PackageOptions {
+                fat: true,
+                ..PackageOptions::default()
+            },
+        )
+        .await
+        .unwrap();
+
+        let pom = std::fs::read_to_string(module.artifacts_dir().join("fat-0.1.0.pom")).unwrap();
+        assert!(pom.contains(
+            "<artifactId>provided</artifactId><version>1.0</version><scope>provided</scope>"
+        ));
+        assert!(pom.contains(
+            "<artifactId>bundled</artifactId><version>1.0</version><scope>runtime</scope>"
+        ));
+        assert!(!pom.contains("junit"));
+
+        let fat = JarContents::read(&module.artifacts_dir().join("fat-0.1.0-all.jar")).unwrap();
+        assert!(fat.entries.contains(&"Main.class".to_string()));
+        assert!(fat.entries.contains(&"marais/Bundled.class".to_string()));
+        assert!(!fat.entries.contains(&"marais/Provided.class".to_string()));
+        assert!(!fat.entries.contains(&"marais/Junit.class".to_string()));
+    }
+
+    #[tokio::test]
+    async fn test_package_with_libs() {
+        if find_program("jar").is_none() {
+            return;
+        }
+        let env = Env::new(false);
+        let dir = temp_dir("package-libs");
+        std::fs::create_dir_all(dir.join("libs")).unwrap();
+        std::fs::create_dir_all(dir.join("target/classes/marais")).unwrap();
+        std::fs::write(
+            dir.join("target/classes/marais/Main.class"),
+            b"\xca\xfe\xba\xbe",
+        )
+        .unwrap();
+        write_jar(&dir.join("libs/provided-1.0.jar"), "marais/Provided.class");
+        write_jar(&dir.join("libs/bundled-1.0.jar"), "marais/Bundled.class");
+        write_jar(
+            &dir.join("libs/a-rather-long-runtime-library-name-1.0.jar"),
+            "marais/Long.class",
+        );
+        let manifest = ModuleManifest::parse(
+            r#"
+            group = "marais"
+            artifact = "thin"
+            version = "0.1.0"
+
+            [dependencies]
+            compile = ["marais:provided:1.0"]
+            runtime = ["marais:bundled:1.0", "marais:a-rather-long-runtime-library-name:1.0"]
+
+            [[entrypoints]]
+            class = "marais.Main"
+            "#,
+            None,
+        )
+        .unwrap();
+        let module = Module::from_manifest(&dir, manifest, &env).unwrap();
+
+        package(
+            &module,
+            &env.package_backend,
+            PackageOptions {
+                with_libs: true,
+                entrypoint: Some("marais.Main".to_string()),
+                ..PackageOptions::default()
+            },
+        )
+        .await
+        .unwrap();
+
+        let jar = JarContents::read(&module.artifacts_dir().join("thin-0.1.0.jar")).unwrap();
+        let manifest = jar.manifest.unwrap();
+        assert_eq!(manifest.main_class(), Some("marais.Main"));
+        assert_eq!(
+            manifest.class_path(),
+            [
+                "lib/bundled-1.0.jar",
+                "lib/a-rather-long-runtime-library-name-1.0.jar"
+            ]
+        );
+        let lib = module.artifacts_dir().join("lib");
+        assert!(lib.join("bundled-1.0.jar").is_file());
+        assert!(lib
+            .join("a-rather-long-runtime-library-name-1.0.jar")
+            .is_file());
+        assert!(!lib.join("provided-1.0.jar").exists());
+    }
+
+    #[tokio::test]
+    async fn test_sources_jar_resources() {
+        if find_program("jar").is_none() {
+            return;
+        }
+        let env = Env::new(false);
+        let dir = temp_dir("sources-jar");
+        std::fs::create_dir_all(dir.join("target/classes")).unwrap();
+        std::fs::create_dir_all(dir.join("src/marais")).unwrap();
+        std::fs::create_dir_all(dir.join("resources/marais")).unwrap();
+        std::fs::write(dir.join("src/marais/Main.java"), "package marais;").unwrap();
+        std::fs::write(dir.join("resources/marais/app.properties"), "a=b").unwrap();
+        let manifest = ModuleManifest::parse(
+            r#"
+            group = "marais"
+            artifact = "sourced"
+            version = "0.1.0"
+            "#,
+            None,
+        )
+        .unwrap();
+        let module = Module::from_manifest(&dir, manifest, &env).unwrap();
+
+        package(
+            &module,
+            &env.package_backend,
+            PackageOptions {
+                sources: true,
+                ..PackageOptions::default()
+            },
+        )
+        .await
+        .unwrap();
+
+        let sources =
+            JarContents::read(&module.artifacts_dir().join("sourced-0.1.0-sources.jar")).unwrap();
+        assert!(sources.entries.contains(&"marais/Main.java".to_string()));
+        assert!(sources
+            .entries
+            .contains(&"marais/app.properties".to_string()));
+    }
+
+    #[test]
+    fn test_jvm_flags() {
+        let env = Env::new(false);
+        let manifest = ModuleManifest::parse(
+            r#"
+            group = "marais"
+            artifact = "internals"
+            version = "0.1.0"
+
+            [jvm]
+            add_modules = ["jdk.incubator.vector"]
+            add_exports = ["java.base/sun.nio.ch=ALL-UNNAMED"]
+            add_opens = ["java.base/java.lang=ALL-UNNAMED"]
+            "#,
+            None,
+        )
+        .unwrap();
+        let module = Module::from_manifest(Path::new("internals"), manifest, &env).unwrap();
+        let args = |cmd: tokio::process::Command| {
+            cmd.as_std()
+                .get_args()
+                .map(|it| it.to_string_lossy().into_owned())
+                .collect::<Vec<_>>()
+                .join(" ")
+        };
+
+        let javac = args(javac_command(&module, &env.comp_backend));
+        assert!(javac.contains("--add-modules jdk.incubator.vector"));
+        assert!(javac.contains("--add-exports java.base/sun.nio.ch=ALL-UNNAMED"));
+        assert!(!javac.contains("--add-opens"));
+
+        let java = args(java_command(&module, &env.runtime, "marais.Main"));
+        assert!(java.contains("--add-modules jdk.incubator.vector"));
+        assert!(java.contains("--add-exports java.base/sun.nio.ch=ALL-UNNAMED"));
+        assert!(java.contains("--add-opens java.base/java.lang=ALL-UNNAMED"));
+        assert!(java.ends_with(" marais.Main"));
+    }
+
+    #[test]
+    fn test_run_assertions() {
+        let env = Env::new(false);
+        let args = |module: &Module, cli: &[&str]| -> Vec<String> {
+            let assertions =
+                match Task::from_iter_safe(["jcargo", "run"].iter().chain(cli)).unwrap() {
+                    Task::Run {
+                        enable_assertions, ..
+                    } => enable_assertions,
+                    _ => unreachable!(),
+                };
+            java_command_from(
+                module,
+                &env.runtime,
+                &module.classes_dir(),
+                "Main",
+                &Profiling::default(),
+                assertions.as_deref(),
+            )
+            .as_std()
+            .get_args()
+            .map(|it| it.to_string_lossy().into_owned())
+            .collect()
+        };
+        let module = fixture_module(&env);
+        assert!(!args(&module, &[]).iter().any(|it| it.starts_with("-ea")));
+        assert!(args(&module, &["--enable-assertions"]).contains(&"-ea".to_string()));
+        let scoped = args(&module, &["--ea=com.example...,com.example.Main"]);
+        assert!(scoped.contains(&"-ea:com.example...".to_string()));
+        assert!(scoped.contains(&"-ea:com.example.Main".to_string()));
+        assert!(!scoped.contains(&"-ea".to_string()));
+
+        let manifest = ModuleManifest::parse(
+            "group = \"marais\"\nartifact = \"asserted\"\nversion = \"0.1.0\"\n[run]\nassertions = true\n",
+            None,
+        )
+        .unwrap();
+        let module = Module::from_manifest(Path::new("asserted"), manifest, &env).unwrap();
+        let args = args(&module, &[]);
+        let ea = args.iter().position(|it| it == "-ea").unwrap();
+        assert!(ea < args.iter().position(|it| it == "Main").unwrap());
+    }
+
+    #[test]
+    fn test_run_jfr() {
+        let env = Env::new(false);
+        let module = fixture_module(&env);
+        let profiling =
+            |args: &[&str]| match Task::from_iter_safe(["jcargo", "run"].iter().chain(args))
+                .unwrap()
+            {
+                Task::Run { profiling, .. } => profiling,
+                _ => unreachable!(),
+            };
+        let args = |profiling: &Profiling| -> Vec<String> {
+            java_command_from(
+                &module,
+                &env.runtime,
+                &module.classes_dir(),
+                "Main",
+                profiling,
+                None,
+            )
+            .as_std()
+            .get_args()
+            .map(|it| it.to_string_lossy().into_owned())
+            .collect()
+        };
+        let recording = module.target_dir().join(JFR_RECORDING);
+
+        let jfr = args(&profiling(&["--jfr", "Other"]));
+        assert!(jfr.contains(&format!(
+            "-XX:StartFlightRecording=filename={}",
+            recording.display()
+        )));
+        assert!(!jfr.iter().any(|it| it == "-XX:TieredStopAtLevel=1"));
+
+        let settings = args(&profiling(&["--jfr=profile"]));
+        assert!(settings.contains(&format!(
+            "-XX:StartFlightRecording=filename={},settings=profile",
+            recording.display()
+        )));
+
+        let agent = args(&profiling(&[
+            "--profiler",
+            "async:/opt/libasyncProfiler.so",
+        ]));
+        assert!(agent.iter().any(|it| it
+            .starts_with("-agentpath:/opt/libasyncProfiler.so=start,")
+            && it.ends_with(&format!(
+                "file={}",
+                module.target_dir().join(PROFILER_OUTPUT).display()
+            ))));
+        assert!(Task::from_iter_safe(["jcargo", "run", "--profiler", "yourkit"]).is_err());
+    }
+
+    #[test]
+    fn test_run_jar() {
+        let env = Env::new(false);
+        let dir = temp_dir("run-jar");
+        std::fs::create_dir_all(dir.join("target/classes/marais")).unwrap();
+        std::fs::write(dir.join("target/classes/marais/App.class"), b"\xca\xfe").unwrap();
+        let manifest = ModuleManifest::parse(
+            r#"
+            group = "marais"
+            artifact = "app"
+            version = "1.0"
+
+            [dependencies]
+            runtime = ["marais:dep:1.0"]
+            "#,
+            None,
+        )
+        .unwrap();
+        let module = Module::from_manifest(&dir, manifest, &env).unwrap();
+        let jar = dir.join("app-1.0.jar");
+        write_fat_jar(&jar, &module.classes_dir(), &[], Some("marais.App")).unwrap();
+
+        let cmd = jar_command(&module, &env.runtime, &jar, &Profiling::default(), None).unwrap();
+        let args: Vec<_> = cmd
+            .as_std()
+            .get_args()
+            .map(|it| it.to_string_lossy().into_owned())
+            .collect();
+        let cp = &args[args.iter().position(|it| it == "-cp").unwrap() + 1];
+        assert_eq!(
+            cp,
+            &[
+                dir.join("libs/dep-1.0.jar").display().to_string(),
+                jar.display().to_string()
+            ]
+            .join(CLASSPATH_SEPARATOR)
+        );
+        assert_eq!(args.last().unwrap(), "marais.App");
+
+        let bare = dir.join("bare.jar");
+        write_fat_jar(&bare, &module.classes_dir(), &[], None).unwrap();
+        assert!(
+            jar_command(&module, &env.runtime, &bare, &Profiling::default(), None)
+                .unwrap_err()
+                .to_string()
+                .contains("has no Main-Class")
+        );
+    }
+
+    #[test]
+    fn test_exclude_scope() {
+        let mut env = Env::new(false);
+        env.excluded_scopes = vec!["runtime".to_string()];
+        let dir = temp_dir("exclude-scope");
+        let manifest = ModuleManifest::parse(
+            r#"
+            group = "marais"
+            artifact = "excluded"
+            version = "0.1.0"
+
+            [dependencies]
+            runtime = ["marais:impl:1.0"]
+            compileRuntime = ["marais:shared:1.0"]
+            test = ["marais:junit:1.0"]
+            "#,
+            None,
+        )
+        .unwrap();
+        let module = Module::from_manifest(&dir, manifest, &env).unwrap();
+
+        assert_eq!(
+            classpath(&module, "runtime", ":").unwrap(),
+            format!(
+                "{}:{}",
+                dir.join("libs/shared-1.0.jar").display(),
+                module.classes_dir().display()
+            )
+        );
+        let test = classpath(&module, "test", ":").unwrap();
+        assert!(!test.contains("impl-1.0.jar"), "{}", test);
+        assert!(test.contains("junit-1.0.jar"), "{}", test);
+        // Still resolved
+        assert_eq!(module.dependencies.iter().count(), 3);
+    }
+
+    #[test]
+    fn test_classpath_separator() {
+        let env = Env::new(false);
+        let dir = temp_dir("classpath-separator");
+        let manifest = ModuleManifest::parse(
+            r#"
+            group = "marais"
+            artifact = "cross"
+            version = "0.1.0"
+
+            [dependencies]
+            compile = ["marais:api:1.0"]
+            runtime = ["marais:impl:1.0"]
+            "#,
+            None,
+        )
+        .unwrap();
+        let module = Module::from_manifest(&dir, manifest, &env).unwrap();
+        let entries = [
+            dir.join("libs/impl-1.0.jar").display().to_string(),
+            module.classes_dir().display().to_string(),
+        ];
+
+        for separator in [";", ":"] {
+            assert_eq!(
+                classpath(&module, "runtime", separator).unwrap(),
+                entries.join(separator)
+            );
+        }
+        assert_eq!(
+            classpath(&module, "compile", env.path_separator).unwrap(),
+            compile_classpath(&module)
+        );
+        assert!(classpath(&module, "provided", ";").is_err());
+    }
+
+    #[test]
+    fn test_processor_flags() {
+        let env = Env::new(false);
+        let manifest = ModuleManifest::parse(
+            r#"
+            group = "marais"
+            artifact = "processed"
+            version = "0.1.0"
+
+            [javac]
+            processors = ["marais.Proc", "marais.Other"]
+            proc = "only"
+            "#,
+            None,
+        )
+        .unwrap();
+        let module = Module::from_manifest(Path::new("processed"), manifest, &env).unwrap();
+        let javac: Vec<_> = javac_command(&module, &env.comp_backend)
+            .as_std()
+            .get_args()
+            .map(|it| it.to_string_lossy().into_owned())
+            .collect();
+        let javac = javac.join(" ");
+        assert!(javac.contains("-processor marais.Proc,marais.Other -proc:only -cp"));
+
+        let manifest = ModuleManifest::parse(
+            "group = \"marais\"\nartifact = \"plain\"\nversion = \"0.1.0\"\n",
+            None,
+        )
+        .unwrap();
+        assert!(manifest.javac.processor_args().is_empty());
+    }
+
+    #[test]
+    fn test_implicit_flag() {
+        let env = Env::new(false);
+        let javac = |doc: &str| {
+            let manifest = ModuleManifest::parse(doc, None).unwrap();
+            let module = Module::from_manifest(Path::new("implicit"), manifest, &env).unwrap();
+            javac_flags(&module, &env.comp_backend)
+        };
+        let plain = "group = \"marais\"\nartifact = \"implicit\"\nversion = \"0.1.0\"\n";
+        assert!(!javac(plain).iter().any(|it| it.starts_with("-implicit")));
+        let javac = javac(&format!("{}[javac]\nimplicit = \"none\"\n", plain));
+        assert!(javac.contains(&"-implicit:none".to_string()), "{:?}", javac);
+    }
+
+    #[test]
+    fn test_extra_classpath() {
+        let env = Env::new(false);
+        let manifest = ModuleManifest::parse(
+            r#"
+            group = "marais"
+            artifact = "legacy"
+            version = "0.1.0"
+
+            [dependencies]
+            compile = ["marais:lib:1.0"]
+
+            [javac]
+            extra_classpath = ["vendor/tools.jar"]
+            bootclasspath = ["jre/lib/rt.jar", "jre/lib/jce.jar"]
+            "#,
+            None,
+        )
+        .unwrap();
+        let module = Module::from_manifest(Path::new("legacy"), manifest, &env).unwrap();
+        let javac: Vec<_> = javac_command(&module, &env.comp_backend)
+            .as_std()
+            .get_args()
+            .map(|it| it.to_string_lossy().into_owned())
+            .collect();
+
+        let boot = javac.iter().position(|it| it == "-bootclasspath").unwrap();
+        assert_eq!(
+            javac[boot + 1],
+            format!(
+                "legacy/jre/lib/rt.jar{}legacy/jre/lib/jce.jar",
+                CLASSPATH_SEPARATOR
+            )
+        );
+        let cp = javac.iter().position(|it| it == "-cp").unwrap();
+        assert_eq!(
+            javac[cp + 1],
+            [
+                "legacy/libs/lib-1.0.jar",
+                "legacy/vendor/tools.jar",
+                "legacy/target/classes"
+            ]
+            .join(CLASSPATH_SEPARATOR)
+        );
+    }
+
+    #[test]
+    fn test_enable_preview() {
+        let env = Env::new(false);
+        let manifest = ModuleManifest::parse(
+            r#"
+            group = "marais"
+            artifact = "preview"
+            version = "0.1.0"
+            entrypoints = [{ class = "marais.Main" }]
+
+            [java]
+            source = "21"
+            target = "21"
+            enable_preview = true
+            "#,
+            None,
+        )
+        .unwrap();
+        let module = Module::from_manifest(Path::new("preview"), manifest, &env).unwrap();
+        let args = |cmd: tokio::process::Command| -> Vec<String> {
+            cmd.as_std()
+                .get_args()
+                .map(|it| it.to_string_lossy().into_owned())
+                .collect()
+        };
+        let javac = args(javac_command(&module, &env.comp_backend));
+        let preview = javac
+            .iter()
+            .position(|it| it == "--enable-preview")
+            .unwrap();
+        assert_eq!(javac[preview + 1..preview + 3], ["-source", "21"]);
+        let java = args(java_command(&module, &env.runtime, "marais.Main"));
+        assert!(java.contains(&"--enable-preview".to_string()));
+        assert!(args(junit_command(&module, &env.runtime, None))
+            .contains(&"--enable-preview".to_string()));
+
+        let plain = fixture_module(&env);
+        assert!(!args(javac_command(&plain, &env.comp_backend))
+            .contains(&"--enable-preview".to_string()));
+    }
+
+    #[tokio::test]
+    async fn test_unknown_jdk_module() {
+        let env = Env::new(false);
+        if env.runtime.modules().await.is_none() {
+            return;
+        }
+        let manifest = ModuleManifest::parse(
+            r#"
+            group = "marais"
+            artifact = "incubating"
+            version = "0.1.0"
+
+            [jvm]
+            add_modules = ["java.sql,jdk.incubator.vectr", "ALL-MODULE-PATH"]
+            "#,
+            None,
+        )
+        .unwrap();
+        let module = Module::from_manifest(Path::new("incubating"), manifest, &env).unwrap();
+        let (_guard, logs) = capture_logs();
+        assert_eq!(
+            check_jdk_modules(&module, &env.runtime).await,
+            vec!["jdk.incubator.vectr"]
+        );
+        assert!(logs
+            .contents()
+            .contains("WARN jcargo::tasks: Module 'jdk.incubator.vectr' of [jvm] add_modules is not in the installed JDK"));
+    }
+
+    #[tokio::test]
+    async fn test_compiler_max_heap() {
+        let env = Env::new(false);
+        let manifest = ModuleManifest::parse(
+            r#"
+            group = "marais"
+            artifact = "big"
+            version = "0.1.0"
+
+            [javac]
+            max_heap = "2g"
+
+            [kotlin]
+            max_heap = "3g"
+            "#,
+            None,
+        )
+        .unwrap();
+        let dir = temp_dir("max-heap");
+        let module = Module::from_manifest(&dir, manifest, &env).unwrap();
+        let mut javac = javac_command(&module, &env.comp_backend);
+        assert_eq!(javac.as_std().get_args().next().unwrap(), "-J-Xmx2g");
+        assert_eq!(kotlinc_args(&module)[0], "-J-Xmx3g");
+        assert!(!javac_command(&fixture_module(&env), &env.comp_backend)
+            .as_std()
+            .get_args()
+            .any(|it| it.to_string_lossy().starts_with("-J")));
+        assert!(javac_jvm_args(&module, &JavaCompilationBackend::NativeJavac).is_empty());
+
+        // Not allowed in argfiles
+        javac.args((0..2000).map(|i| format!("src/marais/Source{}.java", i)));
+        let argfile = dir.join("javac.args");
+        let javac = use_argfile(javac, &argfile).await.unwrap();
+        let args: Vec<_> = javac.as_std().get_args().collect();
+        assert_eq!(args[0], "-J-Xmx2g");
+        assert_eq!(args.len(), 2);
+        assert!(!std::fs::read_to_string(&argfile).unwrap().contains("-J"));
+    }
+
+    /// Arguments of a javac argfile, as written by [use_argfile]
+    fn read_argfile(text: &str) -> Vec<String> {
+        text.lines()
+            .map(|line| {
+                let mut arg = String::new();
+                let mut chars = line[1..line.len() - 1].chars();
+                while let Some(c) = chars.next() {
+                    arg.push(if c == '\\' { chars.next().unwrap() } else { c });
+                }
+                arg
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_argfile() {
+        let dir = temp_dir("argfile");
+        let argfile = dir.join("javac.args");
+
+        let mut cmd = tokio::process::Command::new("javac");
+        cmd.args(["-d", "target/classes"]);
+        let short = use_argfile(cmd, &argfile).await.unwrap();
+        assert_eq!(short.as_std().get_args().len(), 2);
+        assert!(!argfile.exists());
+
+        let mut args = vec![
+            "-cp".to_string(),
+            r#"C:\libs\my "quoted" lib.jar"#.to_string(),
+        ];
+        args.extend((0..2000).map(|i| format!("src/marais/Source{}.java", i)));
+        let mut cmd = tokio::process::Command::new("javac");
+        cmd.args(&args);
+        let long = use_argfile(cmd, &argfile).await.unwrap();
+        let long: Vec<_> = long.as_std().get_args().collect();
+        assert_eq!(long, vec![format!("@{}", argfile.display()).as_str()]);
+        assert_eq!(
+            read_argfile(&std::fs::read_to_string(&argfile).unwrap()),
+            args
+        );
+    }
+
+    #[tokio::test]
+    async fn test_kotlinc_argfile() {
+        let env = Env::new(false);
+        let dir = temp_dir("kotlinc-argfile");
+        std::fs::create_dir_all(dir.join("src/marais")).unwrap();
+        for i in 0..500 {
+            std::fs::write(
+                dir.join(format!("src/marais/Source{}.kt", i)),
+                "package marais",
+            )
+            .unwrap();
+        }
+        let manifest = ModuleManifest::parse(
+            r#"
+            group = "marais"
+            artifact = "kotlin"
+            version = "0.1.0"
+            "#,
+            None,
+        )
+        .unwrap();
+        let module = Module::from_manifest(&dir, manifest, &env).unwrap();
+        let argfile = dir.join("kotlinc.args");
+
+        // The program doesn't matter, kotlinc may not be installed
+        let mut cmd = tokio::process::Command::new("kotlinc");
+        cmd.args(kotlinc_args(&module));
+        let cmd = use_argfile(cmd, &argfile).await.unwrap();
+        let args: Vec<_> = cmd.as_std().get_args().collect();
+        assert_eq!(args, vec![format!("@{}", argfile.display()).as_str()]);
+        let written = read_argfile(&std::fs::read_to_string(&argfile).unwrap());
+        assert_eq!(written, kotlinc_args(&module));
+        assert_eq!(written.iter().filter(|it| it.ends_with(".kt")).count(), 500);
+    }
+
+    #[test]
+    fn test_command_line() {
+        let mut cmd = tokio::process::Command::new("javac");
+        cmd.args(["-d", "/home/marais/My Projects/target/classes", "it's"]);
+        cmd.env("JAVA_HOME", "/opt/jdk 17");
+        assert_eq!(
+            command_line(&cmd),
+            "JAVA_HOME='/opt/jdk 17' javac -d '/home/marais/My Projects/target/classes' 'it'\\''s'"
+        );
+    }
+
+    #[test]
+    fn test_provided_api() {
+        let env = Env::new(false);
+        let dir = temp_dir("provided-api");
+        std::fs::create_dir_all(dir.join("libs")).unwrap();
+        std::fs::write(
+            dir.join("libs/api-1.0.pom"),
+            r#"<project xsi:schemaLocation="http://maven.apache.org/POM/4.0.0 http://maven.apache.org/maven-v4_0_0.xsd"><modelVersion>4.0.0</modelVersion><groupId>marais</groupId><artifactId>api</artifactId><version>1.0</version><dependencies><dependency><groupId>marais</groupId><artifactId>annotations</artifactId><version>2.0</version><scope>provided</scope></dependency><dependency><groupId>com.sun</groupId><artifactId>tools</artifactId><version>1.8</version><scope>system</scope><systemPath>/opt/jdk/lib/tools.jar</systemPath></dependency></dependencies></project>"#,
+        )
+        .unwrap();
+        let manifest = ModuleManifest::parse(
+            r#"
+            group = "marais"
+            artifact = "consumer"
+            version = "0.1.0"
+
+            [dependencies]
+            compileRuntime = ["marais:api:1.0"]
+            "#,
+            None,
+        )
+        .unwrap();
+        let module = Module::from_manifest(&dir, manifest, &env).unwrap();
+
+        let cp = compile_classpath(&module);
+        assert!(cp.contains("libs/api-1.0.jar"));
+        assert!(cp.contains("libs/annotations-2.0.jar"));
+        assert!(cp.contains("/opt/jdk/lib/tools.jar"));
+        assert!(test_classpath(&module).contains("/opt/jdk/lib/tools.jar"));
+
+        let java: Vec<_> = java_command(&module, &env.runtime, "marais.Main")
+            .as_std()
+            .get_args()
+            .map(|it| it.to_string_lossy().into_owned())
+            .collect();
+        assert!(java.iter().any(|it| it.contains("libs/api-1.0.jar")));
+        assert!(!java
+            .iter()
+            .any(|it| it.contains("annotations") || it.contains("tools.jar")));
+    }
+
+    #[test]
+    fn test_junit_command() {
+        let env = Env::new(false);
+        let manifest = ModuleManifest::parse(
+            r#"
+            group = "marais"
+            artifact = "tested"
+            version = "0.1.0"
+            "#,
+            None,
+        )
+        .unwrap();
+        let module = Module::from_manifest(Path::new("tested"), manifest, &env).unwrap();
+        let args: Vec<_> = junit_command(&module, &env.runtime, Some("marais\\..*Test"))
+            .as_std()
+            .get_args()
+            .map(|it| it.to_string_lossy().into_owned())
+            .collect();
+
+        let launcher = args.iter().position(|it| it == CONSOLE_LAUNCHER).unwrap();
+        assert!(args[launcher - 1].starts_with(&format!(
+            "tested/target/test-classes{}tested/target/classes",
+            CLASSPATH_SEPARATOR
+        )));
+        let options = args[launcher + 1..].join(" ");
+        assert!(options.contains("--scan-class-path tested/target/test-classes"));
+        assert!(options.contains("--reports-dir tested/target/test-results"));
+        assert!(options.ends_with("--include-classname marais\\..*Test"));
+    }
+
+    #[tokio::test]
+    async fn test_test_classes_dir() {
+        if find_program("javac").is_none() {
+            return;
+        }
+        let env = Env::new(false);
+        let dir = temp_dir("test-classes");
+        std::fs::create_dir_all(dir.join("src/marais")).unwrap();
+        std::fs::create_dir_all(dir.join("test/marais")).unwrap();
+        std::fs::write(
+            dir.join("src/marais/Calc.java"),
+            "package marais; public class Calc { public static int add(int a, int b) { return a + b; } }",
+        )
+        .unwrap();
+        std::fs::write(
+            dir.join("test/marais/CalcTest.java"),
+            "package marais; public class CalcTest { int three = Calc.add(1, 2); }",
+        )
+        .unwrap();
+        let manifest = ModuleManifest::parse(
+            r#"
+            group = "marais"
+            artifact = "separated"
+            version = "0.1.0"
+            "#,
+            None,
+        )
+        .unwrap();
+        let module = Module::from_manifest(&dir, manifest, &env).unwrap();
+
+        build(&module, &env.comp_backend, false).await.unwrap();
+        let main_classes: Vec<_> = collect_files(module.classes_dir(), None).collect();
+        assert!(build_tests(&module, &env.comp_backend, false)
+            .await
+            .unwrap());
+
+        assert!(module
+            .test_classes_dir()
+            .join("marais/CalcTest.class")
+            .is_file());
+        assert!(!module.test_classes_dir().join("marais/Calc.class").exists());
+        let after: Vec<_> = collect_files(module.classes_dir(), None).collect();
+        assert_eq!(after, main_classes);
+        assert_eq!(after, [module.classes_dir().join("marais/Calc.class")]);
+    }
+
+    #[test]
+    fn test_jarsigner_command() {
+        let dir = temp_dir("jarsigner");
+        // Stub JDK, the command isn't run
+        let home = dir.join("jdk");
+        std::fs::create_dir_all(home.join("bin")).unwrap();
+        for tool in ["javac", "jarsigner"] {
+            std::fs::write(home.join("bin").join(exe_name(tool)), "").unwrap();
+        }
+        let backend = PackageBackend::JdkJar(Jdk::at(&home).unwrap());
+        let mut signing = Toolchains::parse(
+            r#"
+            [signing]
+            keystore = "release.p12"
+            alias = "release"
+            credentials = "SIGNING"
+            "#,
+        )
+        .unwrap()
+        .signing;
+        let keystore = dir.join("release.p12");
+        signing.keystore = Some(keystore.clone());
+        let jar = dir.join("signed-0.1.0.jar");
+        let no_vars = |_: &str| None;
+
+        let e = jarsigner_command(&SigningDef::default(), &backend, &jar, no_vars).unwrap_err();
+        assert!(e.to_string().starts_with("No signing.keystore"), "{}", e);
+
+        let e = jarsigner_command(&signing, &backend, &jar, no_vars).unwrap_err();
+        assert!(e.to_string().contains("release.p12 doesn't exist"), "{}", e);
+
+        std::fs::write(&keystore, "").unwrap();
+        let e = jarsigner_command(&signing, &backend, &jar, no_vars).unwrap_err();
+        assert!(e.to_string().contains("set SIGNING_STOREPASS"), "{}", e);
+
+        let vars = |name: &str| (name == "SIGNING_STOREPASS").then(|| "secret".to_string());
+        let cmd = jarsigner_command(&signing, &backend, &jar, vars).unwrap();
+        assert_eq!(
+            command_line(&cmd),
+            format!(
+                "{} -keystore {} -storepass:env SIGNING_STOREPASS {} release",
+                home.join("bin/jarsigner").display(),
+                keystore.display(),
+                jar.display()
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn test_package_tests_classifier() {
+        if find_program("javac").is_none() || find_program("jar").is_none() {
+            return;
+        }
+        let env = Env::new(false);
+        let dir = temp_dir("package-tests");
+        std::fs::create_dir_all(dir.join("src/marais")).unwrap();
+        std::fs::create_dir_all(dir.join("test/marais")).unwrap();
+        std::fs::write(
+            dir.join("src/marais/Calc.java"),
+            "package marais; public class Calc {}",
+        )
+        .unwrap();
+        std::fs::write(
+            dir.join("test/marais/CalcTest.java"),
+            "package marais; public class CalcTest { Calc calc = new Calc(); }",
+        )
+        .unwrap();
+        let manifest = ModuleManifest::parse(
+            r#"
+            group = "marais"
+            artifact = "classified"
+            version = "0.1.0"
+
+            [package]
+            classifiers = ["tests"]
+            "#,
+            None,
+        )
+        .unwrap();
+        let module = Module::from_manifest(&dir, manifest, &env).unwrap();
+        assert_eq!(module.package.classifiers, [Classifier::Tests]);
+        assert!(Task::from_iter_safe(["jcargo", "package", "--classifier", "shaded"]).is_err());
+
+        build(&module, &env.comp_backend, false).await.unwrap();
+        assert!(build_tests(&module, &env.comp_backend, false)
+            .await
+            .unwrap());
+        let jar = package_classified(&module, &env.package_backend, Classifier::Tests)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            jar,
+            module.artifacts_dir().join("classified-0.1.0-tests.jar")
+        );
+        let contents = JarContents::read(&jar).unwrap();
+        assert!(contents
+            .entries
+            .contains(&"marais/CalcTest.class".to_string()));
+        assert!(!contents.entries.contains(&"marais/Calc.class".to_string()));
+    }
+
+    #[tokio::test]
+    async fn test_generated_sources() {
+        let env = Env::new(false);
+        let dir = temp_dir("generated-sources");
+        std::fs::create_dir_all(dir.join("src/marais")).unwrap();
+        std::fs::write(
+            dir.join("src/marais/Main.java"),
+            "package marais; public class Main {}",
+        )
+        .unwrap();
+        let module = |proc: &str| {
+            let manifest = ModuleManifest::parse(
+                &format!(
+                    r#"
+                    group = "marais"
+                    artifact = "generating"
+                    version = "0.1.0"
+
+                    [javac]
+                    proc = "{}"
+                    "#,
+                    proc
+                ),
+                None,
+            )
+            .unwrap();
+            Module::from_manifest(&dir, manifest, &env).unwrap()
+        };
+        let skipped = module("none");
+        let javac: Vec<_> = javac_command(&skipped, &env.comp_backend)
+            .as_std()
+            .get_args()
+            .map(|it| it.to_string_lossy().into_owned())
+            .collect();
+        let s = javac.iter().position(|it| it == "-s").unwrap();
+        assert_eq!(
+            javac[s + 1],
+            dir.join("target/generated-sources").display().to_string()
+        );
+        if find_program("javac").is_none() {
+            return;
+        }
+
+        // Left by the processors of a previous build
+        let generated = skipped
+            .generated_sources_dir()
+            .join("marais/Generated.java");
+        std::fs::create_dir_all(generated.parent().unwrap()).unwrap();
+        std::fs::write(&generated, "package marais; class Generated {}").unwrap();
+        let stats = build(&skipped, &env.comp_backend, false).await.unwrap();
+        assert_eq!(stats.compiled_files, 2);
+        assert!(skipped
+            .classes_dir()
+            .join("marais/Generated.class")
+            .is_file());
+
+        // They run again and regenerate everything
+        build(&module("full"), &env.comp_backend, false)
+            .await
+            .unwrap();
+        assert!(!generated.exists());
+        assert!(skipped.generated_sources_dir().is_dir());
+    }
+
+    #[tokio::test]
+    async fn test_build_cache_restore() {
+        if find_program("javac").is_none() {
+            return;
+        }
+        let root = temp_dir("build-cache");
+        let mut env = Env::new(false);
+        env.build_cache = Some(root.join("cache"));
+        let checkout = |name: &str| {
+            let dir = root.join(name);
+            std::fs::create_dir_all(dir.join("src/marais")).unwrap();
+            std::fs::write(
+                dir.join("src/marais/Cached.java"),
+                "package marais; public class Cached {}",
+            )
+            .unwrap();
+            let manifest = ModuleManifest::parse(
+                r#"
+                group = "marais"
+                artifact = "cached"
+                version = "0.1.0"
+                "#,
+                None,
+            )
+            .unwrap();
+            Module::from_manifest(&dir, manifest, &env).unwrap()
+        };
+
+        let first = build_with_cache(&checkout("first"), &env).await.unwrap();
+        assert!(!first.restored);
+        assert_eq!(first.compiled_files, 1);
+
+        // Same inputs in another directory, as on another CI runner
+        let module = checkout("second");
+        let second = build_with_cache(&module, &env).await.unwrap();
+        assert!(second.restored);
+        assert_eq!(second.compiled_files, 0);
+        assert!(module.classes_dir().join("marais/Cached.class").is_file());
+
+        // An incomplete entry is compiled again
+        for file in collect_files(root.join("cache"), Some(&[".class"])) {
+            std::fs::remove_file(file).unwrap();
+        }
+        let third = build_with_cache(&checkout("third"), &env).await.unwrap();
+        assert!(!third.restored);
+        assert_eq!(third.compiled_files, 1);
+        let fourth = build_with_cache(&checkout("fourth"), &env).await.unwrap();
+        assert!(fourth.restored);
+    }
+
+    #[tokio::test]
+    async fn test_deploy() {
+        let server = MockServer::start(|_| response("201 Created", b"")).await;
+        let env = Env::new(false);
+        let dir = temp_dir("deploy");
+        let manifest = ModuleManifest::parse(
+            &format!(
+                r#"
+                group = "marais.app"
+                artifact = "deployed"
+                version = "0.1.0-SNAPSHOT"
+
+                [publish]
+                repository = "{0}releases"
+                snapshot_repository = "{0}snapshots"
+                credentials = "JCARGO_TEST_DEPLOY"
+                "#,
+                server.url
+            ),
+            None,
+        )
+        .unwrap();
+        let module = Module::from_manifest(&dir, manifest, &env).unwrap();
+        std::fs::create_dir_all(module.artifacts_dir()).unwrap();
+        for file in [
+            "deployed-0.1.0-SNAPSHOT.jar",
+            "deployed-0.1.0-SNAPSHOT-docs.jar",
+            // Never published
+            "deployed-0.1.0-SNAPSHOT-all.jar",
+        ] {
+            std::fs::write(module.artifacts_dir().join(file), file).unwrap();
+        }
+        std::env::set_var("JCARGO_TEST_DEPLOY_USERNAME", "marais");
+        std::env::set_var("JCARGO_TEST_DEPLOY_PASSWORD", "hunter2");
+
+        deploy(&module, &http_client()).await.unwrap();
+
+        let requests = server.requests();
+        let base = "/snapshots/marais/app/deployed/0.1.0-SNAPSHOT/deployed-0.1.0-SNAPSHOT";
+        let paths: Vec<_> = requests.iter().map(|it| it.path.as_str()).collect();
+        assert_eq!(
+            paths,
+            [
+                ".jar",
+                ".jar.sha1",
+                ".jar.md5",
+                "-javadoc.jar",
+                "-javadoc.jar.sha1",
+                "-javadoc.jar.md5"
+            ]
+            .iter()
+            .map(|it| format!("{}{}", base, it))
+            .collect::<Vec<_>>()
+        );
+        assert!(requests.iter().all(|it| it.method == "PUT"));
+        // marais:hunter2
+        assert!(requests
+            .iter()
+            .all(|it| it.headers.get("authorization").map(String::as_str)
+                == Some("Basic bWFyYWlzOmh1bnRlcjI=")));
+        assert_eq!(requests[0].body, b"deployed-0.1.0-SNAPSHOT.jar");
+        assert_eq!(
+            requests[1].body,
+            b"0ce3a85f46fd7f44f2a29db30a004e27389fc9c5"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_outdated() {
+        let server = MockServer::start(|req| match req.path.as_str() {
+            "/org/slf4j/slf4j-api/maven-metadata.xml" => response(
+                "200 OK",
+                br#"<metadata>
+                    <groupId>org.slf4j</groupId>
+                    <artifactId>slf4j-api</artifactId>
+                    <versioning>
+                        <latest>2.0.0-SNAPSHOT</latest>
+                        <release>1.7.32</release>
+                        <versions>
+                            <version>1.7.9</version>
+                            <version>1.7.32</version>
+                            <version>1.7.36</version>
+                            <version>2.0.0-alpha1</version>
+                            <version>2.0.0-SNAPSHOT</version>
+                        </versions>
+                    </versioning>
+                </metadata>"#,
+            ),
+            "/marais/current/maven-metadata.xml" => response(
+                "200 OK",
+                br#"<metadata>
+                    <groupId>marais</groupId>
+                    <artifactId>current</artifactId>
+                    <versioning>
+                        <latest>1.0</latest>
+                        <release>1.0</release>
+                        <versions><version>0.9</version><version>1.0</version></versions>
+                    </versioning>
+                </metadata>"#,
+            ),
+            _ => response("404 Not Found", b""),
+        })
+        .await;
+        let mut env = Env::new(false);
+        env.repos = vec![Arc::new(MavenRepo {
+            name: "mock".to_string(),
+            url: server.url.clone(),
+            layout: RepoLayout::Maven,
+        })];
+        let manifest = ModuleManifest::parse(
+            r#"
+            group = "marais"
+            artifact = "outdated"
+            version = "0.1.0"
+
+            [dependencies]
+            compile = ["org.slf4j:slf4j-api:1.7.32", "marais:current:1.0", "marais:missing:1.0"]
+            "#,
+            None,
+        )
+        .unwrap();
+        let module = Module::from_manifest(Path::new("outdated"), manifest, &env).unwrap();
+
+        let outdated = outdated(&module, &http_client()).await;
+        assert_eq!(
+            outdated,
+            vec![Outdated {
+                dependency: "org.slf4j:slf4j-api".to_string(),
+                current: "1.7.32".to_string(),
+                latest: "2.0.0-alpha1".to_string(),
+            }]
+        );
+        assert_eq!(
+            outdated_table(&outdated),
+            "   Dependency           Current  Latest\n   org.slf4j:slf4j-api  1.7.32   2.0.0-alpha1\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_partial_download_failures() {
+        let pom = |artifact: &str| {
+            format!(
+                r#"<project>
+                    <modelVersion>4.0.0</modelVersion>
+                    <groupId>marais</groupId>
+                    <artifactId>{}</artifactId>
+                    <version>1.0</version>
+                </project>"#,
+                artifact
+            )
+        };
+        let server = MockServer::start(move |req| match req.path.as_str() {
+            "/marais/ok/1.0/ok-1.0.pom" => response("200 OK", pom("ok").as_bytes()),
+            "/marais/nojar/1.0/nojar-1.0.pom" => response("200 OK", pom("nojar").as_bytes()),
+            "/marais/ok/1.0/ok-1.0.jar" => response("200 OK", b"jar"),
+            _ => response("404 Not Found", b""),
+        })
+        .await;
+        let mut env = Env::new(false);
+        env.repos = vec![Arc::new(MavenRepo {
+            name: "mock".to_string(),
+            url: server.url.clone(),
+            layout: RepoLayout::Maven,
+        })];
+        let dir = temp_dir("partial-failures");
+        let manifest = ModuleManifest::parse(
+            r#"
+            group = "marais"
+            artifact = "partial"
+            version = "0.1.0"
+
+            [dependencies]
+            compile = ["marais:nojar:1.0", "marais:ok:1.0", "marais:nopom:1.0"]
+            "#,
+            None,
+        )
+        .unwrap();
+        let module = Module::from_manifest(&dir, manifest, &env).unwrap();
+
+        let err = setup_all_dependencies(&module, &env, false, false)
+            .await
+            .unwrap_err()
+            .to_string();
+        let lines: Vec<_> = err.lines().collect();
+        assert_eq!(lines[0], "Failed to set up 2 dependencies :");
+        assert!(lines[1].starts_with("   marais:nojar:1.0 : "), "{}", err);
+        assert!(lines[2].starts_with("   marais:nopom:1.0 : "), "{}", err);
+        assert_eq!(lines.len(), 3);
+        assert!(dir.join("libs/ok-1.0.jar").exists());
+    }
+
+    #[tokio::test]
+    async fn test_scope_repositories() {
+        let pom = |artifact: &str| {
+            format!(
+                r#"<project>
+                    <modelVersion>4.0.0</modelVersion>
+                    <groupId>marais</groupId>
+                    <artifactId>{}</artifactId>
+                    <version>1.0</version>
+                </project>"#,
+                artifact
+            )
+        };
+        let global = MockServer::start(move |req| match req.path.as_str() {
+            "/marais/prod/1.0/prod-1.0.pom" => response("200 OK", pom("prod").as_bytes()),
+            "/marais/prod/1.0/prod-1.0.jar" => response("200 OK", b"jar"),
+            _ => response("404 Not Found", b""),
+        })
+        .await;
+        let tests_only = MockServer::start(move |req| match req.path.as_str() {
+            "/marais/mock/1.0/mock-1.0.pom" => response("200 OK", pom("mock").as_bytes()),
+            "/marais/mock/1.0/mock-1.0.jar" => response("200 OK", b"jar"),
+            "/marais/internal/1.0/internal-1.0.pom" => {
+                response("200 OK", pom("internal").as_bytes())
+            }
+            "/marais/internal/1.0/internal-1.0.jar" => response("200 OK", b"jar"),
+            _ => response("404 Not Found", b""),
+        })
+        .await;
+        let mut env = Env::new(false);
+        env.repos = vec![Arc::new(MavenRepo {
+            name: "global".to_string(),
+            url: global.url.clone(),
+            layout: RepoLayout::Maven,
+        })];
+        let dir = temp_dir("scope-repos");
+        let manifest = ModuleManifest::parse(
+            &format!(
+                r#"
+                group = "marais"
+                artifact = "scoped"
+                version = "0.1.0"
+
+                [dependencies]
+                compile = ["marais:prod:1.0", "marais:internal:1.0"]
+                test = ["marais:mock:1.0"]
+
+                [[dependencies.repositories.test]]
+                name = "tests-only"
+                url = "{}"
+                "#,
+                tests_only.url
+            ),
+            None,
+        )
+        .unwrap();
+        let module = Module::from_manifest(&dir, manifest, &env).unwrap();
+
+        let err = setup_all_dependencies(&module, &env, false, false)
+            .await
+            .unwrap_err()
+            .to_string();
+        assert!(dir.join("libs/prod-1.0.jar").exists());
+        assert!(dir.join("libs/mock-1.0.jar").exists());
+        // Only resolved from the global repository
+        assert!(err.contains("marais:internal:1.0"), "{}", err);
+        assert!(!dir.join("libs/internal-1.0.jar").exists());
+        assert!(tests_only
+            .requests()
+            .iter()
+            .all(|it| it.path.starts_with("/marais/mock/")));
+    }
+
+    #[tokio::test]
+    async fn test_root_repository_falls_back_to_central() {
+        let central = MockServer::start(|req| match req.path.as_str() {
+            "/marais/lib/1.0/lib-1.0.pom" => response(
+                "200 OK",
+                br#"<project>
+                    <modelVersion>4.0.0</modelVersion>
+                    <groupId>marais</groupId>
+                    <artifactId>lib</artifactId>
+                    <version>1.0</version>
+                </project>"#,
+            ),
+            "/marais/lib/1.0/lib-1.0.jar" => response("200 OK", b"jar"),
+            _ => response("404 Not Found", b""),
+        })
+        .await;
+        let company = MockServer::start(|_| response("404 Not Found", b"")).await;
+        let dir = temp_dir("root-repository-fallback");
+        std::fs::write(
+            dir.join("jcargo.toml"),
+            format!(
+                r#"
+                group = "marais"
+                artifact = "fallback"
+                version = "0.1.0"
+
+                [dependencies]
+                compile = ["marais:lib:1.0"]
+
+                [[repositories]]
+                name = "company"
+                url = "{}"
+                "#,
+                company.url
+            ),
+        )
+        .unwrap();
+        let mut env = Env::new(false);
+        env.set_central_mirror(central.url.as_str()).unwrap();
+        load_http_config(&dir.join("jcargo.toml"), &mut env).unwrap();
+        assert_eq!(env.repos[0].name, "company");
+        let module = Module::load(&dir, &env).await.unwrap();
+
+        setup_all_dependencies(&module, &env, false, false)
+            .await
+            .unwrap();
+        assert_eq!(std::fs::read(dir.join("libs/lib-1.0.jar")).unwrap(), b"jar");
+        // Tried first
+        assert!(company
+            .requests()
+            .iter()
+            .any(|it| it.path == "/marais/lib/1.0/lib-1.0.pom"));
+    }
+
+    #[tokio::test]
+    async fn test_fetch_then_offline() {
+        let pom = |artifact: &str, deps: &str| {
+            format!(
+                r#"<project>
+                    <modelVersion>4.0.0</modelVersion>
+                    <groupId>marais</groupId>
+                    <artifactId>{}</artifactId>
+                    <version>1.0</version>
+                    <dependencies>{}</dependencies>
+                </project>"#,
+                artifact, deps
+            )
+        };
+        let server = MockServer::start(move |req| match req.path.as_str() {
+            "/marais/lib/1.0/lib-1.0.pom" => response(
+                "200 OK",
+                pom(
+                    "lib",
+                    "<dependency><groupId>marais</groupId><artifactId>dep</artifactId><version>1.0</version></dependency>",
+                )
+                .as_bytes(),
+            ),
+            "/marais/dep/1.0/dep-1.0.pom" => response("200 OK", pom("dep", "").as_bytes()),
+            "/marais/lib/1.0/lib-1.0.jar" | "/marais/dep/1.0/dep-1.0.jar" => {
+                response("200 OK", b"jar")
+            }
+            "/marais/lib/1.0/lib-1.0-sources.jar" => response("200 OK", b"sources"),
+            _ => response("404 Not Found", b""),
+        })
+        .await;
+        let mut env = Env::new(false);
+        env.repos = vec![Arc::new(MavenRepo {
+            name: "mock".to_string(),
+            url: server.url.clone(),
+            layout: RepoLayout::Maven,
+        })];
+        let dir = temp_dir("fetch");
+        let manifest = ModuleManifest::parse(
+            r#"
+            group = "marais"
+            artifact = "fetched"
+            version = "0.1.0"
+
+            [dependencies]
+            compile = ["marais:lib:1.0"]
+            "#,
+            None,
+        )
+        .unwrap();
+        let module = Module::from_manifest(&dir, manifest, &env).unwrap();
+
+        let stats = fetch(&module, &env, true, false).await.unwrap();
+        // 2 poms, 2 jars and the only sources jar published
+        assert_eq!(stats.artifacts, 5);
+        assert_eq!(stats.bytes, dir_size(&dir.join("libs")));
+        assert!(dir.join("libs/dep-1.0.jar").exists());
+        assert!(dir.join("libs/lib-1.0-sources.jar").exists());
+
+        let requests = server.requests().len();
+        env.offline = true;
+        env.build_report = false;
+        execute_task_mod(Task::Build { emit: Vec::new() }, &env, &module)
+            .await
+            .unwrap();
+        assert_eq!(server.requests().len(), requests);
+    }
+
+    #[tokio::test]
+    async fn test_deploy_classifiers() {
+        let server = MockServer::start(|_| response("201 Created", b"")).await;
+        let env = Env::new(false);
+        let dir = temp_dir("deploy-classifiers");
+        let manifest = ModuleManifest::parse(
+            &format!(
+                r#"
+                group = "marais"
+                artifact = "classified"
+                version = "0.1.0"
+
+                [package]
+                classifiers = ["tests"]
+
+                [publish]
+                repository = "{}"
+                "#,
+                server.url
+            ),
+            None,
+        )
+        .unwrap();
+        let module = Module::from_manifest(&dir, manifest, &env).unwrap();
+        std::fs::create_dir_all(module.artifacts_dir()).unwrap();
+        std::fs::write(module.artifacts_dir().join("classified-0.1.0.jar"), "").unwrap();
+
+        let err = deploy(&module, &http_client()).await.unwrap_err();
+        assert!(err.to_string().starts_with("The tests jar isn't packaged"));
+
+        for file in ["classified-0.1.0-tests.jar", "classified-0.1.0-other.jar"] {
+            std::fs::write(module.artifacts_dir().join(file), "").unwrap();
+        }
+        deploy(&module, &http_client()).await.unwrap();
+
+        let paths: Vec<_> = server
+            .requests()
+            .into_iter()
+            .map(|it| it.path)
+            .filter(|it| it.ends_with(".jar"))
+            .collect();
+        assert_eq!(
+            paths,
+            [
+                "/marais/classified/0.1.0/classified-0.1.0.jar",
+                "/marais/classified/0.1.0/classified-0.1.0-tests.jar"
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_deploy_existing() {
+        let server = MockServer::start(|_| response("409 Conflict", b"")).await;
+        let env = Env::new(false);
+        let dir = temp_dir("deploy-existing");
+        let manifest = ModuleManifest::parse(
+            &format!(
+                r#"
+                group = "marais"
+                artifact = "deployed"
+                version = "0.1.0"
+
+                [publish]
+                repository = "{}releases/"
+                "#,
+                server.url
+            ),
+            None,
+        )
+        .unwrap();
+        let module = Module::from_manifest(&dir, manifest, &env).unwrap();
+        std::fs::create_dir_all(module.artifacts_dir()).unwrap();
+        std::fs::write(module.artifacts_dir().join("deployed-0.1.0.jar"), "").unwrap();
+
+        let err = deploy(&module, &http_client()).await.unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "{}releases/marais/deployed/0.1.0/deployed-0.1.0.jar already exists in the repository",
+                server.url
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn test_locked_digest_mismatch() {
+        let server = MockServer::start(|req| match req.path.as_str() {
+            "/marais/lib/1.0/lib-1.0.pom" => response(
+                "200 OK",
+                br#"<project xsi:schemaLocation="http://maven.apache.org/POM/4.0.0 http://maven.apache.org/maven-v4_0_0.xsd">
+                    <modelVersion>4.0.0</modelVersion>
+                    <groupId>marais</groupId>
+                    <artifactId>lib</artifactId>
+                    <version>1.0</version>
+                </project>"#,
+            ),
+            "/marais/lib/1.0/lib-1.0.jar" => response("200 OK", b"jar"),
+            _ => response("404 Not Found", b""),
+        })
+        .await;
+        let mut env = Env::new(false);
+        env.locked = true;
+        env.repos = vec![Arc::new(MavenRepo {
+            name: "mock".to_string(),
+            url: server.url.clone(),
+            layout: RepoLayout::Maven,
+        })];
+        let dir = temp_dir("locked-digest");
+        std::fs::write(
+            dir.join("jcargo.lock"),
+            r#"
+            [[dependency]]
+            group = "marais"
+            artifact = "lib"
+            version = "1.0"
+            sha1 = "f92e777f4341930bad9b2422283c4680d00dbc06"
+            "#,
+        )
+        .unwrap();
+        let manifest = ModuleManifest::parse(
+            r#"
+            group = "marais"
+            artifact = "locked"
+            version = "0.1.0"
+
+            [dependencies]
+            compile = ["marais:lib:1.0"]
+            "#,
+            None,
+        )
+        .unwrap();
+        let module = Module::from_manifest(&dir, manifest, &env).unwrap();
+
+        check(&module, &env).await.unwrap();
+
+        // Cached, it won't be downloaded again
+        std::fs::write(dir.join("libs/lib-1.0.jar"), b"tampered").unwrap();
+        let err = check(&module, &env).await.unwrap_err();
+        assert!(err
+            .to_string()
+            .starts_with("Checksum mismatch of the jar of 'marais:lib:1.0' (sha1)"));
+    }
+
+    #[tokio::test]
+    async fn test_lockfile_written() {
+        let server = MockServer::start(|req| match req.path.as_str() {
+            "/marais/lib/1.0/lib-1.0.pom" => response(
+                "200 OK",
+                br#"<project xsi:schemaLocation="http://maven.apache.org/POM/4.0.0 http://maven.apache.org/maven-v4_0_0.xsd">
+                    <modelVersion>4.0.0</modelVersion>
+                    <groupId>marais</groupId>
+                    <artifactId>lib</artifactId>
+                    <version>1.0</version>
+                </project>"#,
+            ),
+            "/marais/lib/1.0/lib-1.0.jar" => response("200 OK", b"jar"),
+            "/marais/lib/1.0/lib-1.0-natives.jar" => response("200 OK", b"natives"),
+            _ => response("404 Not Found", b""),
+        })
+        .await;
+        let mut env = Env::new(false);
+        env.repos = vec![Arc::new(MavenRepo {
+            name: "mock".to_string(),
+            url: server.url.clone(),
+            layout: RepoLayout::Maven,
+        })];
+        let dir = temp_dir("lockfile-written");
+        let manifest = ModuleManifest::parse(
+            r#"
+            group = "marais"
+            artifact = "locked"
+            version = "0.1.0"
+
+            [dependencies]
+            compile = ["marais:lib:1.0", "marais:lib:1.0:natives"]
+            "#,
+            None,
+        )
+        .unwrap();
+        let module = Module::from_manifest(&dir, manifest, &env).unwrap();
+
+        check(&module, &env).await.unwrap();
+        let lockfile = Lockfile::load(&dir).await.unwrap().unwrap();
+        let locked: Vec<_> = lockfile
+            .dependencies
+            .iter()
+            .map(|it| (it.notation(), it.sha1.as_deref().unwrap()))
+            .collect();
+        assert_eq!(
+            locked,
+            vec![
+                (
+                    "marais:lib:1.0".to_string(),
+                    "f92e777f4341930bad9b2422283c4680d00dbc06"
+                ),
+                (
+                    "marais:lib:1.0:natives".to_string(),
+                    "fb3103717e10ef27c5be5504af5004c858897e9e"
+                ),
+            ]
+        );
+
+        // The classified jar is checked against its own digests
+        env.locked = true;
+        std::fs::write(dir.join("libs/lib-1.0-natives.jar"), b"tampered").unwrap();
+        let err = check(&module, &env).await.unwrap_err();
+        assert!(err
+            .to_string()
+            .starts_with("Checksum mismatch of the jar of 'marais:lib:1.0:natives' (sha1)"));
+    }
+
+    #[test]
+    fn test_classpath_order() {
+        let env = Env::new(false);
+        let module = |order: &str| {
+            let manifest = ModuleManifest::parse(
+                &format!(
+                    r#"
+                    group = "marais"
+                    artifact = "ordered"
+                    version = "0.1.0"
+                    {}
+
+                    [dependencies]
+                    compile = ["marais:lib:1.0"]
+                    "#,
+                    order
+                ),
+                None,
+            )
+            .unwrap();
+            Module::from_manifest(Path::new("ordered"), manifest, &env).unwrap()
+        };
+        let entries = |module: &Module| {
+            compile_classpath(module)
+                .split(CLASSPATH_SEPARATOR)
+                .map(|it| it.to_string())
+                .collect::<Vec<_>>()
+        };
+        let jar = Path::new("ordered/libs/lib-1.0.jar").display().to_string();
+
+        for order in ["", "[javac]\nclasspath_order = \"deps-first\""] {
+            let module = module(order);
+            let classes = module.classes_dir().display().to_string();
+            assert_eq!(entries(&module), vec![jar.clone(), classes]);
+        }
+
+        let module = module("[javac]\nclasspath_order = \"classes-first\"");
+        let classes = module.classes_dir().display().to_string();
+        assert_eq!(entries(&module), vec![classes, jar]);
+    }
+
+    #[test]
+    fn test_multiplatform_sources() {
+        let env = Env::new(false);
+        let dir = temp_dir("multiplatform");
+        for set in ["commonMain", "jvmMain", "jsMain"] {
+            let kotlin = dir.join("src").join(set).join("kotlin");
+            std::fs::create_dir_all(&kotlin).unwrap();
+            std::fs::write(kotlin.join("Platform.kt"), "").unwrap();
+        }
+        let manifest = ModuleManifest::parse(
+            r#"
+            group = "marais"
+            artifact = "multiplatform"
+            version = "0.1.0"
+            "#,
+            None,
+        )
+        .unwrap();
+        let module = Module::from_manifest(&dir, manifest, &env).unwrap();
+        let source = |set: &str| {
+            dir.join("src")
+                .join(set)
+                .join("kotlin/Platform.kt")
+                .display()
+                .to_string()
+        };
+
+        let args = kotlinc_args(&module);
+        assert!(args.contains(&"-Xmulti-platform".to_string()));
+        assert!(args.contains(&format!("-Xcommon-sources={}", source("commonMain"))));
+        assert!(args.contains(&source("commonMain")));
+        assert!(args.contains(&source("jvmMain")));
+        assert!(!args.contains(&source("jsMain")));
+    }
 }
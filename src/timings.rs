@@ -0,0 +1,194 @@
+use std::fmt::Write;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use tokio::fs;
+
+/// Written in the target dir of the workspace root with `--timings`
+pub const TIMINGS_FILE: &str = "timings.json";
+pub const TIMINGS_HTML: &str = "timings.html";
+
+/// Start and end of the units of work of a run, shared by the concurrent tasks
+#[derive(Debug, Clone)]
+pub struct Timings {
+    origin: Instant,
+    units: Arc<Mutex<Vec<Recorded>>>,
+}
+
+/// Since the origin
+#[derive(Debug, Clone)]
+struct Recorded {
+    kind: String,
+    name: String,
+    start: Duration,
+    end: Duration,
+}
+
+/// What `timings.json` contains
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TimingReport {
+    pub total_ms: f64,
+    /// Most units running at the same time
+    pub max_concurrency: usize,
+    /// Names of the units the run waited on, in order: from the last to end, each one is
+    /// preceded by the unit that ended last before it started
+    pub critical_path: Vec<String>,
+    /// Sorted by start
+    pub units: Vec<Unit>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Unit {
+    /// `resolve`, `download`, `check` or `compile`
+    pub kind: String,
+    pub name: String,
+    /// Since the start of the run
+    pub start_ms: f64,
+    pub duration_ms: f64,
+    /// Row of the chart, overlapping units never share one
+    pub lane: usize,
+}
+
+impl Default for Timings {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Timings {
+    pub fn new() -> Self {
+        Self {
+            origin: Instant::now(),
+            units: Arc::new(Mutex::new(Vec::new())),
+        }
+    }
+
+    /// Record a unit that started at `start` and ends now
+    pub fn record(&self, kind: &str, name: impl Into<String>, start: Instant) {
+        let end = self.origin.elapsed();
+        let start = start.saturating_duration_since(self.origin);
+        self.units.lock().unwrap().push(Recorded {
+            kind: kind.to_string(),
+            name: name.into(),
+            start,
+            end,
+        });
+    }
+
+    pub fn report(&self) -> TimingReport {
+        let ms = |it: Duration| it.as_secs_f64() * 1000.0;
+        let mut recorded = self.units.lock().unwrap().clone();
+        recorded.sort_by_key(|it| it.start);
+
+        // End of the last unit of each lane
+        let mut lanes: Vec<Duration> = Vec::new();
+        let mut units = Vec::new();
+        for unit in &recorded {
+            let lane = match lanes.iter().position(|it| *it <= unit.start) {
+                Some(lane) => lane,
+                None => {
+                    lanes.push(Duration::ZERO);
+                    lanes.len() - 1
+                }
+            };
+            lanes[lane] = unit.end;
+            units.push(Unit {
+                kind: unit.kind.clone(),
+                name: unit.name.clone(),
+                start_ms: ms(unit.start),
+                duration_ms: ms(unit.end.saturating_sub(unit.start)),
+                lane,
+            });
+        }
+
+        let mut critical_path = Vec::new();
+        let mut current = recorded.iter().max_by_key(|it| it.end);
+        while let Some(unit) = current {
+            critical_path.push(unit.name.clone());
+            current = recorded
+                .iter()
+                .filter(|it| it.end <= unit.start)
+                .max_by_key(|it| it.end);
+        }
+        critical_path.reverse();
+
+        TimingReport {
+            total_ms: ms(self.origin.elapsed()),
+            max_concurrency: lanes.len(),
+            critical_path,
+            units,
+        }
+    }
+
+    /// Write `timings.json` and its chart in `target_dir`
+    pub async fn write(&self, target_dir: &Path) -> Result<()> {
+        let report = self.report();
+        fs::create_dir_all(target_dir).await?;
+        fs::write(
+            target_dir.join(TIMINGS_FILE),
+            serde_json::to_string_pretty(&report)?,
+        )
+        .await?;
+        fs::write(target_dir.join(TIMINGS_HTML), report.html()).await?;
+        Ok(())
+    }
+}
+
+impl TimingReport {
+    /// A bar per unit on its lane, the critical path is highlighted
+    pub fn html(&self) -> String {
+        let scale = if self.total_ms > 0.0 {
+            100.0 / self.total_ms
+        } else {
+            0.0
+        };
+        let mut out = String::new();
+        writeln!(
+            out,
+            "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\"><title>jcargo timings</title>\n\
+             <style>body{{font-family:sans-serif}} .chart{{position:relative;height:{}px}} \
+             .unit{{position:absolute;height:18px;font-size:11px;overflow:hidden;white-space:nowrap;\
+             background:#8ab;border-radius:2px}} .critical{{background:#d86}}</style></head><body>",
+            self.max_concurrency * 22
+        )
+        .unwrap();
+        writeln!(
+            out,
+            "<h1>Timings</h1>\n<p>{:.0} ms, up to {} units at once</p>\n<div class=\"chart\">",
+            self.total_ms, self.max_concurrency
+        )
+        .unwrap();
+        for unit in &self.units {
+            let class = if self.critical_path.contains(&unit.name) {
+                "unit critical"
+            } else {
+                "unit"
+            };
+            writeln!(
+                out,
+                "<div class=\"{}\" style=\"left:{:.3}%;width:{:.3}%;top:{}px\" title=\"{} {} ({:.1} ms)\">{}</div>",
+                class,
+                unit.start_ms * scale,
+                unit.duration_ms * scale,
+                unit.lane * 22,
+                unit.kind,
+                escape(&unit.name),
+                unit.duration_ms,
+                escape(&unit.name)
+            )
+            .unwrap();
+        }
+        out.push_str("</div>\n</body></html>\n");
+        out
+    }
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
@@ -0,0 +1,537 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Context, Result};
+use reqwest::Client;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use tokio::fs;
+use tokio::io::AsyncReadExt;
+use tokio::process::Command;
+use tracing::info;
+use url::Url;
+use walkdir::WalkDir;
+
+use crate::backend::Jdk;
+use crate::dependencies::error::ResolutionError;
+use crate::dependencies::maven_metadata::compare_versions;
+use crate::io::{download_file, download_memory, DOWNLOAD_ATTEMPTS};
+
+/// Global configuration, in `$JCARGO_HOME` or `~/.jcargo`
+pub const GLOBAL_CONFIG: &str = "config.toml";
+
+/// Files of the project root naming the java version to build with, in priority order
+pub const VERSION_FILES: [&str; 2] = [".java-version", ".sdkmanrc"];
+
+/// Where JDKs are provisioned from
+pub const ADOPTIUM_API: &str = "https://api.adoptium.net/";
+
+/// Provisioned JDKs, by major version, in the jcargo home
+pub const JDKS_DIR: &str = "jdks";
+
+/// The JDKs installed on this machine, from the `[toolchains]` table of the global config :
+/// ```toml
+/// # Download the missing JDKs, like --auto-provision
+/// auto_provision = true
+///
+/// [toolchains]
+/// 17 = "/usr/lib/jvm/temurin-17"
+/// ```
+/// It also holds the `[signing]` key, which doesn't belong in a shared manifest.
+#[derive(Debug, Default)]
+pub struct Toolchains {
+    /// Version to JDK home
+    jdks: BTreeMap<String, PathBuf>,
+    pub auto_provision: bool,
+    pub signing: SigningDef,
+}
+
+#[derive(Debug, Deserialize)]
+struct GlobalConfigDef {
+    #[serde(default)]
+    auto_provision: bool,
+    #[serde(default)]
+    toolchains: BTreeMap<String, PathBuf>,
+    #[serde(default)]
+    signing: SigningDef,
+}
+
+/// Key signing the jars with `package --sign`, from the global config
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SigningDef {
+    /// Keystore file, relative to the global config
+    pub keystore: Option<PathBuf>,
+    /// Of the signing key in the keystore
+    pub alias: Option<String>,
+    /// Prefix of the environment variables holding the passwords, `credentials = "SIGNING"`
+    /// reads `SIGNING_STOREPASS` and `SIGNING_KEYPASS` if set. Asked by jarsigner otherwise.
+    pub credentials: Option<String>,
+}
+
+/// `$JCARGO_HOME`, or `~/.jcargo`
+pub fn jcargo_home() -> Option<PathBuf> {
+    match std::env::var_os("JCARGO_HOME") {
+        Some(home) => Some(PathBuf::from(home)),
+        None => std::env::var_os("HOME")
+            .or_else(|| std::env::var_os("USERPROFILE"))
+            .map(|home| PathBuf::from(home).join(".jcargo")),
+    }
+}
+
+/// Where the global config is, `$JCARGO_HOME/config.toml` or `~/.jcargo/config.toml`
+pub fn global_config_path() -> Option<PathBuf> {
+    jcargo_home().map(|it| it.join(GLOBAL_CONFIG))
+}
+
+impl Toolchains {
+    pub fn parse(document: &str) -> Result<Self> {
+        let def: GlobalConfigDef = toml::from_str(document)?;
+        Ok(Self {
+            jdks: def.toolchains,
+            auto_provision: def.auto_provision,
+            signing: def.signing,
+        })
+    }
+
+    /// The global config, empty when there is none
+    pub fn load() -> Result<Self> {
+        let path = match global_config_path() {
+            Some(path) if path.exists() => path,
+            _ => return Ok(Self::default()),
+        };
+        let document = std::fs::read_to_string(&path)
+            .with_context(|| format!("Can't read {}", path.display()))?;
+        let mut config =
+            Self::parse(&document).with_context(|| format!("Invalid {}", path.display()))?;
+        if let (Some(keystore), Some(dir)) = (&mut config.signing.keystore, path.parent()) {
+            *keystore = dir.join(&*keystore);
+        }
+        Ok(config)
+    }
+
+    /// The JDK configured for exactly this version, else the newest one of the same major
+    pub fn select(&self, version: &str) -> Option<&Path> {
+        if let Some(home) = self.jdks.get(version) {
+            return Some(home);
+        }
+        let major = major_version(version);
+        self.jdks
+            .iter()
+            .filter(|(it, _)| major_version(it) == major)
+            .max_by(|(a, _), (b, _)| compare_versions(a, b))
+            .map(|(_, home)| home.as_path())
+    }
+}
+
+/// The java version requested by the `.java-version` or the `.sdkmanrc` in `dir`, with the file it
+/// comes from
+pub fn requested_version(dir: &Path) -> Result<Option<(&'static str, String)>> {
+    for file in VERSION_FILES {
+        let path = dir.join(file);
+        if !path.is_file() {
+            continue;
+        }
+        let text = std::fs::read_to_string(&path)
+            .with_context(|| format!("Can't read {}", path.display()))?;
+        let version = if file == ".sdkmanrc" {
+            // java=17.0.2-tem, the vendor doesn't matter
+            text.lines()
+                .filter_map(|it| it.trim().strip_prefix("java="))
+                .map(|it| it.split('-').next().unwrap_or(it).trim().to_string())
+                .next()
+        } else {
+            text.lines()
+                .map(str::trim)
+                .find(|it| !it.is_empty() && !it.starts_with('#'))
+                .map(str::to_string)
+        };
+        if let Some(version) = version {
+            return Ok(Some((file, version)));
+        }
+    }
+    Ok(None)
+}
+
+/// A java version requested by a file of the project root
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RequestedJdk {
+    /// `.java-version` or `.sdkmanrc`
+    pub file: &'static str,
+    pub version: String,
+    /// Home of the toolchain matching the version, `None` when no toolchain does
+    pub home: Option<PathBuf>,
+}
+
+/// The version requested in `dir` and its toolchain, `None` when no version is requested
+pub fn project_jdk(dir: &Path, toolchains: &Toolchains) -> Result<Option<RequestedJdk>> {
+    Ok(requested_version(dir)?.map(|(file, version)| RequestedJdk {
+        file,
+        home: toolchains.select(&version).map(Path::to_path_buf),
+        version,
+    }))
+}
+
+/// Downloads the JDKs no toolchain provides
+#[derive(Debug, Clone)]
+pub struct Provisioner {
+    pub client: Client,
+    /// Root of the Adoptium API
+    pub api: Url,
+    /// Where the JDKs are extracted, one directory per major version
+    pub jdks: PathBuf,
+}
+
+#[derive(Debug, Deserialize)]
+struct AssetDef {
+    binary: BinaryDef,
+}
+
+#[derive(Debug, Deserialize)]
+struct BinaryDef {
+    package: PackageDef,
+}
+
+#[derive(Debug, Deserialize)]
+struct PackageDef {
+    name: String,
+    link: String,
+    /// sha256 of the archive
+    checksum: String,
+}
+
+/// Operating system of this machine, as Adoptium names it
+pub fn adoptium_os() -> &'static str {
+    match std::env::consts::OS {
+        "macos" => "mac",
+        os => os,
+    }
+}
+
+/// Architecture of this machine, as Adoptium names it
+pub fn adoptium_arch() -> &'static str {
+    match std::env::consts::ARCH {
+        "x86_64" => "x64",
+        "x86" => "x32",
+        "powerpc64" => "ppc64le",
+        arch => arch,
+    }
+}
+
+impl Provisioner {
+    pub fn new(client: Client, jdks: PathBuf) -> Self {
+        Self {
+            client,
+            api: Url::parse(ADOPTIUM_API).unwrap(),
+            jdks,
+        }
+    }
+
+    /// Assets of the latest release of the JDK `major` for this os and architecture
+    pub fn assets_url(&self, major: u32, os: &str, arch: &str) -> Url {
+        let mut url = self
+            .api
+            .join(&format!("v3/assets/latest/{}/hotspot", major))
+            .unwrap();
+        url.query_pairs_mut()
+            .append_pair("architecture", arch)
+            .append_pair("image_type", "jdk")
+            .append_pair("os", os)
+            .append_pair("vendor", "eclipse");
+        url
+    }
+
+    /// Where the JDK `major` is extracted
+    pub fn home(&self, major: u32) -> PathBuf {
+        self.jdks.join(major.to_string())
+    }
+
+    /// The home of the JDK `major`, downloaded and extracted the first time. The archive is
+    /// checked against the sha256 published by Adoptium.
+    pub async fn provision(&self, major: u32) -> Result<PathBuf> {
+        let home = self.home(major);
+        if Jdk::at(&home).is_ok() {
+            return Ok(home);
+        }
+        let (os, arch) = (adoptium_os(), adoptium_arch());
+        let assets: Vec<AssetDef> = serde_json::from_str(
+            &download_memory(&self.client, self.assets_url(major, os, arch))
+                .await
+                .with_context(|| format!("Can't list the JDK {} releases", major))?,
+        )?;
+        let package = assets
+            .into_iter()
+            .next()
+            .map(|it| it.binary.package)
+            .ok_or_else(|| anyhow!("Adoptium has no JDK {} for {} {}", major, os, arch))?;
+        // The name becomes a path in the jdks dir
+        if package.name.is_empty()
+            || package.name == ".."
+            || package.name.contains(|c| c == '/' || c == '\\')
+        {
+            return Err(anyhow!("Invalid JDK archive name '{}'", package.name));
+        }
+
+        fs::create_dir_all(&self.jdks).await?;
+        let archive = self.jdks.join(&package.name);
+        info!("Downloading the JDK {} ({})", major, package.name);
+        download_file(
+            &self.client,
+            Url::parse(&package.link)?,
+            &archive,
+            DOWNLOAD_ATTEMPTS,
+        )
+        .await?;
+        let checksum = sha256_file(&archive).await?;
+        if !checksum.eq_ignore_ascii_case(&package.checksum) {
+            fs::remove_file(&archive).await?;
+            return Err(ResolutionError::ChecksumMismatch {
+                name: package.name,
+                expected: package.checksum,
+                actual: checksum,
+            }
+            .into());
+        }
+
+        // Extracted aside, the home only appears once complete
+        let partial = self.jdks.join(format!(".{}-partial", major));
+        if partial.exists() {
+            fs::remove_dir_all(&partial).await?;
+        }
+        fs::create_dir_all(&partial).await?;
+        // tar also reads the zip archives on windows
+        let status = Command::new("tar")
+            .arg("-xf")
+            .arg(&archive)
+            .arg("-C")
+            .arg(&partial)
+            .status()
+            .await
+            .context("Can't run tar to extract the JDK")?;
+        if !status.success() {
+            return Err(anyhow!("Can't extract {}", archive.display()));
+        }
+        // The archives have a top directory, and macOS ones a Contents/Home
+        let extracted = WalkDir::new(&partial)
+            .max_depth(4)
+            .into_iter()
+            .filter_map(|it| it.ok())
+            .find(|it| it.file_type().is_dir() && Jdk::at(it.path()).is_ok())
+            .map(|it| it.path().to_path_buf())
+            .ok_or_else(|| anyhow!("{} doesn't contain a JDK", package.name))?;
+        if home.exists() {
+            fs::remove_dir_all(&home).await?;
+        }
+        fs::rename(&extracted, &home).await?;
+        fs::remove_dir_all(&partial).await?;
+        fs::remove_file(&archive).await?;
+        info!("Provisioned the JDK {} in {}", major, home.display());
+        Ok(home)
+    }
+}
+
+/// sha256 of the file at `path`, read by chunks
+async fn sha256_file(path: &Path) -> Result<String> {
+    let mut file = fs::File::open(path).await?;
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0; 64 * 1024];
+    loop {
+        let read = file.read(&mut buf).await?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buf[..read]);
+    }
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// The JDK to build the project in `dir` with, `None` to keep the one in PATH.
+/// A version requested by a `.java-version` or a `.sdkmanrc` comes from the toolchains, or is
+/// provisioned when none matches. Otherwise the JDK `source` is only needed when the `javac` in
+/// PATH (its major version) is missing or older, it is then provisioned.
+pub async fn select_jdk(
+    dir: &Path,
+    source: &str,
+    javac: Option<u32>,
+    toolchains: &Toolchains,
+    provisioner: Option<&Provisioner>,
+) -> Result<Option<PathBuf>> {
+    match (project_jdk(dir, toolchains)?, provisioner) {
+        (
+            Some(RequestedJdk {
+                home: Some(home), ..
+            }),
+            _,
+        ) => Ok(Some(home)),
+        (Some(requested), Some(provisioner)) => provisioner
+            .provision(major_version(&requested.version))
+            .await
+            .map(Some),
+        (Some(requested), None) => Err(anyhow!(
+            "{} requests java {}, but no JDK of the [toolchains] of the global config matches it",
+            requested.file,
+            requested.version
+        )),
+        (None, Some(provisioner)) => {
+            let major = major_version(source);
+            if javac.map_or(false, |it| it >= major) {
+                return Ok(None);
+            }
+            match toolchains.select(source) {
+                Some(home) => Ok(Some(home.to_path_buf())),
+                None => provisioner.provision(major).await.map(Some),
+            }
+        }
+        (None, None) => Ok(None),
+    }
+}
+
+/// `[java] source` of the manifest `document`, 17 by default
+pub fn manifest_source(document: &str) -> String {
+    document
+        .parse::<toml::Value>()
+        .ok()
+        .and_then(|it| it.get("java")?.get("source")?.as_str().map(str::to_string))
+        .unwrap_or_else(|| "17".to_string())
+}
+
+/// Major java version of `17`, `17.0.2`, `1.8.0_292` or `temurin-17.0.2`
+pub fn major_version(version: &str) -> u32 {
+    let version = version.trim_start_matches(|c: char| !c.is_ascii_digit());
+    let version = version.strip_prefix("1.").unwrap_or(version);
+    let digits = version
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(version.len());
+    version[..digits].parse().unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+
+    use sha2::{Digest, Sha256};
+
+    use crate::backend::exe_name;
+    use crate::io::http_client;
+    use crate::test_utils::{response, temp_dir, MockServer};
+    use crate::toolchains::{project_jdk, select_jdk, Provisioner, RequestedJdk, Toolchains};
+
+    #[tokio::test]
+    async fn test_java_version_file() {
+        let toolchains = Toolchains::parse(
+            r#"
+            [toolchains]
+            11 = "/usr/lib/jvm/java-11"
+            17 = "/usr/lib/jvm/temurin-17"
+            "1.8" = "/usr/lib/jvm/java-8"
+            "#,
+        )
+        .unwrap();
+        let dir = temp_dir("java-version-file");
+        assert_eq!(project_jdk(&dir, &toolchains).unwrap(), None);
+        let home = |dir: &Path| project_jdk(dir, &toolchains).unwrap().unwrap().home;
+
+        std::fs::write(dir.join(".java-version"), "17\n").unwrap();
+        assert_eq!(home(&dir), Some(PathBuf::from("/usr/lib/jvm/temurin-17")));
+
+        std::fs::remove_file(dir.join(".java-version")).unwrap();
+        std::fs::write(dir.join(".sdkmanrc"), "# sdk env\njava=8.0.302-tem\n").unwrap();
+        assert_eq!(home(&dir), Some(PathBuf::from("/usr/lib/jvm/java-8")));
+
+        std::fs::write(dir.join(".sdkmanrc"), "java=21.0.1-tem\n").unwrap();
+        assert_eq!(
+            project_jdk(&dir, &toolchains).unwrap(),
+            Some(RequestedJdk {
+                file: ".sdkmanrc",
+                version: "21.0.1".to_string(),
+                home: None,
+            })
+        );
+        assert!(select_jdk(&dir, "17", None, &toolchains, None)
+            .await
+            .unwrap_err()
+            .to_string()
+            .starts_with(".sdkmanrc requests java 21.0.1,"));
+    }
+
+    #[test]
+    fn test_select_newest_of_major() {
+        let toolchains = Toolchains::parse(
+            r#"
+            [toolchains]
+            "17.0.10" = "/usr/lib/jvm/temurin-17.0.10"
+            "17.0.2" = "/usr/lib/jvm/temurin-17.0.2"
+            "11.0.20" = "/usr/lib/jvm/java-11"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            toolchains.select("17"),
+            Some(Path::new("/usr/lib/jvm/temurin-17.0.10"))
+        );
+        assert_eq!(
+            toolchains.select("17.0.2"),
+            Some(Path::new("/usr/lib/jvm/temurin-17.0.2"))
+        );
+        assert_eq!(toolchains.select("21"), None);
+    }
+
+    #[tokio::test]
+    async fn test_provision_jdk() {
+        let dir = temp_dir("provision");
+        // A fake JDK archive with the layout of the Adoptium ones
+        let bin = dir.join("archive/jdk-17.0.2+8/bin");
+        std::fs::create_dir_all(&bin).unwrap();
+        std::fs::write(bin.join(exe_name("javac")), "").unwrap();
+        let status = std::process::Command::new("tar")
+            .args(["-czf", "jdk.tar.gz", "-C", "archive", "jdk-17.0.2+8"])
+            .current_dir(&dir)
+            .status()
+            .unwrap();
+        assert!(status.success());
+        let archive = std::fs::read(dir.join("jdk.tar.gz")).unwrap();
+        let checksum = format!("{:x}", Sha256::digest(&archive));
+
+        let server = MockServer::start(move |req| {
+            let (name, checksum) = match req.path.split('?').next().unwrap() {
+                "/v3/assets/latest/17/hotspot" => ("jdk.tar.gz", checksum.as_str()),
+                "/v3/assets/latest/11/hotspot" => ("jdk.tar.gz", "0000"),
+                "/v3/assets/latest/21/hotspot" => ("../jdk.tar.gz", checksum.as_str()),
+                "/jdk.tar.gz" => return response("200 OK", &archive),
+                _ => return response("404 Not Found", b""),
+            };
+            let assets = format!(
+                r#"[{{"binary":{{"package":{{"name":"{}","link":"http://{}/jdk.tar.gz","checksum":"{}"}}}}}}]"#,
+                name, req.headers["host"], checksum
+            );
+            response("200 OK", assets.as_bytes())
+        })
+        .await;
+        let jdks = dir.join("jdks");
+        let mut provisioner = Provisioner::new(http_client(), jdks.clone());
+        assert_eq!(
+            provisioner.assets_url(17, "linux", "x64").as_str(),
+            "https://api.adoptium.net/v3/assets/latest/17/hotspot?architecture=x64&image_type=jdk&os=linux&vendor=eclipse"
+        );
+        provisioner.api = server.url.clone();
+
+        let err = provisioner.provision(11).await.unwrap_err();
+        assert!(err
+            .to_string()
+            .starts_with("Checksum mismatch of jdk.tar.gz"));
+        assert!(!jdks.join("11").exists());
+        assert!(!jdks.join("jdk.tar.gz").exists());
+
+        let err = provisioner.provision(21).await.unwrap_err();
+        assert_eq!(err.to_string(), "Invalid JDK archive name '../jdk.tar.gz'");
+
+        let home = provisioner.provision(17).await.unwrap();
+        assert_eq!(home, jdks.join("17"));
+        assert!(home.join("bin").join(exe_name("javac")).is_file());
+        assert!(!jdks.join("jdk.tar.gz").exists());
+        // Cached
+        let requests = server.requests().len();
+        provisioner.provision(17).await.unwrap();
+        assert_eq!(server.requests().len(), requests);
+    }
+}
@@ -0,0 +1,723 @@
+use std::collections::HashSet;
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use anyhow::{anyhow, Context, Result};
+
+use crate::dependencies::{Dependency, JcargoLocalDependency};
+use crate::manifest::{ModuleManifest, WorkspaceManifest};
+use crate::module::{find_manifest_dir, read_manifest, Module, MANIFEST_FILE};
+use crate::{status, Env};
+
+/// A set of modules built together.
+/// A project without a `[workspace]` table is a workspace with a single member.
+#[derive(Debug)]
+pub struct Workspace {
+    /// Workspace root directory
+    pub root: PathBuf,
+    /// Members, sorted so that a member always comes after its dependencies
+    pub members: Vec<Module>,
+    /// Directories of the members processed when none is selected, all of them if empty
+    pub default_members: Vec<PathBuf>,
+}
+
+/// The directory of the manifest to load when running from `start` and the member to select:
+/// the closest manifest, unless it belongs to a member of an enclosing workspace, then it is the
+/// workspace with this member
+pub fn find_workspace_root(start: &Path) -> Result<(PathBuf, Option<PathBuf>)> {
+    let dir = find_manifest_dir(start)?;
+    for parent in dir.ancestors().skip(1) {
+        let file = parent.join(MANIFEST_FILE);
+        if !file.is_file() {
+            continue;
+        }
+        let document = std::fs::read_to_string(&file)
+            .with_context(|| format!("Can't read {}", file.display()))?;
+        let def = match WorkspaceManifest::parse(&document)
+            .with_context(|| format!("Invalid {}", file.display()))?
+            .workspace
+        {
+            Some(def) => def,
+            // A module nested in another one, not a workspace
+            None => continue,
+        };
+        let member = def
+            .members
+            .iter()
+            .filter(|m| !def.exclude.iter().any(|ex| Path::new(ex) == Path::new(m)))
+            .any(|m| parent.join(m).canonicalize().ok().as_ref() == Some(&dir));
+        // Like cargo, the closest workspace is the only one that can own the module
+        return Ok(if member {
+            (parent.to_path_buf(), Some(dir))
+        } else {
+            (dir, None)
+        });
+    }
+    Ok((dir, None))
+}
+
+impl Workspace {
+    pub async fn load(path: &Path, env: &Env) -> Result<Self> {
+        let document = read_manifest(path).await?;
+        Self::from_document(&document, &path.join(MANIFEST_FILE), path, env).await
+    }
+
+    /// Load the manifest `file` instead of the `jcargo.toml` in `path`, the members still have
+    /// theirs
+    pub async fn load_from(file: &Path, path: &Path, env: &Env) -> Result<Self> {
+        let document = tokio::fs::read_to_string(file)
+            .await
+            .with_context(|| format!("Can't read {}", file.display()))?;
+        Self::from_document(&document, file, path, env).await
+    }
+
+    async fn from_document(document: &str, file: &Path, path: &Path, env: &Env) -> Result<Self> {
+        let root = WorkspaceManifest::parse(document)?;
+        let (members, default_members) = match root.workspace {
+            Some(def) => {
+                let mut members = Vec::with_capacity(def.members.len());
+                for member in def
+                    .members
+                    .iter()
+                    .filter(|m| !def.exclude.iter().any(|ex| Path::new(ex) == Path::new(m)))
+                {
+                    // Absolute so other members can reference it from their own directory
+                    let dir = path.join(member).canonicalize()?;
+                    let document = read_manifest(&dir).await?;
+                    let mut manifest = ModuleManifest::parse_with_overrides(
+                        &document,
+                        &env.config,
+                        Some(&root.properties),
+                    )
+                    .with_context(|| format!("Invalid {}", dir.join(MANIFEST_FILE).display()))?;
+                    // Group can be inherited from the workspace
+                    if manifest.group.is_none() {
+                        manifest.group = root.group.clone();
+                    }
+                    members.push(Module::from_manifest(&dir, manifest, env)?);
+                }
+                link_members(&mut members);
+                let default_members = def
+                    .default_members
+                    .iter()
+                    .map(|m| {
+                        let dir = path.join(m).canonicalize()?;
+                        if members.iter().any(|it| it.dir == dir) {
+                            Ok(dir)
+                        } else {
+                            Err(anyhow!("Default member '{}' is not a workspace member", m))
+                        }
+                    })
+                    .collect::<Result<_>>()?;
+                (sort_members(members)?, default_members)
+            }
+            None => (
+                vec![Module::from_document(document, file, path, env)?],
+                Vec::new(),
+            ),
+        };
+        Ok(Self {
+            root: path.to_path_buf(),
+            members,
+            default_members,
+        })
+    }
+
+    /// Process the member in `dir` when none is named, instead of the default members
+    pub fn select_current(&mut self, dir: PathBuf) {
+        self.default_members = vec![dir];
+    }
+
+    /// Only keep the members named in `names` (by artifact or path) or the default members if
+    /// `names` is empty, along with everything they depend on.
+    pub fn select(&mut self, names: &[String]) -> Result<()> {
+        let mut wanted = Vec::new();
+        if names.is_empty() {
+            if self.default_members.is_empty() {
+                return Ok(());
+            }
+            for dir in &self.default_members {
+                wanted.extend(self.members.iter().position(|m| &m.dir == dir));
+            }
+        } else {
+            for name in names {
+                let dir = self.root.join(name).canonicalize().ok();
+                let i = self
+                    .members
+                    .iter()
+                    .position(|m| m.artifact == *name || Some(&m.dir) == dir.as_ref())
+                    .ok_or_else(|| anyhow!("No workspace member named '{}'", name))?;
+                wanted.push(i);
+            }
+        }
+        self.retain_with_dependencies(wanted);
+        Ok(())
+    }
+
+    /// Only keep the members with files changed since the git ref `since` and the members
+    /// depending on them, along with everything they depend on. Returns the changed members.
+    pub fn select_changed(&mut self, since: &str) -> Result<Vec<String>> {
+        let files = changed_files(&self.root, since)?;
+        let mut wanted: Vec<usize> = Vec::new();
+        for file in &files {
+            // The innermost member containing the file, build outputs and downloads don't count
+            if let Some(i) = (0..self.members.len())
+                .filter(|&i| file.starts_with(&self.members[i].dir))
+                .max_by_key(|&i| self.members[i].dir.components().count())
+                .filter(|&i| {
+                    let member = &self.members[i];
+                    !file.starts_with(member.target_dir())
+                        && !file.starts_with(member.dir.join("libs"))
+                })
+            {
+                if !wanted.contains(&i) {
+                    wanted.push(i);
+                }
+            }
+        }
+        wanted.sort_unstable();
+        let changed = wanted
+            .iter()
+            .map(|&i| self.members[i].artifact.clone())
+            .collect();
+        // Dependencies come first, so a single pass finds the dependents of dependents
+        for (i, member) in self.members.iter().enumerate() {
+            if !wanted.contains(&i)
+                && self
+                    .member_dependencies(member)
+                    .iter()
+                    .any(|it| wanted.contains(it))
+            {
+                wanted.push(i);
+            }
+        }
+        self.retain_with_dependencies(wanted);
+        Ok(changed)
+    }
+
+    fn retain_with_dependencies(&mut self, mut wanted: Vec<usize>) {
+        let mut selected = HashSet::new();
+        while let Some(i) = wanted.pop() {
+            if selected.insert(i) {
+                wanted.extend(self.member_dependencies(&self.members[i]));
+            }
+        }
+        let mut i = 0;
+        self.members.retain(|_| {
+            i += 1;
+            selected.contains(&(i - 1))
+        });
+    }
+
+    /// Indices of the members `module` depends on
+    fn member_dependencies(&self, module: &Module) -> Vec<usize> {
+        member_dependencies(&self.members, module)
+    }
+
+    /// Import the BOMs of the members, see [crate::dependencies::Dependencies::import_boms]
+    pub async fn import_boms(&mut self, env: &Env) -> Result<()> {
+        for member in &mut self.members {
+            member.dependencies.import_boms(&member.dir, env).await?;
+        }
+        Ok(())
+    }
+
+    /// Run `f` on each member in order.
+    /// Stops on the first failure unless `keep_going` is set, in which case independent members
+    /// are still processed and members depending on a failed one are skipped.
+    pub async fn for_each_member<'a, F, Fut>(&'a self, keep_going: bool, mut f: F) -> Result<()>
+    where
+        F: FnMut(&'a Module) -> Fut,
+        Fut: Future<Output = Result<()>>,
+    {
+        // Members that failed or were skipped
+        let mut broken = HashSet::new();
+        let mut failures = Vec::new();
+        for (i, member) in self.members.iter().enumerate() {
+            if let Some(dep) = self
+                .member_dependencies(member)
+                .into_iter()
+                .find(|it| broken.contains(it))
+            {
+                status!(
+                    "   Skipping {} (depends on failed member {})",
+                    member.artifact,
+                    self.members[dep].artifact
+                );
+                broken.insert(i);
+                continue;
+            }
+            if let Err(e) = f(member).await {
+                if !keep_going {
+                    return Err(e);
+                }
+                status!("   Failed {}: {:#}", member.artifact, e);
+                broken.insert(i);
+                failures.push(member.artifact.as_str());
+            }
+        }
+        if failures.is_empty() {
+            Ok(())
+        } else {
+            Err(anyhow!(
+                "{} member(s) failed: {}",
+                failures.len(),
+                failures.join(", ")
+            ))
+        }
+    }
+}
+
+/// Files changed since the git ref `since` in the repository containing `dir`, untracked ones
+/// included
+fn changed_files(dir: &Path, since: &str) -> Result<Vec<PathBuf>> {
+    let git = |args: &[&str]| -> Result<String> {
+        let output = Command::new("git").args(args).current_dir(dir).output()?;
+        if !output.status.success() {
+            return Err(anyhow!(
+                "git {} failed: {}",
+                args.join(" "),
+                String::from_utf8_lossy(&output.stderr).trim()
+            ));
+        }
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    };
+    let top = PathBuf::from(git(&["rev-parse", "--show-toplevel"])?.trim()).canonicalize()?;
+    let diff = git(&["diff", "--name-only", since, "--"])?;
+    let untracked = git(&["ls-files", "--others", "--exclude-standard", "--full-name"])?;
+    Ok(diff
+        .lines()
+        .chain(untracked.lines())
+        .filter(|it| !it.is_empty())
+        .map(|it| top.join(it))
+        .collect())
+}
+
+fn member_dependencies(members: &[Module], module: &Module) -> Vec<usize> {
+    module
+        .dependencies
+        .iter()
+        .filter_map(|dep| match dep {
+            Dependency::JcargoLocal(local) => members
+                .iter()
+                .position(|m| m.dir.as_path() == Path::new(&local.path)),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Replace dependencies on other members by local dependencies
+fn link_members(members: &mut [Module]) {
+    let coordinates: Vec<(String, String, String)> = members
+        .iter()
+        .map(|m| {
+            (
+                m.group.clone(),
+                m.artifact.clone(),
+                m.dir.display().to_string(),
+            )
+        })
+        .collect();
+    for member in members.iter_mut() {
+        for dep in member.dependencies.iter_mut() {
+            if let Dependency::MavenRepo(repodep) = dep {
+                if let Some((_, _, path)) = coordinates.iter().find(|(g, a, _)| {
+                    *g == repodep.coordinate.group && *a == repodep.coordinate.artifact
+                }) {
+                    *dep = Dependency::JcargoLocal(JcargoLocalDependency { path: path.clone() });
+                }
+            }
+        }
+    }
+}
+
+/// Sort members so dependencies come first, keeping the declaration order otherwise
+fn sort_members(mut members: Vec<Module>) -> Result<Vec<Module>> {
+    let mut sorted: Vec<Module> = Vec::with_capacity(members.len());
+    while !members.is_empty() {
+        let next = members
+            .iter()
+            .position(|m| member_dependencies(&members, m).is_empty());
+        match next {
+            Some(i) => sorted.push(members.remove(i)),
+            None => {
+                return Err(anyhow!(
+                    "Cycle between workspace members: {}",
+                    members
+                        .iter()
+                        .map(|m| m.artifact.as_str())
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                ))
+            }
+        }
+    }
+    Ok(sorted)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+    use std::sync::Mutex;
+
+    use anyhow::anyhow;
+
+    use crate::dependencies::Dependency;
+    use crate::manifest::ModuleManifest;
+    use crate::module::find_manifest_dir;
+    use crate::test_utils::temp_dir;
+    use crate::workspace::{find_workspace_root, link_members, sort_members, Workspace};
+    use crate::{Env, Module};
+
+    fn member(env: &Env, artifact: &str, deps: &str) -> Module {
+        let manifest = ModuleManifest::parse(
+            &format!(
+                r#"
+                group = "marais"
+                artifact = "{}"
+                version = "0.1.0"
+
+                [dependencies]
+                compileRuntime = [{}]
+                "#,
+                artifact, deps
+            ),
+            None,
+        )
+        .unwrap();
+        Module::from_manifest(&Path::new("ws").join(artifact), manifest, env).unwrap()
+    }
+
+    fn workspace(members: Vec<Module>) -> Workspace {
+        let mut members = members;
+        link_members(&mut members);
+        Workspace {
+            root: Path::new("ws").to_path_buf(),
+            members: sort_members(members).unwrap(),
+            default_members: Vec::new(),
+        }
+    }
+
+    fn write_member(root: &Path, artifact: &str, deps: &str) {
+        let dir = root.join(artifact);
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(
+            dir.join("jcargo.toml"),
+            format!(
+                r#"
+                artifact = "{}"
+                version = "0.1.0"
+
+                [dependencies]
+                compileRuntime = [{}]
+                "#,
+                artifact, deps
+            ),
+        )
+        .unwrap();
+    }
+
+    fn artifacts(ws: &Workspace) -> Vec<&str> {
+        ws.members.iter().map(|m| m.artifact.as_str()).collect()
+    }
+
+    #[tokio::test]
+    async fn test_load_from_subdirectory() {
+        let root = temp_dir("workspace-subdirectory");
+        let src = root.join("project/src");
+        std::fs::create_dir_all(&src).unwrap();
+        std::fs::write(
+            root.join("project/jcargo.toml"),
+            "group = \"marais\"\nartifact = \"project\"\nversion = \"0.1.0\"\n",
+        )
+        .unwrap();
+
+        let dir = find_manifest_dir(&src).unwrap();
+        let ws = Workspace::load(&dir, &Env::new(false)).await.unwrap();
+        assert_eq!(ws.root, root.join("project").canonicalize().unwrap());
+        assert_eq!(artifacts(&ws), vec!["project"]);
+        assert_eq!(ws.members[0].dir, ws.root);
+    }
+
+    #[tokio::test]
+    async fn test_load_from_config_file() {
+        let root = temp_dir("workspace-config-file");
+        std::fs::create_dir_all(root.join("build")).unwrap();
+        let file = root.join("build/service-a.toml");
+        std::fs::write(
+            &file,
+            "group = \"marais\"\nartifact = \"service-a\"\nversion = \"1.2.0\"\n",
+        )
+        .unwrap();
+        let env = Env::new(false);
+
+        let ws = Workspace::load_from(&file, &root.join("build"), &env)
+            .await
+            .unwrap();
+        let module = &ws.members[0];
+        assert_eq!(
+            (
+                module.group.as_str(),
+                module.artifact.as_str(),
+                module.version.as_str()
+            ),
+            ("marais", "service-a", "1.2.0")
+        );
+        assert_eq!(module.dir, root.join("build"));
+
+        let ws = Workspace::load_from(&file, &root, &env).await.unwrap();
+        assert_eq!(ws.members[0].dir, root);
+        assert!(!root.join("jcargo.toml").exists());
+    }
+
+    #[tokio::test]
+    async fn test_select_changed() {
+        let root = temp_dir("workspace-changed");
+        write_member(&root, "lib", "");
+        write_member(&root, "app", r#""marais:lib:0.1.0""#);
+        write_member(&root, "tool", "");
+        std::fs::write(
+            root.join("jcargo.toml"),
+            "group = \"marais\"\n\n[workspace]\nmembers = [\"lib\", \"app\", \"tool\"]\n",
+        )
+        .unwrap();
+        let git = |args: &[&str]| {
+            let status = std::process::Command::new("git")
+                .args([
+                    "-c",
+                    "user.name=marais",
+                    "-c",
+                    "user.email=marais@example.com",
+                ])
+                .args(args)
+                .current_dir(&root)
+                .output()
+                .unwrap()
+                .status;
+            assert!(status.success(), "git {:?}", args);
+        };
+        git(&["init", "-q"]);
+        git(&["add", "."]);
+        git(&["commit", "-q", "-m", "init"]);
+        git(&["tag", "base"]);
+        std::fs::create_dir_all(root.join("lib/src")).unwrap();
+        std::fs::write(root.join("lib/src/Lib.java"), "class Lib {}").unwrap();
+        std::fs::create_dir_all(root.join("tool/target")).unwrap();
+        std::fs::write(root.join("tool/target/out.txt"), "").unwrap();
+
+        let mut ws = Workspace::load(&root, &Env::new(false)).await.unwrap();
+        let changed = ws.select_changed("base").unwrap();
+        assert_eq!(changed, vec!["lib"]);
+        assert_eq!(artifacts(&ws), vec!["lib", "app"]);
+
+        git(&["add", "."]);
+        git(&["commit", "-q", "-m", "lib"]);
+        let mut ws = Workspace::load(&root, &Env::new(false)).await.unwrap();
+        assert!(ws.select_changed("HEAD").unwrap().is_empty());
+        assert!(ws.members.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_default_members_and_exclude() {
+        let root = temp_dir("workspace-select");
+        write_member(&root, "lib", "");
+        write_member(&root, "app", r#""marais:lib:0.1.0""#);
+        write_member(&root, "tool", "");
+        write_member(&root, "broken", "");
+        std::fs::write(root.join("broken/jcargo.toml"), "not a manifest").unwrap();
+        std::fs::write(
+            root.join("jcargo.toml"),
+            r#"
+            group = "marais"
+
+            [workspace]
+            members = ["lib", "app", "tool", "broken"]
+            default_members = ["app"]
+            exclude = ["broken"]
+            "#,
+        )
+        .unwrap();
+        let env = Env::new(false);
+
+        // Default members with their dependencies, the excluded member is never loaded
+        let mut ws = Workspace::load(&root, &env).await.unwrap();
+        assert_eq!(artifacts(&ws), vec!["lib", "app", "tool"]);
+        ws.select(&[]).unwrap();
+        assert_eq!(artifacts(&ws), vec!["lib", "app"]);
+
+        // An explicit selection overrides the default members
+        let mut ws = Workspace::load(&root, &env).await.unwrap();
+        ws.select(&["tool".to_string()]).unwrap();
+        assert_eq!(artifacts(&ws), vec!["tool"]);
+
+        let mut ws = Workspace::load(&root, &env).await.unwrap();
+        assert!(ws.select(&["unknown".to_string()]).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_load_from_member() {
+        let root = temp_dir("workspace-from-member");
+        write_member(&root, "lib", "");
+        write_member(&root, "app", r#""marais:lib:0.1.0""#);
+        write_member(&root, "excluded", "");
+        std::fs::write(
+            root.join("excluded/jcargo.toml"),
+            "group = \"marais\"\nartifact = \"excluded\"\nversion = \"0.1.0\"\n",
+        )
+        .unwrap();
+        std::fs::write(
+            root.join("jcargo.toml"),
+            r#"
+            group = "marais"
+
+            [workspace]
+            members = ["lib", "app", "excluded"]
+            exclude = ["excluded"]
+            "#,
+        )
+        .unwrap();
+        let src = root.join("app/src/marais");
+        std::fs::create_dir_all(&src).unwrap();
+        let root = root.canonicalize().unwrap();
+
+        let (dir, member) = find_workspace_root(&src).unwrap();
+        assert_eq!(dir, root);
+        assert_eq!(member, Some(root.join("app")));
+        let mut ws = Workspace::load(&dir, &Env::new(false)).await.unwrap();
+        ws.select_current(member.unwrap());
+        ws.select(&[]).unwrap();
+        // The group is inherited from the workspace
+        assert_eq!(artifacts(&ws), vec!["lib", "app"]);
+        assert_eq!(ws.members[1].group, "marais");
+
+        // An excluded member is a project of its own
+        assert_eq!(
+            find_workspace_root(&root.join("excluded")).unwrap(),
+            (root.join("excluded"), None)
+        );
+        assert_eq!(find_workspace_root(&root).unwrap(), (root, None));
+    }
+
+    #[tokio::test]
+    async fn test_inherited_properties() {
+        let root = temp_dir("workspace-properties");
+        write_member(
+            &root,
+            "lib",
+            r#""com.google.code.gson:gson:${gson.version}""#,
+        );
+        write_member(
+            &root,
+            "app",
+            r#""com.google.code.gson:gson:${gson.version}""#,
+        );
+        let app = root.join("app/jcargo.toml");
+        let document = std::fs::read_to_string(&app).unwrap();
+        std::fs::write(
+            &app,
+            format!(
+                "{}
+[properties]
+\"gson.version\" = \"2.9.0\"\n",
+                document
+            ),
+        )
+        .unwrap();
+        std::fs::write(
+            root.join("jcargo.toml"),
+            r#"
+            group = "marais"
+
+            [properties]
+            "gson.version" = "2.8.9"
+
+            [workspace]
+            members = ["lib", "app"]
+            "#,
+        )
+        .unwrap();
+        let gson = |module: &Module| match module.dependencies.iter_compile().next() {
+            Some(Dependency::MavenRepo(it)) => it.coordinate.version.clone(),
+            other => panic!("Unexpected dependency {:?}", other),
+        };
+
+        let mut env = Env::new(false);
+        let ws = Workspace::load(&root, &env).await.unwrap();
+        assert_eq!(artifacts(&ws), vec!["lib", "app"]);
+        assert_eq!(gson(&ws.members[0]), "2.8.9");
+        // A member's own properties take precedence
+        assert_eq!(gson(&ws.members[1]), "2.9.0");
+
+        // Also through the --config overrides
+        env.config = vec!["version=1.0.0".parse().unwrap()];
+        let ws = Workspace::load(&root, &env).await.unwrap();
+        assert_eq!(ws.members[0].version, "1.0.0");
+        assert_eq!(gson(&ws.members[0]), "2.8.9");
+    }
+
+    #[tokio::test]
+    async fn test_keep_going() {
+        let env = Env::new(false);
+        let ws = workspace(vec![
+            member(&env, "broken", ""),
+            member(&env, "fine", ""),
+            member(&env, "dependent", r#""marais:broken:0.1.0""#),
+        ]);
+
+        let built = Mutex::new(Vec::new());
+        let res = ws
+            .for_each_member(true, |m| {
+                let built = &built;
+                async move {
+                    if m.artifact == "broken" {
+                        return Err(anyhow!("compilation failed"));
+                    }
+                    built.lock().unwrap().push(m.artifact.clone());
+                    Ok(())
+                }
+            })
+            .await;
+
+        assert_eq!(
+            res.unwrap_err().to_string(),
+            "1 member(s) failed: broken".to_string()
+        );
+        // The independent member is still built, the dependent one is skipped
+        assert_eq!(*built.lock().unwrap(), vec!["fine".to_string()]);
+    }
+
+    #[tokio::test]
+    async fn test_stop_on_first_failure() {
+        let env = Env::new(false);
+        let ws = workspace(vec![member(&env, "broken", ""), member(&env, "fine", "")]);
+
+        let built = Mutex::new(Vec::new());
+        let res = ws
+            .for_each_member(false, |m| {
+                let built = &built;
+                async move {
+                    if m.artifact == "broken" {
+                        return Err(anyhow!("compilation failed"));
+                    }
+                    built.lock().unwrap().push(m.artifact.clone());
+                    Ok(())
+                }
+            })
+            .await;
+
+        assert!(res.is_err());
+        assert!(built.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_members_order() {
+        let env = Env::new(false);
+        let ws = workspace(vec![
+            member(&env, "app", r#""marais:lib:0.1.0""#),
+            member(&env, "lib", ""),
+        ]);
+        let order: Vec<_> = ws.members.iter().map(|m| m.artifact.as_str()).collect();
+        assert_eq!(order, vec!["lib", "app"]);
+    }
+}
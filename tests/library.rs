@@ -0,0 +1,38 @@
+use std::fs;
+use std::path::PathBuf;
+
+use jcargo::tasks::build;
+use jcargo::{Env, Module};
+
+fn fixture() -> PathBuf {
+    let dir = std::env::temp_dir().join("jcargo-it-library");
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(dir.join("src/marais")).unwrap();
+    fs::write(
+        dir.join("jcargo.toml"),
+        r#"
+        group = "marais"
+        artifact = "embedded"
+        version = "0.1.0"
+        "#,
+    )
+    .unwrap();
+    fs::write(
+        dir.join("src/marais/Main.java"),
+        "package marais; public class Main { public static void main(String[] args) {} }",
+    )
+    .unwrap();
+    dir
+}
+
+#[tokio::test]
+async fn test_build_as_library() {
+    let dir = fixture();
+    let env = Env::new(false);
+    let module = Module::load(&dir, &env).await.unwrap();
+    assert_eq!(module.artifact, "embedded");
+
+    build(&module, &env.comp_backend, false).await.unwrap();
+
+    assert!(module.classes_dir().join("marais/Main.class").is_file());
+}
@@ -0,0 +1,113 @@
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+fn fixture() -> PathBuf {
+    let dir = std::env::temp_dir().join("jcargo-it-run-stdin");
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(dir.join("src/marais")).unwrap();
+    fs::write(
+        dir.join("jcargo.toml"),
+        r#"
+        group = "marais"
+        artifact = "echo"
+        version = "0.1.0"
+
+        [[entrypoints]]
+        class = "marais.Echo"
+        "#,
+    )
+    .unwrap();
+    fs::write(
+        dir.join("src/marais/Echo.java"),
+        r#"package marais;
+        public class Echo {
+            public static void main(String[] args) throws Exception {
+                String line = new java.io.BufferedReader(new java.io.InputStreamReader(System.in)).readLine();
+                System.out.println("Hello, " + line);
+            }
+        }"#,
+    )
+    .unwrap();
+    dir
+}
+
+fn jcargo(dir: &Path, task: &str) -> Command {
+    let mut cmd = Command::new(env!("CARGO_BIN_EXE_jcargo"));
+    cmd.args(["--working-dir", dir.to_str().unwrap(), "--no-report", task]);
+    cmd
+}
+
+#[test]
+fn test_run_forwards_stdin() {
+    if Command::new("javac").arg("-version").output().is_err() {
+        return;
+    }
+    let dir = fixture();
+    assert!(jcargo(&dir, "build").status().unwrap().success());
+
+    let mut child = jcargo(&dir, "run")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(b"marais\n").unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stdout).contains("Hello, marais"));
+}
+
+#[test]
+fn test_print_rejects_task() {
+    let dir = std::env::temp_dir().join("jcargo-it-print-task");
+    fs::create_dir_all(&dir).unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_jcargo"))
+        .args([
+            "--working-dir",
+            dir.to_str().unwrap(),
+            "--print",
+            "cfg",
+            "build",
+        ])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr)
+        .contains("--print cfg replaces the task, run them separately"));
+}
+
+#[test]
+fn test_info_from_member() {
+    let dir = std::env::temp_dir().join("jcargo-it-info-member");
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(dir.join("app/src/marais")).unwrap();
+    fs::create_dir_all(dir.join("lib")).unwrap();
+    fs::write(
+        dir.join("jcargo.toml"),
+        "group = \"marais\"\n\n[workspace]\nmembers = [\"lib\", \"app\"]\n",
+    )
+    .unwrap();
+    // The members inherit their group from the workspace
+    for member in ["lib", "app"] {
+        fs::write(
+            dir.join(member).join("jcargo.toml"),
+            format!("artifact = \"{}\"\nversion = \"0.1.0\"\n", member),
+        )
+        .unwrap();
+    }
+
+    let output = Command::new(env!("CARGO_BIN_EXE_jcargo"))
+        .args(["--offline", "info"])
+        .current_dir(dir.join("app/src/marais"))
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("marais:app:0.1.0"));
+    assert!(!stdout.contains("marais:lib:0.1.0"));
+}